@@ -0,0 +1,6 @@
+-- email address (unique, case-insensitive) + verification state
+ALTER TABLE users
+  ADD COLUMN email TEXT,
+  ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE UNIQUE INDEX idx_users_email_lower ON users (lower(email));
@@ -0,0 +1,3 @@
+-- usernames are case-folded on registration; older mixed-case rows still
+-- must not collide with a lower-case registration
+CREATE UNIQUE INDEX idx_users_username_lower ON users (lower(username));
@@ -0,0 +1,15 @@
+-- service accounts: users that cannot log in and call the api with client credentials
+ALTER TABLE users ADD COLUMN service_account BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- oauth clients (client_credentials grant), the secret is sha256 hashed and shown once
+CREATE TABLE oauth_clients (
+  id BIGSERIAL PRIMARY KEY,
+  client_id TEXT UNIQUE NOT NULL,
+  client_secret_hash TEXT NOT NULL,
+  user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  name TEXT NOT NULL,
+  last_used_at TIMESTAMP WITH TIME ZONE,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_oauth_clients_user_id ON oauth_clients (user_id);
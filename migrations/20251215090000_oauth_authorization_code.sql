@@ -0,0 +1,5 @@
+-- clients of the authorization code flow: no service account, public clients (spa / mobile)
+-- have no secret and rely on PKCE
+ALTER TABLE oauth_clients ALTER COLUMN user_id DROP NOT NULL;
+ALTER TABLE oauth_clients ALTER COLUMN client_secret_hash DROP NOT NULL;
+ALTER TABLE oauth_clients ADD COLUMN redirect_uris TEXT[] NOT NULL DEFAULT '{}';
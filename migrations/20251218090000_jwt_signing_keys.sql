@@ -0,0 +1,8 @@
+-- rotated JWT signing keys, shared by every instance (PEM files live in JWT_KEY_DIR)
+CREATE TABLE jwt_signing_keys (
+  kid TEXT PRIMARY KEY,
+  alg TEXT NOT NULL,
+  file TEXT,
+  active_at TIMESTAMP WITH TIME ZONE NOT NULL,
+  retire_at TIMESTAMP WITH TIME ZONE
+);
@@ -0,0 +1,2 @@
+-- permission scopes an authorization code client may request (openid / profile / email are always allowed)
+ALTER TABLE oauth_clients ADD COLUMN scopes TEXT[] NOT NULL DEFAULT '{}';
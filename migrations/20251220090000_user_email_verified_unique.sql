@@ -0,0 +1,3 @@
+-- only verified email addresses are unique: an unverified address cannot block its owner
+DROP INDEX idx_users_email_lower;
+CREATE UNIQUE INDEX idx_users_email_lower ON users (lower(email)) WHERE email_verified;
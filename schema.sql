@@ -4,9 +4,18 @@ CREATE TABLE users (
   username TEXT UNIQUE NOT NULL,
   password_hash TEXT NOT NULL,
   disabled BOOLEAN NOT NULL DEFAULT FALSE,
+  totp_secret TEXT,
+  totp_enabled BOOLEAN NOT NULL DEFAULT FALSE,
+  email TEXT,
+  email_verified BOOLEAN NOT NULL DEFAULT FALSE,
+  service_account BOOLEAN NOT NULL DEFAULT FALSE,
   created_at TIMESTAMP WITH TIME ZONE DEFAULT now()
 );
 
+-- username and verified email are unique ignoring case
+CREATE UNIQUE INDEX idx_users_username_lower ON users (lower(username));
+CREATE UNIQUE INDEX idx_users_email_lower ON users (lower(email)) WHERE email_verified;
+
 -- roles
 CREATE TABLE roles (
   id BIGSERIAL PRIMARY KEY,
@@ -33,3 +42,84 @@ CREATE TABLE role_permissions (
   permission_id BIGINT REFERENCES permissions(id) ON DELETE CASCADE,
   PRIMARY KEY (role_id, permission_id)
 );
+
+-- user_recovery_codes (TOTP recovery, sha256 hashed)
+CREATE TABLE user_recovery_codes (
+  id BIGSERIAL PRIMARY KEY,
+  user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  code_hash TEXT NOT NULL,
+  used_at TIMESTAMP WITH TIME ZONE,
+  created_at TIMESTAMP WITH TIME ZONE DEFAULT now()
+);
+
+CREATE INDEX idx_user_recovery_codes_user_id ON user_recovery_codes (user_id);
+
+-- personal_access_tokens (scripts / CI, sha256 hashed)
+CREATE TABLE personal_access_tokens (
+  id BIGSERIAL PRIMARY KEY,
+  user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  name TEXT NOT NULL,
+  token_hash TEXT UNIQUE NOT NULL,
+  scopes TEXT[] NOT NULL DEFAULT '{}',
+  expires_at TIMESTAMP WITH TIME ZONE,
+  last_used_at TIMESTAMP WITH TIME ZONE,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_personal_access_tokens_user_id ON personal_access_tokens (user_id);
+
+-- oauth_clients (sha256 hashed secrets)
+-- service account clients have user_id (client_credentials),
+-- authorization code clients have redirect_uris and the permission scopes they may request;
+-- public clients have no secret
+CREATE TABLE oauth_clients (
+  id BIGSERIAL PRIMARY KEY,
+  client_id TEXT UNIQUE NOT NULL,
+  client_secret_hash TEXT,
+  user_id BIGINT REFERENCES users(id) ON DELETE CASCADE,
+  name TEXT NOT NULL,
+  redirect_uris TEXT[] NOT NULL DEFAULT '{}',
+  scopes TEXT[] NOT NULL DEFAULT '{}',
+  last_used_at TIMESTAMP WITH TIME ZONE,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_oauth_clients_user_id ON oauth_clients (user_id);
+
+-- user_identities ((provider, subject) at an upstream OIDC provider -> local user)
+CREATE TABLE user_identities (
+  id BIGSERIAL PRIMARY KEY,
+  user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  provider TEXT NOT NULL,
+  subject TEXT NOT NULL,
+  email TEXT,
+  last_login_at TIMESTAMP WITH TIME ZONE,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+  UNIQUE (provider, subject),
+  UNIQUE (user_id, provider)
+);
+
+-- webauthn_credentials (credential_id is base64url, public_key is the COSE key)
+CREATE TABLE webauthn_credentials (
+  id BIGSERIAL PRIMARY KEY,
+  user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  credential_id TEXT NOT NULL UNIQUE,
+  public_key BYTEA NOT NULL,
+  sign_count BIGINT NOT NULL DEFAULT 0,
+  name TEXT NOT NULL,
+  transports TEXT[] NOT NULL DEFAULT '{}',
+  last_used_at TIMESTAMP WITH TIME ZONE,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_webauthn_credentials_user_id ON webauthn_credentials (user_id);
+
+-- jwt_signing_keys (file: <JWT_KEY_DIR>/<file>_private.pem / _public.pem, NULL for the configured key;
+-- signs from active_at, verifies until retire_at)
+CREATE TABLE jwt_signing_keys (
+  kid TEXT PRIMARY KEY,
+  alg TEXT NOT NULL,
+  file TEXT,
+  active_at TIMESTAMP WITH TIME ZONE NOT NULL,
+  retire_at TIMESTAMP WITH TIME ZONE
+);
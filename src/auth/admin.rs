@@ -0,0 +1,218 @@
+use crate::auth::extractor::AuthUser;
+use crate::auth::keys::valid_key_name;
+use crate::error::{AppError, AppResult};
+use crate::repositories::user_repo::get_user_by_id;
+use crate::services::key_service;
+use crate::services::login_throttle::{Subject, clear};
+use crate::services::oauth_client_service::{register_app_client, validate_redirect_uri};
+use crate::services::service_account_service::{
+    add_client, create_account, list_account_clients, remove_client,
+};
+use crate::state::AppState;
+use crate::utils::validation::{
+    FieldErrors, ValidJson, Validate, bounded_text, normalize_username,
+};
+use axum::Json;
+use axum::extract::{Path, State};
+use jsonwebtoken::Algorithm;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::str::FromStr;
+
+pub async fn list_keys_handler(State(state): State<AppState>) -> AppResult<Json<Value>> {
+    key_service::sync(&state).await?;
+    Ok(Json(json!({ "keys": state.jwt_keys.status() })))
+}
+
+#[derive(Deserialize)]
+pub struct RotateKeyInput {
+    pub alg: String,
+    /// JWT_KEY_DIR 中的文件名前缀：`<name>_private.pem` / `<name>_public.pem`
+    pub name: String,
+    pub kid: Option<String>,
+}
+
+impl Validate for RotateKeyInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        if Algorithm::from_str(&self.alg).is_err() {
+            errors.add("alg", "unsupported algorithm");
+        }
+        if !valid_key_name(&self.name) {
+            errors.add(
+                "name",
+                "must be a file name prefix of letters, digits, - or _",
+            );
+        }
+        if let Some(kid) = &self.kid {
+            errors.check("kid", bounded_text(kid, 128));
+        }
+    }
+}
+
+/// 轮换签名密钥：记录在数据库中，所有实例同步后新密钥开始签发，旧密钥在 refresh ttl 内仍可验证
+pub async fn rotate_key_handler(
+    State(state): State<AppState>,
+    ValidJson(payload): ValidJson<RotateKeyInput>,
+) -> AppResult<Json<Value>> {
+    let alg = Algorithm::from_str(&payload.alg)
+        .map_err(|_| AppError::invalid_field("alg", "unsupported algorithm"))?;
+    let key = key_service::rotate(alg, &payload.name, payload.kid, &state).await?;
+    Ok(Json(json!({
+        "ok": true,
+        "kid": key.kid,
+        "active_at": key.active_at.timestamp(),
+    })))
+}
+
+pub async fn retire_key_handler(
+    State(state): State<AppState>,
+    Path(kid): Path<String>,
+) -> AppResult<Json<Value>> {
+    key_service::retire(&kid, &state).await?;
+    Ok(Json(json!({ "ok": true })))
+}
+
+/// 解除账号的登录锁定 / 退避
+pub async fn unlock_user_handler(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> AppResult<Json<Value>> {
+    get_user_by_id(&state.db, id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("user not found".into()))?;
+    clear(&Subject::User(id), &state).await?;
+    Ok(Json(json!({"ok": true})))
+}
+
+#[derive(Deserialize)]
+pub struct CreateServiceAccountInput {
+    pub name: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+impl Validate for CreateServiceAccountInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        match normalize_username(&self.name) {
+            Ok(name) => self.name = name,
+            Err(message) => errors.add("name", message),
+        }
+        if self
+            .roles
+            .iter()
+            .any(|role| bounded_text(role, 64).is_err())
+        {
+            errors.add("roles", "must be non-empty role names");
+        }
+    }
+}
+
+/// 创建服务账号，返回第一个客户端凭证（secret 只返回这一次）
+pub async fn create_service_account_handler(
+    State(state): State<AppState>,
+    user: AuthUser,
+    ValidJson(payload): ValidJson<CreateServiceAccountInput>,
+) -> AppResult<Json<Value>> {
+    let account = create_account(&user, &payload.name, &payload.roles, &state).await?;
+    Ok(Json(json!({
+        "id": account.id,
+        "username": account.username,
+        "roles": account.roles,
+        "client_id": account.client.client_id,
+        "client_secret": account.client_secret,
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct CreateClientInput {
+    pub name: String,
+}
+
+impl Validate for CreateClientInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        self.name = self.name.trim().to_string();
+        errors.check("name", bounded_text(&self.name, 64));
+    }
+}
+
+/// 再签发一个客户端凭证，用于轮换 secret
+pub async fn create_client_handler(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    ValidJson(payload): ValidJson<CreateClientInput>,
+) -> AppResult<Json<Value>> {
+    let (client_secret, client) = add_client(id, &payload.name, &state).await?;
+    let mut body = json!(client);
+    body["client_secret"] = json!(client_secret);
+    Ok(Json(body))
+}
+
+pub async fn list_clients_handler(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> AppResult<Json<Value>> {
+    let clients = list_account_clients(id, &state).await?;
+    Ok(Json(json!({ "clients": clients })))
+}
+
+pub async fn delete_client_handler(
+    State(state): State<AppState>,
+    Path((id, client_id)): Path<(i64, String)>,
+) -> AppResult<Json<Value>> {
+    remove_client(id, &client_id, &state).await?;
+    Ok(Json(json!({"ok": true})))
+}
+
+#[derive(Deserialize)]
+pub struct CreateAppClientInput {
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    /// 可以请求的权限 scope
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// 有后端能保存 secret 的客户端；spa / 移动端为 false，只用 PKCE
+    #[serde(default)]
+    pub confidential: bool,
+}
+
+impl Validate for CreateAppClientInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        self.name = self.name.trim().to_string();
+        errors.check("name", bounded_text(&self.name, 64));
+        if self.redirect_uris.is_empty() {
+            errors.add("redirect_uris", "at least one redirect uri is required");
+        }
+        for uri in &self.redirect_uris {
+            if let Err(message) = validate_redirect_uri(uri) {
+                errors.add("redirect_uris", format!("{}: {}", uri, message));
+            }
+        }
+        if self
+            .scopes
+            .iter()
+            .any(|scope| bounded_text(scope, 128).is_err() || scope.contains(char::is_whitespace))
+        {
+            errors.add("scopes", "must be permission names without spaces");
+        }
+    }
+}
+
+/// 注册 authorization code 客户端，confidential 客户端的 secret 只返回这一次
+pub async fn create_app_client_handler(
+    State(state): State<AppState>,
+    ValidJson(payload): ValidJson<CreateAppClientInput>,
+) -> AppResult<Json<Value>> {
+    let (client_secret, client) = register_app_client(
+        &payload.name,
+        &payload.redirect_uris,
+        &payload.scopes,
+        payload.confidential,
+        &state,
+    )
+    .await?;
+    let mut body = json!(client);
+    if let Some(client_secret) = client_secret {
+        body["client_secret"] = json!(client_secret);
+    }
+    Ok(Json(body))
+}
@@ -0,0 +1,457 @@
+use crate::auth::extractor::ClientInfo;
+use crate::error::{AppError, AppResult};
+use crate::models::authorization_code::AuthorizationCode;
+use crate::models::webauthn::AssertionCredential;
+use crate::services::auth_service::{authenticate_password, verify_mfa};
+use crate::services::mfa_service::{second_factors, start_challenge};
+use crate::services::oauth_client_service::{check_scopes, resolve_redirect};
+use crate::services::oauth_service::issue_authorization_code;
+use crate::services::webauthn_service::verify_mfa_passkey;
+use crate::state::AppState;
+use crate::utils::redis_keys::authorize_form_key;
+use crate::utils::token::{hash_token, random_token};
+use axum::extract::rejection::{FormRejection, QueryRejection};
+use axum::extract::{Form, Query, State};
+use axum::http::{StatusCode, header};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use chrono::Utc;
+use deadpool_redis::redis::{AsyncCommands, cmd};
+use serde::Deserialize;
+use url::Url;
+
+/// 授权请求参数（GET 的 query，POST 时由页面的隐藏字段带回）
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuthorizeParams {
+    pub response_type: Option<String>,
+    pub client_id: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    /// OIDC（scope 含 openid 时）
+    pub nonce: Option<String>,
+}
+
+/// 页面上的 CSRF token 的有效期
+const FORM_TOKEN_TTL_SECS: usize = 600;
+
+/// 登录 / 同意页面提交的表单
+#[derive(Deserialize)]
+pub struct AuthorizeForm {
+    #[serde(flatten)]
+    pub params: AuthorizeParams,
+    /// 每次渲染页面时签发的一次性 token，证明表单来自我们的页面
+    pub form_token: Option<String>,
+    /// "allow" | "deny"
+    pub action: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// 第二步：mfa pending token + 验证码，或者页面脚本填入的 passkey 认证结果（JSON）
+    pub mfa_token: Option<String>,
+    pub otp: Option<String>,
+    pub passkey: Option<String>,
+}
+
+/// 已确认可以重定向回去的授权请求
+struct Authorization {
+    client_name: String,
+    redirect_uri: String,
+    /// 已确认客户端可以请求的 scope，显示在同意页面上
+    scopes: Vec<String>,
+    params: AuthorizeParams,
+}
+
+impl Authorization {
+    /// 带着 code 或 error 回到客户端，原样带回 state
+    fn redirect(&self, pairs: &[(&str, &str)]) -> Response {
+        let Ok(mut url) = Url::parse(&self.redirect_uri) else {
+            return error_page("redirect_uri is invalid");
+        };
+        {
+            let mut query = url.query_pairs_mut();
+            for (key, value) in pairs {
+                query.append_pair(key, value);
+            }
+            if let Some(state) = &self.params.state {
+                query.append_pair("state", state);
+            }
+        }
+        Redirect::to(url.as_str()).into_response()
+    }
+
+    fn redirect_error(&self, error: &str, description: &str) -> Response {
+        self.redirect(&[("error", error), ("error_description", description)])
+    }
+}
+
+/// 校验授权请求：
+// 1) client_id / redirect_uri 不合法：直接显示错误页，不能重定向
+// 2) 其余错误重定向回客户端（error=invalid_request 等）
+// 3) 只支持 response_type=code，必须带 PKCE S256 的 code_challenge
+// 4) scope 只能是 OIDC scope 或客户端注册时允许的权限（invalid_scope）
+async fn check_request(
+    params: AuthorizeParams,
+    state: &AppState,
+) -> AppResult<Result<Authorization, Response>> {
+    let Some(client_id) = params.client_id.as_deref() else {
+        return Ok(Err(error_page("client_id is required")));
+    };
+    let (client, redirect_uri) =
+        match resolve_redirect(client_id, params.redirect_uri.as_deref(), state).await {
+            Ok(resolved) => resolved,
+            Err(AppError::Validation(message)) => return Ok(Err(error_page(&message))),
+            Err(e) => return Err(e),
+        };
+    let scopes = check_scopes(&client, params.scope.as_deref()).map_err(str::to_string);
+    let authorization = Authorization {
+        client_name: client.name,
+        redirect_uri,
+        scopes: scopes.clone().unwrap_or_default(),
+        params,
+    };
+    let params = &authorization.params;
+    if params.response_type.as_deref() != Some("code") {
+        return Ok(Err(authorization.redirect_error(
+            "unsupported_response_type",
+            "only response_type=code is supported",
+        )));
+    }
+    let challenge_ok = params.code_challenge.as_deref().is_some_and(|c| {
+        c.len() == 43
+            && c.bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    });
+    if !challenge_ok || params.code_challenge_method.as_deref() != Some("S256") {
+        return Ok(Err(authorization.redirect_error(
+            "invalid_request",
+            "PKCE with code_challenge_method=S256 is required",
+        )));
+    }
+    if let Err(scope) = scopes {
+        return Ok(Err(authorization.redirect_error(
+            "invalid_scope",
+            &format!("scope {} is not allowed for this client", scope),
+        )));
+    }
+    Ok(Ok(authorization))
+}
+
+/// 显示登录 / 同意页面
+pub async fn authorize_page_handler(
+    State(state): State<AppState>,
+    params: Result<Query<AuthorizeParams>, QueryRejection>,
+) -> AppResult<Response> {
+    let Ok(Query(params)) = params else {
+        return Ok(error_page("malformed authorization request"));
+    };
+    match check_request(params, &state).await? {
+        Ok(authorization) => login_page(&authorization, Step::Password, None, &state).await,
+        Err(response) => Ok(response),
+    }
+}
+
+/// 提交登录 / 同意页面：
+// 1) 重新校验授权请求（隐藏字段不可信），表单必须带着页面签发的一次性 token（防 CSRF）
+// 2) deny：error=access_denied
+// 3) 用户名密码（与 /api/login 共用失败计数与锁定）；开启了 2FA 时再显示验证码或 passkey 页面
+// 4) 成功：签发授权码，303 回到 redirect_uri?code=...&state=...
+pub async fn authorize_handler(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    form: Result<Form<AuthorizeForm>, FormRejection>,
+) -> AppResult<Response> {
+    let Ok(Form(form)) = form else {
+        return Ok(error_page("malformed authorization request"));
+    };
+    let authorization = match check_request(form.params, &state).await? {
+        Ok(authorization) => authorization,
+        Err(response) => return Ok(response),
+    };
+    if !consume_form_token(form.form_token.as_deref(), &authorization, &state).await? {
+        return login_page(
+            &authorization,
+            Step::Password,
+            Some("this page has expired, please sign in again"),
+            &state,
+        )
+        .await;
+    }
+    if form.action.as_deref() == Some("deny") {
+        return Ok(authorization.redirect_error("access_denied", "the user denied the request"));
+    }
+
+    let user = match (&form.mfa_token, &form.username, &form.password) {
+        (Some(mfa_token), _, _) => {
+            let verified = match form.passkey.as_deref() {
+                Some(passkey) => match serde_json::from_str::<AssertionCredential>(passkey) {
+                    Ok(credential) => verify_mfa_passkey(mfa_token, &credential, &state).await,
+                    Err(_) => Err(AppError::unauthorized("invalid passkey assertion")),
+                },
+                None => {
+                    let code = form.otp.as_deref().unwrap_or_default();
+                    verify_mfa(mfa_token, code, &state).await
+                }
+            };
+            match verified {
+                Ok((user, _)) => user,
+                Err(e) => return user_error(&authorization, Step::Password, e, &state).await,
+            }
+        }
+        (None, Some(username), Some(password)) => {
+            let user = match authenticate_password(username, password, &client, &state).await {
+                Ok(user) => user,
+                Err(e) => return user_error(&authorization, Step::Password, e, &state).await,
+            };
+            // 开启了 TOTP 时用验证码，只注册了 passkey 时用 passkey
+            let methods = second_factors(&user, &state).await?;
+            if !methods.is_empty() {
+                let mfa_token = start_challenge(user.id, &client, &state).await?;
+                let step = if methods.contains(&"totp") {
+                    Step::Mfa(&mfa_token)
+                } else {
+                    Step::Passkey(&mfa_token)
+                };
+                return login_page(&authorization, step, None, &state).await;
+            }
+            user
+        }
+        _ => {
+            return login_page(
+                &authorization,
+                Step::Password,
+                Some("enter your username and password"),
+                &state,
+            )
+            .await;
+        }
+    };
+
+    let params = &authorization.params;
+    let code = issue_authorization_code(
+        &AuthorizationCode {
+            client_id: params.client_id.clone().unwrap_or_default(),
+            user_id: user.id,
+            redirect_uri: authorization.redirect_uri.clone(),
+            redirect_uri_requested: params.redirect_uri.is_some(),
+            code_challenge: params.code_challenge.clone().unwrap_or_default(),
+            scope: params.scope.clone(),
+            nonce: params.nonce.clone(),
+            auth_time: Utc::now().timestamp(),
+        },
+        &state,
+    )
+    .await?;
+    Ok(authorization.redirect(&[("code", &code)]))
+}
+
+/// 认证失败显示在页面上，内部错误照常返回
+async fn user_error(
+    authorization: &Authorization,
+    step: Step<'_>,
+    e: AppError,
+    state: &AppState,
+) -> AppResult<Response> {
+    match e {
+        AppError::Internal(_) => Err(e),
+        e => login_page(authorization, step, Some(&e.to_string()), state).await,
+    }
+}
+
+/// 消费页面签发的 token（GETDEL），必须是为同一个客户端签发的
+async fn consume_form_token(
+    token: Option<&str>,
+    authorization: &Authorization,
+    state: &AppState,
+) -> AppResult<bool> {
+    let Some(token) = token else {
+        return Ok(false);
+    };
+    let mut conn = state.redis.get().await?;
+    let client_id: Option<String> = cmd("GETDEL")
+        .arg(authorize_form_key(&hash_token(token)))
+        .query_async(&mut conn)
+        .await?;
+    Ok(client_id.is_some() && client_id == authorization.params.client_id)
+}
+
+enum Step<'a> {
+    Password,
+    Mfa(&'a str),
+    Passkey(&'a str),
+}
+
+/// passkey 页面的脚本：用 mfa pending token 取选项，调用 navigator.credentials.get，结果填进表单提交
+const PASSKEY_SCRIPT: &str = r#"<script>
+const decode = s => Uint8Array.from(atob(s.replace(/-/g, '+').replace(/_/g, '/')), c => c.charCodeAt(0));
+const encode = b => btoa(String.fromCharCode(...new Uint8Array(b))).replace(/\+/g, '-').replace(/\//g, '_').replace(/=+$/, '');
+document.getElementById('passkey').addEventListener('click', async event => {
+  const form = event.target.form;
+  const response = await fetch('/api/login/2fa/passkey/options', {
+    method: 'POST',
+    headers: {'content-type': 'application/json'},
+    body: JSON.stringify({mfa_token: form.mfa_token.value}),
+  });
+  const options = await response.json();
+  options.challenge = decode(options.challenge);
+  options.allowCredentials = options.allowCredentials.map(c => ({...c, id: decode(c.id)}));
+  const credential = await navigator.credentials.get({publicKey: options});
+  const r = credential.response;
+  form.passkey.value = JSON.stringify({
+    id: credential.id,
+    response: {
+      clientDataJSON: encode(r.clientDataJSON),
+      authenticatorData: encode(r.authenticatorData),
+      signature: encode(r.signature),
+      userHandle: r.userHandle && encode(r.userHandle),
+    },
+  });
+  form.submit();
+});
+</script>"#;
+
+/// 渲染登录 / 同意页面，每次渲染签发新的一次性表单 token
+async fn login_page(
+    authorization: &Authorization,
+    step: Step<'_>,
+    error: Option<&str>,
+    state: &AppState,
+) -> AppResult<Response> {
+    let params = &authorization.params;
+    let form_token = random_token(32);
+    let mut conn = state.redis.get().await?;
+    let _: () = conn
+        .set_ex(
+            authorize_form_key(&hash_token(&form_token)),
+            params.client_id.clone().unwrap_or_default(),
+            FORM_TOKEN_TTL_SECS,
+        )
+        .await?;
+
+    let mut hidden = String::new();
+    for (name, value) in [
+        ("response_type", &params.response_type),
+        ("client_id", &params.client_id),
+        ("redirect_uri", &params.redirect_uri),
+        ("scope", &params.scope),
+        ("state", &params.state),
+        ("code_challenge", &params.code_challenge),
+        ("code_challenge_method", &params.code_challenge_method),
+        ("nonce", &params.nonce),
+        ("form_token", &Some(form_token)),
+    ] {
+        if let Some(value) = value {
+            hidden.push_str(&format!(
+                r#"<input type="hidden" name="{}" value="{}">"#,
+                name,
+                escape_html(value)
+            ));
+        }
+    }
+    let mut allow = r#"<button name="action" value="allow">Allow</button>"#.to_string();
+    let fields = match step {
+        Step::Password => concat!(
+            r#"<label>Username or email <input name="username" autocomplete="username" required></label>"#,
+            r#"<label>Password <input name="password" type="password" autocomplete="current-password" required></label>"#
+        )
+        .to_string(),
+        Step::Mfa(mfa_token) => format!(
+            r#"<input type="hidden" name="mfa_token" value="{}"><label>Two-factor code <input name="otp" autocomplete="one-time-code" required></label>"#,
+            escape_html(mfa_token)
+        ),
+        Step::Passkey(mfa_token) => {
+            allow = format!(
+                r#"<button type="button" id="passkey">Allow with passkey</button>{}"#,
+                PASSKEY_SCRIPT
+            );
+            format!(
+                r#"<input type="hidden" name="mfa_token" value="{}"><input type="hidden" name="passkey">"#,
+                escape_html(mfa_token)
+            )
+        }
+    };
+    let error = error
+        .map(|e| format!(r#"<p class="error">{}</p>"#, escape_html(e)))
+        .unwrap_or_default();
+    let mut scopes = String::new();
+    for scope in &authorization.scopes {
+        scopes.push_str(&format!(
+            r#"<li data-scope="{}">{}</li>"#,
+            escape_html(scope),
+            escape_html(&describe_scope(scope))
+        ));
+    }
+    if scopes.is_empty() {
+        scopes.push_str("<li>See your basic account details</li>");
+    }
+    let body = format!(
+        r#"<!doctype html>
+<html><head><meta charset="utf-8"><title>Sign in</title></head>
+<body>
+<h1>Sign in to {client}</h1>
+<p>{client} is asking to access your account. If you allow it, {client} will be able to:</p>
+<ul class="scopes">{scopes}</ul>
+{error}
+<form method="post" action="/oauth/authorize">
+{hidden}
+{fields}
+{allow}
+</form>
+<form method="post" action="/oauth/authorize">
+{hidden}
+<button name="action" value="deny" formnovalidate>Deny</button>
+</form>
+</body></html>"#,
+        client = escape_html(&authorization.client_name),
+    );
+    Ok(page(StatusCode::OK, body))
+}
+
+/// 同意页面上对每个 scope 的说明
+fn describe_scope(scope: &str) -> String {
+    match scope {
+        "openid" => "Confirm who you are".to_string(),
+        "profile" => "See your username".to_string(),
+        "email" => "See your email address".to_string(),
+        permission => format!("Use the {} permission on your behalf", permission),
+    }
+}
+
+fn error_page(message: &str) -> Response {
+    let body = format!(
+        r#"<!doctype html>
+<html><head><meta charset="utf-8"><title>Authorization error</title></head>
+<body><h1>Authorization error</h1><p>{}</p></body></html>"#,
+        escape_html(message)
+    );
+    page(StatusCode::BAD_REQUEST, body)
+}
+
+/// 页面不能被缓存，也不能被嵌入 iframe（防点击劫持）
+fn page(status: StatusCode, body: String) -> Response {
+    (
+        status,
+        [
+            (header::CACHE_CONTROL, "no-store"),
+            (header::X_FRAME_OPTIONS, "DENY"),
+            (header::CONTENT_SECURITY_POLICY, "frame-ancestors 'none'"),
+        ],
+        Html(body),
+    )
+        .into_response()
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,346 @@
+use crate::utils::redis_keys::{blacklist_key, user_permissions_key};
+use crate::{
+    auth::jwt::{TokenType, decode_claims},
+    error::AppError,
+    repositories::{permission_repo::get_permissions_for_user, role_repo::get_roles_for_user},
+    services::{
+        pat_service::{PAT_PREFIX, authenticate_pat},
+        session_service::{load_session, touch_session},
+    },
+    state::AppState,
+};
+use anyhow::Context as _;
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{
+        Extensions, HeaderMap,
+        header::{AUTHORIZATION, USER_AGENT},
+        request::Parts,
+    },
+};
+use deadpool_redis::redis::AsyncCommands;
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+};
+
+/// 已认证的用户，由 `AuthLayer` 写入 request extensions，handler 直接作为参数提取
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub id: i64,
+    pub jti: String,
+    pub roles: Vec<String>,
+    pub permissions: Vec<String>,
+    /// 通过个人访问令牌认证时为 token id（此时 jti 为 `pat:{id}`，没有会话）
+    pub pat_id: Option<i64>,
+    /// 令牌被限制到的权限（个人访问令牌、请求了 scope 的 OAuth token），None 为账号的全部权限
+    pub scopes: Option<Vec<String>>,
+}
+
+impl AuthUser {
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|p| p == permission)
+    }
+
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+
+    /// 完整的登录会话：不是个人访问令牌，也没有被限制 scope
+    pub fn is_login_session(&self) -> bool {
+        self.pat_id.is_none() && self.scopes.is_none()
+    }
+}
+
+/// `require_permission` 通过后写入 request extensions：这个路由要求的权限
+#[derive(Debug, Clone, Copy)]
+pub struct RoutePermission(pub &'static str);
+
+/// 从 `Authorization: Bearer <token>` 中取出 token
+pub fn bearer_token(parts: &Parts) -> Result<&str, AppError> {
+    let auth_hdr = parts
+        .headers
+        .get(AUTHORIZATION)
+        .ok_or_else(|| AppError::unauthorized("Missing Authorization"))?
+        .to_str()
+        .unwrap_or("");
+    auth_hdr
+        .strip_prefix("Bearer ")
+        .ok_or_else(|| AppError::unauthorized("Invalid token"))
+}
+
+/// 校验 access token：
+// 0) `pat_` 开头的是个人访问令牌，见 `authenticate_pat`
+// 1) 解码 jwt，必须是 access token
+// 2) redis: blacklist:{jti} 不存在, session:{jti} 存在且属于该用户（顺便更新 last_seen）
+// 3) 加载角色与权限（权限缓存在 user:{id}:perms），会话限定了 scope 时取交集
+pub async fn authenticate(token: &str, state: &AppState) -> Result<AuthUser, AppError> {
+    if token.starts_with(PAT_PREFIX) {
+        return authenticate_pat(token, state).await;
+    }
+    let claims = decode_claims(&state.jwt_keys, token, TokenType::Access)
+        .map_err(|_| AppError::unauthorized("Invalid token"))?;
+
+    let roles = get_roles_for_user(&state.db, claims.sub)
+        .await
+        .unwrap_or_default();
+
+    let mut scopes = None;
+    let permissions = match state.redis.get().await {
+        Ok(mut conn) => {
+            let black: Option<String> = conn.get(blacklist_key(&claims.jti)).await.ok();
+            if black.is_some() {
+                return Err(AppError::unauthorized("Token blacklisted"));
+            }
+            let session = load_session(&mut conn, &claims.jti)
+                .await
+                .ok()
+                .flatten()
+                .filter(|s| s.user_id == claims.sub)
+                .ok_or_else(|| AppError::unauthorized("Session expired"))?;
+            touch_session(&mut conn, &session).await.unwrap_or(());
+
+            let perm_key = user_permissions_key(claims.sub);
+            let perms_cached: Option<String> = conn.get(&perm_key).await.ok();
+            let mut perms = match perms_cached {
+                // cached as JSON array of strings
+                Some(p) => serde_json::from_str::<Vec<String>>(&p).unwrap_or_default(),
+                None => {
+                    let perms = get_permissions_for_user(&state.db, claims.sub)
+                        .await
+                        .unwrap_or_default();
+                    let _: () = conn
+                        .set_ex(&perm_key, serde_json::to_string(&perms).unwrap(), 60 * 5)
+                        .await
+                        .unwrap_or(());
+                    perms
+                }
+            };
+            // scoped tokens only keep the requested permissions
+            if let Some(limited) = &session.scopes {
+                perms.retain(|p| limited.contains(p));
+            }
+            scopes = session.scopes;
+            perms
+        }
+        // if no redis access, fall back to DB check (simpler)
+        Err(_) => get_permissions_for_user(&state.db, claims.sub)
+            .await
+            .unwrap_or_default(),
+    };
+
+    Ok(AuthUser {
+        id: claims.sub,
+        jti: claims.jti,
+        roles,
+        permissions,
+        pat_id: None,
+        scopes,
+    })
+}
+
+/// AuthLayer 已认证的用户，否则按 bearer token 认证
+async fn authenticated(parts: &mut Parts, state: &AppState) -> Result<AuthUser, AppError> {
+    if let Some(user) = parts.extensions.get::<AuthUser>() {
+        return Ok(user.clone());
+    }
+    let token = bearer_token(parts)?;
+    let user = authenticate(token, state).await?;
+    parts.extensions.insert(user.clone());
+    Ok(user)
+}
+
+/// 登录会话与 OAuth token 可以调用任何受保护的路由；
+/// 个人访问令牌只能调用 `require_permission` 要求的权限在其 scopes 中的路由
+#[async_trait]
+impl FromRequestParts<AppState> for AuthUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let user = authenticated(parts, state).await?;
+        if user.pat_id.is_some() {
+            let in_scope = parts
+                .extensions
+                .get::<RoutePermission>()
+                .zip(user.scopes.as_ref())
+                .is_some_and(|(RoutePermission(required), scopes)| {
+                    scopes.iter().any(|scope| scope == required)
+                });
+            if !in_scope {
+                return Err(AppError::forbidden(
+                    "personal access tokens can only call routes that require one of their scopes",
+                ));
+            }
+        }
+        Ok(user)
+    }
+}
+
+/// 只读的身份信息（/api/me）：任何凭证都可以，包括个人访问令牌
+pub struct AnyAuthUser(pub AuthUser);
+
+#[async_trait]
+impl FromRequestParts<AppState> for AnyAuthUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(authenticated(parts, state).await?))
+    }
+}
+
+/// 账号管理（邮箱、密码、2FA、会话、令牌等）只能用完整的登录会话：
+/// 个人访问令牌和限定了 scope 的 token 泄露后不能用来接管账号
+pub struct SessionUser(pub AuthUser);
+
+#[async_trait]
+impl FromRequestParts<AppState> for SessionUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let user = authenticated(parts, state).await?;
+        if !user.is_login_session() {
+            return Err(AppError::forbidden(
+                "account management requires a login session",
+            ));
+        }
+        Ok(Self(user))
+    }
+}
+
+/// 可信的反向代理：ip 或 CIDR 网段（TRUSTED_PROXIES，逗号分隔）；
+/// 没有配置时不信任任何转发头，只用连接地址
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Vec<(IpAddr, u8)>);
+
+impl TrustedProxies {
+    /// 例如 `10.0.0.0/8, 127.0.0.1, ::1`
+    pub fn parse(list: &str) -> anyhow::Result<Self> {
+        let mut networks = Vec::new();
+        for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (addr, prefix) = entry.split_once('/').unwrap_or((entry, ""));
+            let addr: IpAddr = addr
+                .parse()
+                .with_context(|| format!("invalid trusted proxy {}", entry))?;
+            let max = if addr.is_ipv4() { 32 } else { 128 };
+            let prefix = match prefix {
+                "" => max,
+                p => p
+                    .parse()
+                    .ok()
+                    .filter(|p| *p <= max)
+                    .with_context(|| format!("invalid trusted proxy {}", entry))?,
+            };
+            networks.push((addr, prefix));
+        }
+        Ok(Self(networks))
+    }
+
+    pub fn from_env() -> anyhow::Result<Self> {
+        match std::env::var("TRUSTED_PROXIES") {
+            Ok(list) => Self::parse(&list),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4-mapped IPv6 peers (dual-stack listeners) match IPv4 networks
+        let ip = ip.to_canonical();
+        self.0.iter().any(|(network, prefix)| match (network, ip) {
+            (IpAddr::V4(n), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - *prefix as u32).unwrap_or(0);
+                u32::from(*n) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(n), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - *prefix as u32).unwrap_or(0);
+                u128::from(*n) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        })
+    }
+}
+
+/// 客户端 ip：
+// 1) 默认就是连接地址，X-Forwarded-For / X-Real-IP 可以被客户端随意伪造
+// 2) 连接来自可信代理时，从右往左跳过可信代理，取 X-Forwarded-For 中第一个不可信的地址
+//    （更左边的项由客户端提供，不可信）；没有 X-Forwarded-For 时用代理设置的 X-Real-IP
+// 3) 可信代理左边是无法解析的内容时，用最后一个可信代理的地址
+pub fn client_ip(
+    headers: &HeaderMap,
+    extensions: &Extensions,
+    trusted: &TrustedProxies,
+) -> Option<String> {
+    let peer = extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_canonical())?;
+    if !trusted.contains(peer) {
+        return Some(peer.to_string());
+    }
+    let forwarded: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .collect();
+    if forwarded.is_empty() {
+        let real_ip = headers
+            .get("x-real-ip")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<IpAddr>().ok());
+        return Some(real_ip.unwrap_or(peer).to_canonical().to_string());
+    }
+    // closest address we know so far; every hop trusted ends at the left-most one
+    let mut closest = peer;
+    for hop in forwarded.iter().rev() {
+        match hop.parse::<IpAddr>().map(|ip| ip.to_canonical()) {
+            Ok(ip) if trusted.contains(ip) => closest = ip,
+            Ok(ip) => return Some(ip.to_string()),
+            // garbage added before a trusted proxy: the proxy's own view is all we have
+            Err(_) => break,
+        }
+    }
+    Some(closest.to_string())
+}
+
+/// 客户端信息（ip / user agent / 设备名），登录时写入会话
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// 由客户端在登录请求体中给出
+    pub device: Option<String>,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(ClientInfo {
+            ip: client_ip(&parts.headers, &parts.extensions, &state.trusted_proxies),
+            user_agent: parts
+                .headers
+                .get(USER_AGENT)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+            device: None,
+        })
+    }
+}
@@ -0,0 +1,130 @@
+use crate::auth::extractor::{ClientInfo, SessionUser};
+use crate::auth::handlers::login_response;
+use crate::error::{AppError, AppResult};
+use crate::services::federation_service::{
+    FederatedOutcome, LOGIN_STATE_TTL_SECS, complete_federated_login, list_user_identities,
+    start_federated_login, unlink_identity,
+};
+use crate::state::AppState;
+use axum::Json;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, HeaderValue, header};
+use axum::response::{IntoResponse, Redirect, Response};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+/// 上游回调参数：成功时 code + state，失败时 error（+ error_description）
+#[derive(Deserialize)]
+pub struct CallbackParams {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+/// 保存 state 的 cookie：回调时必须与 query 中的 state 一致，
+/// 别人发起的登录 / 关联不能在这个浏览器里完成（login CSRF）
+const STATE_COOKIE: &str = "federated_state";
+
+/// HttpOnly、SameSite=Lax（上游跳转回来是顶层 GET，仍会带上），只发往 /auth；max_age 为 0 时删除
+fn state_cookie(value: &str, max_age: usize, state: &AppState) -> HeaderValue {
+    let secure = if state.oidc_issuer.starts_with("https://") {
+        "; Secure"
+    } else {
+        ""
+    };
+    let cookie = format!(
+        "{}={}; Path=/auth; Max-Age={}; HttpOnly; SameSite=Lax{}",
+        STATE_COOKIE, value, max_age, secure
+    );
+    HeaderValue::from_str(&cookie).expect("cookie is a valid header value")
+}
+
+/// 请求 `Cookie` 头中的 state
+fn cookie_state(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == STATE_COOKIE)
+        .map(|(_, value)| value)
+}
+
+/// 跳转到上游登录
+pub async fn federated_login_handler(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+) -> AppResult<Response> {
+    let (url, login_state) = start_federated_login(&provider, None, &state).await?;
+    let cookie = state_cookie(&login_state, LOGIN_STATE_TTL_SECS, &state);
+    Ok(([(header::SET_COOKIE, cookie)], Redirect::to(&url)).into_response())
+}
+
+/// 上游回调：state 必须与发起请求的浏览器的 cookie 一致；
+/// 登录返回与 /api/login 相同的结果，关联请求返回关联的身份
+pub async fn federated_callback_handler(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Path(provider): Path<String>,
+    params: Result<Query<CallbackParams>, QueryRejection>,
+) -> AppResult<Response> {
+    let Query(params) = params.map_err(|e| AppError::Validation(e.body_text()))?;
+    if let Some(error) = params.error {
+        let detail = params.error_description.unwrap_or(error);
+        return Err(AppError::unauthorized(format!(
+            "identity provider returned an error: {}",
+            detail
+        )));
+    }
+    let (Some(code), Some(login_state)) = (params.code, params.state) else {
+        return Err(AppError::Validation("code and state are required".into()));
+    };
+    if cookie_state(&headers) != Some(login_state.as_str()) {
+        return Err(AppError::unauthorized(
+            "login was not started in this browser",
+        ));
+    }
+    let body =
+        match complete_federated_login(&provider, &code, &login_state, &client, &state).await? {
+            FederatedOutcome::Login(outcome) => login_response(outcome),
+            FederatedOutcome::Linked(identity) => Json(json!({ "linked": identity })),
+        };
+    let cookie = state_cookie("", 0, &state);
+    Ok(([(header::SET_COOKIE, cookie)], body).into_response())
+}
+
+/// 开始关联：返回上游授权地址，同一个浏览器完成登录后回调时关联到当前用户
+pub async fn link_identity_handler(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+    Path(provider): Path<String>,
+) -> AppResult<Response> {
+    let (url, login_state) = start_federated_login(&provider, Some(user.id), &state).await?;
+    let cookie = state_cookie(&login_state, LOGIN_STATE_TTL_SECS, &state);
+    Ok((
+        [(header::SET_COOKIE, cookie)],
+        Json(json!({ "authorization_url": url })),
+    )
+        .into_response())
+}
+
+pub async fn list_identities_handler(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+) -> AppResult<Json<Value>> {
+    let identities = list_user_identities(user.id, &state).await?;
+    Ok(Json(json!({ "identities": identities })))
+}
+
+pub async fn unlink_identity_handler(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+    Path(provider): Path<String>,
+) -> AppResult<Json<Value>> {
+    unlink_identity(user.id, &provider, &state).await?;
+    Ok(Json(json!({"ok": true})))
+}
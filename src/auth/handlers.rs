@@ -1,81 +1,507 @@
-pub struct RequiredPermission(pub &'static str);
-
-use crate::services::auth_service::{login, logout_all, refresh_tokens, register};
+use crate::auth::extractor::{AnyAuthUser, AuthUser, ClientInfo, SessionUser};
+use crate::error::AppResult;
+use crate::services::auth_service::{
+    LoginOutcome, login, login_mfa, logout, logout_all, refresh_tokens, register,
+};
+use crate::services::email_service::{change_email, verify_email};
+use crate::services::magic_link_service::{login_with_magic_link, send_magic_link};
+use crate::services::mfa_service::{confirm_totp, disable_totp, enroll_totp};
+use crate::services::password_service::{change_password, forgot_password, reset_password};
+use crate::services::pat_service::{PAT_MAX_EXPIRES_DAYS, create_pat, list_pats, revoke_pat};
+use crate::services::session_service::{
+    get_session, list_sessions, revoke_other_sessions, revoke_session,
+};
 use crate::state::AppState;
+use crate::utils::validation::{
+    FieldErrors, ValidJson, Validate, bounded_text, normalize_email, normalize_username,
+};
 use axum::Json;
-use axum::extract::State;
+use axum::extract::{Path, State};
 use axum::response::IntoResponse;
 use serde::Deserialize;
-use serde_json::json;
+use serde_json::{Value, json};
+
+/// 登录 / 修改密码时接受的最长密码（新密码另由密码策略限制）
+pub(crate) const MAX_PASSWORD_BYTES: usize = 1024;
 
 #[derive(Deserialize)]
 pub struct LoginInput {
+    /// 用户名或已验证的邮箱
     pub username: String,
     pub password: String,
+    /// 设备名，显示在会话列表中
+    pub device: Option<String>,
+}
+
+impl Validate for LoginInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        // usernames or emails; legacy usernames may not follow today's rules
+        self.username = self.username.trim().to_string();
+        errors.check("username", bounded_text(&self.username, 254));
+        if self.password.is_empty() || self.password.len() > MAX_PASSWORD_BYTES {
+            errors.add("password", "must be 1 to 1024 bytes");
+        }
+        if let Some(device) = &self.device {
+            errors.check("device", bounded_text(device, 64));
+        }
+    }
 }
 
 pub async fn login_handler(
     State(state): State<AppState>,
-    Json(payload): Json<LoginInput>,
-) -> impl IntoResponse {
-    match login(&payload.username, &payload.password, &state).await {
-        Ok(r) => Json(json!({
+    client: ClientInfo,
+    ValidJson(payload): ValidJson<LoginInput>,
+) -> AppResult<Json<Value>> {
+    let client = ClientInfo {
+        device: payload.device,
+        ..client
+    };
+    let outcome = login(&payload.username, &payload.password, &client, &state).await?;
+    Ok(login_response(outcome))
+}
+
+/// 登录结果：token，或者等待 /api/login/2fa 的 mfa pending token
+pub(crate) fn login_response(outcome: LoginOutcome) -> Json<Value> {
+    match outcome {
+        LoginOutcome::Complete(r) => Json(json!({
             "access_token": r.access_token,
             "refresh_token": r.refresh_token,
             "user": r.user
-        }))
-        .into_response(),
-        Err(e) => Json(json!({ "error": e.to_string() })).into_response(),
+        })),
+        LoginOutcome::MfaRequired { mfa_token, methods } => Json(json!({
+            "mfa_required": true,
+            "mfa_token": mfa_token,
+            "methods": methods
+        })),
     }
 }
 
 #[derive(Deserialize)]
-pub struct RefreshInput {
-    pub refresh_token: String,
+pub struct MagicLinkInput {
+    /// 用户名或已验证的邮箱
+    pub username: String,
 }
 
-pub async fn refresh_handler(
+impl Validate for MagicLinkInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        self.username = self.username.trim().to_string();
+        errors.check("username", bounded_text(&self.username, 254));
+    }
+}
+
+/// 发送登录链接；无论用户是否存在都返回 ok
+pub async fn magic_link_handler(
     State(state): State<AppState>,
-    Json(payload): Json<RefreshInput>,
-) -> impl IntoResponse {
-    match refresh_tokens(&payload.refresh_token, &state).await {
-        Ok(r) => Json(json!({
-            "access_token": r.access_token,
-            "refresh_token": r.refresh_token,
-            "user": r.user
-        }))
-        .into_response(),
-        Err(e) => Json(json!({ "error": e.to_string() })).into_response(),
+    ValidJson(payload): ValidJson<MagicLinkInput>,
+) -> AppResult<Json<Value>> {
+    send_magic_link(&payload.username, &state).await?;
+    Ok(Json(json!({"ok": true})))
+}
+
+#[derive(Deserialize)]
+pub struct MagicLinkVerifyInput {
+    pub token: String,
+    /// 设备名，显示在会话列表中
+    pub device: Option<String>,
+}
+
+impl Validate for MagicLinkVerifyInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        errors.check("token", bounded_text(&self.token, 128));
+        if let Some(device) = &self.device {
+            errors.check("device", bounded_text(device, 64));
+        }
     }
 }
 
+/// 用登录链接中的 token 登录，结果与 /api/login 相同
+pub async fn magic_link_verify_handler(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    ValidJson(payload): ValidJson<MagicLinkVerifyInput>,
+) -> AppResult<Json<Value>> {
+    let client = ClientInfo {
+        device: payload.device,
+        ..client
+    };
+    let outcome = login_with_magic_link(&payload.token, &client, &state).await?;
+    Ok(login_response(outcome))
+}
+
 #[derive(Deserialize)]
-pub struct LogoutInput {
-    pub user_id: i64,
+pub struct LoginMfaInput {
+    pub mfa_token: String,
+    /// TOTP 验证码或恢复码
+    pub code: String,
 }
 
-pub async fn logout_handler(
+impl Validate for LoginMfaInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        errors.check("mfa_token", bounded_text(&self.mfa_token, 128));
+        errors.check("code", bounded_text(&self.code, 32));
+    }
+}
+
+pub async fn login_mfa_handler(
     State(state): State<AppState>,
-    Json(payload): Json<LogoutInput>,
-) -> impl IntoResponse {
-    match logout_all(payload.user_id, &state).await {
-        Ok(_) => Json(json!({"ok": true})).into_response(),
-        Err(e) => Json(json!({"error": format!("{}", e)})).into_response(),
+    ValidJson(payload): ValidJson<LoginMfaInput>,
+) -> AppResult<Json<Value>> {
+    let r = login_mfa(&payload.mfa_token, &payload.code, &state).await?;
+    Ok(Json(json!({
+        "access_token": r.access_token,
+        "refresh_token": r.refresh_token,
+        "user": r.user
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct RefreshInput {
+    pub refresh_token: String,
+}
+
+impl Validate for RefreshInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        // a JWT; RSA signatures make it a few hundred bytes
+        errors.check("refresh_token", bounded_text(&self.refresh_token, 4096));
     }
 }
 
+pub async fn refresh_handler(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    ValidJson(payload): ValidJson<RefreshInput>,
+) -> AppResult<Json<Value>> {
+    let r = refresh_tokens(&payload.refresh_token, &client, None, &state).await?;
+    Ok(Json(json!({
+        "access_token": r.access_token,
+        "refresh_token": r.refresh_token,
+        "user": r.user
+    })))
+}
+
+/// 登出当前会话（由 access token 确定用户与 jti）
+pub async fn logout_handler(
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> AppResult<Json<Value>> {
+    logout(user.id, &user.jti, &state).await?;
+    Ok(Json(json!({"ok": true})))
+}
+
+/// 登出自己的所有会话
+pub async fn logout_all_handler(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+) -> AppResult<Json<Value>> {
+    logout_all(user.id, &state).await?;
+    Ok(Json(json!({"ok": true})))
+}
+
 #[derive(Deserialize)]
 pub struct RegisterInput {
     pub username: String,
     pub password: String,
+    pub email: Option<String>,
+}
+
+impl Validate for RegisterInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        if let Some(username) = errors.check("username", normalize_username(&self.username)) {
+            self.username = username;
+        }
+        // the password itself is checked against the password policy by the service
+        if let Some(email) = &self.email
+            && let Some(email) = errors.check("email", normalize_email(email))
+        {
+            self.email = Some(email);
+        }
+    }
 }
 
 pub async fn register_handler(
     State(state): State<AppState>,
-    Json(payload): Json<RegisterInput>,
-) -> impl IntoResponse {
-    match register(&payload.username, &payload.password, &state).await {
-        Ok(id) => Json(json!({"ok": true,"id":id})).into_response(),
-        Err(e) => Json(json!({"error": format!("{}", e)})).into_response(),
+    ValidJson(payload): ValidJson<RegisterInput>,
+) -> AppResult<Json<Value>> {
+    let id = register(
+        &payload.username,
+        &payload.password,
+        payload.email.as_deref(),
+        &state,
+    )
+    .await?;
+    Ok(Json(json!({"ok": true,"id":id})))
+}
+
+pub async fn me_handler(AnyAuthUser(user): AnyAuthUser) -> impl IntoResponse {
+    Json(json!({
+        "id": user.id,
+        "roles": user.roles,
+        "permissions": user.permissions
+    }))
+}
+
+pub async fn list_sessions_handler(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+) -> AppResult<Json<Value>> {
+    let sessions = list_sessions(user.id, &user.jti, &state).await?;
+    Ok(Json(json!({ "sessions": sessions })))
+}
+
+pub async fn get_session_handler(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+    Path(session_id): Path<String>,
+) -> AppResult<Json<Value>> {
+    let session = get_session(user.id, &session_id, &user.jti, &state).await?;
+    Ok(Json(json!(session)))
+}
+
+pub async fn revoke_session_handler(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+    Path(session_id): Path<String>,
+) -> AppResult<Json<Value>> {
+    revoke_session(user.id, &session_id, &state).await?;
+    Ok(Json(json!({"ok": true})))
+}
+
+pub async fn revoke_other_sessions_handler(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+) -> AppResult<Json<Value>> {
+    let revoked = revoke_other_sessions(user.id, &user.jti, &state).await?;
+    Ok(Json(json!({"ok": true, "revoked": revoked})))
+}
+
+/// 发布验证 access token 用的公钥（JWKS）
+pub async fn jwks_handler(State(state): State<AppState>) -> Json<Value> {
+    Json(json!({ "keys": state.jwt_keys.jwks() }))
+}
+
+pub async fn totp_enroll_handler(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+) -> AppResult<Json<Value>> {
+    let enrollment = enroll_totp(user.id, &state).await?;
+    Ok(Json(json!({
+        "secret": enrollment.secret,
+        "otpauth_uri": enrollment.otpauth_uri
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct TotpCodeInput {
+    pub code: String,
+}
+
+impl Validate for TotpCodeInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        errors.check("code", bounded_text(&self.code, 32));
+    }
+}
+
+/// 确认绑定，返回的恢复码只显示一次
+pub async fn totp_confirm_handler(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+    ValidJson(payload): ValidJson<TotpCodeInput>,
+) -> AppResult<Json<Value>> {
+    let recovery_codes = confirm_totp(user.id, &payload.code, &state).await?;
+    Ok(Json(json!({"ok": true, "recovery_codes": recovery_codes})))
+}
+
+pub async fn totp_disable_handler(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+    ValidJson(payload): ValidJson<TotpCodeInput>,
+) -> AppResult<Json<Value>> {
+    disable_totp(user.id, &payload.code, &state).await?;
+    Ok(Json(json!({"ok": true})))
+}
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordInput {
+    /// 用户名或邮箱
+    pub username: String,
+}
+
+impl Validate for ForgotPasswordInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        self.username = self.username.trim().to_string();
+        errors.check("username", bounded_text(&self.username, 254));
+    }
+}
+
+/// 无论用户是否存在都返回 ok
+pub async fn forgot_password_handler(
+    State(state): State<AppState>,
+    ValidJson(payload): ValidJson<ForgotPasswordInput>,
+) -> AppResult<Json<Value>> {
+    forgot_password(&payload.username, &state).await?;
+    Ok(Json(json!({"ok": true})))
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordInput {
+    pub token: String,
+    pub new_password: String,
+}
+
+impl Validate for ResetPasswordInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        errors.check("token", bounded_text(&self.token, 128));
+    }
+}
+
+pub async fn reset_password_handler(
+    State(state): State<AppState>,
+    ValidJson(payload): ValidJson<ResetPasswordInput>,
+) -> AppResult<Json<Value>> {
+    reset_password(&payload.token, &payload.new_password, &state).await?;
+    Ok(Json(json!({"ok": true})))
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordInput {
+    pub current_password: String,
+    pub new_password: String,
+    /// 同时吊销其他设备上的会话（当前会话保留）
+    #[serde(default)]
+    pub revoke_other_sessions: bool,
+}
+
+impl Validate for ChangePasswordInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        if self.current_password.is_empty() || self.current_password.len() > MAX_PASSWORD_BYTES {
+            errors.add("current_password", "must be 1 to 1024 bytes");
+        }
+    }
+}
+
+pub async fn change_password_handler(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+    ValidJson(payload): ValidJson<ChangePasswordInput>,
+) -> AppResult<Json<Value>> {
+    let revoked = change_password(
+        user.id,
+        &user.jti,
+        &payload.current_password,
+        &payload.new_password,
+        payload.revoke_other_sessions,
+        &state,
+    )
+    .await?;
+    Ok(Json(json!({"ok": true, "revoked_sessions": revoked})))
+}
+
+#[derive(Deserialize)]
+pub struct EmailInput {
+    pub email: String,
+}
+
+impl Validate for EmailInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        if let Some(email) = errors.check("email", normalize_email(&self.email)) {
+            self.email = email;
+        }
+    }
+}
+
+/// 设置 / 修改邮箱，发送验证邮件（同一邮箱再次提交即重发）
+pub async fn change_email_handler(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+    ValidJson(payload): ValidJson<EmailInput>,
+) -> AppResult<Json<Value>> {
+    change_email(user.id, &payload.email, &state).await?;
+    Ok(Json(json!({"ok": true})))
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailInput {
+    pub token: String,
+}
+
+impl Validate for VerifyEmailInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        errors.check("token", bounded_text(&self.token, 128));
     }
 }
+
+pub async fn verify_email_handler(
+    State(state): State<AppState>,
+    ValidJson(payload): ValidJson<VerifyEmailInput>,
+) -> AppResult<Json<Value>> {
+    verify_email(&payload.token, &state).await?;
+    Ok(Json(json!({"ok": true})))
+}
+
+#[derive(Deserialize)]
+pub struct CreatePatInput {
+    pub name: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// 不填则不过期
+    pub expires_in_days: Option<i64>,
+}
+
+impl Validate for CreatePatInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        self.name = self.name.trim().to_string();
+        errors.check("name", bounded_text(&self.name, 64));
+        if self
+            .scopes
+            .iter()
+            .any(|scope| bounded_text(scope, 128).is_err())
+        {
+            errors.add("scopes", "must be non-empty permission codes");
+        }
+        if self
+            .expires_in_days
+            .is_some_and(|days| !(1..=PAT_MAX_EXPIRES_DAYS).contains(&days))
+        {
+            errors.add(
+                "expires_in_days",
+                format!("must be between 1 and {}", PAT_MAX_EXPIRES_DAYS),
+            );
+        }
+    }
+}
+
+/// 创建个人访问令牌，明文 token 只返回这一次
+pub async fn create_pat_handler(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+    ValidJson(payload): ValidJson<CreatePatInput>,
+) -> AppResult<Json<Value>> {
+    let (token, record) = create_pat(
+        &user,
+        &payload.name,
+        &payload.scopes,
+        payload.expires_in_days,
+        &state,
+    )
+    .await?;
+    let mut body = json!(record);
+    body["token"] = json!(token);
+    Ok(Json(body))
+}
+
+pub async fn list_pats_handler(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+) -> AppResult<Json<Value>> {
+    let tokens = list_pats(user.id, &state).await?;
+    Ok(Json(json!({ "tokens": tokens })))
+}
+
+pub async fn revoke_pat_handler(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+    Path(id): Path<i64>,
+) -> AppResult<Json<Value>> {
+    revoke_pat(user.id, id, &state).await?;
+    Ok(Json(json!({"ok": true})))
+}
@@ -1,39 +1,79 @@
-use anyhow::Result;
+use crate::auth::keys::KeyRing;
+use crate::models::user::UserClaims;
+use anyhow::{Result, anyhow, bail};
 use chrono::Utc;
-use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use jsonwebtoken::{Header, Validation, decode, decode_header, encode};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenType {
+    Access,
+    Refresh,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: i64,
     pub iat: i64,
     pub exp: i64,
     pub jti: String,
+    /// session family：同一次登录派生出的所有 access / refresh token 共享
+    #[serde(default)]
+    pub fam: String,
+    /// access token 不能当 refresh token 用，反之亦然
+    pub typ: TokenType,
 }
 
-pub fn make_claims(user_id: i64, expires_secs: i64) -> Claims {
+pub fn make_claims(typ: TokenType, user_id: i64, expires_secs: i64, family: &str) -> Claims {
     let now = Utc::now().timestamp();
     Claims {
         sub: user_id,
         iat: now,
         exp: now + expires_secs,
         jti: Uuid::new_v4().to_string(),
+        fam: family.to_string(),
+        typ,
     }
 }
 
-pub fn encode_claims(secret: &[u8], claims: &Claims) -> Result<String> {
-    let token = encode(
-        &Header::new(Algorithm::HS256),
-        claims,
-        &EncodingKey::from_secret(secret),
-    )?;
+/// OpenID Connect ID token：给客户端确认登录的用户，不带 typ，不能当 access token 用
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    /// client_id
+    pub aud: String,
+    pub exp: i64,
+    pub iat: i64,
+    /// 用户在 /oauth/authorize 完成认证的时间
+    pub auth_time: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(flatten)]
+    pub user: UserClaims,
+}
+
+/// 用密钥环的当前密钥签名，header 带 kid
+pub fn encode_claims<T: Serialize>(keys: &KeyRing, claims: &T) -> Result<String> {
+    let key = keys.current();
+    let mut header = Header::new(key.alg);
+    header.kid = Some(key.kid.clone());
+    let token = encode(&header, claims, key.encoding_key())?;
     Ok(token)
 }
 
-pub fn decode_claims(secret: &[u8], token: &str) -> Result<Claims> {
-    let mut validation = Validation::new(Algorithm::HS256);
+/// 按 header 中的 kid 选择验证密钥，算法以密钥为准（不信任 header.alg）
+pub fn decode_claims(keys: &KeyRing, token: &str, typ: TokenType) -> Result<Claims> {
+    let header = decode_header(token)?;
+    let key = keys
+        .find(header.kid.as_deref())
+        .ok_or_else(|| anyhow!("unknown signing key"))?;
+    let mut validation = Validation::new(key.alg);
     validation.validate_exp = true;
-    let data = decode::<Claims>(token, &DecodingKey::from_secret(secret), &validation)?;
+    let data = decode::<Claims>(token, key.decoding_key(), &validation)?;
+    if data.claims.typ != typ {
+        bail!("unexpected token type");
+    }
     Ok(data.claims)
 }
@@ -0,0 +1,323 @@
+use std::{
+    env, fs,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{Context, Result, bail};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::Utc;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use rsa::{pkcs8::DecodePublicKey, traits::PublicKeyParts};
+use serde::Serialize;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+/// JWT 签名 / 验证密钥。
+/// HS256 使用共享密钥（不会出现在 JWKS 中），RS256 / ES256 / EdDSA 从 PEM 文件加载，
+/// 公钥通过 `/.well-known/jwks.json` 发布，其他服务只能验证不能签发
+pub struct JwtKey {
+    pub kid: String,
+    pub alg: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    jwk: Option<Value>,
+}
+
+impl JwtKey {
+    pub fn hs256(secret: &[u8]) -> Self {
+        Self {
+            kid: "default".to_string(),
+            alg: Algorithm::HS256,
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+            jwk: None,
+        }
+    }
+
+    /// 非对称密钥：私钥 PKCS#8 PEM，公钥 SPKI PEM；kid 为空时使用 RFC 7638 thumbprint
+    pub fn from_pem(
+        alg: Algorithm,
+        private_pem: &[u8],
+        public_pem: &[u8],
+        kid: Option<String>,
+    ) -> Result<Self> {
+        let public_str = std::str::from_utf8(public_pem).context("public key is not utf-8")?;
+        let (encoding, decoding, params) = match alg {
+            Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 => {
+                let key = rsa::RsaPublicKey::from_public_key_pem(public_str)
+                    .context("invalid RSA public key")?;
+                let params = json!({
+                    "kty": "RSA",
+                    "n": URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+                    "e": URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()),
+                });
+                (
+                    EncodingKey::from_rsa_pem(private_pem)?,
+                    DecodingKey::from_rsa_pem(public_pem)?,
+                    params,
+                )
+            }
+            Algorithm::ES256 => {
+                let key = p256::PublicKey::from_public_key_pem(public_str)
+                    .context("invalid P-256 public key")?;
+                let point = p256::EncodedPoint::from(key);
+                let params = json!({
+                    "kty": "EC",
+                    "crv": "P-256",
+                    "x": URL_SAFE_NO_PAD.encode(point.x().context("missing x")?),
+                    "y": URL_SAFE_NO_PAD.encode(point.y().context("missing y")?),
+                });
+                (
+                    EncodingKey::from_ec_pem(private_pem)?,
+                    DecodingKey::from_ec_pem(public_pem)?,
+                    params,
+                )
+            }
+            Algorithm::EdDSA => {
+                let key = ed25519_dalek::VerifyingKey::from_public_key_pem(public_str)
+                    .context("invalid Ed25519 public key")?;
+                let params = json!({
+                    "kty": "OKP",
+                    "crv": "Ed25519",
+                    "x": URL_SAFE_NO_PAD.encode(key.as_bytes()),
+                });
+                (
+                    EncodingKey::from_ed_pem(private_pem)?,
+                    DecodingKey::from_ed_pem(public_pem)?,
+                    params,
+                )
+            }
+            other => bail!("unsupported asymmetric algorithm {:?}", other),
+        };
+
+        let kid = kid.unwrap_or_else(|| thumbprint(&params));
+        let mut jwk = params;
+        jwk["kid"] = json!(kid);
+        jwk["alg"] = json!(alg_name(alg));
+        jwk["use"] = json!("sig");
+
+        Ok(Self {
+            kid,
+            alg,
+            encoding,
+            decoding,
+            jwk: Some(jwk),
+        })
+    }
+
+    /// 读取环境变量：
+    // JWT_ALGORITHM=HS256（默认，使用 JWT_SECRET）| RS256 | ES256 | EdDSA
+    // JWT_PRIVATE_KEY_PATH / JWT_PUBLIC_KEY_PATH: PEM 文件
+    // JWT_KEY_ID: 可选，默认 RFC 7638 thumbprint
+    pub fn from_env() -> Result<Self> {
+        let alg = env::var("JWT_ALGORITHM").unwrap_or_else(|_| "HS256".to_string());
+        let alg = Algorithm::from_str(&alg).context("invalid JWT_ALGORITHM")?;
+        if alg == Algorithm::HS256 {
+            return Ok(Self::hs256(env::var("JWT_SECRET")?.as_bytes()));
+        }
+        Self::from_files(
+            alg,
+            &env::var("JWT_PRIVATE_KEY_PATH")?,
+            &env::var("JWT_PUBLIC_KEY_PATH")?,
+            env::var("JWT_KEY_ID").ok(),
+        )
+    }
+
+    pub fn from_files(
+        alg: Algorithm,
+        private_path: &str,
+        public_path: &str,
+        kid: Option<String>,
+    ) -> Result<Self> {
+        let private_pem = fs::read(private_path).with_context(|| private_path.to_string())?;
+        let public_pem = fs::read(public_path).with_context(|| public_path.to_string())?;
+        Self::from_pem(alg, &private_pem, &public_pem, kid)
+    }
+
+    /// 从密钥目录加载 `<name>_private.pem` / `<name>_public.pem`；name 只能是文件名，不能带路径
+    pub fn from_dir(alg: Algorithm, dir: &Path, name: &str, kid: Option<String>) -> Result<Self> {
+        if !valid_key_name(name) {
+            bail!("invalid key name {:?}", name);
+        }
+        let private_path = dir.join(format!("{}_private.pem", name));
+        let public_path = dir.join(format!("{}_public.pem", name));
+        Self::from_files(
+            alg,
+            &private_path.to_string_lossy(),
+            &public_path.to_string_lossy(),
+            kid,
+        )
+    }
+
+    pub fn encoding_key(&self) -> &EncodingKey {
+        &self.encoding
+    }
+
+    pub fn decoding_key(&self) -> &DecodingKey {
+        &self.decoding
+    }
+
+    /// 公钥 JWK；对称密钥没有
+    pub fn jwk(&self) -> Option<&Value> {
+        self.jwk.as_ref()
+    }
+}
+
+impl From<Vec<u8>> for JwtKey {
+    fn from(secret: Vec<u8>) -> Self {
+        Self::hs256(&secret)
+    }
+}
+
+pub fn alg_name(alg: Algorithm) -> &'static str {
+    match alg {
+        Algorithm::HS256 => "HS256",
+        Algorithm::HS384 => "HS384",
+        Algorithm::HS512 => "HS512",
+        Algorithm::RS256 => "RS256",
+        Algorithm::RS384 => "RS384",
+        Algorithm::RS512 => "RS512",
+        Algorithm::PS256 => "PS256",
+        Algorithm::PS384 => "PS384",
+        Algorithm::PS512 => "PS512",
+        Algorithm::ES256 => "ES256",
+        Algorithm::ES384 => "ES384",
+        Algorithm::EdDSA => "EdDSA",
+    }
+}
+
+/// 密钥目录中的文件名前缀：字母、数字、`-`、`_`
+pub fn valid_key_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// RFC 7638 JWK thumbprint：必需成员按字典序序列化后 sha256
+fn thumbprint(params: &Value) -> String {
+    // serde_json::Map is a BTreeMap, so keys are already sorted
+    let canonical = serde_json::to_string(params).unwrap_or_default();
+    URL_SAFE_NO_PAD.encode(Sha256::digest(canonical.as_bytes()))
+}
+
+struct VerifyKey {
+    key: Arc<JwtKey>,
+    /// 过期后不再用于验证（unix 秒）；None 表示还没开始签发的新密钥
+    retire_at: Option<i64>,
+}
+
+impl VerifyKey {
+    fn usable(&self, now: i64) -> bool {
+        self.retire_at.is_none_or(|at| at > now)
+    }
+}
+
+struct Ring {
+    current: Arc<JwtKey>,
+    verify_only: Vec<VerifyKey>,
+}
+
+/// 密钥环：一个当前签名密钥 + 若干只用于验证的密钥（按 kid 选择）。
+/// 轮换记录在数据库里，每个实例定期用 `replace` 重建自己的密钥环（见 `key_service`）
+pub struct KeyRing {
+    /// 启动时配置的密钥，数据库里还没有轮换记录时使用
+    configured: Arc<JwtKey>,
+    ring: RwLock<Ring>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KeyStatus {
+    pub kid: String,
+    pub alg: String,
+    pub current: bool,
+    pub retire_at: Option<i64>, // None: the signing key, or one that has not started signing yet
+}
+
+impl KeyRing {
+    pub fn new(current: JwtKey) -> Self {
+        let current = Arc::new(current);
+        Self {
+            configured: current.clone(),
+            ring: RwLock::new(Ring {
+                current,
+                verify_only: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn configured(&self) -> Arc<JwtKey> {
+        self.configured.clone()
+    }
+
+    pub fn current(&self) -> Arc<JwtKey> {
+        self.ring.read().unwrap().current.clone()
+    }
+
+    /// 按 kid 查找可用于验证的密钥；没有 kid 的 token 使用当前密钥
+    pub fn find(&self, kid: Option<&str>) -> Option<Arc<JwtKey>> {
+        let ring = self.ring.read().unwrap();
+        let Some(kid) = kid else {
+            return Some(ring.current.clone());
+        };
+        if ring.current.kid == kid {
+            return Some(ring.current.clone());
+        }
+        let now = Utc::now().timestamp();
+        ring.verify_only
+            .iter()
+            .find(|v| v.key.kid == kid && v.usable(now))
+            .map(|v| v.key.clone())
+    }
+
+    /// 整体替换：verify_only 中 retire_at 为 None 的密钥一直可验证
+    pub fn replace(&self, current: Arc<JwtKey>, verify_only: Vec<(Arc<JwtKey>, Option<i64>)>) {
+        let mut ring = self.ring.write().unwrap();
+        ring.current = current;
+        ring.verify_only = verify_only
+            .into_iter()
+            .map(|(key, retire_at)| VerifyKey { key, retire_at })
+            .collect();
+    }
+
+    pub fn status(&self) -> Vec<KeyStatus> {
+        let ring = self.ring.read().unwrap();
+        let now = Utc::now().timestamp();
+        let mut keys = vec![KeyStatus {
+            kid: ring.current.kid.clone(),
+            alg: alg_name(ring.current.alg).to_string(),
+            current: true,
+            retire_at: None,
+        }];
+        keys.extend(
+            ring.verify_only
+                .iter()
+                .filter(|v| v.usable(now))
+                .map(|v| KeyStatus {
+                    kid: v.key.kid.clone(),
+                    alg: alg_name(v.key.alg).to_string(),
+                    current: false,
+                    retire_at: v.retire_at,
+                }),
+        );
+        keys
+    }
+
+    /// 所有仍可验证的公钥
+    pub fn jwks(&self) -> Vec<Value> {
+        let ring = self.ring.read().unwrap();
+        let now = Utc::now().timestamp();
+        std::iter::once(&ring.current)
+            .chain(
+                ring.verify_only
+                    .iter()
+                    .filter(|v| v.usable(now))
+                    .map(|v| &v.key),
+            )
+            .filter_map(|k| k.jwk().cloned())
+            .collect()
+    }
+}
@@ -1,3 +1,12 @@
+pub mod admin;
+pub mod authorize;
+pub mod extractor;
+pub mod federation;
 pub mod handlers;
 pub mod jwt;
+pub mod keys;
 pub mod middleware;
+pub mod oauth;
+pub mod oidc;
+pub mod rate_limit;
+pub mod webauthn;
@@ -0,0 +1,203 @@
+use crate::auth::extractor::ClientInfo;
+use crate::error::OAuthError;
+use crate::services::oauth_client_service::authenticate_app_client;
+use crate::services::oauth_service::{
+    TokenResponse, client_credentials, exchange_authorization_code, introspect_token,
+    refresh_grant, revoke_token,
+};
+use crate::services::service_account_service::authenticate_client;
+use crate::state::AppState;
+use axum::Json;
+use axum::extract::rejection::FormRejection;
+use axum::extract::{Form, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::IntoResponse;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::Deserialize;
+
+/// `application/x-www-form-urlencoded` token 请求
+#[derive(Deserialize)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub scope: Option<String>,
+    /// authorization_code
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+    /// refresh_token
+    pub refresh_token: Option<String>,
+}
+
+/// 客户端认证：`Authorization: Basic` 或请求体中的 client_id（+ client_secret），不能同时使用；
+/// 公开客户端只有 client_id
+fn client_auth(
+    headers: &HeaderMap,
+    client_id: &Option<String>,
+    client_secret: &Option<String>,
+) -> Result<(String, Option<String>), OAuthError> {
+    let basic = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Basic "));
+    match (basic, client_id, client_secret) {
+        (Some(encoded), None, None) => STANDARD
+            .decode(encoded.trim())
+            .ok()
+            .and_then(|raw| String::from_utf8(raw).ok())
+            .and_then(|pair| {
+                pair.split_once(':')
+                    .map(|(id, secret)| (id.to_string(), Some(secret.to_string())))
+            })
+            .ok_or_else(|| OAuthError::InvalidClient("malformed basic credentials".into())),
+        (None, Some(id), secret) => Ok((id.clone(), secret.clone())),
+        (Some(_), _, _) => Err(OAuthError::InvalidRequest(
+            "use only one client authentication method".into(),
+        )),
+        (None, None, _) => Err(OAuthError::InvalidClient(
+            "client authentication required".into(),
+        )),
+    }
+}
+
+/// 必须带 secret 的客户端（服务账号）
+fn client_credentials_of(
+    headers: &HeaderMap,
+    client_id: &Option<String>,
+    client_secret: &Option<String>,
+) -> Result<(String, String), OAuthError> {
+    match client_auth(headers, client_id, client_secret)? {
+        (client_id, Some(client_secret)) => Ok((client_id, client_secret)),
+        (_, None) => Err(OAuthError::InvalidClient(
+            "client authentication required".into(),
+        )),
+    }
+}
+
+/// OAuth2 token 端点：client_credentials / authorization_code（PKCE）/ refresh_token
+pub async fn token_handler(
+    State(state): State<AppState>,
+    client_info: ClientInfo,
+    headers: HeaderMap,
+    payload: Result<Form<TokenRequest>, FormRejection>,
+) -> Result<impl IntoResponse, OAuthError> {
+    let Form(payload) = payload.map_err(|e| OAuthError::InvalidRequest(e.body_text()))?;
+    let response: TokenResponse = match payload.grant_type.as_str() {
+        "client_credentials" => {
+            let (client_id, client_secret) =
+                client_credentials_of(&headers, &payload.client_id, &payload.client_secret)?;
+            client_credentials(
+                &client_id,
+                &client_secret,
+                payload.scope.as_deref(),
+                &client_info,
+                &state,
+            )
+            .await?
+        }
+        "authorization_code" => {
+            let (client_id, client_secret) =
+                client_auth(&headers, &payload.client_id, &payload.client_secret)?;
+            let client =
+                authenticate_app_client(&client_id, client_secret.as_deref(), &state).await?;
+            let code = required(&payload.code, "code")?;
+            exchange_authorization_code(
+                &client,
+                code,
+                payload.redirect_uri.as_deref(),
+                payload.code_verifier.as_deref(),
+                &client_info,
+                &state,
+            )
+            .await?
+        }
+        "refresh_token" => {
+            let (client_id, client_secret) =
+                client_auth(&headers, &payload.client_id, &payload.client_secret)?;
+            let client =
+                authenticate_app_client(&client_id, client_secret.as_deref(), &state).await?;
+            let refresh_token = required(&payload.refresh_token, "refresh_token")?;
+            refresh_grant(&client, refresh_token, &client_info, &state).await?
+        }
+        _ => return Err(OAuthError::UnsupportedGrantType),
+    };
+    // RFC 6749 §5.1: token responses must not be cached
+    Ok((
+        [
+            (header::CACHE_CONTROL, "no-store"),
+            (header::PRAGMA, "no-cache"),
+        ],
+        Json(response),
+    ))
+}
+
+fn required<'a>(value: &'a Option<String>, name: &str) -> Result<&'a str, OAuthError> {
+    value
+        .as_deref()
+        .ok_or_else(|| OAuthError::InvalidRequest(format!("{} is required", name)))
+}
+
+/// introspection / revocation 请求（RFC 7662 / RFC 7009）
+#[derive(Deserialize)]
+pub struct TokenParams {
+    pub token: String,
+    pub token_type_hint: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+/// 调用方必须是服务账号的客户端（网关 / 后端服务）
+async fn authenticated_params(
+    headers: &HeaderMap,
+    payload: Result<Form<TokenParams>, FormRejection>,
+    state: &AppState,
+) -> Result<TokenParams, OAuthError> {
+    let Form(payload) = payload.map_err(|e| OAuthError::InvalidRequest(e.body_text()))?;
+    let (client_id, client_secret) =
+        client_credentials_of(headers, &payload.client_id, &payload.client_secret)?;
+    authenticate_client(&client_id, &client_secret, state).await?;
+    Ok(payload)
+}
+
+/// token 是否仍然有效：吊销（黑名单 / 会话已删除）的 token 在 exp 之前也是 inactive
+pub async fn introspect_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    payload: Result<Form<TokenParams>, FormRejection>,
+) -> Result<impl IntoResponse, OAuthError> {
+    let params = authenticated_params(&headers, payload, &state).await?;
+    let introspection =
+        introspect_token(&params.token, params.token_type_hint.as_deref(), &state).await?;
+    Ok(([(header::CACHE_CONTROL, "no-store")], Json(introspection)))
+}
+
+/// 吊销签发给调用方的 access / refresh token；无效的 token 同样返回 200。
+/// 调用方可以是服务账号的客户端，也可以是授权码客户端（公开客户端只带 client_id）
+pub async fn revoke_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    payload: Result<Form<TokenParams>, FormRejection>,
+) -> Result<impl IntoResponse, OAuthError> {
+    let Form(params) = payload.map_err(|e| OAuthError::InvalidRequest(e.body_text()))?;
+    let (client_id, client_secret) =
+        client_auth(&headers, &params.client_id, &params.client_secret)?;
+    let client = match (
+        authenticate_app_client(&client_id, client_secret.as_deref(), &state).await,
+        client_secret.as_deref(),
+    ) {
+        (Ok(client), _) => client,
+        (Err(OAuthError::InvalidClient(_)), Some(secret)) => {
+            authenticate_client(&client_id, secret, &state).await?.0
+        }
+        (Err(e), _) => return Err(e),
+    };
+    revoke_token(
+        &params.token,
+        params.token_type_hint.as_deref(),
+        &client.client_id,
+        &state,
+    )
+    .await?;
+    Ok(StatusCode::OK)
+}
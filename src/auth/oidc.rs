@@ -0,0 +1,32 @@
+use crate::auth::extractor::AuthUser;
+use crate::error::{AppError, AppResult};
+use crate::models::user::UserClaims;
+use crate::repositories::user_repo::get_user_by_id;
+use crate::services::oidc_service::{discovery_document, oidc_enabled, user_claims};
+use crate::state::AppState;
+use axum::Json;
+use axum::extract::State;
+use serde_json::Value;
+
+/// 没有非对称签名密钥时不提供 OIDC（404）
+pub async fn openid_configuration_handler(State(state): State<AppState>) -> AppResult<Json<Value>> {
+    if !oidc_enabled(&state) {
+        return Err(AppError::NotFound("OpenID Connect is not enabled".into()));
+    }
+    Ok(Json(discovery_document(&state)))
+}
+
+/// OIDC userinfo：access token 对应用户的标准 claims，按 token 授予的 scope 过滤
+pub async fn userinfo_handler(
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> AppResult<Json<UserClaims>> {
+    if !oidc_enabled(&state) {
+        return Err(AppError::NotFound("OpenID Connect is not enabled".into()));
+    }
+    let claims = get_user_by_id(&state.db, user.id)
+        .await?
+        .map(|u| user_claims(&u, user.scopes.as_deref()))
+        .ok_or_else(|| AppError::NotFound("user not found".into()))?;
+    Ok(Json(claims))
+}
@@ -0,0 +1,241 @@
+use crate::{
+    auth::extractor::{AuthUser, TrustedProxies, client_ip},
+    error::AppError,
+    utils::redis_keys::rate_limit_key,
+};
+use axum::{
+    body::Body,
+    http::{HeaderMap, HeaderValue, Request},
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use deadpool_redis::{Pool as RedisPool, redis};
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+use tower::{Layer, Service};
+
+/// 按什么计数；取不到时退回按 ip
+#[derive(Debug, Clone, Copy)]
+pub enum RateLimitKey {
+    Ip,
+    /// `AuthUser` 的 id，需放在 `AuthLayer` 之内
+    User,
+    /// 个人访问令牌的 id，不是令牌认证时同 `User`；需放在 `AuthLayer` 之内，
+    /// 只认已认证的令牌，未认证请求头里的值不作数
+    ApiKey,
+}
+
+/// 每 `window_secs` 秒最多 `limit` 次（滑动窗口：上一窗口按剩余比例计入）
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitPolicy {
+    pub name: &'static str,
+    pub limit: u64,
+    pub window_secs: u64,
+    pub key: RateLimitKey,
+}
+
+/// 一次计数后的结果，用于 `RateLimit-*` 响应头
+struct Usage {
+    estimate: u64,
+    reset_secs: u64,
+}
+
+/// 限流：计数存在 redis（`ratelimit:{policy}:{subject}:{window}`），
+/// redis 不可用时退回进程内计数（多实例下各自计数）
+// 按路由使用：`post(handler).layer(RateLimitLayer::new(state.redis.clone(), POLICY))`
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    redis: RedisPool,
+    policy: RateLimitPolicy,
+    local: Arc<LocalWindows>,
+    trusted_proxies: Arc<TrustedProxies>,
+}
+
+impl RateLimitLayer {
+    pub fn new(redis: RedisPool, policy: RateLimitPolicy) -> Self {
+        Self {
+            redis,
+            policy,
+            local: Arc::new(LocalWindows::default()),
+            trusted_proxies: Arc::new(TrustedProxies::default()),
+        }
+    }
+
+    /// 部署在反向代理之后时按代理转发的客户端地址计数（见 `client_ip`）
+    pub fn with_trusted_proxies(mut self, trusted_proxies: Arc<TrustedProxies>) -> Self {
+        self.trusted_proxies = trusted_proxies;
+        self
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    layer: RateLimitLayer,
+}
+
+impl<S> Service<Request<Body>> for RateLimit<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let mut inner = self.inner.clone();
+        let layer = self.layer.clone();
+        Box::pin(async move {
+            let policy = layer.policy;
+            let subject = subject(&req, policy.key, &layer.trusted_proxies);
+            let usage = layer.hit(&subject).await;
+
+            let mut response = if usage.estimate > policy.limit {
+                AppError::TooManyRequests {
+                    retry_after: usage.reset_secs.max(1),
+                }
+                .into_response()
+            } else {
+                inner.call(req).await?
+            };
+            set_headers(response.headers_mut(), &policy, &usage);
+            Ok(response)
+        })
+    }
+}
+
+impl RateLimitLayer {
+    async fn hit(&self, subject: &str) -> Usage {
+        let policy = &self.policy;
+        let now_ms = Utc::now().timestamp_millis().max(0) as u64;
+        let window_ms = policy.window_secs * 1000;
+        let window = now_ms / window_ms;
+        let elapsed_ms = now_ms % window_ms;
+
+        let (current, previous) = match self.redis_hit(subject, window).await {
+            Ok(counts) => counts,
+            Err(e) => {
+                tracing::warn!("rate limit falling back to in-process counters: {}", e);
+                self.local.hit(policy.name, subject, window)
+            }
+        };
+        // weight of the previous window still inside the sliding window
+        let carried = previous * (window_ms - elapsed_ms) / window_ms;
+        Usage {
+            estimate: current + carried,
+            reset_secs: (window_ms - elapsed_ms).div_ceil(1000),
+        }
+    }
+
+    async fn redis_hit(&self, subject: &str, window: u64) -> Result<(u64, u64), AppError> {
+        let policy = &self.policy;
+        let current_key = rate_limit_key(policy.name, subject, window);
+        let previous_key = rate_limit_key(policy.name, subject, window.saturating_sub(1));
+        let mut conn = self.redis.get().await?;
+        let (current, previous): (u64, Option<u64>) = redis::pipe()
+            .incr(&current_key, 1)
+            .expire(&current_key, (policy.window_secs * 2) as usize)
+            .ignore()
+            .get(&previous_key)
+            .query_async(&mut conn)
+            .await?;
+        Ok((current, previous.unwrap_or(0)))
+    }
+}
+
+/// 没有客户端地址（如进程内调用）的请求共用 `unknown` 计数，不放行
+fn subject(req: &Request<Body>, key: RateLimitKey, trusted: &TrustedProxies) -> String {
+    let by_key = match key {
+        RateLimitKey::Ip => None,
+        RateLimitKey::User => req
+            .extensions()
+            .get::<AuthUser>()
+            .map(|u| format!("user:{}", u.id)),
+        RateLimitKey::ApiKey => req.extensions().get::<AuthUser>().map(|u| match u.pat_id {
+            Some(pat_id) => format!("pat:{}", pat_id),
+            None => format!("user:{}", u.id),
+        }),
+    };
+    by_key
+        .or_else(|| {
+            client_ip(req.headers(), req.extensions(), trusted).map(|ip| format!("ip:{}", ip))
+        })
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// RateLimit-Limit / RateLimit-Remaining / RateLimit-Reset (draft-ietf-httpapi-ratelimit-headers)
+fn set_headers(headers: &mut HeaderMap, policy: &RateLimitPolicy, usage: &Usage) {
+    let remaining = policy.limit.saturating_sub(usage.estimate);
+    for (name, value) in [
+        ("ratelimit-limit", policy.limit),
+        ("ratelimit-remaining", remaining),
+        ("ratelimit-reset", usage.reset_secs),
+    ] {
+        headers.insert(name, HeaderValue::from(value));
+    }
+}
+
+/// 进程内的窗口计数
+#[derive(Default)]
+struct LocalWindows {
+    counts: Mutex<HashMap<(&'static str, String), LocalWindow>>,
+}
+
+struct LocalWindow {
+    window: u64,
+    current: u64,
+    previous: u64,
+}
+
+/// 进程内最多保留的计数条目，超过时清理过期窗口
+const LOCAL_MAX_ENTRIES: usize = 10_000;
+
+impl LocalWindows {
+    fn hit(&self, policy: &'static str, subject: &str, window: u64) -> (u64, u64) {
+        let mut counts = self.counts.lock().unwrap();
+        if counts.len() > LOCAL_MAX_ENTRIES {
+            counts.retain(|_, w| w.window + 1 >= window);
+        }
+        let entry = counts
+            .entry((policy, subject.to_string()))
+            .or_insert(LocalWindow {
+                window,
+                current: 0,
+                previous: 0,
+            });
+        if entry.window != window {
+            let previous = if entry.window + 1 == window {
+                entry.current
+            } else {
+                0
+            };
+            *entry = LocalWindow {
+                window,
+                current: 0,
+                previous,
+            };
+        }
+        entry.current += 1;
+        (entry.current, entry.previous)
+    }
+}
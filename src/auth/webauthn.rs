@@ -0,0 +1,195 @@
+use crate::auth::extractor::{ClientInfo, SessionUser};
+use crate::auth::handlers::{MAX_PASSWORD_BYTES, login_response};
+use crate::error::AppResult;
+use crate::models::webauthn::{AssertionCredential, RegistrationCredential};
+use crate::services::auth_service::LoginOutcome;
+use crate::services::webauthn_service::{
+    delete_passkey, list_passkeys, login_mfa_passkey, login_options, login_with_passkey,
+    register_passkey, registration_options, rename_passkey, second_factor_options,
+};
+use crate::state::AppState;
+use crate::utils::validation::{FieldErrors, ValidJson, Validate, bounded_text};
+use axum::Json;
+use axum::extract::{Path, State};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+/// 重新验证身份：账号有密码时填密码，开启了 TOTP 时填当前验证码（或恢复码）
+#[derive(Deserialize)]
+pub struct RegistrationOptionsInput {
+    pub password: Option<String>,
+    pub code: Option<String>,
+}
+
+impl Validate for RegistrationOptionsInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        if self
+            .password
+            .as_ref()
+            .is_some_and(|p| p.is_empty() || p.len() > MAX_PASSWORD_BYTES)
+        {
+            errors.add("password", "must be 1 to 1024 bytes");
+        }
+        if let Some(code) = &self.code {
+            errors.check("code", bounded_text(code, 32));
+        }
+    }
+}
+
+/// 开始注册：重新验证身份后返回 navigator.credentials.create() 的 publicKey 选项
+pub async fn passkey_registration_options_handler(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+    ValidJson(payload): ValidJson<RegistrationOptionsInput>,
+) -> AppResult<Json<Value>> {
+    let options = registration_options(
+        &user,
+        payload.password.as_deref(),
+        payload.code.as_deref(),
+        &state,
+    )
+    .await?;
+    Ok(Json(options))
+}
+
+#[derive(Deserialize)]
+pub struct RegisterPasskeyInput {
+    pub name: String,
+    pub credential: RegistrationCredential,
+}
+
+impl Validate for RegisterPasskeyInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        self.name = self.name.trim().to_string();
+        errors.check("name", bounded_text(&self.name, 64));
+        let transports = &self.credential.response.transports;
+        if transports.len() > 8 || transports.iter().any(|t| bounded_text(t, 32).is_err()) {
+            errors.add("credential", "invalid transports");
+        }
+    }
+}
+
+pub async fn register_passkey_handler(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+    ValidJson(payload): ValidJson<RegisterPasskeyInput>,
+) -> AppResult<Json<Value>> {
+    let credential = register_passkey(user.id, &payload.name, &payload.credential, &state).await?;
+    Ok(Json(json!(credential)))
+}
+
+pub async fn list_passkeys_handler(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+) -> AppResult<Json<Value>> {
+    let passkeys = list_passkeys(user.id, &state).await?;
+    Ok(Json(json!({ "passkeys": passkeys })))
+}
+
+#[derive(Deserialize)]
+pub struct RenamePasskeyInput {
+    pub name: String,
+}
+
+impl Validate for RenamePasskeyInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        self.name = self.name.trim().to_string();
+        errors.check("name", bounded_text(&self.name, 64));
+    }
+}
+
+pub async fn rename_passkey_handler(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+    Path(id): Path<i64>,
+    ValidJson(payload): ValidJson<RenamePasskeyInput>,
+) -> AppResult<Json<Value>> {
+    let credential = rename_passkey(user.id, id, &payload.name, &state).await?;
+    Ok(Json(json!(credential)))
+}
+
+pub async fn delete_passkey_handler(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+    Path(id): Path<i64>,
+) -> AppResult<Json<Value>> {
+    delete_passkey(user.id, id, &state).await?;
+    Ok(Json(json!({"ok": true})))
+}
+
+/// 只用 passkey 登录：返回 navigator.credentials.get() 的 publicKey 选项
+pub async fn passkey_login_options_handler(
+    State(state): State<AppState>,
+) -> AppResult<Json<Value>> {
+    Ok(Json(login_options(&state).await?))
+}
+
+#[derive(Deserialize)]
+pub struct PasskeyLoginInput {
+    pub credential: AssertionCredential,
+    /// 设备名，显示在会话列表中
+    pub device: Option<String>,
+}
+
+impl Validate for PasskeyLoginInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        if let Some(device) = &self.device {
+            errors.check("device", bounded_text(device, 64));
+        }
+    }
+}
+
+/// 结果与 /api/login 相同（passkey 本身满足多因素，不会再要求第二步）
+pub async fn passkey_login_handler(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    ValidJson(payload): ValidJson<PasskeyLoginInput>,
+) -> AppResult<Json<Value>> {
+    let client = ClientInfo {
+        device: payload.device,
+        ..client
+    };
+    let r = login_with_passkey(&payload.credential, &client, &state).await?;
+    Ok(login_response(LoginOutcome::Complete(r)))
+}
+
+#[derive(Deserialize)]
+pub struct PasskeyOptionsInput {
+    pub mfa_token: String,
+}
+
+impl Validate for PasskeyOptionsInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        errors.check("mfa_token", bounded_text(&self.mfa_token, 128));
+    }
+}
+
+/// 登录第二步用 passkey：返回只允许这个用户凭证的选项
+pub async fn passkey_mfa_options_handler(
+    State(state): State<AppState>,
+    ValidJson(payload): ValidJson<PasskeyOptionsInput>,
+) -> AppResult<Json<Value>> {
+    Ok(Json(
+        second_factor_options(&payload.mfa_token, &state).await?,
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct PasskeyMfaInput {
+    pub mfa_token: String,
+    pub credential: AssertionCredential,
+}
+
+impl Validate for PasskeyMfaInput {
+    fn validate(&mut self, errors: &mut FieldErrors) {
+        errors.check("mfa_token", bounded_text(&self.mfa_token, 128));
+    }
+}
+
+pub async fn passkey_mfa_handler(
+    State(state): State<AppState>,
+    ValidJson(payload): ValidJson<PasskeyMfaInput>,
+) -> AppResult<Json<Value>> {
+    let r = login_mfa_passkey(&payload.mfa_token, &payload.credential, &state).await?;
+    Ok(login_response(LoginOutcome::Complete(r)))
+}
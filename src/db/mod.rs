@@ -10,13 +10,11 @@ type RedisPool = Pool;
 pub async fn init_db_pool() -> PgPool {
     dotenv().ok();
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let pool = PgPoolOptions::new()
+    PgPoolOptions::new()
         .max_connections(5)
         .connect(&database_url)
         .await
-        .expect("Failed to connect to database");
-
-    pool
+        .expect("Failed to connect to database")
 }
 
 pub fn init_redis_pool() -> RedisPool {
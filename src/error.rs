@@ -0,0 +1,238 @@
+use axum::{
+    Json,
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+
+use crate::utils::validation::FieldError;
+
+pub type AppResult<T> = Result<T, AppError>;
+
+/// 统一错误类型：handler / service / repository 都返回它，
+/// 转换成 RFC 7807 `application/problem+json` 响应
+#[derive(Debug, thiserror::Error)]
+pub enum AppError {
+    #[error("invalid credentials")]
+    InvalidCredentials,
+    #[error("user disabled")]
+    UserDisabled,
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    Validation(String),
+    /// 请求体字段校验失败，每个字段一条
+    #[error("{} invalid field(s)", .0.len())]
+    InvalidFields(Vec<FieldError>),
+    #[error("too many requests, retry in {retry_after}s")]
+    TooManyRequests { retry_after: u64 },
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+impl AppError {
+    pub fn unauthorized(detail: impl Into<String>) -> Self {
+        Self::Unauthorized(detail.into())
+    }
+
+    pub fn forbidden(detail: impl Into<String>) -> Self {
+        Self::Forbidden(detail.into())
+    }
+
+    /// 单个字段校验失败
+    pub fn invalid_field(field: &str, message: impl Into<String>) -> Self {
+        Self::InvalidFields(vec![FieldError {
+            field: field.to_string(),
+            message: message.into(),
+        }])
+    }
+
+    /// (status, problem type slug, title)
+    fn kind(&self) -> (StatusCode, &'static str, &'static str) {
+        match self {
+            Self::InvalidCredentials => (
+                StatusCode::UNAUTHORIZED,
+                "invalid-credentials",
+                "Invalid credentials",
+            ),
+            Self::UserDisabled => (StatusCode::FORBIDDEN, "user-disabled", "User disabled"),
+            Self::Conflict(_) => (StatusCode::CONFLICT, "conflict", "Conflict"),
+            Self::NotFound(_) => (StatusCode::NOT_FOUND, "not-found", "Not found"),
+            Self::Unauthorized(_) => (StatusCode::UNAUTHORIZED, "unauthorized", "Unauthorized"),
+            Self::Forbidden(_) => (StatusCode::FORBIDDEN, "forbidden", "Forbidden"),
+            Self::Validation(_) | Self::InvalidFields(_) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "validation",
+                "Validation failed",
+            ),
+            Self::TooManyRequests { .. } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "too-many-requests",
+                "Too many requests",
+            ),
+            Self::Internal(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal",
+                "Internal server error",
+            ),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let (status, slug, title) = self.kind();
+        // never leak internal error messages to clients
+        let detail = match &self {
+            Self::Internal(e) => {
+                tracing::error!("internal error: {:?}", e);
+                title.to_string()
+            }
+            other => other.to_string(),
+        };
+        let mut body = json!({
+            "type": format!("/problems/{}", slug),
+            "title": title,
+            "status": status.as_u16(),
+            "detail": detail,
+        });
+        if let Self::InvalidFields(errors) = &self {
+            body["errors"] = json!(errors);
+        }
+        let mut response = (
+            status,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            Json(body),
+        )
+            .into_response();
+        if let Self::TooManyRequests { retry_after } = self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, retry_after.into());
+        }
+        response
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound("record not found".into()),
+            // unique_violation: a concurrent insert won the race
+            sqlx::Error::Database(db) if db.code().as_deref() == Some("23505") => {
+                Self::Conflict(match db.constraint() {
+                    Some("users_username_key" | "idx_users_username_lower") => {
+                        "username already exists".into()
+                    }
+                    Some("idx_users_email_lower") => "email already in use".into(),
+                    Some("jwt_signing_keys_pkey") => "kid already in use".into(),
+                    _ => "resource already exists".into(),
+                })
+            }
+            e => Self::Internal(e.into()),
+        }
+    }
+}
+
+impl From<deadpool_redis::PoolError> for AppError {
+    fn from(e: deadpool_redis::PoolError) -> Self {
+        Self::Internal(e.into())
+    }
+}
+
+impl From<deadpool_redis::redis::RedisError> for AppError {
+    fn from(e: deadpool_redis::redis::RedisError) -> Self {
+        Self::Internal(e.into())
+    }
+}
+
+impl From<bcrypt::BcryptError> for AppError {
+    fn from(e: bcrypt::BcryptError) -> Self {
+        Self::Internal(e.into())
+    }
+}
+
+impl From<serde_json::Error> for AppError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Internal(e.into())
+    }
+}
+
+/// `/oauth/*` 端点的错误：按 RFC 6749 §5.2 返回 `{"error", "error_description"}`，
+/// 其余错误（内部错误、限流等）仍按 `AppError` 返回
+#[derive(Debug, thiserror::Error)]
+pub enum OAuthError {
+    #[error("{0}")]
+    InvalidRequest(String),
+    #[error("{0}")]
+    InvalidClient(String),
+    #[error("{0}")]
+    InvalidGrant(String),
+    #[error("grant type not supported")]
+    UnsupportedGrantType,
+    #[error("{0}")]
+    InvalidScope(String),
+    /// 客户端无权对这个 token 执行操作（RFC 7009 §2.1）
+    #[error("{0}")]
+    UnauthorizedClient(String),
+    #[error(transparent)]
+    App(AppError),
+}
+
+impl OAuthError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidRequest(_) => "invalid_request",
+            Self::InvalidClient(_) => "invalid_client",
+            Self::InvalidGrant(_) => "invalid_grant",
+            Self::UnsupportedGrantType => "unsupported_grant_type",
+            Self::InvalidScope(_) => "invalid_scope",
+            Self::UnauthorizedClient(_) => "unauthorized_client",
+            Self::App(_) => "server_error",
+        }
+    }
+}
+
+impl From<AppError> for OAuthError {
+    fn from(e: AppError) -> Self {
+        match e {
+            AppError::InvalidCredentials | AppError::Unauthorized(_) => {
+                Self::InvalidClient(e.to_string())
+            }
+            AppError::UserDisabled => Self::InvalidClient("client disabled".into()),
+            AppError::Validation(_) | AppError::InvalidFields(_) => {
+                Self::InvalidRequest(e.to_string())
+            }
+            e => Self::App(e),
+        }
+    }
+}
+
+impl IntoResponse for OAuthError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Self::App(e) => return e.into_response(),
+            Self::InvalidClient(_) => StatusCode::UNAUTHORIZED,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        let body = json!({
+            "error": self.code(),
+            "error_description": self.to_string(),
+        });
+        let mut response =
+            (status, [(header::CACHE_CONTROL, "no-store")], Json(body)).into_response();
+        if status == StatusCode::UNAUTHORIZED {
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                header::HeaderValue::from_static("Basic realm=\"oauth\""),
+            );
+        }
+        response
+    }
+}
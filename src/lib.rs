@@ -1,5 +1,6 @@
 pub mod auth;
 pub mod db;
+pub mod error;
 pub mod models;
 pub mod repositories;
 pub mod routes;
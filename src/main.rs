@@ -1,11 +1,16 @@
 use dotenvy::dotenv;
-use std::env;
+use std::net::SocketAddr;
 use tokio::net::TcpListener;
-use tracing_subscriber;
 use web_backend::{
+    auth::{extractor::TrustedProxies, keys::JwtKey},
     db::{init_db_pool, init_redis_pool},
     routes::create_router,
+    services::{
+        key_service, mailer::mailer_from_env, password_policy::PasswordPolicy,
+        upstream_oidc::UpstreamProvider, webauthn::RelyingParty,
+    },
     state::AppState,
+    utils::hash::PasswordHasher,
 };
 
 #[tokio::main]
@@ -17,9 +22,57 @@ async fn main() -> anyhow::Result<()> {
 
     let redis_pool = init_redis_pool();
 
-    let jwt_secret = env::var("JWT_SECRET")?.into_bytes();
+    let jwt_key = JwtKey::from_env()?;
 
-    let state = AppState::new(pg_pool, redis_pool, jwt_secret);
+    let mut state = AppState::new(pg_pool, redis_pool, jwt_key).with_mailer(mailer_from_env());
+    state.password_hasher = PasswordHasher::from_env()?;
+    state.password_policy = PasswordPolicy::from_env()?;
+    if let Ok(dir) = std::env::var("JWT_KEY_DIR") {
+        state.jwt_key_dir = dir.into();
+    }
+    if let Some(secs) = std::env::var("JWT_KEY_SYNC_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+    {
+        state.key_sync_secs = secs;
+    }
+    if let Ok(url) = std::env::var("PASSWORD_RESET_URL") {
+        state.password_reset_url = url;
+    }
+    if let Ok(url) = std::env::var("EMAIL_VERIFY_URL") {
+        state.email_verify_url = url;
+    }
+    if let Ok(url) = std::env::var("MAGIC_LINK_URL") {
+        state.magic_link_url = url;
+    }
+    if let Ok(issuer) = std::env::var("OIDC_ISSUER") {
+        // relying parties verify id tokens with the published public key
+        if state.jwt_keys.current().jwk().is_none() {
+            anyhow::bail!(
+                "OIDC_ISSUER requires an asymmetric JWT_ALGORITHM (RS256, ES256 or EdDSA)"
+            );
+        }
+        state.oidc_issuer = issuer.trim_end_matches('/').to_string();
+    }
+    state.upstream_providers = std::sync::Arc::new(UpstreamProvider::from_env()?);
+    state.webauthn = RelyingParty::from_env();
+    state.trusted_proxies = std::sync::Arc::new(TrustedProxies::from_env()?);
+    if let Some(n) = std::env::var("LOGIN_MAX_FAILURES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+    {
+        state.login_max_failures = n;
+    }
+    if let Some(secs) = std::env::var("LOGIN_LOCKOUT_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+    {
+        state.login_lockout_secs = secs;
+    }
+
+    // keys rotated by any instance are shared through the db
+    key_service::sync(&state).await?;
+    tokio::spawn(key_service::sync_forever(state.clone()));
 
     // Create router
     let app = create_router(state.clone());
@@ -29,7 +82,11 @@ async fn main() -> anyhow::Result<()> {
     println!("listening on {}", listener.local_addr()?);
 
     // Start server (Axum 0.7)
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+/// 存在 redis `oauth_code:{sha256(code)}` 中的授权码（JSON），只能兑换一次
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthorizationCode {
+    pub client_id: String,
+    pub user_id: i64,
+    /// 授权请求使用的 redirect_uri（未带时为客户端唯一注册的那个）
+    pub redirect_uri: String,
+    /// 授权请求带了 redirect_uri：兑换时必须带上完全一致的值（RFC 6749 §4.1.3）
+    #[serde(default)]
+    pub redirect_uri_requested: bool,
+    /// PKCE S256：BASE64URL(SHA256(code_verifier))
+    pub code_challenge: String,
+    pub scope: Option<String>,
+    /// OIDC：原样放进 ID token
+    #[serde(default)]
+    pub nonce: Option<String>,
+    /// 用户完成认证的时间（unix 秒）
+    #[serde(default)]
+    pub auth_time: i64,
+}
@@ -0,0 +1,14 @@
+use chrono::{DateTime, Utc};
+
+/// 轮换过的签名密钥；每个实例按这张表重建自己的 `KeyRing`
+#[derive(Debug, sqlx::FromRow)]
+pub struct SigningKeyRecord {
+    pub kid: String,
+    pub alg: String,
+    /// JWT_KEY_DIR 中的文件名前缀；NULL 表示启动时配置的密钥
+    pub file: Option<String>,
+    /// 从这个时间开始用于签发
+    pub active_at: DateTime<Utc>,
+    /// 到这个时间停止验证；当前签名密钥为 NULL
+    pub retire_at: Option<DateTime<Utc>>,
+}
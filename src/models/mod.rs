@@ -1 +1,8 @@
+pub mod authorization_code;
+pub mod jwt_key;
+pub mod oauth_client;
+pub mod personal_access_token;
+pub mod session;
 pub mod user;
+pub mod user_identity;
+pub mod webauthn;
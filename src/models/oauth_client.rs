@@ -0,0 +1,23 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// OAuth 客户端：
+// - 服务账号的客户端（user_id）用 client_credentials 以服务账号身份调用 api
+// - 应用客户端（redirect_uris）走 authorization code，公开客户端（spa / 移动端）没有 secret
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct OAuthClient {
+    #[serde(skip)]
+    pub id: i64,
+    pub client_id: String,
+    /// 数据库只存 sha256，明文只在创建时返回一次
+    #[serde(skip)]
+    pub client_secret_hash: Option<String>,
+    #[serde(skip)]
+    pub user_id: Option<i64>,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    /// 应用客户端可以请求的权限 scope；openid / profile / email 总是可以请求
+    pub scopes: Vec<String>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,16 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// 个人访问令牌（不含 token 本身，数据库只存 sha256）
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct PersonalAccessToken {
+    pub id: i64,
+    #[serde(skip)]
+    pub user_id: i64,
+    pub name: String,
+    /// 可用的权限，使用时还会与用户当前的权限取交集
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+
+/// 存在 redis `session:{access_jti}` 与 `refresh:{refresh_jti}` 中的会话信息（JSON）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub user_id: i64,
+    pub family: String,
+    pub access_jti: String,
+    pub refresh_jti: String,
+    pub created_at: i64,
+    pub last_seen: i64,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub device: Option<String>,
+    /// token 被限制到的权限（client credentials 请求了 scope、授权码授予的 scope），None 为账号的全部权限
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<String>>,
+    /// 签发给的 OAuth 客户端；只有它能刷新、吊销这个会话
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+}
+
+/// 会话签发给谁、限制到哪些权限；自己登录的会话两者都为 None
+#[derive(Debug, Clone, Default)]
+pub struct SessionGrant {
+    pub client_id: Option<String>,
+    pub scopes: Option<Vec<String>>,
+}
+
+#[derive(Serialize)]
+pub struct SessionResponse {
+    pub id: String,
+    pub current: bool,
+    pub created_at: i64,
+    pub last_seen: i64,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub device: Option<String>,
+}
+
+impl SessionResponse {
+    pub fn new(info: &SessionInfo, current_jti: &str) -> Self {
+        Self {
+            id: info.access_jti.clone(),
+            current: info.access_jti == current_jti,
+            created_at: info.created_at,
+            last_seen: info.last_seen,
+            ip: info.ip.clone(),
+            user_agent: info.user_agent.clone(),
+            device: info.device.clone(),
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, sqlx::FromRow)]
 pub struct User {
@@ -6,12 +6,34 @@ pub struct User {
     pub username: String,
     pub password_hash: String,
     pub disabled: bool,
+    /// base32；enrollment 未确认时 totp_enabled 仍为 false
+    pub totp_secret: Option<String>,
+    pub totp_enabled: bool,
+    pub email: Option<String>,
+    /// 新设置 / 修改的邮箱需要验证后才能用于登录和找回密码
+    pub email_verified: bool,
+    /// 服务账号只能通过 client credentials 获取 token
+    pub service_account: bool,
+}
+
+impl User {
+    /// 已验证的邮箱
+    pub fn verified_email(&self) -> Option<&str> {
+        self.email.as_deref().filter(|_| self.email_verified)
+    }
+
+    /// 服务账号和通过上游身份创建的用户没有可用的密码（password_hash 为 '!'）
+    pub fn has_password(&self) -> bool {
+        self.password_hash != "!"
+    }
 }
 
 #[derive(Serialize)]
 pub struct UserResponse {
     pub id: i64,
     pub username: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
 }
 
 impl From<&User> for UserResponse {
@@ -19,6 +41,29 @@ impl From<&User> for UserResponse {
         Self {
             id: u.id,
             username: u.username.clone(),
+            email: u.verified_email().map(str::to_string),
+        }
+    }
+}
+
+/// OIDC 标准 claims（ID token 与 /userinfo），由 `UserResponse` 而来：邮箱只有验证过才出现
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserClaims {
+    pub sub: String,
+    pub preferred_username: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+}
+
+impl From<UserResponse> for UserClaims {
+    fn from(u: UserResponse) -> Self {
+        Self {
+            sub: u.id.to_string(),
+            preferred_username: u.username,
+            email_verified: u.email.as_ref().map(|_| true),
+            email: u.email,
         }
     }
 }
@@ -0,0 +1,29 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// 关联到本地用户的上游 OIDC 身份（provider + sub 唯一）
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct UserIdentity {
+    #[serde(skip)]
+    pub id: i64,
+    #[serde(skip)]
+    pub user_id: i64,
+    pub provider: String,
+    pub subject: String,
+    /// 最近一次登录时上游提供的邮箱（仅展示用）
+    pub email: Option<String>,
+    pub last_login_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// 存在 redis `federated_login:{sha256(state)}` 中的上游登录请求，回调时只能使用一次
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FederatedLogin {
+    pub provider: String,
+    /// 原样出现在上游 ID token 中
+    pub nonce: String,
+    /// PKCE
+    pub code_verifier: String,
+    /// 已登录用户发起的关联请求；None 表示登录
+    pub link_user_id: Option<i64>,
+}
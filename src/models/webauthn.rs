@@ -0,0 +1,73 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// 已注册的 passkey / 安全密钥
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct WebauthnCredential {
+    pub id: i64,
+    #[serde(skip)]
+    pub user_id: i64,
+    /// base64url，与浏览器中的 credential id 一致
+    pub credential_id: String,
+    /// COSE key
+    #[serde(skip)]
+    pub public_key: Vec<u8>,
+    #[serde(skip)]
+    pub sign_count: i64,
+    pub name: String,
+    /// 认证器支持的传输方式（usb / nfc / ble / internal / hybrid），登录时原样交给浏览器
+    pub transports: Vec<String>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// 存在 redis `webauthn_challenge:{sha256(challenge)}` 中的 ceremony，只能使用一次
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "purpose", rename_all = "snake_case")]
+pub enum WebauthnChallenge {
+    /// 已登录用户注册新凭证
+    Registration { user_id: i64 },
+    /// 只用 passkey 登录（discoverable credential，不知道是哪个用户）
+    Login,
+    /// 密码之后的第二因素，绑定到 mfa pending token
+    SecondFactor {
+        user_id: i64,
+        mfa_token_hash: String,
+    },
+}
+
+/// `navigator.credentials.create()` 的结果（PublicKeyCredential JSON，二进制字段为 base64url）
+#[derive(Debug, Deserialize)]
+pub struct RegistrationCredential {
+    pub id: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    pub attestation_object: String,
+    #[serde(default)]
+    pub transports: Vec<String>,
+}
+
+/// `navigator.credentials.get()` 的结果
+#[derive(Debug, Deserialize)]
+pub struct AssertionCredential {
+    pub id: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "authenticatorData")]
+    pub authenticator_data: String,
+    pub signature: String,
+    /// discoverable credential 返回注册时的 user.id
+    #[serde(rename = "userHandle")]
+    pub user_handle: Option<String>,
+}
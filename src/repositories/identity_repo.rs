@@ -0,0 +1,84 @@
+use crate::{error::AppResult, models::user_identity::UserIdentity};
+use sqlx::PgPool;
+
+/// (provider, subject) 已关联到其他用户、或该用户已关联了这个 provider 时，
+/// unique 冲突由 `AppError::from(sqlx::Error)` 转成 Conflict
+pub async fn create_identity(
+    pool: &PgPool,
+    user_id: i64,
+    provider: &str,
+    subject: &str,
+    email: Option<&str>,
+) -> AppResult<UserIdentity> {
+    Ok(sqlx::query_as!(
+        UserIdentity,
+        r#"
+        INSERT INTO user_identities (user_id, provider, subject, email, last_login_at)
+        VALUES ($1, $2, $3, $4, now())
+        RETURNING id, user_id, provider, subject, email, last_login_at, created_at
+        "#,
+        user_id,
+        provider,
+        subject,
+        email
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+pub async fn find_identity(
+    pool: &PgPool,
+    provider: &str,
+    subject: &str,
+) -> AppResult<Option<UserIdentity>> {
+    Ok(sqlx::query_as!(
+        UserIdentity,
+        r#"
+        SELECT id, user_id, provider, subject, email, last_login_at, created_at
+        FROM user_identities
+        WHERE provider = $1 AND subject = $2
+        "#,
+        provider,
+        subject
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub async fn list_identities(pool: &PgPool, user_id: i64) -> AppResult<Vec<UserIdentity>> {
+    Ok(sqlx::query_as!(
+        UserIdentity,
+        r#"
+        SELECT id, user_id, provider, subject, email, last_login_at, created_at
+        FROM user_identities
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// 登录成功：记录时间和上游当前的邮箱
+pub async fn touch_identity(pool: &PgPool, id: i64, email: Option<&str>) -> AppResult<()> {
+    sqlx::query!(
+        r#"UPDATE user_identities SET last_login_at = now(), email = $2 WHERE id = $1"#,
+        id,
+        email
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_identity(pool: &PgPool, user_id: i64, provider: &str) -> AppResult<bool> {
+    let result = sqlx::query!(
+        r#"DELETE FROM user_identities WHERE user_id = $1 AND provider = $2"#,
+        user_id,
+        provider
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
@@ -0,0 +1,84 @@
+use crate::{error::AppResult, models::jwt_key::SigningKeyRecord};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+/// 所有记录（包括已停用的），按启用时间排序
+pub async fn list_keys(pool: &PgPool) -> AppResult<Vec<SigningKeyRecord>> {
+    Ok(sqlx::query_as!(
+        SigningKeyRecord,
+        r#"
+        SELECT kid, alg, file, active_at, retire_at
+        FROM jwt_signing_keys
+        ORDER BY active_at
+        "#
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// 记录新的签名密钥：从 active_at 开始签发，之前所有未停用的密钥保留验证到 retire_at。
+/// 启动时配置的密钥第一次被替换时也记下来，这样其他实例知道它什么时候停用
+pub async fn promote_key(
+    pool: &PgPool,
+    configured_kid: &str,
+    configured_alg: &str,
+    new: &SigningKeyRecord,
+    retire_at: DateTime<Utc>,
+) -> AppResult<()> {
+    let mut tx = pool.begin().await?;
+    // concurrent rotations must not both leave a key without retire_at
+    sqlx::query!("LOCK TABLE jwt_signing_keys IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO jwt_signing_keys (kid, alg, file, active_at)
+        VALUES ($1, $2, NULL, 'epoch')
+        ON CONFLICT (kid) DO NOTHING
+        "#,
+        configured_kid,
+        configured_alg
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "UPDATE jwt_signing_keys SET retire_at = $1 WHERE retire_at IS NULL",
+        retire_at
+    )
+    .execute(&mut *tx)
+    .await?;
+    // kid 重复时 unique 冲突由 `AppError::from(sqlx::Error)` 转成 Conflict
+    sqlx::query!(
+        r#"
+        INSERT INTO jwt_signing_keys (kid, alg, file, active_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        new.kid,
+        new.alg,
+        new.file,
+        new.active_at
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// 立即停用一个验证密钥；还没开始签发的、以及没有更新的密钥接替签发的（当前签名密钥）不能停用
+pub async fn retire_key(pool: &PgPool, kid: &str) -> AppResult<bool> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE jwt_signing_keys k SET retire_at = now()
+        WHERE k.kid = $1 AND k.active_at <= now() AND k.retire_at > now()
+          AND EXISTS (
+            SELECT 1 FROM jwt_signing_keys newer
+            WHERE newer.active_at > k.active_at AND newer.active_at <= now()
+              AND (newer.retire_at IS NULL OR newer.retire_at > now())
+          )
+        "#,
+        kid
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
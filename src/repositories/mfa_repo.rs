@@ -0,0 +1,90 @@
+use crate::error::AppResult;
+use sqlx::PgPool;
+
+/// 保存待确认的 TOTP secret（重新 enroll 会覆盖，且关闭已启用的 2FA）
+pub async fn set_totp_secret(pool: &PgPool, user_id: i64, secret: &str) -> AppResult<()> {
+    sqlx::query!(
+        r#"UPDATE users SET totp_secret = $2, totp_enabled = FALSE WHERE id = $1"#,
+        user_id,
+        secret
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn set_totp_enabled(pool: &PgPool, user_id: i64, enabled: bool) -> AppResult<()> {
+    sqlx::query!(
+        r#"UPDATE users SET totp_enabled = $2 WHERE id = $1"#,
+        user_id,
+        enabled
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn clear_totp(pool: &PgPool, user_id: i64) -> AppResult<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"UPDATE users SET totp_secret = NULL, totp_enabled = FALSE WHERE id = $1"#,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"DELETE FROM user_recovery_codes WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// 替换全部恢复码
+pub async fn replace_recovery_codes(
+    pool: &PgPool,
+    user_id: i64,
+    code_hashes: &[String],
+) -> AppResult<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"DELETE FROM user_recovery_codes WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO user_recovery_codes (user_id, code_hash)
+        SELECT $1, UNNEST($2::TEXT[])
+        "#,
+        user_id,
+        code_hashes
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// 使用一个恢复码，成功返回 true（每个码只能用一次）
+pub async fn consume_recovery_code(
+    pool: &PgPool,
+    user_id: i64,
+    code_hash: &str,
+) -> AppResult<bool> {
+    let id = sqlx::query_scalar!(
+        r#"
+        UPDATE user_recovery_codes SET used_at = now()
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        RETURNING id
+        "#,
+        user_id,
+        code_hash
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(id.is_some())
+}
@@ -1,3 +1,9 @@
+pub mod identity_repo;
+pub mod jwt_key_repo;
+pub mod mfa_repo;
+pub mod oauth_client_repo;
+pub mod pat_repo;
 pub mod permission_repo;
 pub mod role_repo;
 pub mod user_repo;
+pub mod webauthn_repo;
@@ -0,0 +1,106 @@
+use crate::{error::AppResult, models::oauth_client::OAuthClient};
+use sqlx::PgPool;
+
+pub async fn create_client(
+    pool: &PgPool,
+    user_id: i64,
+    name: &str,
+    client_id: &str,
+    client_secret_hash: &str,
+) -> AppResult<OAuthClient> {
+    Ok(sqlx::query_as!(
+        OAuthClient,
+        r#"
+        INSERT INTO oauth_clients (user_id, name, client_id, client_secret_hash)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, client_id, client_secret_hash, user_id, name, redirect_uris, scopes, last_used_at, created_at
+        "#,
+        user_id,
+        name,
+        client_id,
+        client_secret_hash
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+/// 应用客户端（authorization code），公开客户端没有 secret
+pub async fn create_app_client(
+    pool: &PgPool,
+    name: &str,
+    client_id: &str,
+    client_secret_hash: Option<&str>,
+    redirect_uris: &[String],
+    scopes: &[String],
+) -> AppResult<OAuthClient> {
+    Ok(sqlx::query_as!(
+        OAuthClient,
+        r#"
+        INSERT INTO oauth_clients (name, client_id, client_secret_hash, redirect_uris, scopes)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, client_id, client_secret_hash, user_id, name, redirect_uris, scopes, last_used_at, created_at
+        "#,
+        name,
+        client_id,
+        client_secret_hash,
+        redirect_uris,
+        scopes
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+pub async fn list_clients(pool: &PgPool, user_id: i64) -> AppResult<Vec<OAuthClient>> {
+    Ok(sqlx::query_as!(
+        OAuthClient,
+        r#"
+        SELECT id, client_id, client_secret_hash, user_id, name, redirect_uris, scopes, last_used_at, created_at
+        FROM oauth_clients
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+pub async fn find_client(pool: &PgPool, client_id: &str) -> AppResult<Option<OAuthClient>> {
+    Ok(sqlx::query_as!(
+        OAuthClient,
+        r#"
+        SELECT id, client_id, client_secret_hash, user_id, name, redirect_uris, scopes, last_used_at, created_at
+        FROM oauth_clients
+        WHERE client_id = $1
+        "#,
+        client_id
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+/// 只能删除该服务账号的客户端，返回是否删除
+pub async fn delete_client(pool: &PgPool, user_id: i64, client_id: &str) -> AppResult<bool> {
+    let result = sqlx::query!(
+        r#"DELETE FROM oauth_clients WHERE client_id = $1 AND user_id = $2"#,
+        client_id,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// 记录最近使用时间（最多每分钟写一次）
+pub async fn touch_client(pool: &PgPool, id: i64) -> AppResult<()> {
+    sqlx::query!(
+        r#"
+        UPDATE oauth_clients SET last_used_at = now()
+        WHERE id = $1 AND (last_used_at IS NULL OR last_used_at < now() - interval '1 minute')
+        "#,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
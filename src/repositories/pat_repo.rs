@@ -0,0 +1,87 @@
+use crate::{error::AppResult, models::personal_access_token::PersonalAccessToken};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+pub async fn create_token(
+    pool: &PgPool,
+    user_id: i64,
+    name: &str,
+    token_hash: &str,
+    scopes: &[String],
+    expires_at: Option<DateTime<Utc>>,
+) -> AppResult<PersonalAccessToken> {
+    Ok(sqlx::query_as!(
+        PersonalAccessToken,
+        r#"
+        INSERT INTO personal_access_tokens (user_id, name, token_hash, scopes, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, user_id, name, scopes, expires_at, last_used_at, created_at
+        "#,
+        user_id,
+        name,
+        token_hash,
+        scopes,
+        expires_at
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+pub async fn list_tokens(pool: &PgPool, user_id: i64) -> AppResult<Vec<PersonalAccessToken>> {
+    Ok(sqlx::query_as!(
+        PersonalAccessToken,
+        r#"
+        SELECT id, user_id, name, scopes, expires_at, last_used_at, created_at
+        FROM personal_access_tokens
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// 未过期的 token
+pub async fn find_active_token(
+    pool: &PgPool,
+    token_hash: &str,
+) -> AppResult<Option<PersonalAccessToken>> {
+    Ok(sqlx::query_as!(
+        PersonalAccessToken,
+        r#"
+        SELECT id, user_id, name, scopes, expires_at, last_used_at, created_at
+        FROM personal_access_tokens
+        WHERE token_hash = $1 AND (expires_at IS NULL OR expires_at > now())
+        "#,
+        token_hash
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+/// 只能删除自己的 token，返回是否删除
+pub async fn delete_token(pool: &PgPool, user_id: i64, id: i64) -> AppResult<bool> {
+    let result = sqlx::query!(
+        r#"DELETE FROM personal_access_tokens WHERE id = $1 AND user_id = $2"#,
+        id,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// 记录最近使用时间（最多每分钟写一次）
+pub async fn touch_token(pool: &PgPool, id: i64) -> AppResult<()> {
+    sqlx::query!(
+        r#"
+        UPDATE personal_access_tokens SET last_used_at = now()
+        WHERE id = $1 AND (last_used_at IS NULL OR last_used_at < now() - interval '1 minute')
+        "#,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
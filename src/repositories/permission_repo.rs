@@ -1,6 +1,7 @@
+use crate::error::AppResult;
 use sqlx::PgPool;
-pub async fn get_permissions_for_user(pool: &PgPool, user_id: i64) -> sqlx::Result<Vec<String>> {
-    sqlx::query_scalar!(
+pub async fn get_permissions_for_user(pool: &PgPool, user_id: i64) -> AppResult<Vec<String>> {
+    Ok(sqlx::query_scalar!(
         r#"
         SELECT DISTINCT p.code
         FROM permissions p
@@ -11,5 +12,20 @@ pub async fn get_permissions_for_user(pool: &PgPool, user_id: i64) -> sqlx::Resu
         user_id
     )
     .fetch_all(pool)
-    .await
+    .await?)
+}
+
+/// 这些角色合起来拥有的权限
+pub async fn get_permissions_for_roles(pool: &PgPool, role_ids: &[i64]) -> AppResult<Vec<String>> {
+    Ok(sqlx::query_scalar!(
+        r#"
+        SELECT DISTINCT p.code
+        FROM permissions p
+        JOIN role_permissions rp ON rp.permission_id = p.id
+        WHERE rp.role_id = ANY($1)
+        "#,
+        role_ids
+    )
+    .fetch_all(pool)
+    .await?)
 }
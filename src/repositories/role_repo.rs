@@ -1,9 +1,22 @@
+use crate::error::AppResult;
 use sqlx::PgPool;
-pub async fn get_roles_for_user(pool: &PgPool, user_id: i64) -> sqlx::Result<Vec<String>> {
-    sqlx::query_scalar!(
+pub async fn get_roles_for_user(pool: &PgPool, user_id: i64) -> AppResult<Vec<String>> {
+    Ok(    sqlx::query_scalar!(
         r#"SELECT r.name FROM roles r JOIN user_roles ur ON ur.role_id = r.id WHERE ur.user_id = $1"#,
         user_id
     )
     .fetch_all(pool)
-    .await
+    .await?)
+}
+
+/// 按名称查找角色，返回 (id, name)；不存在的名称不在结果中
+pub async fn find_roles_by_name(pool: &PgPool, names: &[String]) -> AppResult<Vec<(i64, String)>> {
+    Ok(
+        sqlx::query!(r#"SELECT id, name FROM roles WHERE name = ANY($1)"#, names)
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .map(|r| (r.id, r.name))
+            .collect(),
+    )
 }
@@ -1,49 +1,211 @@
-use crate::models::user::User;
+use crate::{
+    error::AppResult,
+    models::{oauth_client::OAuthClient, user::User},
+};
 use sqlx::PgPool;
 
-pub async fn get_user_by_username(pool: &PgPool, username: &str) -> sqlx::Result<Option<User>> {
-    sqlx::query_as!(
+pub async fn get_user_by_username(pool: &PgPool, username: &str) -> AppResult<Option<User>> {
+    Ok(sqlx::query_as!(
         User,
-        r#"SELECT id, username, password_hash, disabled FROM users WHERE username = $1"#,
+        r#"SELECT id, username, password_hash, disabled, totp_secret, totp_enabled, email, email_verified, service_account FROM users WHERE lower(username) = lower($1)"#,
         username
     )
     .fetch_optional(pool)
-    .await
+    .await?)
 }
 
-pub async fn get_user_by_id(pool: &PgPool, id: i64) -> sqlx::Result<Option<User>> {
-    sqlx::query_as!(
+/// 邮箱已验证的用户，不区分大小写（未验证的邮箱可能有多个用户填写）
+pub async fn get_user_by_email(pool: &PgPool, email: &str) -> AppResult<Option<User>> {
+    Ok(sqlx::query_as!(
         User,
-        r#"SELECT id, username, password_hash, disabled FROM users WHERE id = $1"#,
+        r#"SELECT id, username, password_hash, disabled, totp_secret, totp_enabled, email, email_verified, service_account FROM users WHERE lower(email) = lower($1) AND email_verified"#,
+        email
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub async fn get_user_by_id(pool: &PgPool, id: i64) -> AppResult<Option<User>> {
+    Ok(sqlx::query_as!(
+        User,
+        r#"SELECT id, username, password_hash, disabled, totp_secret, totp_enabled, email, email_verified, service_account FROM users WHERE id = $1"#,
         id
     )
     .fetch_optional(pool)
-    .await
+    .await?)
 }
 
-pub async fn exist_by_username(pool: &PgPool, username: &str) -> sqlx::Result<Option<bool>> {
-    sqlx::query_scalar!(
-        r#"SELECT EXISTS(SELECT 1 FROM users WHERE username = $1)"#,
+pub async fn exist_by_username(pool: &PgPool, username: &str) -> AppResult<Option<bool>> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM users WHERE lower(username) = lower($1))"#,
         username
     )
     .fetch_one(pool)
-    .await
+    .await?)
 }
 
+/// unique 冲突（用户名 / 邮箱）由 `AppError::from(sqlx::Error)` 转成 Conflict
 pub async fn register_by_username_password_hash(
     pool: &PgPool,
     username: &str,
     password_hash: &str,
-) -> sqlx::Result<Option<i64>> {
-    sqlx::query_scalar!(
+    email: Option<&str>,
+) -> AppResult<Option<i64>> {
+    Ok(sqlx::query_scalar!(
         r#"
-        INSERT INTO users (username, password_hash)
-        VALUES ($1, $2)
+        INSERT INTO users (username, password_hash, email)
+        VALUES ($1, $2, $3)
         RETURNING id
         "#,
         username,
-        password_hash
+        password_hash,
+        email
     )
     .fetch_optional(pool)
-    .await
+    .await?)
+}
+
+pub async fn update_password_hash(pool: &PgPool, id: i64, password_hash: &str) -> AppResult<()> {
+    sqlx::query!(
+        r#"UPDATE users SET password_hash = $2 WHERE id = $1"#,
+        id,
+        password_hash
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// 是否已有用户验证了这个邮箱
+pub async fn exist_by_email(pool: &PgPool, email: &str) -> AppResult<Option<bool>> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM users WHERE lower(email) = lower($1) AND email_verified)"#,
+        email
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+/// 设置新邮箱，验证状态重置
+pub async fn set_email(pool: &PgPool, id: i64, email: &str) -> AppResult<()> {
+    sqlx::query!(
+        r#"UPDATE users SET email = $2, email_verified = FALSE WHERE id = $1"#,
+        id,
+        email
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// 只有邮箱仍是发出验证邮件时的那个才标记为已验证；
+/// 期间被其他用户验证过的邮箱违反 `idx_users_email_lower`（409）
+pub async fn mark_email_verified(pool: &PgPool, id: i64, email: &str) -> AppResult<bool> {
+    let result = sqlx::query!(
+        r#"UPDATE users SET email_verified = TRUE WHERE id = $1 AND lower(email) = lower($2)"#,
+        id,
+        email
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// 服务账号：不能用密码登录（password_hash 不是合法的哈希）
+/// 服务账号、角色和第一个客户端凭证在同一个事务里创建，任一步失败都不留下半成品
+pub async fn create_service_account(
+    pool: &PgPool,
+    username: &str,
+    role_ids: &[i64],
+    client_id: &str,
+    client_secret_hash: &str,
+) -> AppResult<(i64, OAuthClient)> {
+    let mut tx = pool.begin().await?;
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO users (username, password_hash, service_account)
+        VALUES ($1, '!', TRUE)
+        RETURNING id
+        "#,
+        username
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO user_roles (user_id, role_id)
+        SELECT $1, UNNEST($2::BIGINT[])
+        ON CONFLICT DO NOTHING
+        "#,
+        id,
+        role_ids
+    )
+    .execute(&mut *tx)
+    .await?;
+    let client = sqlx::query_as!(
+        OAuthClient,
+        r#"
+        INSERT INTO oauth_clients (user_id, name, client_id, client_secret_hash)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, client_id, client_secret_hash, user_id, name, redirect_uris, scopes, last_used_at, created_at
+        "#,
+        id,
+        username,
+        client_id,
+        client_secret_hash
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok((id, client))
+}
+
+/// 通过上游身份自动创建的用户：没有密码，上游已验证的邮箱直接标记为已验证。
+/// 用户、默认角色和身份在同一个事务里写入，中途失败不会留下没有身份的用户
+pub async fn create_federated_user(
+    pool: &PgPool,
+    username: &str,
+    email: Option<&str>,
+    role_ids: &[i64],
+    provider: &str,
+    subject: &str,
+    upstream_email: Option<&str>,
+) -> AppResult<i64> {
+    let mut tx = pool.begin().await?;
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO users (username, password_hash, email, email_verified)
+        VALUES ($1, '!', $2::TEXT, $2::TEXT IS NOT NULL)
+        RETURNING id
+        "#,
+        username,
+        email
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO user_roles (user_id, role_id)
+        SELECT $1, UNNEST($2::BIGINT[])
+        ON CONFLICT DO NOTHING
+        "#,
+        id,
+        role_ids
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO user_identities (user_id, provider, subject, email, last_login_at)
+        VALUES ($1, $2, $3, $4, now())
+        "#,
+        id,
+        provider,
+        subject,
+        upstream_email
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(id)
 }
@@ -0,0 +1,118 @@
+use crate::{error::AppResult, models::webauthn::WebauthnCredential};
+use sqlx::PgPool;
+
+/// credential_id 已被注册时 unique 冲突由 `AppError::from(sqlx::Error)` 转成 Conflict
+pub async fn create_credential(
+    pool: &PgPool,
+    user_id: i64,
+    credential_id: &str,
+    public_key: &[u8],
+    sign_count: i64,
+    name: &str,
+    transports: &[String],
+) -> AppResult<WebauthnCredential> {
+    Ok(sqlx::query_as!(
+        WebauthnCredential,
+        r#"
+        INSERT INTO webauthn_credentials (user_id, credential_id, public_key, sign_count, name, transports)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, user_id, credential_id, public_key, sign_count, name, transports, last_used_at, created_at
+        "#,
+        user_id,
+        credential_id,
+        public_key,
+        sign_count,
+        name,
+        transports
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+pub async fn list_credentials(pool: &PgPool, user_id: i64) -> AppResult<Vec<WebauthnCredential>> {
+    Ok(sqlx::query_as!(
+        WebauthnCredential,
+        r#"
+        SELECT id, user_id, credential_id, public_key, sign_count, name, transports, last_used_at, created_at
+        FROM webauthn_credentials
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+pub async fn find_credential(
+    pool: &PgPool,
+    credential_id: &str,
+) -> AppResult<Option<WebauthnCredential>> {
+    Ok(sqlx::query_as!(
+        WebauthnCredential,
+        r#"
+        SELECT id, user_id, credential_id, public_key, sign_count, name, transports, last_used_at, created_at
+        FROM webauthn_credentials
+        WHERE credential_id = $1
+        "#,
+        credential_id
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub async fn count_credentials(pool: &PgPool, user_id: i64) -> AppResult<i64> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM webauthn_credentials WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+/// 登录成功：保存新的签名计数；计数不能回退（并发登录时只有一个能成功）
+pub async fn update_sign_count(pool: &PgPool, id: i64, sign_count: i64) -> AppResult<bool> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE webauthn_credentials SET sign_count = $2, last_used_at = now()
+        WHERE id = $1 AND (sign_count < $2 OR $2 = 0)
+        "#,
+        id,
+        sign_count
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn rename_credential(
+    pool: &PgPool,
+    user_id: i64,
+    id: i64,
+    name: &str,
+) -> AppResult<Option<WebauthnCredential>> {
+    Ok(sqlx::query_as!(
+        WebauthnCredential,
+        r#"
+        UPDATE webauthn_credentials SET name = $3
+        WHERE id = $1 AND user_id = $2
+        RETURNING id, user_id, credential_id, public_key, sign_count, name, transports, last_used_at, created_at
+        "#,
+        id,
+        user_id,
+        name
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub async fn delete_credential(pool: &PgPool, user_id: i64, id: i64) -> AppResult<bool> {
+    let result = sqlx::query!(
+        r#"DELETE FROM webauthn_credentials WHERE id = $1 AND user_id = $2"#,
+        id,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
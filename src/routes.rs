@@ -1,21 +1,213 @@
 use crate::auth::{
-    handlers::{login_handler, refresh_handler, register_handler},
-    middleware::AuthLayer,
+    admin::{
+        create_app_client_handler, create_client_handler, create_service_account_handler,
+        delete_client_handler, list_clients_handler, list_keys_handler, retire_key_handler,
+        rotate_key_handler, unlock_user_handler,
+    },
+    authorize::{authorize_handler, authorize_page_handler},
+    federation::{
+        federated_callback_handler, federated_login_handler, link_identity_handler,
+        list_identities_handler, unlink_identity_handler,
+    },
+    handlers::{
+        change_email_handler, change_password_handler, create_pat_handler, forgot_password_handler,
+        get_session_handler, jwks_handler, list_pats_handler, list_sessions_handler, login_handler,
+        login_mfa_handler, logout_all_handler, logout_handler, magic_link_handler,
+        magic_link_verify_handler, me_handler, refresh_handler, register_handler,
+        reset_password_handler, revoke_other_sessions_handler, revoke_pat_handler,
+        revoke_session_handler, totp_confirm_handler, totp_disable_handler, totp_enroll_handler,
+        verify_email_handler,
+    },
+    middleware::{AuthLayer, require_permission},
+    oauth::{introspect_handler, revoke_handler, token_handler},
+    oidc::{openid_configuration_handler, userinfo_handler},
+    rate_limit::{RateLimitKey, RateLimitLayer, RateLimitPolicy},
+    webauthn::{
+        delete_passkey_handler, list_passkeys_handler, passkey_login_handler,
+        passkey_login_options_handler, passkey_mfa_handler, passkey_mfa_options_handler,
+        passkey_registration_options_handler, register_passkey_handler, rename_passkey_handler,
+    },
 };
 use crate::state::AppState;
-use axum::{Router, routing::post};
+use axum::{
+    Router,
+    handler::Handler,
+    routing::{delete, get, patch, post},
+};
+
+/// 注册：每个 ip 每小时 10 次
+pub const REGISTER_LIMIT: RateLimitPolicy = RateLimitPolicy {
+    name: "register",
+    limit: 10,
+    window_secs: 60 * 60,
+    key: RateLimitKey::Ip,
+};
+
+/// 找回密码会发邮件：每个 ip 每小时 5 次
+pub const PASSWORD_FORGOT_LIMIT: RateLimitPolicy = RateLimitPolicy {
+    name: "password_forgot",
+    limit: 5,
+    window_secs: 60 * 60,
+    key: RateLimitKey::Ip,
+};
+
+/// 登录链接会发邮件：每个 ip 每小时 5 次
+pub const MAGIC_LINK_LIMIT: RateLimitPolicy = RateLimitPolicy {
+    name: "magic_link",
+    limit: 5,
+    window_secs: 60 * 60,
+    key: RateLimitKey::Ip,
+};
+
+/// 创建个人访问令牌：每个用户每小时 10 次
+pub const PAT_CREATE_LIMIT: RateLimitPolicy = RateLimitPolicy {
+    name: "pat_create",
+    limit: 10,
+    window_secs: 60 * 60,
+    key: RateLimitKey::User,
+};
+
+/// 需登录的 API：每个个人访问令牌（会话按用户）每分钟 300 次
+pub const API_LIMIT: RateLimitPolicy = RateLimitPolicy {
+    name: "api",
+    limit: 300,
+    window_secs: 60,
+    key: RateLimitKey::ApiKey,
+};
 
 pub fn create_router(state: AppState) -> Router {
+    let limit = |policy| {
+        RateLimitLayer::new(state.redis.clone(), policy)
+            .with_trusted_proxies(state.trusted_proxies.clone())
+    };
+
     let public_router = Router::new()
-        .route("/api/register", post(register_handler))
-        .route("/api/login", post(login_handler));
+        .route(
+            "/api/register",
+            post(register_handler).layer(limit(REGISTER_LIMIT)),
+        )
+        .route("/api/login", post(login_handler))
+        .route("/api/login/2fa", post(login_mfa_handler))
+        .route(
+            "/api/login/2fa/passkey/options",
+            post(passkey_mfa_options_handler),
+        )
+        .route("/api/login/2fa/passkey", post(passkey_mfa_handler))
+        .route(
+            "/api/login/passkey/options",
+            post(passkey_login_options_handler),
+        )
+        .route("/api/login/passkey", post(passkey_login_handler))
+        .route(
+            "/api/login/magic",
+            post(magic_link_handler).layer(limit(MAGIC_LINK_LIMIT)),
+        )
+        .route("/api/login/magic/verify", post(magic_link_verify_handler))
+        .route(
+            "/api/password/forgot",
+            post(forgot_password_handler).layer(limit(PASSWORD_FORGOT_LIMIT)),
+        )
+        .route("/api/password/reset", post(reset_password_handler))
+        .route("/api/email/verify", post(verify_email_handler))
+        // guarded by the refresh token in the body, not by an access token
+        .route("/api/refresh", post(refresh_handler))
+        // login / consent page of the authorization code flow
+        .route(
+            "/oauth/authorize",
+            get(authorize_page_handler).post(authorize_handler),
+        )
+        // clients authenticate with their own credentials
+        .route("/oauth/token", post(token_handler))
+        .route("/oauth/introspect", post(introspect_handler))
+        .route("/oauth/revoke", post(revoke_handler))
+        // federated login: redirect to the upstream provider and back
+        .route("/auth/:provider/login", get(federated_login_handler))
+        .route("/auth/:provider/callback", get(federated_callback_handler))
+        .route("/.well-known/jwks.json", get(jwks_handler))
+        .route(
+            "/.well-known/openid-configuration",
+            get(openid_configuration_handler),
+        );
 
     let protected_router = Router::new()
-        .route("/api/refresh", post(refresh_handler))
-        .layer(AuthLayer);
+        .route("/api/logout", post(logout_handler))
+        .route("/api/logout/all", post(logout_all_handler))
+        .route("/api/me", get(me_handler))
+        .route("/userinfo", get(userinfo_handler).post(userinfo_handler))
+        .route("/api/me/password", post(change_password_handler))
+        .route("/api/me/email", post(change_email_handler))
+        .route(
+            "/api/me/tokens",
+            get(list_pats_handler).post(create_pat_handler.layer(limit(PAT_CREATE_LIMIT))),
+        )
+        .route("/api/me/tokens/:id", delete(revoke_pat_handler))
+        .route("/api/me/identities", get(list_identities_handler))
+        .route(
+            "/api/me/identities/:provider",
+            post(link_identity_handler).delete(unlink_identity_handler),
+        )
+        .route(
+            "/api/me/passkeys",
+            get(list_passkeys_handler).post(register_passkey_handler),
+        )
+        .route(
+            "/api/me/passkeys/options",
+            post(passkey_registration_options_handler),
+        )
+        .route(
+            "/api/me/passkeys/:id",
+            patch(rename_passkey_handler).delete(delete_passkey_handler),
+        )
+        .route("/api/me/2fa/totp/enroll", post(totp_enroll_handler))
+        .route("/api/me/2fa/totp/confirm", post(totp_confirm_handler))
+        .route("/api/me/2fa/totp/disable", post(totp_disable_handler))
+        .route("/api/sessions", get(list_sessions_handler))
+        .route(
+            "/api/sessions/others",
+            delete(revoke_other_sessions_handler),
+        )
+        .route(
+            "/api/sessions/:id",
+            get(get_session_handler).delete(revoke_session_handler),
+        )
+        // inside `AuthLayer`, so it can count per token / user
+        .route_layer(limit(API_LIMIT))
+        .layer(AuthLayer::new(state.clone()));
+
+    let admin_router = Router::new()
+        .route("/api/admin/keys", get(list_keys_handler))
+        .route("/api/admin/keys/rotate", post(rotate_key_handler))
+        .route("/api/admin/keys/:kid", delete(retire_key_handler))
+        .route_layer(require_permission("admin:keys"))
+        .layer(AuthLayer::new(state.clone()));
+
+    let admin_users_router = Router::new()
+        .route("/api/admin/users/:id/unlock", post(unlock_user_handler))
+        .route(
+            "/api/admin/service-accounts",
+            post(create_service_account_handler),
+        )
+        .route(
+            "/api/admin/service-accounts/:id/clients",
+            get(list_clients_handler).post(create_client_handler),
+        )
+        .route(
+            "/api/admin/service-accounts/:id/clients/:client_id",
+            delete(delete_client_handler),
+        )
+        .route_layer(require_permission("admin:users"))
+        .layer(AuthLayer::new(state.clone()));
+
+    let admin_clients_router = Router::new()
+        .route("/api/admin/oauth/clients", post(create_app_client_handler))
+        .route_layer(require_permission("admin:clients"))
+        .layer(AuthLayer::new(state.clone()));
 
     Router::new()
         .merge(public_router)
         .merge(protected_router)
+        .merge(admin_router)
+        .merge(admin_users_router)
+        .merge(admin_clients_router)
         .with_state(state)
 }
@@ -1,17 +1,44 @@
 use crate::{
-    auth::jwt::{encode_claims, make_claims},
+    auth::{
+        extractor::ClientInfo,
+        jwt::{Claims, TokenType, decode_claims, encode_claims, make_claims},
+    },
+    error::{AppError, AppResult},
+    models::{
+        session::{SessionGrant, SessionInfo},
+        user::User,
+    },
     repositories::user_repo::{
-        exist_by_username, get_user_by_id, get_user_by_username, register_by_username_password_hash,
+        exist_by_username, get_user_by_id, register_by_username_password_hash, update_password_hash,
+    },
+    services::{
+        email_service::{
+            ensure_email_available, find_user_by_login, send_verification, validate_email,
+        },
+        login_throttle::{Subject, check_allowed, clear, record_failure},
+        mfa_service::{
+            claim_attempt, fail_challenge, finish_challenge, second_factors, start_challenge,
+            verify_second_factor,
+        },
+        security_event::{SecurityEvent, emit},
+        session_service::{
+            active_sessions, enforce_max_sessions, load_family, load_session, revoke,
+            revoke_session, store_session,
+        },
     },
     state::AppState,
     utils::{
-        hash::hash_password,
-        redis_keys::{blacklist_key, refresh_key, session_key, user_sessions_key},
+        redis_keys::{blacklist_key, refresh_key, user_sessions_key},
+        validation::normalize_username,
     },
 };
-use anyhow::{Ok, Result, bail};
-use bcrypt::verify;
-use deadpool_redis::redis::AsyncCommands;
+use chrono::Utc;
+use deadpool_redis::{
+    Connection,
+    redis::{AsyncCommands, cmd},
+};
+use std::sync::OnceLock;
+use uuid::Uuid;
 
 pub struct LoginResult {
     pub access_token: String,
@@ -19,131 +46,315 @@ pub struct LoginResult {
     pub user: crate::models::user::UserResponse,
 }
 
+/// 签发一对 access / refresh token 并保存会话
+async fn issue_tokens(
+    conn: &mut Connection,
+    user_id: i64,
+    client: &ClientInfo,
+    family: &str,
+    created_at: i64,
+    grant: SessionGrant,
+    state: &AppState,
+) -> AppResult<(String, String, SessionInfo)> {
+    let access_claims = make_claims(TokenType::Access, user_id, state.session_ttl_secs, family);
+    let access_token = encode_claims(&state.jwt_keys, &access_claims)?;
+
+    // create refresh token (longer TTL) — we reuse Claims but longer
+    let refresh_claims = make_claims(TokenType::Refresh, user_id, state.refresh_ttl_secs, family);
+    let refresh_token = encode_claims(&state.jwt_keys, &refresh_claims)?;
+
+    let info = SessionInfo {
+        user_id,
+        family: family.to_string(),
+        access_jti: access_claims.jti,
+        refresh_jti: refresh_claims.jti,
+        created_at,
+        last_seen: Utc::now().timestamp(),
+        ip: client.ip.clone(),
+        user_agent: client.user_agent.clone(),
+        device: client.device.clone(),
+        scopes: grant.scopes,
+        client_id: grant.client_id,
+    };
+    store_session(conn, &info, state).await?;
+    Ok((access_token, refresh_token, info))
+}
+
+/// 登录结果：直接签发 token，或者需要第二因素
+pub enum LoginOutcome {
+    Complete(LoginResult),
+    MfaRequired {
+        mfa_token: String,
+        /// 可用的第二因素：totp / webauthn
+        methods: Vec<&'static str>,
+    },
+}
+
 /// 登录：
-// 1) 验证用户名密码
-// 2) 生成 access Claims (短期), refresh Claims (长期)
-// 3) 存 access-session 到 redis: session:{jti} -> {user_id}  TTL = access_ttl
-// 4) 存 refresh 到 redis: refresh:{jti} -> {user_id} TTL = refresh_ttl
-// 5) 在 user:{user_id}:sessions SET 添加 jti（用于多端管理）
-pub async fn login(username: &str, password: &str, state: &AppState) -> Result<LoginResult> {
-    let user = get_user_by_username(&state.db, username)
+// 1) authenticate_password 校验用户名密码
+// 2) complete_login：2FA 或签发 token
+pub async fn login(
+    username: &str,
+    password: &str,
+    client: &ClientInfo,
+    state: &AppState,
+) -> AppResult<LoginOutcome> {
+    let user = authenticate_password(username, password, client, state).await?;
+    complete_login(&user, client, state).await
+}
+
+/// 第一因素通过之后（密码 / 上游身份 / 登录链接）：
+// 1) 开启了 TOTP 或注册了 passkey：返回 mfa pending token，等待 /api/login/2fa 或 /api/login/2fa/passkey
+// 2) 否则 start_session 签发 token
+pub async fn complete_login(
+    user: &User,
+    client: &ClientInfo,
+    state: &AppState,
+) -> AppResult<LoginOutcome> {
+    let methods = second_factors(user, state).await?;
+    if !methods.is_empty() {
+        let mfa_token = start_challenge(user.id, client, state).await?;
+        return Ok(LoginOutcome::MfaRequired { mfa_token, methods });
+    }
+
+    Ok(LoginOutcome::Complete(
+        start_session(user, client, state).await?,
+    ))
+}
+
+/// 校验用户名密码（登录与 /oauth/authorize 共用）：
+// 1) 账号 / ip 处于锁定或退避期：429（ip 见 `client_ip`，只采信可信代理转发的地址）
+// 2) 用户不存在与密码错误返回同样的错误（并同样执行一次哈希）；服务账号不能用密码登录
+// 3) 失败计数；成功时清零，有第二因素的账号等第二步通过后才清零（否则知道密码就能重置第二步的失败次数）
+// 4) 旧哈希顺便重新哈希；禁用的用户 403
+pub async fn authenticate_password(
+    username: &str,
+    password: &str,
+    client: &ClientInfo,
+    state: &AppState,
+) -> AppResult<User> {
+    // service accounts only authenticate with client credentials
+    let user = find_user_by_login(username, state)
         .await?
-        .ok_or_else(|| anyhow::anyhow!("user not found"))?;
+        .filter(|u| !u.service_account);
+    let account = match &user {
+        Some(u) => Subject::User(u.id),
+        None => Subject::Login(username),
+    };
+    let mut subjects = vec![account];
+    if let Some(ip) = client.ip.as_deref() {
+        subjects.push(Subject::Ip(ip));
+    }
+    check_allowed(&subjects, state).await?;
 
-    if user.disabled {
-        anyhow::bail!("user disabled");
+    let password_ok = match &user {
+        Some(u) => state.password_hasher.verify(password, &u.password_hash)?,
+        None => {
+            let dummy = dummy_password_hash(state)?;
+            state.password_hasher.verify(password, dummy)?;
+            false
+        }
+    };
+    let Some(user) = user.filter(|_| password_ok) else {
+        record_failure(&subjects, state).await?;
+        return Err(AppError::InvalidCredentials);
+    };
+    if second_factors(&user, state).await?.is_empty() {
+        clear(&subjects[0], state).await?;
     }
 
-    if !verify(password, &user.password_hash)? {
-        anyhow::bail!("invalid credentials");
+    // migrate old hashes (bcrypt, weaker argon2 params) while we know the password
+    if state.password_hasher.needs_rehash(&user.password_hash) {
+        let rehashed = state.password_hasher.hash(password)?;
+        update_password_hash(&state.db, user.id, &rehashed).await?;
     }
 
-    // create access token
-    let access_claims = make_claims(user.id, state.session_ttl_secs);
-    let access_token = encode_claims(&state.jwt_secret, &access_claims)?;
+    if user.disabled {
+        return Err(AppError::UserDisabled);
+    }
+    Ok(user)
+}
 
-    // create refresh token (longer TTL) — we reuse Claims but longer
-    let refresh_claims = make_claims(user.id, state.refresh_ttl_secs);
-    let refresh_token = encode_claims(&state.jwt_secret, &refresh_claims)?;
+/// 已登录用户再次输入密码（修改密码、重新认证）：与登录共用账号的失败计数和锁定，
+/// 被盗的 access token 不能用来无限次猜密码；通过时和登录一样，有第二因素的账号不清零
+pub async fn check_current_password(
+    user: &User,
+    password: &str,
+    state: &AppState,
+) -> AppResult<bool> {
+    let subject = Subject::User(user.id);
+    check_allowed(std::slice::from_ref(&subject), state).await?;
+    if !state
+        .password_hasher
+        .verify(password, &user.password_hash)?
+    {
+        record_failure(&[subject], state).await?;
+        return Ok(false);
+    }
+    if second_factors(user, state).await?.is_empty() {
+        clear(&subject, state).await?;
+    }
+    Ok(true)
+}
 
-    // store in redis
-    let mut conn = state.redis.get().await?;
-    // store session access
-    let s_key = session_key(&access_claims.jti);
-    let r_key = refresh_key(&refresh_claims.jti);
-    // store minimal info; could be json
-    let _: () = conn
-        .set_ex(&s_key, user.id, state.session_ttl_secs as usize)
-        .await?;
-    let _: () = conn
-        .set_ex(&r_key, user.id, state.refresh_ttl_secs as usize)
-        .await?;
+/// 用户不存在时也做一次 bcrypt，避免从响应时间判断用户是否存在
+fn dummy_password_hash(state: &AppState) -> AppResult<&'static str> {
+    static HASH: OnceLock<String> = OnceLock::new();
+    if let Some(hash) = HASH.get() {
+        return Ok(hash);
+    }
+    let hash = state.password_hasher.hash("dummy password")?;
+    Ok(HASH.get_or_init(|| hash))
+}
 
-    // add jti into user's session set
-    let user_s_key = user_sessions_key(user.id);
-    let _: () = conn.sadd(&user_s_key, &access_claims.jti).await?;
-    // set TTL on the set slightly longer than refresh ttl
-    let _: () = conn
-        .expire(&user_s_key, state.refresh_ttl_secs as usize)
-        .await?;
+/// 登录第二步：mfa pending token + TOTP 验证码（或恢复码）
+pub async fn login_mfa(mfa_token: &str, code: &str, state: &AppState) -> AppResult<LoginResult> {
+    let (user, client) = verify_mfa(mfa_token, code, state).await?;
+    start_session(&user, &client, state).await
+}
 
-    // enforce max sessions per user (simple LRU not implemented here)
-    let size: i64 = conn.scard(&user_s_key).await?;
-    let size = size as usize;
-
-    if size > state.max_sessions_per_user {
-        let old_jti: Option<String> = conn.spop(&user_s_key).await?;
-        // pop one jti and blacklisting it — simple approach
-        if let Some(old_jti) = old_jti {
-            let black = blacklist_key(&old_jti);
-            let _: () = conn
-                .set_ex(&black, "1", state.refresh_ttl_secs as usize)
-                .await?;
-            // also remove its session key
-            let _: () = conn.del(session_key(&old_jti)).await?;
-        }
+/// 校验第二因素，返回用户与发起登录时的客户端信息；验证码错误计入 challenge 与账号的失败次数
+pub async fn verify_mfa(
+    mfa_token: &str,
+    code: &str,
+    state: &AppState,
+) -> AppResult<(User, ClientInfo)> {
+    let challenge = claim_attempt(mfa_token, state).await?;
+    let user = get_user_by_id(&state.db, challenge.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("user not found".into()))?;
+    if user.disabled {
+        return Err(AppError::UserDisabled);
+    }
+
+    if !verify_second_factor(&user, code, state).await? {
+        fail_challenge(mfa_token, &challenge, state).await?;
+        return Err(AppError::unauthorized("invalid two-factor code"));
     }
+    finish_challenge(mfa_token, &challenge, state).await?;
+    Ok((user, challenge.client()))
+}
+
+/// 身份验证完成后开启新会话：
+// 1) 生成 access Claims (短期), refresh Claims (长期)，同属一个新的 session family
+// 2) 存会话到 redis: session:{access_jti} -> SessionInfo(json)  TTL = refresh_ttl
+// 3) 存 refresh 到 redis: refresh:{refresh_jti} -> {access_jti} TTL = refresh_ttl
+// 4) 存 family 到 redis: family:{family} -> {access_jti}  TTL = refresh_ttl
+// 5) 在 user:{user_id}:sessions SET 添加 jti（用于多端管理）
+pub async fn start_session(
+    user: &User,
+    client: &ClientInfo,
+    state: &AppState,
+) -> AppResult<LoginResult> {
+    start_client_session(user, client, SessionGrant::default(), state).await
+}
+
+/// 与 start_session 相同，会话绑定到 OAuth 客户端并限制到授予的 scope
+pub async fn start_client_session(
+    user: &User,
+    client: &ClientInfo,
+    grant: SessionGrant,
+    state: &AppState,
+) -> AppResult<LoginResult> {
+    let mut conn = state.redis.get().await?;
+    // a new login starts a new session family
+    let family = Uuid::new_v4().to_string();
+    let (access_token, refresh_token, info) = issue_tokens(
+        &mut conn,
+        user.id,
+        client,
+        &family,
+        Utc::now().timestamp(),
+        grant,
+        state,
+    )
+    .await?;
+
+    // enforce max sessions per user: evict the least recently used ones
+    enforce_max_sessions(&mut conn, user.id, &info.access_jti, state).await?;
 
     Ok(LoginResult {
         access_token,
         refresh_token,
-        user: (&user).into(),
+        user: user.into(),
     })
 }
 
-/// 刷新 token：提供 refresh_token -> 验证 -> 生成新的 access + rotate refresh (选做)
-pub async fn refresh_tokens(refresh_token: &str, state: &AppState) -> Result<LoginResult> {
-    use crate::auth::jwt::decode_claims;
-    let claims = decode_claims(&state.jwt_secret, refresh_token)?;
-    // check blacklist
+/// 刷新 token：提供 refresh_token -> 验证 -> 生成新的 access + rotate refresh
+// 新的一对 token 沿用原会话的 family / created_at / 设备信息，旧会话被吊销。
+// 已轮换的 refresh token 被再次使用（family 仍然存活但当前 refresh 不是它）
+// 视为 token 被盗：吊销整个 family 并记录安全事件。
+// 会话只能由签发给的客户端刷新（client_id 为 None 是 /api/refresh），scope 保持不变；
+// 客户端不匹配时不消耗 refresh token；禁用的用户 403 并吊销会话。
+// refresh token 用 GETDEL 消耗，并发的刷新只有一个能成功，其余按重复使用处理
+pub async fn refresh_tokens(
+    refresh_token: &str,
+    client: &ClientInfo,
+    client_id: Option<&str>,
+    state: &AppState,
+) -> AppResult<LoginResult> {
+    let claims = decode_claims(&state.jwt_keys, refresh_token, TokenType::Refresh)
+        .map_err(|_| AppError::unauthorized("invalid refresh token"))?;
     let mut conn = state.redis.get().await?;
-    let black_key = blacklist_key(&claims.jti);
-    let is_black: Option<String> = conn.get(&black_key).await.ok();
-    if is_black.is_some() {
-        anyhow::bail!("token blacklisted");
-    }
-
-    // check refresh key exists
-    let r_key = refresh_key(&claims.jti);
-    let user_id_opt: Option<i64> = conn.get(&r_key).await.ok();
-    let user_id = user_id_opt.ok_or_else(|| anyhow::anyhow!("refresh expired"))?;
-
-    // optional: rotate refresh token — create new refresh claim and blacklist old
-    let new_refresh_claims = make_claims(user_id, state.refresh_ttl_secs);
-    let new_refresh_token = encode_claims(&state.jwt_secret, &new_refresh_claims)?;
-    // store new refresh
-    let _: () = conn
-        .set_ex(
-            refresh_key(&new_refresh_claims.jti),
-            user_id,
-            state.refresh_ttl_secs as usize,
-        )
-        .await?;
-    // blacklist old refresh
-    let _: () = conn
-        .set_ex(black_key, "1", state.refresh_ttl_secs as usize)
-        .await?;
-    let _: () = conn.del(&r_key).await?;
-
-    // create access
-    let access_claims = make_claims(user_id, state.session_ttl_secs);
-    let access_token = encode_claims(&state.jwt_secret, &access_claims)?;
-    let _: () = conn
-        .set_ex(
-            session_key(&access_claims.jti),
-            user_id,
-            state.session_ttl_secs as usize,
-        )
-        .await?;
-    let user_s_key = user_sessions_key(user_id);
-    let _: () = conn.sadd(&user_s_key, &access_claims.jti).await?;
-    let _: () = conn
-        .expire(&user_s_key, state.refresh_ttl_secs as usize)
-        .await?;
 
-    let user = get_user_by_id(&state.db, user_id)
+    // check blacklist, refresh key exists and its session is still alive
+    let is_black: Option<String> = conn.get(blacklist_key(&claims.jti)).await?;
+    let access_jti: Option<String> = match is_black {
+        Some(_) => None,
+        None => conn.get(refresh_key(&claims.jti)).await?,
+    };
+    let old = match access_jti {
+        Some(jti) => load_session(&mut conn, &jti)
+            .await?
+            .filter(|s| s.user_id == claims.sub && s.refresh_jti == claims.jti),
+        None => None,
+    };
+    let Some(old) = old else {
+        return Err(reject_refresh(&mut conn, &claims, is_black.is_some(), client, state).await?);
+    };
+    if old.client_id.as_deref() != client_id {
+        return Err(AppError::unauthorized(
+            "refresh token was issued to another client",
+        ));
+    }
+
+    let user = get_user_by_id(&state.db, old.user_id)
         .await?
-        .ok_or_else(|| anyhow::anyhow!("user not found"))?;
+        .ok_or_else(|| AppError::NotFound("user not found".into()))?;
+    if user.disabled {
+        revoke(&mut conn, &old, state).await?;
+        return Err(AppError::UserDisabled);
+    }
+
+    let consumed: Option<String> = cmd("GETDEL")
+        .arg(refresh_key(&claims.jti))
+        .query_async(&mut conn)
+        .await?;
+    if consumed.as_deref() != Some(old.access_jti.as_str()) {
+        return Err(reject_refresh(&mut conn, &claims, false, client, state).await?);
+    }
+
+    // rotate: blacklist the old pair and issue a new one for the same device
+    revoke(&mut conn, &old, state).await?;
+    let client = ClientInfo {
+        ip: client.ip.clone().or(old.ip),
+        user_agent: client.user_agent.clone().or(old.user_agent),
+        device: old.device,
+    };
+    let grant = SessionGrant {
+        client_id: old.client_id,
+        scopes: old.scopes,
+    };
+    let (access_token, new_refresh_token, _) = issue_tokens(
+        &mut conn,
+        user.id,
+        &client,
+        &old.family,
+        old.created_at,
+        grant,
+        state,
+    )
+    .await?;
 
     Ok(LoginResult {
         access_token,
@@ -152,33 +363,81 @@ pub async fn refresh_tokens(refresh_token: &str, state: &AppState) -> Result<Log
     })
 }
 
-/// 登出：将 session jti 与 refresh jti 全部加入黑名单或删除 redis session
-pub async fn logout_all(user_id: i64, state: &AppState) -> Result<()> {
+/// 无效的 refresh token：family 仍然存活说明已轮换的 token 被再次使用，吊销整个 family
+async fn reject_refresh(
+    conn: &mut Connection,
+    claims: &Claims,
+    blacklisted: bool,
+    client: &ClientInfo,
+    state: &AppState,
+) -> AppResult<AppError> {
+    if let Some(current) = load_family(conn, &claims.fam).await? {
+        revoke(conn, &current, state).await?;
+        emit(&SecurityEvent::RefreshTokenReuse {
+            user_id: current.user_id,
+            family: &claims.fam,
+            jti: &claims.jti,
+            ip: client.ip.as_deref(),
+            user_agent: client.user_agent.as_deref(),
+        });
+        return Ok(AppError::unauthorized("refresh token reuse detected"));
+    }
+    if blacklisted {
+        return Ok(AppError::unauthorized("token blacklisted"));
+    }
+    Ok(AppError::unauthorized("refresh expired"))
+}
+
+/// 登出当前会话：access 与配对的 refresh 一起加入黑名单
+pub async fn logout(user_id: i64, jti: &str, state: &AppState) -> AppResult<()> {
+    revoke_session(user_id, jti, state).await
+}
+
+/// 登出：吊销用户所有会话（session 与 refresh jti 都加入黑名单）
+pub async fn logout_all(user_id: i64, state: &AppState) -> AppResult<()> {
     let mut conn = state.redis.get().await?;
-    let user_s_key = user_sessions_key(user_id);
-    let jtis: Vec<String> = conn.smembers(&user_s_key).await?;
-    for jti in jtis.iter() {
-        let _: () = conn.del(session_key(jti)).await?;
-        let black = blacklist_key(jti);
-        let _: () = conn
-            .set_ex(&black, "1", state.refresh_ttl_secs as usize)
-            .await?;
-    }
-    let _: () = conn.del(&user_s_key).await?;
+    for info in active_sessions(&mut conn, user_id).await? {
+        revoke(&mut conn, &info, state).await?;
+    }
+    let _: () = conn.del(user_sessions_key(user_id)).await?;
     Ok(())
 }
 
-pub async fn register(username: &str, password: &str, state: &AppState) -> Result<i64> {
-    let exists = exist_by_username(&state.db, username)
+/// 注册：用户名规范化（trim + 小写），email 可选，提供时发送验证邮件
+pub async fn register(
+    username: &str,
+    password: &str,
+    email: Option<&str>,
+    state: &AppState,
+) -> AppResult<i64> {
+    let username = normalize_username(username)
+        .map_err(|message| AppError::invalid_field("username", message))?;
+    let exists = exist_by_username(&state.db, &username)
         .await?
         .unwrap_or(false);
     if exists {
-        bail!("username already exists")
+        return Err(AppError::Conflict("username already exists".into()));
     }
+    let email = email.map(validate_email).transpose()?;
+    if let Some(email) = &email {
+        ensure_email_available(email, state).await?;
+    }
+    let user_inputs: Vec<&str> = std::iter::once(username.as_str())
+        .chain(email.as_deref())
+        .collect();
+    state
+        .password_policy
+        .check("password", password, &user_inputs)
+        .await?;
 
-    let password_hash = hash_password(password);
-    let id: i64 = register_by_username_password_hash(&state.db, username, &password_hash)
-        .await?
-        .unwrap_or(0);
+    let password_hash = state.password_hasher.hash(password)?;
+    // a concurrent registration of the same name / email surfaces as Conflict
+    let id: i64 =
+        register_by_username_password_hash(&state.db, &username, &password_hash, email.as_deref())
+            .await?
+            .unwrap_or(0);
+    if let Some(email) = &email {
+        send_verification(id, email, state).await?;
+    }
     Ok(id)
 }
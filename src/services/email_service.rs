@@ -0,0 +1,120 @@
+use crate::{
+    error::{AppError, AppResult},
+    models::user::User,
+    repositories::user_repo::{
+        exist_by_email, get_user_by_email, get_user_by_id, get_user_by_username,
+        mark_email_verified, set_email,
+    },
+    services::mailer::Mail,
+    state::AppState,
+    utils::{
+        redis_keys::email_verify_key,
+        token::{hash_token, random_token},
+        validation::normalize_email,
+    },
+};
+use deadpool_redis::redis::{AsyncCommands, cmd};
+use serde::{Deserialize, Serialize};
+
+/// 验证链接有效期
+const VERIFY_TOKEN_TTL_SECS: usize = 60 * 60 * 24;
+
+/// redis email_verify:{sha256(token)} 中保存的内容
+#[derive(Serialize, Deserialize)]
+struct EmailVerification {
+    user_id: i64,
+    email: String,
+}
+
+/// 邮箱格式检查，返回 trim 后的邮箱
+pub fn validate_email(email: &str) -> AppResult<String> {
+    normalize_email(email).map_err(|message| AppError::invalid_field("email", message))
+}
+
+/// 按用户名或邮箱查找（含 @ 视为邮箱，只匹配已验证的邮箱）
+pub async fn find_user_by_login(identifier: &str, state: &AppState) -> AppResult<Option<User>> {
+    if identifier.contains('@') {
+        return get_user_by_email(&state.db, identifier.trim()).await;
+    }
+    get_user_by_username(&state.db, identifier).await
+}
+
+/// 邮箱不能是其他用户已验证的邮箱（不区分大小写）；未验证的邮箱不占用
+pub async fn ensure_email_available(email: &str, state: &AppState) -> AppResult<()> {
+    if exist_by_email(&state.db, email).await?.unwrap_or(false) {
+        return Err(AppError::Conflict("email already in use".into()));
+    }
+    Ok(())
+}
+
+/// 设置 / 修改邮箱：
+// 1) 校验格式和唯一性
+// 2) 写入 email，email_verified = false
+// 3) 发送验证邮件
+pub async fn change_email(user_id: i64, email: &str, state: &AppState) -> AppResult<()> {
+    let email = validate_email(email)?;
+    let user = get_user_by_id(&state.db, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("user not found".into()))?;
+    let unchanged = user
+        .email
+        .as_deref()
+        .is_some_and(|e| e.eq_ignore_ascii_case(&email));
+    if unchanged && user.email_verified {
+        return Ok(());
+    }
+    if !unchanged {
+        ensure_email_available(&email, state).await?;
+        set_email(&state.db, user_id, &email).await?;
+    }
+    send_verification(user_id, &email, state).await
+}
+
+/// 发送验证邮件：token 只存 sha256，TTL 24h
+pub async fn send_verification(user_id: i64, email: &str, state: &AppState) -> AppResult<()> {
+    let token = random_token(32);
+    let pending = EmailVerification {
+        user_id,
+        email: email.to_string(),
+    };
+    let mut conn = state.redis.get().await?;
+    let _: () = conn
+        .set_ex(
+            email_verify_key(&hash_token(&token)),
+            serde_json::to_string(&pending)?,
+            VERIFY_TOKEN_TTL_SECS,
+        )
+        .await?;
+
+    state
+        .mailer
+        .send(Mail {
+            to: email.to_string(),
+            subject: "Verify your email address".into(),
+            body: format!(
+                "Open the link below within 24 hours to verify your email address:\n\n{}?token={}\n",
+                state.email_verify_url, token
+            ),
+        })
+        .await?;
+    Ok(())
+}
+
+/// 验证邮箱：token 只能用一次；期间邮箱被修改过则失效，被其他用户验证过则 409
+pub async fn verify_email(token: &str, state: &AppState) -> AppResult<()> {
+    let mut conn = state.redis.get().await?;
+    let raw: Option<String> = cmd("GETDEL")
+        .arg(email_verify_key(&hash_token(token)))
+        .query_async(&mut conn)
+        .await?;
+    let pending: EmailVerification = raw
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .ok_or_else(|| AppError::unauthorized("invalid or expired verification token"))?;
+
+    if !mark_email_verified(&state.db, pending.user_id, &pending.email).await? {
+        return Err(AppError::unauthorized(
+            "invalid or expired verification token",
+        ));
+    }
+    Ok(())
+}
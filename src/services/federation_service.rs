@@ -0,0 +1,274 @@
+use crate::{
+    auth::extractor::ClientInfo,
+    error::{AppError, AppResult},
+    models::{
+        user::User,
+        user_identity::{FederatedLogin, UserIdentity},
+    },
+    repositories::{
+        identity_repo::{
+            create_identity, delete_identity, find_identity, list_identities, touch_identity,
+        },
+        role_repo::find_roles_by_name,
+        user_repo::{create_federated_user, exist_by_email, exist_by_username, get_user_by_id},
+    },
+    services::{
+        auth_service::{LoginOutcome, complete_login},
+        oauth_service::pkce_challenge,
+        upstream_oidc::{UpstreamClaims, UpstreamProvider},
+    },
+    state::AppState,
+    utils::{
+        redis_keys::federated_login_key,
+        token::{hash_token, random_token},
+        validation::normalize_username,
+    },
+};
+use deadpool_redis::redis::{AsyncCommands, cmd};
+
+/// 从跳转到上游到回调的最长时间
+pub const LOGIN_STATE_TTL_SECS: usize = 60 * 10;
+
+/// 回调的结果：登录（可能还需要本地 2FA），或者把身份关联到了发起请求的用户
+pub enum FederatedOutcome {
+    Login(LoginOutcome),
+    Linked(UserIdentity),
+}
+
+fn find_provider<'a>(name: &str, state: &'a AppState) -> AppResult<&'a UpstreamProvider> {
+    state
+        .upstream_providers
+        .iter()
+        .find(|p| p.name == name)
+        .ok_or_else(|| AppError::NotFound("unknown identity provider".into()))
+}
+
+/// 在上游注册的回调地址
+fn callback_url(provider: &UpstreamProvider, state: &AppState) -> String {
+    format!("{}/auth/{}/callback", state.oidc_issuer, provider.name)
+}
+
+/// 开始上游登录（link_user_id 为 None）或关联（已登录用户）：
+// 1) 服务账号不能关联身份
+// 2) state / nonce / PKCE verifier 随机生成，请求保存在 redis（只存 state 的 sha256）
+// 3) 返回上游授权地址与 state：浏览器跳转过去，state 同时写进浏览器的 cookie
+pub async fn start_federated_login(
+    provider_name: &str,
+    link_user_id: Option<i64>,
+    state: &AppState,
+) -> AppResult<(String, String)> {
+    let provider = find_provider(provider_name, state)?;
+    if let Some(user_id) = link_user_id {
+        let user = get_user_by_id(&state.db, user_id).await?;
+        if user.is_none_or(|u| u.service_account) {
+            return Err(AppError::forbidden(
+                "service accounts cannot link identities",
+            ));
+        }
+    }
+    let login_state = random_token(32);
+    let login = FederatedLogin {
+        provider: provider.name.clone(),
+        nonce: random_token(16),
+        code_verifier: random_token(32),
+        link_user_id,
+    };
+    let url = provider
+        .authorization_url(
+            &state.http,
+            &callback_url(provider, state),
+            &login_state,
+            &login.nonce,
+            &pkce_challenge(&login.code_verifier),
+        )
+        .await?;
+
+    let mut conn = state.redis.get().await?;
+    let _: () = conn
+        .set_ex(
+            federated_login_key(&hash_token(&login_state)),
+            serde_json::to_string(&login)?,
+            LOGIN_STATE_TTL_SECS,
+        )
+        .await?;
+    Ok((url, login_state))
+}
+
+/// 上游回调：
+// 1) state 只能使用一次（GETDEL），且必须属于这个 provider
+// 2) 用授权码换取并验证上游 ID token
+// 3) 关联请求：把 (provider, sub) 关联到发起请求的用户
+// 4) 登录：已关联的身份直接登录，否则自动创建用户；本地开启了 2FA 时仍要验证
+pub async fn complete_federated_login(
+    provider_name: &str,
+    code: &str,
+    login_state: &str,
+    client: &ClientInfo,
+    state: &AppState,
+) -> AppResult<FederatedOutcome> {
+    let provider = find_provider(provider_name, state)?;
+    let mut conn = state.redis.get().await?;
+    let raw: Option<String> = cmd("GETDEL")
+        .arg(federated_login_key(&hash_token(login_state)))
+        .query_async(&mut conn)
+        .await?;
+    let login: FederatedLogin = raw
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .filter(|login: &FederatedLogin| login.provider == provider.name)
+        .ok_or_else(|| AppError::unauthorized("invalid or expired login state"))?;
+
+    let claims = provider
+        .exchange_code(
+            &state.http,
+            code,
+            &callback_url(provider, state),
+            &login.code_verifier,
+            &login.nonce,
+        )
+        .await?;
+
+    if let Some(user_id) = login.link_user_id {
+        let identity = link_identity(user_id, provider, &claims, state).await?;
+        return Ok(FederatedOutcome::Linked(identity));
+    }
+
+    let user = match find_identity(&state.db, &provider.name, &claims.sub).await? {
+        Some(identity) => {
+            touch_identity(&state.db, identity.id, claims.email.as_deref()).await?;
+            get_user_by_id(&state.db, identity.user_id)
+                .await?
+                .ok_or_else(|| AppError::unauthorized("invalid or expired login state"))?
+        }
+        None => provision_user(provider, &claims, state).await?,
+    };
+    if user.disabled {
+        return Err(AppError::UserDisabled);
+    }
+    let outcome = complete_login(&user, client, state).await?;
+    Ok(FederatedOutcome::Login(outcome))
+}
+
+async fn link_identity(
+    user_id: i64,
+    provider: &UpstreamProvider,
+    claims: &UpstreamClaims,
+    state: &AppState,
+) -> AppResult<UserIdentity> {
+    match find_identity(&state.db, &provider.name, &claims.sub).await? {
+        Some(identity) if identity.user_id == user_id => Ok(identity),
+        Some(_) => Err(AppError::Conflict(
+            "this identity is linked to another account".into(),
+        )),
+        None => {
+            let linked = list_identities(&state.db, user_id).await?;
+            if linked.iter().any(|i| i.provider == provider.name) {
+                return Err(AppError::Conflict(format!(
+                    "another {} identity is already linked",
+                    provider.name
+                )));
+            }
+            create_identity(
+                &state.db,
+                user_id,
+                &provider.name,
+                &claims.sub,
+                claims.email.as_deref(),
+            )
+            .await
+        }
+    }
+}
+
+/// 自动创建用户（JIT provisioning）：
+// 1) 用户名来自 preferred_username / 邮箱，已被占用时加随机后缀
+// 2) 只使用上游已验证的邮箱；已有用户使用这个邮箱时不自动合并（防止通过上游接管账号），
+//    需要先登录本地账号再关联
+// 3) 没有密码，分配 provider 配置的默认角色；用户、角色、身份在一个事务里写入
+async fn provision_user(
+    provider: &UpstreamProvider,
+    claims: &UpstreamClaims,
+    state: &AppState,
+) -> AppResult<User> {
+    let email = claims.verified_email();
+    if let Some(email) = email
+        && exist_by_email(&state.db, email).await? == Some(true)
+    {
+        return Err(AppError::Conflict(
+            "an account with this email already exists, sign in and link the identity instead"
+                .into(),
+        ));
+    }
+    let username = available_username(claims, state).await?;
+    let roles = find_roles_by_name(&state.db, &provider.default_roles).await?;
+    for role in &provider.default_roles {
+        if !roles.iter().any(|(_, name)| name == role) {
+            tracing::warn!(provider = %provider.name, "default role {} does not exist", role);
+        }
+    }
+    let role_ids: Vec<i64> = roles.iter().map(|(id, _)| *id).collect();
+    let user_id = create_federated_user(
+        &state.db,
+        &username,
+        email,
+        &role_ids,
+        &provider.name,
+        &claims.sub,
+        claims.email.as_deref(),
+    )
+    .await?;
+    get_user_by_id(&state.db, user_id)
+        .await?
+        .ok_or_else(|| AppError::Internal(anyhow::anyhow!("provisioned user vanished")))
+}
+
+async fn available_username(claims: &UpstreamClaims, state: &AppState) -> AppResult<String> {
+    let hint = claims
+        .preferred_username
+        .as_deref()
+        .or_else(|| claims.email.as_deref().and_then(|e| e.split('@').next()))
+        .unwrap_or_default();
+    // keep only characters usernames allow, leave room for a suffix
+    let base: String = hint
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' | '_' | '.' | '-' => c,
+            _ => '_',
+        })
+        .skip_while(|c| !c.is_ascii_alphanumeric())
+        .take(48)
+        .collect();
+    let base = normalize_username(&base).unwrap_or_else(|_| "user".to_string());
+    if exist_by_username(&state.db, &base).await? != Some(true) {
+        return Ok(base);
+    }
+    for _ in 0..5 {
+        let candidate = format!("{}_{:06x}", base, rand::random::<u32>() & 0xff_ffff);
+        if exist_by_username(&state.db, &candidate).await? != Some(true) {
+            return Ok(candidate);
+        }
+    }
+    Err(AppError::Conflict("could not pick a username".into()))
+}
+
+pub async fn list_user_identities(user_id: i64, state: &AppState) -> AppResult<Vec<UserIdentity>> {
+    list_identities(&state.db, user_id).await
+}
+
+/// 取消关联；没有密码的用户不能取消最后一个身份（否则无法再登录）
+pub async fn unlink_identity(user_id: i64, provider: &str, state: &AppState) -> AppResult<()> {
+    let user = get_user_by_id(&state.db, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("user not found".into()))?;
+    let identities = list_identities(&state.db, user_id).await?;
+    if !identities.iter().any(|i| i.provider == provider) {
+        return Err(AppError::NotFound("identity not found".into()));
+    }
+    if !user.has_password() && identities.len() == 1 {
+        return Err(AppError::Conflict(
+            "cannot unlink the only sign-in method, set a password first".into(),
+        ));
+    }
+    delete_identity(&state.db, user_id, provider).await?;
+    Ok(())
+}
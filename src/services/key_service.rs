@@ -0,0 +1,165 @@
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use jsonwebtoken::Algorithm;
+
+use crate::{
+    auth::keys::{JwtKey, alg_name},
+    error::{AppError, AppResult},
+    models::jwt_key::SigningKeyRecord,
+    repositories::jwt_key_repo::{list_keys, promote_key, retire_key},
+    state::AppState,
+};
+
+/// 轮换签名密钥：PEM 从 JWT_KEY_DIR 读取（私钥不经过 API 传输）。
+/// 新密钥先作为验证密钥发布，等所有实例都同步过一次（2 × key_sync_secs）后才开始签发，
+/// 旧密钥在那之后的 refresh ttl 内仍可验证
+pub async fn rotate(
+    alg: Algorithm,
+    name: &str,
+    kid: Option<String>,
+    state: &AppState,
+) -> AppResult<SigningKeyRecord> {
+    let key = JwtKey::from_dir(alg, &state.jwt_key_dir, name, kid)
+        .map_err(|e| AppError::Validation(format!("cannot load key: {}", e)))?;
+    let configured = state.jwt_keys.configured();
+    let active_at = Utc::now() + chrono::Duration::seconds(state.key_sync_secs as i64 * 2);
+    let record = SigningKeyRecord {
+        kid: key.kid.clone(),
+        alg: alg_name(alg).to_string(),
+        file: Some(name.to_string()),
+        active_at,
+        retire_at: None,
+    };
+    let retire_at = active_at + chrono::Duration::seconds(state.refresh_ttl_secs);
+    promote_key(
+        &state.db,
+        &configured.kid,
+        alg_name(configured.alg),
+        &record,
+        retire_at,
+    )
+    .await?;
+    sync(state).await?;
+    tracing::info!("jwt signing key {} signs from {}", record.kid, active_at);
+    Ok(record)
+}
+
+/// 立即停用一个验证密钥，所有实例在下一次同步时生效；
+/// 正在签发的和还没开始签发的密钥不能停用（409）
+pub async fn retire(kid: &str, state: &AppState) -> AppResult<()> {
+    let records = list_keys(&state.db).await?;
+    let now = Utc::now();
+    let configured = state.jwt_keys.configured();
+    if kid == signing_kid(&records, &configured.kid, now) {
+        return Err(AppError::Conflict(
+            "cannot retire the current signing key".into(),
+        ));
+    }
+    if records.iter().any(|r| r.kid == kid && r.active_at > now) {
+        return Err(AppError::Conflict(
+            "cannot retire a key that has not started signing".into(),
+        ));
+    }
+    if !retire_key(&state.db, kid).await? {
+        return Err(AppError::NotFound("verification key not found".into()));
+    }
+    sync(state).await
+}
+
+/// `sync` 选来签发的 kid：active_at 已到、未停用的最新一把，没有时为启动时配置的密钥
+fn signing_kid<'a>(
+    records: &'a [SigningKeyRecord],
+    configured: &'a str,
+    now: DateTime<Utc>,
+) -> &'a str {
+    records
+        .iter()
+        .rev()
+        .find(|r| r.active_at <= now && r.retire_at.is_none_or(|at| at > now))
+        .map_or(configured, |r| &r.kid)
+}
+
+struct LoadedKey {
+    key: Arc<JwtKey>,
+    active_at: DateTime<Utc>,
+    retire_at: Option<DateTime<Utc>>,
+}
+
+/// 按数据库重建本实例的密钥环：
+// 1. 启动时配置的密钥没有记录时照常使用，有记录时和其他密钥一样按记录停用
+// 2. 已经加载过的密钥直接复用，新的从 JWT_KEY_DIR 读取（读不到的记录日志后跳过）
+// 3. active_at 已到的最新一把用于签发，其余只用于验证
+pub async fn sync(state: &AppState) -> AppResult<()> {
+    let records = list_keys(&state.db).await?;
+    let configured = state.jwt_keys.configured();
+    let now = Utc::now();
+
+    let mut keys = Vec::new();
+    if !records.iter().any(|r| r.kid == configured.kid) {
+        keys.push(LoadedKey {
+            key: configured.clone(),
+            active_at: DateTime::UNIX_EPOCH,
+            retire_at: None,
+        });
+    }
+    for record in records {
+        if record.retire_at.is_some_and(|at| at <= now) {
+            continue;
+        }
+        let key = if record.kid == configured.kid {
+            configured.clone()
+        } else if let Some(key) = state.jwt_keys.find(Some(&record.kid)) {
+            key
+        } else {
+            match load(&record, state) {
+                Ok(key) => Arc::new(key),
+                Err(e) => {
+                    tracing::error!("cannot load jwt key {}: {:#}", record.kid, e);
+                    continue;
+                }
+            }
+        };
+        keys.push(LoadedKey {
+            key,
+            active_at: record.active_at,
+            retire_at: record.retire_at,
+        });
+    }
+    if keys.is_empty() {
+        // nothing usable left: keep signing rather than fail every request
+        keys.push(LoadedKey {
+            key: configured,
+            active_at: DateTime::UNIX_EPOCH,
+            retire_at: None,
+        });
+    }
+
+    let current = keys.iter().rposition(|k| k.active_at <= now).unwrap_or(0);
+    let current = keys.remove(current).key;
+    let verify_only = keys
+        .into_iter()
+        .map(|k| (k.key, k.retire_at.map(|at| at.timestamp())))
+        .collect();
+    state.jwt_keys.replace(current, verify_only);
+    Ok(())
+}
+
+fn load(record: &SigningKeyRecord, state: &AppState) -> anyhow::Result<JwtKey> {
+    let Some(file) = &record.file else {
+        anyhow::bail!("configured on another deployment");
+    };
+    let alg = Algorithm::from_str(&record.alg)?;
+    JwtKey::from_dir(alg, &state.jwt_key_dir, file, Some(record.kid.clone()))
+}
+
+/// 后台任务：每 key_sync_secs 秒同步一次
+pub async fn sync_forever(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(state.key_sync_secs.max(1)));
+    loop {
+        interval.tick().await;
+        if let Err(e) = sync(&state).await {
+            tracing::warn!("jwt key sync failed: {}", e);
+        }
+    }
+}
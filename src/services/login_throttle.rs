@@ -0,0 +1,104 @@
+use crate::{
+    error::{AppError, AppResult},
+    services::security_event::{SecurityEvent, emit},
+    state::AppState,
+    utils::redis_keys::{login_delay_key, login_failures_key, login_lock_key},
+};
+use deadpool_redis::{Connection, redis::AsyncCommands};
+
+/// 第 2 次失败起的等待时间：1s, 2s, 4s ... 最多 30s
+const BASE_DELAY_SECS: u64 = 1;
+const MAX_DELAY_SECS: u64 = 30;
+
+/// 失败计数的对象：账号（存在时按 user id，否则按提交的用户名）或来源 ip
+pub enum Subject<'a> {
+    User(i64),
+    Login(&'a str),
+    Ip(&'a str),
+}
+
+impl Subject<'_> {
+    fn key(&self) -> String {
+        match self {
+            Self::User(id) => format!("user:{}", id),
+            Self::Login(login) => format!("login:{}", login.trim().to_lowercase()),
+            Self::Ip(ip) => format!("ip:{}", ip),
+        }
+    }
+
+    fn max_failures(&self, state: &AppState) -> u64 {
+        match self {
+            Self::Ip(_) => state.login_ip_max_failures,
+            _ => state.login_max_failures,
+        }
+    }
+}
+
+/// 锁定或等待期间直接拒绝（429 + Retry-After），不校验密码
+pub async fn check_allowed(subjects: &[Subject<'_>], state: &AppState) -> AppResult<()> {
+    let mut conn = state.redis.get().await?;
+    for subject in subjects {
+        let key = subject.key();
+        for blocked in [login_lock_key(&key), login_delay_key(&key)] {
+            // -2: no such key; 0: expires within the second
+            let ttl: i64 = conn.ttl(&blocked).await?;
+            if ttl >= 0 {
+                return Err(AppError::TooManyRequests {
+                    retry_after: ttl.max(1) as u64,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 记录一次失败：
+// 1) INCR login_failures:{subject}，窗口 = lockout 时长（每次失败顺延）
+// 2) 达到阈值：写 login_lock:{subject}，TTL = lockout 时长，计数清零
+// 3) 否则从第 2 次失败起写 login_delay:{subject}，指数退避
+pub async fn record_failure(subjects: &[Subject<'_>], state: &AppState) -> AppResult<()> {
+    let mut conn = state.redis.get().await?;
+    for subject in subjects {
+        let key = subject.key();
+        let failures: u64 = conn.incr(login_failures_key(&key), 1).await?;
+        let _: () = conn
+            .expire(login_failures_key(&key), state.login_lockout_secs as usize)
+            .await?;
+
+        if failures >= subject.max_failures(state) {
+            lock(&mut conn, &key, state.login_lockout_secs).await?;
+            emit(&SecurityEvent::LoginLocked {
+                subject: &key,
+                failures,
+                lockout_secs: state.login_lockout_secs,
+            });
+        } else if failures >= 2 {
+            let delay = (BASE_DELAY_SECS << (failures - 2).min(16)).min(MAX_DELAY_SECS);
+            let _: () = conn
+                .set_ex(login_delay_key(&key), 1, delay as usize)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn lock(conn: &mut Connection, key: &str, secs: u64) -> AppResult<()> {
+    let _: () = conn.set_ex(login_lock_key(key), 1, secs as usize).await?;
+    let _: () = conn.del(login_failures_key(key)).await?;
+    let _: () = conn.del(login_delay_key(key)).await?;
+    Ok(())
+}
+
+/// 登录成功 / 管理员解锁：清除计数、等待和锁定
+pub async fn clear(subject: &Subject<'_>, state: &AppState) -> AppResult<()> {
+    let key = subject.key();
+    let mut conn = state.redis.get().await?;
+    for k in [
+        login_failures_key(&key),
+        login_delay_key(&key),
+        login_lock_key(&key),
+    ] {
+        let _: () = conn.del(k).await?;
+    }
+    Ok(())
+}
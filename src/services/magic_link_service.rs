@@ -0,0 +1,84 @@
+use crate::{
+    auth::extractor::ClientInfo,
+    error::{AppError, AppResult},
+    repositories::user_repo::get_user_by_id,
+    services::{
+        auth_service::{LoginOutcome, complete_login},
+        email_service::find_user_by_login,
+        mailer::Mail,
+    },
+    state::AppState,
+    utils::{
+        redis_keys::magic_link_key,
+        token::{hash_token, random_token},
+    },
+};
+use deadpool_redis::redis::{AsyncCommands, cmd};
+
+/// 登录链接有效期
+const MAGIC_LINK_TTL_SECS: usize = 60 * 15;
+
+/// 发送登录链接：
+// 1) 生成随机 token，redis 只存 sha256: magic_link:{hash} -> user_id  TTL 15 min
+// 2) 通过 mailer 发到已验证的邮箱
+// 用户不存在 / 没有已验证邮箱 / 已禁用 / 服务账号时同样返回成功，避免枚举用户
+pub async fn send_magic_link(login: &str, state: &AppState) -> AppResult<()> {
+    let Some(user) = find_user_by_login(login, state)
+        .await?
+        .filter(|u| !u.disabled && !u.service_account)
+    else {
+        return Ok(());
+    };
+    let Some(email) = user.verified_email() else {
+        return Ok(());
+    };
+
+    let token = random_token(32);
+    let mut conn = state.redis.get().await?;
+    let _: () = conn
+        .set_ex(
+            magic_link_key(&hash_token(&token)),
+            user.id,
+            MAGIC_LINK_TTL_SECS,
+        )
+        .await?;
+
+    state
+        .mailer
+        .send(Mail {
+            to: email.to_string(),
+            subject: "Your sign-in link".into(),
+            body: format!(
+                "Use the link below within {} minutes to sign in. It works only once.\n\n{}?token={}\n\nIf you did not request it, you can ignore this mail.\n",
+                MAGIC_LINK_TTL_SECS / 60,
+                state.magic_link_url,
+                token
+            ),
+        })
+        .await?;
+    Ok(())
+}
+
+/// 用登录链接登录：
+// 1) GETDEL 取出 token（只能用一次）
+// 2) 禁用的用户 403
+// 3) 之后与密码登录相同（complete_login：2FA、会话数量限制）
+pub async fn login_with_magic_link(
+    token: &str,
+    client: &ClientInfo,
+    state: &AppState,
+) -> AppResult<LoginOutcome> {
+    let invalid = || AppError::unauthorized("invalid or expired sign-in link");
+    let mut conn = state.redis.get().await?;
+    let user_id: Option<i64> = cmd("GETDEL")
+        .arg(magic_link_key(&hash_token(token)))
+        .query_async(&mut conn)
+        .await?;
+    let user = get_user_by_id(&state.db, user_id.ok_or_else(invalid)?)
+        .await?
+        .ok_or_else(invalid)?;
+    if user.disabled {
+        return Err(AppError::UserDisabled);
+    }
+    complete_login(&user, client, state).await
+}
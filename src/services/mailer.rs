@@ -0,0 +1,71 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use axum::async_trait;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// 发信接口：生产环境接 SMTP / 第三方服务，本地开发和测试用 LogMailer / FileMailer
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: Mail) -> Result<()>;
+}
+
+/// 只写日志，不真正发送
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, mail: Mail) -> Result<()> {
+        tracing::info!(target: "mailer", "to={} subject={}\n{}", mail.to, mail.subject, mail.body);
+        Ok(())
+    }
+}
+
+/// 每封邮件追加一行 JSON 到文件（测试从文件里取 token）
+pub struct FileMailer {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl FileMailer {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, mail: Mail) -> Result<()> {
+        let line = serde_json::to_string(&mail)?;
+        let _guard = self.lock.lock().unwrap();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", line)?;
+        Ok(())
+    }
+}
+
+/// MAILER_FILE 设置时写文件，否则写日志
+pub fn mailer_from_env() -> Arc<dyn Mailer> {
+    match std::env::var("MAILER_FILE") {
+        Ok(path) => Arc::new(FileMailer::new(path)),
+        Err(_) => Arc::new(LogMailer),
+    }
+}
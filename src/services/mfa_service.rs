@@ -0,0 +1,315 @@
+use crate::{
+    auth::extractor::ClientInfo,
+    error::{AppError, AppResult},
+    models::user::User,
+    repositories::{
+        mfa_repo::{
+            clear_totp, consume_recovery_code, replace_recovery_codes, set_totp_enabled,
+            set_totp_secret,
+        },
+        user_repo::get_user_by_id,
+        webauthn_repo::count_credentials,
+    },
+    services::login_throttle::{Subject, check_allowed, clear, record_failure},
+    state::AppState,
+    utils::{
+        redis_keys::{mfa_attempts_key, mfa_pending_key, totp_used_key},
+        token::{hash_token, random_token},
+    },
+};
+use chrono::Utc;
+use deadpool_redis::redis::{self, AsyncCommands, cmd};
+use rand::{Rng, RngCore, rngs::OsRng};
+use serde::{Deserialize, Serialize};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+const TOTP_ISSUER: &str = "rust_web_backend";
+const TOTP_STEP: u64 = 30;
+const RECOVERY_CODE_COUNT: usize = 10;
+/// mfa pending token 有效期
+const MFA_PENDING_TTL_SECS: usize = 60 * 5;
+/// 同一个 mfa pending token 最多尝试次数
+const MFA_MAX_ATTEMPTS: u32 = 5;
+
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+/// 登录第一步通过后、等待第二因素的状态（存 redis mfa:{sha256(token)}）
+#[derive(Serialize, Deserialize)]
+pub struct MfaChallenge {
+    pub user_id: i64,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub device: Option<String>,
+    /// 本次是第几次尝试（由 `claim_attempt` 填入，计数单独存放）
+    #[serde(skip)]
+    pub attempts: u32,
+}
+
+impl MfaChallenge {
+    pub fn client(&self) -> ClientInfo {
+        ClientInfo {
+            ip: self.ip.clone(),
+            user_agent: self.user_agent.clone(),
+            device: self.device.clone(),
+        }
+    }
+}
+
+/// 用户可用的第二因素：开启了 TOTP 或者注册了 passkey 的用户登录时都要第二步
+pub async fn second_factors(user: &User, state: &AppState) -> AppResult<Vec<&'static str>> {
+    let mut methods = Vec::new();
+    if user.totp_enabled {
+        methods.push("totp");
+    }
+    if count_credentials(&state.db, user.id).await? > 0 {
+        methods.push("webauthn");
+    }
+    Ok(methods)
+}
+
+fn build_totp(secret: &str, account: &str) -> AppResult<TOTP> {
+    let bytes = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| anyhow::anyhow!("invalid totp secret: {:?}", e))?;
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        TOTP_STEP,
+        bytes,
+        Some(TOTP_ISSUER.to_string()),
+        account.to_string(),
+    )
+    .map_err(|e| AppError::Internal(anyhow::anyhow!("invalid totp config: {:?}", e)))
+}
+
+/// 开始绑定：生成 secret（未确认前不生效）
+pub async fn enroll_totp(user_id: i64, state: &AppState) -> AppResult<TotpEnrollment> {
+    let user = get_user_by_id(&state.db, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("user not found".into()))?;
+    if user.totp_enabled {
+        return Err(AppError::Conflict(
+            "two-factor authentication already enabled".into(),
+        ));
+    }
+
+    let mut raw = vec![0u8; 20];
+    OsRng.fill_bytes(&mut raw);
+    let secret = Secret::Raw(raw).to_encoded().to_string();
+    let totp = build_totp(&secret, &user.username)?;
+    set_totp_secret(&state.db, user_id, &secret).await?;
+
+    Ok(TotpEnrollment {
+        otpauth_uri: totp.get_url(),
+        secret,
+    })
+}
+
+/// 用第一个验证码确认绑定，返回恢复码（只显示这一次）。
+/// 错误的验证码计入账号的失败次数；通过时不清零，验证的是刚生成的 secret，证明不了身份
+pub async fn confirm_totp(user_id: i64, code: &str, state: &AppState) -> AppResult<Vec<String>> {
+    let user = get_user_by_id(&state.db, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("user not found".into()))?;
+    if user.totp_enabled {
+        return Err(AppError::Conflict(
+            "two-factor authentication already enabled".into(),
+        ));
+    }
+    if user.totp_secret.is_none() {
+        return Err(AppError::Validation("no pending totp enrollment".into()));
+    }
+    let subject = Subject::User(user_id);
+    check_allowed(std::slice::from_ref(&subject), state).await?;
+    if !verify_totp(&user, code, state).await? {
+        record_failure(&[subject], state).await?;
+        return Err(AppError::Validation("invalid two-factor code".into()));
+    }
+
+    set_totp_enabled(&state.db, user_id, true).await?;
+    regenerate_recovery_codes(user_id, state).await
+}
+
+pub async fn regenerate_recovery_codes(user_id: i64, state: &AppState) -> AppResult<Vec<String>> {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+    let hashes: Vec<String> = codes
+        .iter()
+        .map(|c| hash_token(&normalize_recovery_code(c)))
+        .collect();
+    replace_recovery_codes(&state.db, user_id, &hashes).await?;
+    Ok(codes)
+}
+
+/// 关闭 2FA，需要一个有效的验证码或恢复码（错误计入账号的失败次数，被盗的会话不能猜验证码）
+pub async fn disable_totp(user_id: i64, code: &str, state: &AppState) -> AppResult<()> {
+    let user = get_user_by_id(&state.db, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("user not found".into()))?;
+    if !user.totp_enabled {
+        return Err(AppError::Validation(
+            "two-factor authentication is not enabled".into(),
+        ));
+    }
+    if !check_second_factor(&user, code, state).await? {
+        return Err(AppError::Validation("invalid two-factor code".into()));
+    }
+    clear_totp(&state.db, user_id).await
+}
+
+/// 6 位数字按 TOTP 校验，否则按恢复码校验
+pub async fn verify_second_factor(user: &User, code: &str, state: &AppState) -> AppResult<bool> {
+    let code = code.trim();
+    if code.len() == 6 && code.chars().all(|c| c.is_ascii_digit()) {
+        return verify_totp(user, code, state).await;
+    }
+    let normalized = normalize_recovery_code(code);
+    consume_recovery_code(&state.db, user.id, &hash_token(&normalized)).await
+}
+
+/// 已登录用户再次输入第二因素（重新认证、关闭 2FA）：与登录第二步一样计入账号的失败次数，
+/// 锁定或退避期间 429；通过时清零
+pub async fn check_second_factor(user: &User, code: &str, state: &AppState) -> AppResult<bool> {
+    let subject = Subject::User(user.id);
+    check_allowed(std::slice::from_ref(&subject), state).await?;
+    if !verify_second_factor(user, code, state).await? {
+        record_failure(&[subject], state).await?;
+        return Ok(false);
+    }
+    clear(&subject, state).await?;
+    Ok(true)
+}
+
+/// 允许前后一个时间窗口；同一窗口的验证码只能用一次
+async fn verify_totp(user: &User, code: &str, state: &AppState) -> AppResult<bool> {
+    let Some(secret) = user.totp_secret.as_deref() else {
+        return Ok(false);
+    };
+    let totp = build_totp(secret, &user.username)?;
+    let now = Utc::now().timestamp() as u64;
+    let matched = [now - TOTP_STEP, now, now + TOTP_STEP]
+        .into_iter()
+        .find(|t| totp.generate(*t) == code);
+    let Some(time) = matched else {
+        return Ok(false);
+    };
+
+    let mut conn = state.redis.get().await?;
+    let first_use: Option<String> = cmd("SET")
+        .arg(totp_used_key(user.id, time / TOTP_STEP))
+        .arg("1")
+        .arg("NX")
+        .arg("EX")
+        .arg(TOTP_STEP * 3)
+        .query_async(&mut conn)
+        .await?;
+    Ok(first_use.is_some())
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn generate_recovery_code() -> String {
+    const ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+    let mut rng = OsRng;
+    let mut code: String = (0..10)
+        .map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())] as char)
+        .collect();
+    code.insert(5, '-');
+    code
+}
+
+/// 登录第一步通过：返回 mfa pending token
+pub async fn start_challenge(
+    user_id: i64,
+    client: &ClientInfo,
+    state: &AppState,
+) -> AppResult<String> {
+    let token = random_token(32);
+    let challenge = MfaChallenge {
+        user_id,
+        ip: client.ip.clone(),
+        user_agent: client.user_agent.clone(),
+        device: client.device.clone(),
+        attempts: 0,
+    };
+    let mut conn = state.redis.get().await?;
+    let _: () = conn
+        .set_ex(
+            mfa_pending_key(&hash_token(&token)),
+            serde_json::to_string(&challenge)?,
+            MFA_PENDING_TTL_SECS,
+        )
+        .await?;
+    Ok(token)
+}
+
+pub async fn load_challenge(token: &str, state: &AppState) -> AppResult<MfaChallenge> {
+    let mut conn = state.redis.get().await?;
+    let raw: Option<String> = conn.get(mfa_pending_key(&hash_token(token))).await?;
+    raw.and_then(|s| serde_json::from_str(&s).ok())
+        .ok_or_else(|| AppError::unauthorized("mfa token expired"))
+}
+
+/// 校验第二因素之前占用一次尝试：
+// 1) 账号处于锁定或退避期：429（第二步的失败与密码失败共用账号的计数）
+// 2) INCR mfa:{hash}:attempts，并发的请求也不会超过次数；超过后 token 作废
+pub async fn claim_attempt(token: &str, state: &AppState) -> AppResult<MfaChallenge> {
+    let mut challenge = load_challenge(token, state).await?;
+    check_allowed(&[Subject::User(challenge.user_id)], state).await?;
+    let key = mfa_attempts_key(&hash_token(token));
+    let mut conn = state.redis.get().await?;
+    let (attempts,): (u32,) = redis::pipe()
+        .incr(&key, 1)
+        .expire(&key, MFA_PENDING_TTL_SECS)
+        .ignore()
+        .query_async(&mut conn)
+        .await?;
+    if attempts > MFA_MAX_ATTEMPTS {
+        discard_challenge(token, state).await?;
+        return Err(AppError::unauthorized("mfa token expired"));
+    }
+    challenge.attempts = attempts;
+    Ok(challenge)
+}
+
+/// 记录一次失败：计入账号的登录失败次数（重新输入密码不会清零）；次数用完后 token 作废
+pub async fn fail_challenge(
+    token: &str,
+    challenge: &MfaChallenge,
+    state: &AppState,
+) -> AppResult<()> {
+    record_failure(&[Subject::User(challenge.user_id)], state).await?;
+    if challenge.attempts >= MFA_MAX_ATTEMPTS {
+        discard_challenge(token, state).await?;
+    }
+    Ok(())
+}
+
+/// 第二步通过：token 作废，清除账号的失败计数
+pub async fn finish_challenge(
+    token: &str,
+    challenge: &MfaChallenge,
+    state: &AppState,
+) -> AppResult<()> {
+    discard_challenge(token, state).await?;
+    clear(&Subject::User(challenge.user_id), state).await
+}
+
+async fn discard_challenge(token: &str, state: &AppState) -> AppResult<()> {
+    let token_hash = hash_token(token);
+    let mut conn = state.redis.get().await?;
+    let _: () = conn
+        .del(&[mfa_pending_key(&token_hash), mfa_attempts_key(&token_hash)])
+        .await?;
+    Ok(())
+}
@@ -1 +1,20 @@
 pub mod auth_service;
+pub mod email_service;
+pub mod federation_service;
+pub mod key_service;
+pub mod login_throttle;
+pub mod magic_link_service;
+pub mod mailer;
+pub mod mfa_service;
+pub mod oauth_client_service;
+pub mod oauth_service;
+pub mod oidc_service;
+pub mod password_policy;
+pub mod password_service;
+pub mod pat_service;
+pub mod security_event;
+pub mod service_account_service;
+pub mod session_service;
+pub mod upstream_oidc;
+pub mod webauthn;
+pub mod webauthn_service;
@@ -0,0 +1,121 @@
+use crate::{
+    error::{AppError, AppResult, OAuthError},
+    models::oauth_client::OAuthClient,
+    repositories::oauth_client_repo::{create_app_client, find_client, touch_client},
+    services::oidc_service::OIDC_SCOPES,
+    state::AppState,
+    utils::token::{hash_token, random_token},
+};
+use url::Url;
+
+/// 应用客户端的 client_id 前缀
+pub const APP_CLIENT_ID_PREFIX: &str = "app_";
+
+/// redirect uri 必须是不带 fragment 的绝对地址；http 只允许本机（开发用），
+/// 移动端可以用自定义 scheme（如 `com.example.app:/callback`）
+pub fn validate_redirect_uri(uri: &str) -> Result<(), &'static str> {
+    let url = Url::parse(uri).map_err(|_| "must be an absolute uri")?;
+    if url.fragment().is_some() {
+        return Err("must not contain a fragment");
+    }
+    let local = matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"));
+    if url.scheme() == "http" && !local {
+        return Err("http is only allowed for localhost");
+    }
+    Ok(())
+}
+
+/// 注册应用客户端；confidential 客户端（有后端）返回一次 secret，公开客户端没有 secret。
+/// scopes 是它可以请求的权限，用户同意后 token 只保留这些权限
+pub async fn register_app_client(
+    name: &str,
+    redirect_uris: &[String],
+    scopes: &[String],
+    confidential: bool,
+    state: &AppState,
+) -> AppResult<(Option<String>, OAuthClient)> {
+    if redirect_uris.is_empty() {
+        return Err(AppError::invalid_field(
+            "redirect_uris",
+            "at least one redirect uri is required",
+        ));
+    }
+    for uri in redirect_uris {
+        validate_redirect_uri(uri).map_err(|message| {
+            AppError::invalid_field("redirect_uris", format!("{}: {}", uri, message))
+        })?;
+    }
+    let client_id = format!("{}{}", APP_CLIENT_ID_PREFIX, random_token(12));
+    let client_secret = confidential.then(|| random_token(32));
+    let client = create_app_client(
+        &state.db,
+        name,
+        &client_id,
+        client_secret.as_deref().map(hash_token).as_deref(),
+        redirect_uris,
+        scopes,
+    )
+    .await?;
+    Ok((client_secret, client))
+}
+
+/// /oauth/authorize 的客户端与回调地址：
+// redirect_uri 必须与注册的完全一致；只注册了一个时可以省略
+// 这里的错误不能重定向回客户端（回调地址不可信），由调用方直接展示
+pub async fn resolve_redirect(
+    client_id: &str,
+    redirect_uri: Option<&str>,
+    state: &AppState,
+) -> AppResult<(OAuthClient, String)> {
+    let client = find_client(&state.db, client_id)
+        .await?
+        .filter(|c| !c.redirect_uris.is_empty())
+        .ok_or_else(|| AppError::Validation("unknown client".into()))?;
+    let redirect_uri = match (redirect_uri, client.redirect_uris.as_slice()) {
+        (Some(uri), registered) if registered.iter().any(|r| r == uri) => uri.to_string(),
+        (None, [only]) => only.clone(),
+        _ => {
+            return Err(AppError::Validation(
+                "redirect_uri is not registered".into(),
+            ));
+        }
+    };
+    Ok((client, redirect_uri))
+}
+
+/// 授权请求的 scope：只能是 OIDC scope 或注册客户端时允许的权限，返回第一个不允许的
+pub fn check_scopes<'a>(
+    client: &OAuthClient,
+    scope: Option<&'a str>,
+) -> Result<Vec<String>, &'a str> {
+    let mut scopes: Vec<String> = Vec::new();
+    for scope in scope.unwrap_or_default().split_whitespace() {
+        if !OIDC_SCOPES.contains(&scope) && !client.scopes.iter().any(|s| s == scope) {
+            return Err(scope);
+        }
+        if !scopes.iter().any(|s| s == scope) {
+            scopes.push(scope.to_string());
+        }
+    }
+    Ok(scopes)
+}
+
+/// 兑换授权码的客户端：confidential 客户端必须带正确的 secret，公开客户端只带 client_id
+pub async fn authenticate_app_client(
+    client_id: &str,
+    client_secret: Option<&str>,
+    state: &AppState,
+) -> Result<OAuthClient, OAuthError> {
+    let invalid = || OAuthError::InvalidClient("invalid client credentials".into());
+    let client = find_client(&state.db, client_id)
+        .await?
+        .filter(|c| !c.redirect_uris.is_empty())
+        .ok_or_else(invalid)?;
+    match (&client.client_secret_hash, client_secret) {
+        (None, None) => {}
+        (Some(hash), Some(secret)) if *hash == hash_token(secret) => {}
+        _ => return Err(invalid()),
+    }
+    touch_client(&state.db, client.id).await?;
+    Ok(client)
+}
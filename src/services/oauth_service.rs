@@ -0,0 +1,413 @@
+use crate::{
+    auth::{
+        extractor::ClientInfo,
+        jwt::{Claims, TokenType, decode_claims, encode_claims, make_claims},
+    },
+    error::{AppError, AppResult, OAuthError},
+    models::{
+        authorization_code::AuthorizationCode,
+        oauth_client::OAuthClient,
+        session::{SessionGrant, SessionInfo},
+        user::User,
+    },
+    repositories::{permission_repo::get_permissions_for_user, user_repo::get_user_by_id},
+    services::{
+        auth_service::{refresh_tokens, start_client_session},
+        oidc_service::{issue_id_token, oidc_enabled, wants_id_token},
+        pat_service::{PAT_PREFIX, find_pat},
+        service_account_service::authenticate_client,
+        session_service::{load_session, revoke, store_access_session},
+    },
+    state::AppState,
+    utils::{
+        redis_keys::{blacklist_key, oauth_code_key, refresh_key},
+        token::{hash_token, random_token},
+    },
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::Utc;
+use deadpool_redis::{
+    Connection,
+    redis::{AsyncCommands, cmd},
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// 授权码的有效期
+const AUTHORIZATION_CODE_TTL_SECS: usize = 60;
+
+/// RFC 6749 §5.1 token 响应
+#[derive(Debug, Serialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_in: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    /// OIDC：scope 含 openid 时签发
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+    /// 空格分隔的权限
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+}
+
+impl TokenResponse {
+    fn bearer(access_token: String, state: &AppState) -> Self {
+        Self {
+            access_token,
+            token_type: "Bearer",
+            expires_in: state.session_ttl_secs,
+            refresh_token: None,
+            id_token: None,
+            scope: None,
+        }
+    }
+}
+
+/// client_credentials grant（RFC 6749 §4.4）：
+// 1) 校验 client_id / client_secret，客户端所属的服务账号未被禁用
+// 2) scope 可选，必须是服务账号拥有的权限；不填则为全部权限
+// 3) 签发 access token（不签发 refresh token），会话随 access token 过期
+pub async fn client_credentials(
+    client_id: &str,
+    client_secret: &str,
+    scope: Option<&str>,
+    client_info: &ClientInfo,
+    state: &AppState,
+) -> Result<TokenResponse, OAuthError> {
+    let (client, user) = authenticate_client(client_id, client_secret, state).await?;
+    let granted = get_permissions_for_user(&state.db, user.id).await?;
+    let scopes = match scope {
+        Some(scope) => {
+            let requested: Vec<String> = scope.split_whitespace().map(str::to_string).collect();
+            if let Some(unknown) = requested.iter().find(|s| !granted.contains(s)) {
+                return Err(OAuthError::InvalidScope(format!(
+                    "scope {} is not granted to this client",
+                    unknown
+                )));
+            }
+            Some(requested)
+        }
+        None => None,
+    };
+    let grant = SessionGrant {
+        client_id: Some(client.client_id),
+        scopes: scopes.clone(),
+    };
+    let access_token = issue_access_token(&user, grant, client_info, state).await?;
+    Ok(TokenResponse {
+        scope: Some(scopes.unwrap_or(granted).join(" ")),
+        ..TokenResponse::bearer(access_token, state)
+    })
+}
+
+/// 授权成功后签发授权码（只存 sha256，TTL 很短）
+pub async fn issue_authorization_code(
+    grant: &AuthorizationCode,
+    state: &AppState,
+) -> AppResult<String> {
+    let code = random_token(32);
+    let mut conn = state.redis.get().await?;
+    let _: () = conn
+        .set_ex(
+            oauth_code_key(&hash_token(&code)),
+            serde_json::to_string(grant)?,
+            AUTHORIZATION_CODE_TTL_SECS,
+        )
+        .await?;
+    Ok(code)
+}
+
+/// authorization_code grant（RFC 6749 §4.1.3 + PKCE RFC 7636）：
+// 1) 授权码只能用一次（GETDEL），必须属于该客户端；授权请求带了 redirect_uri 时兑换也必须带上同一个
+// 2) code_verifier 经 S256 后必须等于 code_challenge
+// 3) 签发与 /api/login 相同的 access / refresh token，会话绑定到该客户端、权限限制到授予的 scope；
+//    scope 含 openid 且启用了 OIDC 时加上 ID token
+pub async fn exchange_authorization_code(
+    client: &OAuthClient,
+    code: &str,
+    redirect_uri: Option<&str>,
+    code_verifier: Option<&str>,
+    client_info: &ClientInfo,
+    state: &AppState,
+) -> Result<TokenResponse, OAuthError> {
+    let invalid = |message: &str| OAuthError::InvalidGrant(message.to_string());
+    let mut conn = state.redis.get().await.map_err(AppError::from)?;
+    let raw: Option<String> = cmd("GETDEL")
+        .arg(oauth_code_key(&hash_token(code)))
+        .query_async(&mut conn)
+        .await
+        .map_err(AppError::from)?;
+    let grant: AuthorizationCode = raw
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .ok_or_else(|| invalid("invalid or expired authorization code"))?;
+    if grant.client_id != client.client_id {
+        return Err(invalid("authorization code was issued to another client"));
+    }
+    let redirect_ok = match redirect_uri {
+        Some(uri) => uri == grant.redirect_uri,
+        None => !grant.redirect_uri_requested,
+    };
+    if !redirect_ok {
+        return Err(invalid(
+            "redirect_uri does not match the authorization request",
+        ));
+    }
+    let verifier = code_verifier
+        .ok_or_else(|| OAuthError::InvalidRequest("code_verifier is required".into()))?;
+    if !is_valid_code_verifier(verifier) || pkce_challenge(verifier) != grant.code_challenge {
+        return Err(invalid("code_verifier does not match the code challenge"));
+    }
+
+    let user = get_user_by_id(&state.db, grant.user_id)
+        .await?
+        .filter(|u| !u.disabled)
+        .ok_or_else(|| invalid("user is no longer active"))?;
+    let scopes: Vec<String> = grant
+        .scope
+        .as_deref()
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(str::to_string)
+        .collect();
+    let session = SessionGrant {
+        client_id: Some(client.client_id.clone()),
+        scopes: Some(scopes.clone()),
+    };
+    let login = start_client_session(&user, client_info, session, state).await?;
+    let id_token = if wants_id_token(grant.scope.as_deref()) && oidc_enabled(state) {
+        Some(issue_id_token(
+            &user,
+            &client.client_id,
+            &scopes,
+            grant.nonce,
+            grant.auth_time,
+            state,
+        )?)
+    } else {
+        None
+    };
+    Ok(TokenResponse {
+        refresh_token: Some(login.refresh_token),
+        id_token,
+        scope: grant.scope,
+        ..TokenResponse::bearer(login.access_token, state)
+    })
+}
+
+/// refresh_token grant：与 /api/refresh 相同的轮换规则；refresh token 必须是签发给该客户端的（RFC 6749 §6）
+pub async fn refresh_grant(
+    client: &OAuthClient,
+    refresh_token: &str,
+    client_info: &ClientInfo,
+    state: &AppState,
+) -> Result<TokenResponse, OAuthError> {
+    let login = refresh_tokens(refresh_token, client_info, Some(&client.client_id), state)
+        .await
+        .map_err(|e| match e {
+            AppError::Unauthorized(message) | AppError::NotFound(message) => {
+                OAuthError::InvalidGrant(message)
+            }
+            e => e.into(),
+        })?;
+    Ok(TokenResponse {
+        refresh_token: Some(login.refresh_token),
+        ..TokenResponse::bearer(login.access_token, state)
+    })
+}
+
+/// RFC 7636 §4.1：43 到 128 个 unreserved 字符
+pub fn is_valid_code_verifier(verifier: &str) -> bool {
+    (43..=128).contains(&verifier.len())
+        && verifier
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-._~".contains(&b))
+}
+
+/// S256：BASE64URL(SHA256(code_verifier))
+pub fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// 签发只有 access token 的会话
+async fn issue_access_token(
+    user: &User,
+    grant: SessionGrant,
+    client_info: &ClientInfo,
+    state: &AppState,
+) -> AppResult<String> {
+    let family = Uuid::new_v4().to_string();
+    let claims = make_claims(TokenType::Access, user.id, state.session_ttl_secs, &family);
+    let access_token = encode_claims(&state.jwt_keys, &claims)?;
+    let now = Utc::now().timestamp();
+    let info = SessionInfo {
+        user_id: user.id,
+        family,
+        access_jti: claims.jti,
+        refresh_jti: String::new(),
+        created_at: now,
+        last_seen: now,
+        ip: client_info.ip.clone(),
+        user_agent: client_info.user_agent.clone(),
+        device: grant.client_id.as_ref().map(|id| format!("client:{}", id)),
+        scopes: grant.scopes,
+        client_id: grant.client_id,
+    };
+    let mut conn = state.redis.get().await?;
+    store_access_session(&mut conn, &info, state).await?;
+    Ok(access_token)
+}
+
+/// RFC 7662 introspection 响应；inactive 时只有 `active: false`
+#[derive(Debug, Default, Serialize)]
+pub struct Introspection {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// 空格分隔的生效权限
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+}
+
+impl Introspection {
+    fn active(user: &User, scopes: &[String], jti: String, iat: i64, exp: Option<i64>) -> Self {
+        Self {
+            active: true,
+            sub: Some(user.id.to_string()),
+            username: Some(user.username.clone()),
+            scope: Some(scopes.join(" ")),
+            token_type: None,
+            exp,
+            iat: Some(iat),
+            jti: Some(jti),
+        }
+    }
+}
+
+/// 按 token_type_hint 决定先尝试哪种 jwt
+fn jwt_types(hint: Option<&str>) -> [TokenType; 2] {
+    match hint {
+        Some("refresh_token") => [TokenType::Refresh, TokenType::Access],
+        _ => [TokenType::Access, TokenType::Refresh],
+    }
+}
+
+/// jwt 对应的存活会话：
+// access：blacklist:{jti} 不存在且 session:{jti} 属于该用户
+// refresh：blacklist:{jti} 不存在，refresh:{jti} 指向的会话仍以它为 refresh token
+async fn live_session(conn: &mut Connection, claims: &Claims) -> AppResult<Option<SessionInfo>> {
+    let black: Option<String> = conn.get(blacklist_key(&claims.jti)).await?;
+    if black.is_some() {
+        return Ok(None);
+    }
+    let access_jti = match claims.typ {
+        TokenType::Access => Some(claims.jti.clone()),
+        TokenType::Refresh => conn.get(refresh_key(&claims.jti)).await?,
+    };
+    let Some(access_jti) = access_jti else {
+        return Ok(None);
+    };
+    Ok(load_session(conn, &access_jti).await?.filter(|s| {
+        s.user_id == claims.sub
+            && match claims.typ {
+                TokenType::Access => true,
+                TokenType::Refresh => s.refresh_jti == claims.jti,
+            }
+    }))
+}
+
+/// token introspection（RFC 7662）：
+// 1) pat_ 开头：个人访问令牌未过期、未删除
+// 2) 否则 decode_claims，再检查黑名单与会话（吊销的 token 在 exp 前也是 inactive）
+// 3) 用户不存在或已禁用：inactive
+pub async fn introspect_token(
+    token: &str,
+    hint: Option<&str>,
+    state: &AppState,
+) -> AppResult<Introspection> {
+    if token.starts_with(PAT_PREFIX) {
+        return Ok(match find_pat(token, state).await? {
+            Some((record, user, scopes)) if !user.disabled => Introspection {
+                token_type: Some("Bearer"),
+                ..Introspection::active(
+                    &user,
+                    &scopes,
+                    format!("pat:{}", record.id),
+                    record.created_at.timestamp(),
+                    record.expires_at.map(|t| t.timestamp()),
+                )
+            },
+            _ => Introspection::default(),
+        });
+    }
+
+    let Some(claims) = jwt_types(hint)
+        .into_iter()
+        .find_map(|typ| decode_claims(&state.jwt_keys, token, typ).ok())
+    else {
+        return Ok(Introspection::default());
+    };
+    let mut conn = state.redis.get().await?;
+    let Some(session) = live_session(&mut conn, &claims).await? else {
+        return Ok(Introspection::default());
+    };
+    let Some(user) = get_user_by_id(&state.db, claims.sub)
+        .await?
+        .filter(|u| !u.disabled)
+    else {
+        return Ok(Introspection::default());
+    };
+    let mut scopes = get_permissions_for_user(&state.db, user.id).await?;
+    if let Some(limited) = &session.scopes {
+        scopes.retain(|p| limited.contains(p));
+    }
+    Ok(Introspection {
+        token_type: (claims.typ == TokenType::Access).then_some("Bearer"),
+        ..Introspection::active(&user, &scopes, claims.jti, claims.iat, Some(claims.exp))
+    })
+}
+
+/// token revocation（RFC 7009）：
+// 1) 只能吊销签发给请求方客户端的 token（§2.1）；个人访问令牌不属于任何客户端，由用户自己删除
+// 2) access 或 refresh token 都吊销整个会话（两者一起加入黑名单）
+// 3) 无效 / 已吊销的 token 不报错
+pub async fn revoke_token(
+    token: &str,
+    hint: Option<&str>,
+    client_id: &str,
+    state: &AppState,
+) -> Result<(), OAuthError> {
+    let not_yours = || OAuthError::UnauthorizedClient("token was not issued to this client".into());
+    if token.starts_with(PAT_PREFIX) {
+        return match find_pat(token, state).await? {
+            Some(_) => Err(not_yours()),
+            None => Ok(()),
+        };
+    }
+    let Some(claims) = jwt_types(hint)
+        .into_iter()
+        .find_map(|typ| decode_claims(&state.jwt_keys, token, typ).ok())
+    else {
+        return Ok(());
+    };
+    let mut conn = state.redis.get().await.map_err(AppError::from)?;
+    if let Some(session) = live_session(&mut conn, &claims).await? {
+        if session.client_id.as_deref() != Some(client_id) {
+            return Err(not_yours());
+        }
+        revoke(&mut conn, &session, state).await?;
+    }
+    Ok(())
+}
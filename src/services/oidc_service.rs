@@ -0,0 +1,83 @@
+use crate::{
+    auth::{
+        jwt::{IdTokenClaims, encode_claims},
+        keys::alg_name,
+    },
+    error::AppResult,
+    models::user::{User, UserClaims, UserResponse},
+    state::AppState,
+};
+use chrono::Utc;
+use serde_json::{Value, json};
+
+/// 只有签名密钥是非对称的才提供 OIDC：依赖方只能用 JWKS 里的公钥验证 ID token，
+/// 共享密钥（HS256）既不能发布，也不能交给每个客户端
+pub fn oidc_enabled(state: &AppState) -> bool {
+    state.jwt_keys.current().jwk().is_some()
+}
+
+/// OIDC 的 scope，任何应用客户端都可以请求
+pub const OIDC_SCOPES: [&str; 3] = ["openid", "profile", "email"];
+
+/// 请求了 openid scope 才签发 ID token
+pub fn wants_id_token(scope: Option<&str>) -> bool {
+    scope.is_some_and(|s| s.split_whitespace().any(|s| s == "openid"))
+}
+
+/// 用户的标准 claims；email / email_verified 只在授予了 email scope 时返回（scopes 为 None 是登录会话，不受限制）
+pub fn user_claims(user: &User, scopes: Option<&[String]>) -> UserClaims {
+    let mut claims: UserClaims = UserResponse::from(user).into();
+    if scopes.is_some_and(|scopes| !scopes.iter().any(|s| s == "email")) {
+        claims.email = None;
+        claims.email_verified = None;
+    }
+    claims
+}
+
+/// ID token：aud 为 client_id，nonce / auth_time 来自授权请求，和 access token 用同一把密钥签名
+pub fn issue_id_token(
+    user: &User,
+    client_id: &str,
+    scopes: &[String],
+    nonce: Option<String>,
+    auth_time: i64,
+    state: &AppState,
+) -> AppResult<String> {
+    let now = Utc::now().timestamp();
+    let claims = IdTokenClaims {
+        iss: state.oidc_issuer.clone(),
+        aud: client_id.to_string(),
+        exp: now + state.session_ttl_secs,
+        iat: now,
+        auth_time,
+        nonce,
+        user: user_claims(user, Some(scopes)),
+    };
+    Ok(encode_claims(&state.jwt_keys, &claims)?)
+}
+
+/// `/.well-known/openid-configuration`
+pub fn discovery_document(state: &AppState) -> Value {
+    let issuer = &state.oidc_issuer;
+    let alg = alg_name(state.jwt_keys.current().alg);
+    json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{}/oauth/authorize", issuer),
+        "token_endpoint": format!("{}/oauth/token", issuer),
+        "userinfo_endpoint": format!("{}/userinfo", issuer),
+        "jwks_uri": format!("{}/.well-known/jwks.json", issuer),
+        "introspection_endpoint": format!("{}/oauth/introspect", issuer),
+        "revocation_endpoint": format!("{}/oauth/revoke", issuer),
+        "response_types_supported": ["code"],
+        "grant_types_supported": ["authorization_code", "refresh_token", "client_credentials"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": [alg],
+        "scopes_supported": OIDC_SCOPES,
+        "token_endpoint_auth_methods_supported": ["client_secret_basic", "client_secret_post", "none"],
+        "code_challenge_methods_supported": ["S256"],
+        "claims_supported": [
+            "iss", "sub", "aud", "exp", "iat", "auth_time", "nonce",
+            "preferred_username", "email", "email_verified"
+        ],
+    })
+}
@@ -0,0 +1,184 @@
+use std::{
+    collections::HashSet,
+    env, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use sha1::{Digest, Sha1};
+
+use crate::error::AppResult;
+use crate::utils::validation::FieldErrors;
+
+/// 密码策略：register / 重置密码 / 修改密码共用。
+/// 默认值比较宽松，生产环境通过环境变量收紧（见 `from_env`）
+#[derive(Clone)]
+pub struct PasswordPolicy {
+    /// 最少字符数
+    pub min_length: usize,
+    /// 最多字节数，限制哈希开销
+    pub max_length: usize,
+    /// 小写 / 大写 / 数字 / 其他 四类中至少包含几类
+    pub min_char_classes: usize,
+    /// 不能包含用户名（不区分大小写）
+    pub disallow_username: bool,
+    /// zxcvbn 评分下限 0..=4
+    pub min_strength: u8,
+    pub breached: Option<Arc<BreachedPasswords>>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 6,
+            max_length: 256,
+            min_char_classes: 1,
+            disallow_username: true,
+            min_strength: 0,
+            breached: None,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// 读取环境变量（未设置的使用默认值）：
+    // PASSWORD_MIN_LENGTH / PASSWORD_MAX_LENGTH / PASSWORD_MIN_CHAR_CLASSES / PASSWORD_MIN_STRENGTH
+    // PASSWORD_BREACHED_PATH: 泄露密码列表（见 `BreachedPasswords`）
+    pub fn from_env() -> anyhow::Result<Self> {
+        fn number<T: std::str::FromStr>(name: &str, default: T) -> anyhow::Result<T> {
+            match env::var(name) {
+                Ok(v) => v.parse().ok().with_context(|| format!("invalid {}", name)),
+                Err(_) => Ok(default),
+            }
+        }
+        let default = Self::default();
+        let breached = match env::var("PASSWORD_BREACHED_PATH") {
+            Ok(path) => Some(Arc::new(BreachedPasswords::open(path)?)),
+            Err(_) => None,
+        };
+        Ok(Self {
+            min_length: number("PASSWORD_MIN_LENGTH", default.min_length)?,
+            max_length: number("PASSWORD_MAX_LENGTH", default.max_length)?,
+            min_char_classes: number("PASSWORD_MIN_CHAR_CLASSES", default.min_char_classes)?,
+            disallow_username: default.disallow_username,
+            min_strength: number("PASSWORD_MIN_STRENGTH", default.min_strength)?,
+            breached,
+        })
+    }
+
+    /// 检查新密码，所有不满足的规则都记在 field 下一起返回（422）；
+    /// user_inputs: 用户名、邮箱等，不能出现在密码中，也会降低强度评分
+    pub async fn check(&self, field: &str, password: &str, user_inputs: &[&str]) -> AppResult<()> {
+        let mut problems: Vec<String> = Vec::new();
+
+        let length = password.chars().count();
+        if length < self.min_length {
+            problems.push(format!("must be at least {} characters", self.min_length));
+        }
+        if password.len() > self.max_length {
+            problems.push(format!("must be at most {} bytes", self.max_length));
+        }
+
+        let classes = [
+            password.chars().any(|c| c.is_lowercase()),
+            password.chars().any(|c| c.is_uppercase()),
+            password.chars().any(|c| c.is_ascii_digit()),
+            password.chars().any(|c| !c.is_alphanumeric()),
+        ]
+        .into_iter()
+        .filter(|present| *present)
+        .count();
+        if classes < self.min_char_classes {
+            problems.push(format!(
+                "must mix at least {} of lowercase, uppercase, digits and symbols",
+                self.min_char_classes
+            ));
+        }
+
+        if self.disallow_username {
+            let lowered = password.to_lowercase();
+            let contains_input = user_inputs
+                .iter()
+                .filter(|input| input.chars().count() >= 3)
+                .any(|input| lowered.contains(&input.to_lowercase()));
+            if contains_input {
+                problems.push("must not contain your username or email".into());
+            }
+        }
+
+        // zxcvbn is comparatively slow; only score passwords that passed the cheap rules
+        if problems.is_empty() && self.min_strength > 0 {
+            let score = u8::from(zxcvbn::zxcvbn(password, user_inputs).score());
+            if score < self.min_strength {
+                problems.push(format!(
+                    "is too easy to guess (strength {} of 4, need {})",
+                    score, self.min_strength
+                ));
+            }
+        }
+
+        if problems.is_empty()
+            && let Some(breached) = &self.breached
+            && breached.contains(password).await?
+        {
+            problems.push("appears in a list of breached passwords".into());
+        }
+
+        let mut errors = FieldErrors::default();
+        for problem in problems {
+            errors.add(field, problem);
+        }
+        errors.into_result()
+    }
+}
+
+/// 离线的泄露密码列表，使用 HIBP k-anonymity 格式（大写 SHA-1）：
+// 目录：每个 5 位前缀一个文件 `{dir}/{PREFIX}`（或 `{PREFIX}.txt`），每行 `SUFFIX:COUNT`，
+//       与 `https://api.pwnedpasswords.com/range/{PREFIX}` 的响应相同，查询时只读一个文件
+// 文件：每行完整的 `HASH:COUNT`，启动时全部载入内存，适合较小的列表
+pub enum BreachedPasswords {
+    RangeDir(PathBuf),
+    Hashes(HashSet<String>),
+}
+
+impl BreachedPasswords {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            return Ok(Self::RangeDir(path.to_path_buf()));
+        }
+        let content = fs::read_to_string(path).with_context(|| format!("{}", path.display()))?;
+        let hashes = content
+            .lines()
+            .filter_map(|line| line.split(':').next())
+            .map(|hash| hash.trim().to_ascii_uppercase())
+            .filter(|hash| hash.len() == 40)
+            .collect();
+        Ok(Self::Hashes(hashes))
+    }
+
+    /// 目录格式每次查询读一个文件（异步，不阻塞运行时）
+    pub async fn contains(&self, password: &str) -> AppResult<bool> {
+        let hash = hex::encode_upper(Sha1::digest(password.as_bytes()));
+        match self {
+            Self::Hashes(hashes) => Ok(hashes.contains(&hash)),
+            Self::RangeDir(dir) => {
+                let (prefix, suffix) = hash.split_at(5);
+                for file in [dir.join(prefix), dir.join(format!("{}.txt", prefix))] {
+                    let content = match tokio::fs::read_to_string(&file).await {
+                        Ok(content) => content,
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                        Err(e) => Err(e).with_context(|| format!("{}", file.display()))?,
+                    };
+                    return Ok(content.lines().any(|line| {
+                        line.split(':')
+                            .next()
+                            .is_some_and(|s| s.trim().eq_ignore_ascii_case(suffix))
+                    }));
+                }
+                Ok(false)
+            }
+        }
+    }
+}
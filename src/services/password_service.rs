@@ -0,0 +1,143 @@
+use crate::{
+    error::{AppError, AppResult},
+    models::user::User,
+    repositories::user_repo::{get_user_by_id, update_password_hash},
+    services::{
+        auth_service::{check_current_password, logout_all},
+        email_service::find_user_by_login,
+        mailer::Mail,
+        session_service::revoke_other_sessions,
+    },
+    state::AppState,
+    utils::{
+        redis_keys::password_reset_key,
+        token::{hash_token, random_token},
+    },
+};
+use deadpool_redis::redis::{AsyncCommands, cmd};
+
+/// reset token 有效期
+const RESET_TOKEN_TTL_SECS: usize = 60 * 30;
+
+/// 忘记密码：
+// 1) 生成随机 token，redis 只存 sha256: password_reset:{hash} -> user_id  TTL 30 min
+// 2) 通过 mailer 发到已验证的邮箱
+// 用户不存在 / 没有已验证邮箱时同样返回成功，避免枚举用户
+pub async fn forgot_password(login: &str, state: &AppState) -> AppResult<()> {
+    let Some(user) = find_user_by_login(login, state).await? else {
+        return Ok(());
+    };
+    let Some(email) = user.verified_email() else {
+        return Ok(());
+    };
+    if user.disabled {
+        return Ok(());
+    }
+
+    let token = random_token(32);
+    let mut conn = state.redis.get().await?;
+    let _: () = conn
+        .set_ex(
+            password_reset_key(&hash_token(&token)),
+            user.id,
+            RESET_TOKEN_TTL_SECS,
+        )
+        .await?;
+
+    state
+        .mailer
+        .send(Mail {
+            to: email.to_string(),
+            subject: "Reset your password".into(),
+            body: format!(
+                "Use the link below within {} minutes to reset your password:\n\n{}?token={}\n",
+                RESET_TOKEN_TTL_SECS / 60,
+                state.password_reset_url,
+                token
+            ),
+        })
+        .await?;
+    Ok(())
+}
+
+/// 重置密码：
+// 1) GETDEL 取出 token（只能用一次，新密码不满足策略时也已作废，需要重新申请）
+// 2) 禁用的用户 403；按密码策略检查新密码
+// 3) 写入新的 password_hash
+// 4) 吊销该用户的所有会话（与 logout_all 相同）
+pub async fn reset_password(token: &str, new_password: &str, state: &AppState) -> AppResult<()> {
+    let invalid = || AppError::unauthorized("invalid or expired reset token");
+
+    let mut conn = state.redis.get().await?;
+    let user_id: Option<i64> = cmd("GETDEL")
+        .arg(password_reset_key(&hash_token(token)))
+        .query_async(&mut conn)
+        .await?;
+    let user = get_user_by_id(&state.db, user_id.ok_or_else(invalid)?)
+        .await?
+        .ok_or_else(invalid)?;
+    if user.disabled {
+        return Err(AppError::UserDisabled);
+    }
+    state
+        .password_policy
+        .check("new_password", new_password, &user_inputs(&user))
+        .await?;
+
+    update_password_hash(
+        &state.db,
+        user.id,
+        &state.password_hasher.hash(new_password)?,
+    )
+    .await?;
+    logout_all(user.id, state).await
+}
+
+/// 密码中不能出现的用户信息
+fn user_inputs(user: &User) -> Vec<&str> {
+    std::iter::once(user.username.as_str())
+        .chain(user.email.as_deref())
+        .collect()
+}
+
+/// 修改密码：
+// 1) 校验当前密码（计入账号的失败次数，锁定期间 429）
+// 2) 新密码符合策略且与旧密码不同
+// 3) 写入新的 password_hash
+// 4) 可选：吊销除当前会话外的所有会话，返回吊销数量
+pub async fn change_password(
+    user_id: i64,
+    current_jti: &str,
+    current_password: &str,
+    new_password: &str,
+    revoke_others: bool,
+    state: &AppState,
+) -> AppResult<usize> {
+    let user = get_user_by_id(&state.db, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("user not found".into()))?;
+    if !check_current_password(&user, current_password, state).await? {
+        return Err(AppError::forbidden("current password is incorrect"));
+    }
+    state
+        .password_policy
+        .check("new_password", new_password, &user_inputs(&user))
+        .await?;
+    if current_password == new_password {
+        return Err(AppError::invalid_field(
+            "new_password",
+            "must differ from the current one",
+        ));
+    }
+
+    update_password_hash(
+        &state.db,
+        user_id,
+        &state.password_hasher.hash(new_password)?,
+    )
+    .await?;
+    if !revoke_others {
+        return Ok(0);
+    }
+    revoke_other_sessions(user_id, current_jti, state).await
+}
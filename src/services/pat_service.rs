@@ -0,0 +1,117 @@
+use crate::{
+    auth::extractor::AuthUser,
+    error::{AppError, AppResult},
+    models::{personal_access_token::PersonalAccessToken, user::User},
+    repositories::{
+        pat_repo::{create_token, delete_token, find_active_token, list_tokens, touch_token},
+        permission_repo::get_permissions_for_user,
+        user_repo::get_user_by_id,
+    },
+    state::AppState,
+    utils::token::{hash_token, random_token},
+};
+use chrono::{Duration, Utc};
+
+/// `Authorization: Bearer pat_...`
+pub const PAT_PREFIX: &str = "pat_";
+
+/// 指定有效期时的上限（天）；不指定的 token 不过期
+pub const PAT_MAX_EXPIRES_DAYS: i64 = 365;
+
+/// 创建 token：
+// 1) 只能从登录会话创建，受限的 token（PAT、带 scope 的 OAuth token）不能再派生新 token
+// 2) 服务账号用客户端凭证访问，不签发 PAT
+// 3) scopes 必须是用户当前拥有的权限；token 只在这里返回一次
+pub async fn create_pat(
+    user: &AuthUser,
+    name: &str,
+    scopes: &[String],
+    expires_in_days: Option<i64>,
+    state: &AppState,
+) -> AppResult<(String, PersonalAccessToken)> {
+    if !user.is_login_session() {
+        return Err(AppError::forbidden(
+            "tokens can only be created from a login session",
+        ));
+    }
+    let account = get_user_by_id(&state.db, user.id).await?;
+    if account.is_none_or(|u| u.service_account) {
+        return Err(AppError::forbidden(
+            "service accounts cannot create personal access tokens",
+        ));
+    }
+    if let Some(scope) = scopes.iter().find(|s| !user.has_permission(s)) {
+        return Err(AppError::forbidden(format!(
+            "cannot grant scope {} you don't have",
+            scope
+        )));
+    }
+    let token = format!("{}{}", PAT_PREFIX, random_token(32));
+    let expires_at = expires_in_days.map(|days| Utc::now() + Duration::days(days));
+    let record = create_token(
+        &state.db,
+        user.id,
+        name,
+        &hash_token(&token),
+        scopes,
+        expires_at,
+    )
+    .await?;
+    Ok((token, record))
+}
+
+pub async fn list_pats(user_id: i64, state: &AppState) -> AppResult<Vec<PersonalAccessToken>> {
+    list_tokens(&state.db, user_id).await
+}
+
+pub async fn revoke_pat(user_id: i64, id: i64, state: &AppState) -> AppResult<()> {
+    if !delete_token(&state.db, user_id, id).await? {
+        return Err(AppError::NotFound("token not found".into()));
+    }
+    Ok(())
+}
+
+/// 未过期的 token、其用户与生效的权限（token scopes ∩ 用户当前权限）
+pub async fn find_pat(
+    token: &str,
+    state: &AppState,
+) -> AppResult<Option<(PersonalAccessToken, User, Vec<String>)>> {
+    let Some(record) = find_active_token(&state.db, &hash_token(token)).await? else {
+        return Ok(None);
+    };
+    let Some(user) = get_user_by_id(&state.db, record.user_id).await? else {
+        return Ok(None);
+    };
+    let granted = get_permissions_for_user(&state.db, user.id).await?;
+    let permissions = record
+        .scopes
+        .iter()
+        .filter(|scope| granted.contains(scope))
+        .cloned()
+        .collect();
+    Ok(Some((record, user, permissions)))
+}
+
+/// 校验 pat_ token：
+// 1) 按 sha256 查找未过期的 token，用户未被禁用
+// 2) 权限 = token scopes ∩ 用户当前权限（用户被降权后 token 随之失效）；不带角色
+// 3) 更新 last_used_at
+// 能调用哪些路由由 `AuthUser` 按路由要求的权限检查
+pub async fn authenticate_pat(token: &str, state: &AppState) -> AppResult<AuthUser> {
+    let (record, user, permissions) = find_pat(token, state)
+        .await?
+        .ok_or_else(|| AppError::unauthorized("Invalid token"))?;
+    if user.disabled {
+        return Err(AppError::UserDisabled);
+    }
+    touch_token(&state.db, record.id).await?;
+
+    Ok(AuthUser {
+        id: user.id,
+        jti: format!("pat:{}", record.id),
+        roles: Vec::new(),
+        permissions,
+        pat_id: Some(record.id),
+        scopes: Some(record.scopes),
+    })
+}
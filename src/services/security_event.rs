@@ -0,0 +1,32 @@
+use serde::Serialize;
+
+/// 安全事件：写入 `security` target 的日志，供告警 / 审计收集
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SecurityEvent<'a> {
+    /// 已轮换的 refresh token 被再次使用，整个 session family 已吊销
+    RefreshTokenReuse {
+        user_id: i64,
+        family: &'a str,
+        jti: &'a str,
+        ip: Option<&'a str>,
+        user_agent: Option<&'a str>,
+    },
+    /// 连续登录失败达到阈值，账号或 ip 被临时锁定
+    LoginLocked {
+        subject: &'a str,
+        failures: u64,
+        lockout_secs: u64,
+    },
+    /// passkey 的签名计数没有递增，认证器可能被克隆
+    PasskeyCounterRegression {
+        user_id: i64,
+        credential_id: &'a str,
+        stored: i64,
+    },
+}
+
+pub fn emit(event: &SecurityEvent) {
+    let payload = serde_json::to_string(event).unwrap_or_default();
+    tracing::warn!(target: "security", "{}", payload);
+}
@@ -0,0 +1,165 @@
+use crate::{
+    auth::extractor::AuthUser,
+    error::{AppError, AppResult},
+    models::{oauth_client::OAuthClient, user::User},
+    repositories::{
+        oauth_client_repo::{
+            create_client, delete_client, find_client, list_clients, touch_client,
+        },
+        permission_repo::get_permissions_for_roles,
+        role_repo::find_roles_by_name,
+        user_repo::{create_service_account, get_user_by_id},
+    },
+    state::AppState,
+    utils::{
+        token::{hash_token, random_token},
+        validation::normalize_username,
+    },
+};
+
+/// client_id 前缀，便于在日志 / 配置中识别
+pub const CLIENT_ID_PREFIX: &str = "svc_";
+
+/// 新建的服务账号及其第一个客户端凭证
+pub struct CreatedServiceAccount {
+    pub id: i64,
+    pub username: String,
+    pub roles: Vec<String>,
+    pub client: OAuthClient,
+    pub client_secret: String,
+}
+
+/// 创建服务账号：
+// 1) 名称按用户名规则规范化，与普通用户共用命名空间
+// 2) 角色必须都已存在（权限来自 user_roles，与普通用户一致）
+// 3) 角色的权限必须都是操作者自己拥有的，不能借服务账号提权
+// 4) 账号、角色、客户端凭证一起创建，secret 只在这里返回一次
+pub async fn create_account(
+    admin: &AuthUser,
+    name: &str,
+    roles: &[String],
+    state: &AppState,
+) -> AppResult<CreatedServiceAccount> {
+    let username =
+        normalize_username(name).map_err(|message| AppError::invalid_field("name", message))?;
+    let found = find_roles_by_name(&state.db, roles).await?;
+    if let Some(missing) = roles
+        .iter()
+        .find(|role| !found.iter().any(|(_, name)| name == *role))
+    {
+        return Err(AppError::invalid_field(
+            "roles",
+            format!("unknown role {}", missing),
+        ));
+    }
+
+    let role_ids: Vec<i64> = found.iter().map(|(id, _)| *id).collect();
+    let permissions = get_permissions_for_roles(&state.db, &role_ids).await?;
+    if let Some(permission) = permissions.iter().find(|p| !admin.has_permission(p)) {
+        return Err(AppError::forbidden(format!(
+            "cannot grant permission {} you don't have",
+            permission
+        )));
+    }
+
+    let (client_id, client_secret) = client_credentials();
+    let (id, client) = create_service_account(
+        &state.db,
+        &username,
+        &role_ids,
+        &client_id,
+        &hash_token(&client_secret),
+    )
+    .await?;
+    Ok(CreatedServiceAccount {
+        id,
+        username,
+        roles: found.into_iter().map(|(_, name)| name).collect(),
+        client,
+        client_secret,
+    })
+}
+
+/// 只对服务账号操作，普通用户视为不存在
+async fn service_account(id: i64, state: &AppState) -> AppResult<User> {
+    get_user_by_id(&state.db, id)
+        .await?
+        .filter(|u| u.service_account)
+        .ok_or_else(|| AppError::NotFound("service account not found".into()))
+}
+
+/// 新的 (client_id, client_secret)
+fn client_credentials() -> (String, String) {
+    (
+        format!("{}{}", CLIENT_ID_PREFIX, random_token(12)),
+        random_token(32),
+    )
+}
+
+async fn issue_client(
+    user_id: i64,
+    name: &str,
+    state: &AppState,
+) -> AppResult<(String, OAuthClient)> {
+    let (client_id, client_secret) = client_credentials();
+    let client = create_client(
+        &state.db,
+        user_id,
+        name,
+        &client_id,
+        &hash_token(&client_secret),
+    )
+    .await?;
+    Ok((client_secret, client))
+}
+
+/// 为服务账号再签发一个客户端凭证（用于轮换：先发新的，再删旧的）
+pub async fn add_client(
+    account_id: i64,
+    name: &str,
+    state: &AppState,
+) -> AppResult<(String, OAuthClient)> {
+    let account = service_account(account_id, state).await?;
+    issue_client(account.id, name, state).await
+}
+
+pub async fn list_account_clients(
+    account_id: i64,
+    state: &AppState,
+) -> AppResult<Vec<OAuthClient>> {
+    let account = service_account(account_id, state).await?;
+    list_clients(&state.db, account.id).await
+}
+
+pub async fn remove_client(account_id: i64, client_id: &str, state: &AppState) -> AppResult<()> {
+    if !delete_client(&state.db, account_id, client_id).await? {
+        return Err(AppError::NotFound("client not found".into()));
+    }
+    Ok(())
+}
+
+/// 校验 client_id / client_secret，返回客户端与其服务账号
+// 客户端不存在与 secret 错误返回同样的错误
+pub async fn authenticate_client(
+    client_id: &str,
+    client_secret: &str,
+    state: &AppState,
+) -> AppResult<(OAuthClient, User)> {
+    let invalid = || AppError::unauthorized("invalid client credentials");
+    let client = find_client(&state.db, client_id)
+        .await?
+        .filter(|c| c.client_secret_hash.as_deref() == Some(hash_token(client_secret).as_str()))
+        .ok_or_else(invalid)?;
+    let Some(user_id) = client.user_id else {
+        return Err(invalid());
+    };
+    let user = get_user_by_id(&state.db, user_id)
+        .await?
+        .filter(|u| u.service_account)
+        .ok_or_else(invalid)?;
+    if user.disabled {
+        return Err(AppError::UserDisabled);
+    }
+    touch_client(&state.db, client.id).await?;
+    Ok((client, user))
+}
@@ -0,0 +1,224 @@
+use crate::{
+    error::{AppError, AppResult},
+    models::session::{SessionInfo, SessionResponse},
+    state::AppState,
+    utils::redis_keys::{blacklist_key, family_key, refresh_key, session_key, user_sessions_key},
+};
+use chrono::Utc;
+use deadpool_redis::{
+    Connection,
+    redis::{AsyncCommands, cmd},
+};
+
+/// last_seen 最多每分钟写一次，避免每个请求都写 redis
+const TOUCH_INTERVAL_SECS: i64 = 60;
+
+pub async fn load_session(conn: &mut Connection, jti: &str) -> AppResult<Option<SessionInfo>> {
+    let raw: Option<String> = conn.get(session_key(jti)).await?;
+    Ok(raw.and_then(|s| serde_json::from_str(&s).ok()))
+}
+
+/// 保存会话：session:{access_jti} -> SessionInfo, refresh:{refresh_jti} -> access_jti,
+/// family:{family} -> access_jti
+// session key 与 refresh 同寿命，access token 本身的过期由 jwt exp 保证
+pub async fn store_session(
+    conn: &mut Connection,
+    info: &SessionInfo,
+    state: &AppState,
+) -> AppResult<()> {
+    let _: () = conn
+        .set_ex(
+            session_key(&info.access_jti),
+            serde_json::to_string(info)?,
+            state.refresh_ttl_secs as usize,
+        )
+        .await?;
+    let _: () = conn
+        .set_ex(
+            refresh_key(&info.refresh_jti),
+            &info.access_jti,
+            state.refresh_ttl_secs as usize,
+        )
+        .await?;
+    let _: () = conn
+        .set_ex(
+            family_key(&info.family),
+            &info.access_jti,
+            state.refresh_ttl_secs as usize,
+        )
+        .await?;
+
+    let user_s_key = user_sessions_key(info.user_id);
+    let _: () = conn.sadd(&user_s_key, &info.access_jti).await?;
+    // set TTL on the set slightly longer than refresh ttl
+    let _: () = conn
+        .expire(&user_s_key, state.refresh_ttl_secs as usize)
+        .await?;
+    Ok(())
+}
+
+/// 只有 access token 的会话（client credentials 不签发 refresh token），随 access token 过期
+pub async fn store_access_session(
+    conn: &mut Connection,
+    info: &SessionInfo,
+    state: &AppState,
+) -> AppResult<()> {
+    let _: () = conn
+        .set_ex(
+            session_key(&info.access_jti),
+            serde_json::to_string(info)?,
+            state.session_ttl_secs as usize,
+        )
+        .await?;
+    let user_s_key = user_sessions_key(info.user_id);
+    let _: () = conn.sadd(&user_s_key, &info.access_jti).await?;
+    let _: () = conn
+        .expire(&user_s_key, state.refresh_ttl_secs as usize)
+        .await?;
+    Ok(())
+}
+
+/// 更新 last_seen（保留原 TTL）
+pub async fn touch_session(conn: &mut Connection, info: &SessionInfo) -> AppResult<()> {
+    let now = Utc::now().timestamp();
+    if now - info.last_seen < TOUCH_INTERVAL_SECS {
+        return Ok(());
+    }
+    let touched = SessionInfo {
+        last_seen: now,
+        ..info.clone()
+    };
+    let _: () = cmd("SET")
+        .arg(session_key(&info.access_jti))
+        .arg(serde_json::to_string(&touched)?)
+        .arg("KEEPTTL")
+        .query_async(conn)
+        .await?;
+    Ok(())
+}
+
+/// 吊销一个会话：删除 session / refresh，并把两个 jti 都加入黑名单
+pub async fn revoke(conn: &mut Connection, info: &SessionInfo, state: &AppState) -> AppResult<()> {
+    let _: () = conn.del(session_key(&info.access_jti)).await?;
+    let _: () = conn.del(refresh_key(&info.refresh_jti)).await?;
+    let _: () = conn.del(family_key(&info.family)).await?;
+    // access-only sessions have no refresh jti
+    for jti in [&info.access_jti, &info.refresh_jti]
+        .into_iter()
+        .filter(|jti| !jti.is_empty())
+    {
+        let _: () = conn
+            .set_ex(blacklist_key(jti), "1", state.refresh_ttl_secs as usize)
+            .await?;
+    }
+    let _: () = conn
+        .srem(user_sessions_key(info.user_id), &info.access_jti)
+        .await?;
+    Ok(())
+}
+
+/// family 当前的会话（family 已失效则为 None）
+pub async fn load_family(conn: &mut Connection, family: &str) -> AppResult<Option<SessionInfo>> {
+    let access_jti: Option<String> = conn.get(family_key(family)).await?;
+    match access_jti {
+        Some(jti) => Ok(load_session(conn, &jti)
+            .await?
+            .filter(|s| s.family == family)),
+        None => Ok(None),
+    }
+}
+
+/// 用户的所有有效会话；顺便清理 set 中已过期的 jti
+pub async fn active_sessions(conn: &mut Connection, user_id: i64) -> AppResult<Vec<SessionInfo>> {
+    let user_s_key = user_sessions_key(user_id);
+    let jtis: Vec<String> = conn.smembers(&user_s_key).await?;
+    let mut sessions = Vec::with_capacity(jtis.len());
+    for jti in jtis {
+        match load_session(conn, &jti).await? {
+            Some(info) => sessions.push(info),
+            None => {
+                let _: () = conn.srem(&user_s_key, &jti).await?;
+            }
+        }
+    }
+    Ok(sessions)
+}
+
+/// 超过 max_sessions_per_user 时，踢掉最久未活动的会话（保留 keep_jti）
+pub async fn enforce_max_sessions(
+    conn: &mut Connection,
+    user_id: i64,
+    keep_jti: &str,
+    state: &AppState,
+) -> AppResult<()> {
+    let mut sessions = active_sessions(conn, user_id).await?;
+    if sessions.len() <= state.max_sessions_per_user {
+        return Ok(());
+    }
+    sessions.retain(|s| s.access_jti != keep_jti);
+    sessions.sort_by_key(|s| s.last_seen);
+    let excess = sessions.len() + 1 - state.max_sessions_per_user;
+    for info in sessions.iter().take(excess) {
+        revoke(conn, info, state).await?;
+    }
+    Ok(())
+}
+
+pub async fn list_sessions(
+    user_id: i64,
+    current_jti: &str,
+    state: &AppState,
+) -> AppResult<Vec<SessionResponse>> {
+    let mut conn = state.redis.get().await?;
+    let mut sessions = active_sessions(&mut conn, user_id).await?;
+    sessions.sort_by_key(|s| std::cmp::Reverse(s.last_seen));
+    Ok(sessions
+        .iter()
+        .map(|s| SessionResponse::new(s, current_jti))
+        .collect())
+}
+
+/// 只能查看 / 操作自己的会话
+async fn owned_session(
+    conn: &mut Connection,
+    user_id: i64,
+    session_id: &str,
+) -> AppResult<SessionInfo> {
+    load_session(conn, session_id)
+        .await?
+        .filter(|s| s.user_id == user_id)
+        .ok_or_else(|| AppError::NotFound("session not found".into()))
+}
+
+pub async fn get_session(
+    user_id: i64,
+    session_id: &str,
+    current_jti: &str,
+    state: &AppState,
+) -> AppResult<SessionResponse> {
+    let mut conn = state.redis.get().await?;
+    let info = owned_session(&mut conn, user_id, session_id).await?;
+    Ok(SessionResponse::new(&info, current_jti))
+}
+
+pub async fn revoke_session(user_id: i64, session_id: &str, state: &AppState) -> AppResult<()> {
+    let mut conn = state.redis.get().await?;
+    let info = owned_session(&mut conn, user_id, session_id).await?;
+    revoke(&mut conn, &info, state).await
+}
+
+/// 吊销除当前会话外的所有会话，返回吊销数量
+pub async fn revoke_other_sessions(
+    user_id: i64,
+    current_jti: &str,
+    state: &AppState,
+) -> AppResult<usize> {
+    let mut conn = state.redis.get().await?;
+    let sessions = active_sessions(&mut conn, user_id).await?;
+    let mut revoked = 0;
+    for info in sessions.iter().filter(|s| s.access_jti != current_jti) {
+        revoke(&mut conn, info, state).await?;
+        revoked += 1;
+    }
+    Ok(revoked)
+}
@@ -0,0 +1,265 @@
+use std::env;
+
+use anyhow::{Context, bail};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header, jwk::JwkSet};
+use serde::Deserialize;
+use tokio::sync::OnceCell;
+use url::Url;
+
+use crate::error::{AppError, AppResult};
+
+/// 上游 OIDC 身份提供方（联合登录）；discovery 文档在第一次使用时获取并缓存
+pub struct UpstreamProvider {
+    /// 出现在回调地址 `/auth/{name}/callback` 中，也是 user_identities.provider
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub scopes: Vec<String>,
+    /// 自动创建的用户获得的角色
+    pub default_roles: Vec<String>,
+    metadata: OnceCell<ProviderMetadata>,
+}
+
+/// discovery 文档中用到的字段
+#[derive(Debug, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+/// 上游 ID token 中用到的 claims
+#[derive(Debug, Deserialize)]
+pub struct UpstreamClaims {
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    pub preferred_username: Option<String>,
+}
+
+impl UpstreamClaims {
+    /// 上游声明已验证的邮箱
+    pub fn verified_email(&self) -> Option<&str> {
+        self.email
+            .as_deref()
+            .filter(|_| self.email_verified == Some(true))
+    }
+}
+
+#[derive(Deserialize)]
+struct UpstreamTokenResponse {
+    id_token: Option<String>,
+}
+
+impl UpstreamProvider {
+    /// 默认 scope：openid profile email，没有默认角色
+    pub fn new(name: &str, issuer: &str, client_id: &str, client_secret: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            issuer: issuer.to_string(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            scopes: ["openid", "profile", "email"].map(String::from).to_vec(),
+            default_roles: Vec::new(),
+            metadata: OnceCell::new(),
+        }
+    }
+
+    pub fn with_scopes(mut self, scopes: Vec<String>) -> Self {
+        self.scopes = scopes;
+        self
+    }
+
+    pub fn with_default_roles(mut self, roles: Vec<String>) -> Self {
+        self.default_roles = roles;
+        self
+    }
+
+    /// 读取环境变量：
+    // OIDC_UPSTREAMS=google,corp（名称只能是小写字母、数字、'-'、'_'）
+    // OIDC_UPSTREAM_{NAME}_ISSUER / _CLIENT_ID / _CLIENT_SECRET: 必需
+    // OIDC_UPSTREAM_{NAME}_SCOPES: 空格分隔，默认 "openid profile email"
+    // OIDC_UPSTREAM_{NAME}_DEFAULT_ROLES: 逗号分隔
+    pub fn from_env() -> anyhow::Result<Vec<Self>> {
+        let Ok(names) = env::var("OIDC_UPSTREAMS") else {
+            return Ok(Vec::new());
+        };
+        let mut providers = Vec::new();
+        for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            if !name
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_')
+            {
+                bail!("invalid upstream provider name {:?}", name);
+            }
+            let prefix = format!(
+                "OIDC_UPSTREAM_{}_",
+                name.to_ascii_uppercase().replace('-', "_")
+            );
+            let var = |key: &str| env::var(format!("{}{}", prefix, key));
+            let required = |key: &str| var(key).with_context(|| format!("{}{}", prefix, key));
+            let mut provider = Self::new(
+                name,
+                &required("ISSUER")?,
+                &required("CLIENT_ID")?,
+                &required("CLIENT_SECRET")?,
+            );
+            if let Ok(scopes) = var("SCOPES") {
+                provider.scopes = scopes.split_whitespace().map(String::from).collect();
+            }
+            if let Ok(roles) = var("DEFAULT_ROLES") {
+                provider.default_roles = roles
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|r| !r.is_empty())
+                    .map(String::from)
+                    .collect();
+            }
+            providers.push(provider);
+        }
+        Ok(providers)
+    }
+
+    /// `{issuer}/.well-known/openid-configuration`，issuer 必须与配置的一致（OIDC Discovery §4.3，
+    /// 这里忽略结尾的 '/'；ID token 的 iss 按 discovery 返回的原样校验）
+    pub async fn metadata(&self, http: &reqwest::Client) -> AppResult<&ProviderMetadata> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.issuer.trim_end_matches('/')
+                );
+                let metadata: ProviderMetadata = http
+                    .get(&url)
+                    .send()
+                    .await
+                    .and_then(|r| r.error_for_status())
+                    .with_context(|| format!("fetching {}", url))?
+                    .json()
+                    .await
+                    .with_context(|| format!("parsing {}", url))?;
+                if metadata.issuer.trim_end_matches('/') != self.issuer.trim_end_matches('/') {
+                    return Err(AppError::Internal(anyhow::anyhow!(
+                        "issuer mismatch in {}: {}",
+                        url,
+                        metadata.issuer
+                    )));
+                }
+                Ok(metadata)
+            })
+            .await
+    }
+
+    /// 上游授权地址（authorization code + PKCE S256 + nonce）
+    pub async fn authorization_url(
+        &self,
+        http: &reqwest::Client,
+        redirect_uri: &str,
+        state: &str,
+        nonce: &str,
+        code_challenge: &str,
+    ) -> AppResult<String> {
+        let metadata = self.metadata(http).await?;
+        let mut url = Url::parse(&metadata.authorization_endpoint)
+            .context("invalid upstream authorization_endpoint")?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.client_id)
+            .append_pair("redirect_uri", redirect_uri)
+            .append_pair("scope", &self.scopes.join(" "))
+            .append_pair("state", state)
+            .append_pair("nonce", nonce)
+            .append_pair("code_challenge", code_challenge)
+            .append_pair("code_challenge_method", "S256");
+        Ok(url.into())
+    }
+
+    /// 用授权码换 ID token 并验证：
+    // 1) token 端点使用 client_secret_basic
+    // 2) 签名用上游 JWKS 中 kid 对应的公钥（每次回调重新获取，上游轮换密钥不需要重启）
+    // 3) iss / aud / exp 与 nonce 必须匹配
+    pub async fn exchange_code(
+        &self,
+        http: &reqwest::Client,
+        code: &str,
+        redirect_uri: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> AppResult<UpstreamClaims> {
+        let metadata = self.metadata(http).await?;
+        let response = http
+            .post(&metadata.token_endpoint)
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", redirect_uri),
+                ("code_verifier", code_verifier),
+            ])
+            .send()
+            .await
+            .context("upstream token request failed")?;
+        if !response.status().is_success() {
+            return Err(AppError::unauthorized(
+                "identity provider rejected the authorization code",
+            ));
+        }
+        let id_token = response
+            .json::<UpstreamTokenResponse>()
+            .await
+            .context("parsing upstream token response")?
+            .id_token
+            .ok_or_else(|| AppError::unauthorized("identity provider returned no id token"))?;
+
+        let claims = self.verify_id_token(http, &id_token).await?;
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(AppError::unauthorized("id token nonce does not match"));
+        }
+        Ok(claims)
+    }
+
+    async fn verify_id_token(
+        &self,
+        http: &reqwest::Client,
+        id_token: &str,
+    ) -> AppResult<UpstreamClaims> {
+        let invalid = || AppError::unauthorized("invalid id token");
+        let header = decode_header(id_token).map_err(|_| invalid())?;
+        // symmetric algorithms would let anyone holding the client secret forge tokens
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(invalid());
+        }
+        let metadata = self.metadata(http).await?;
+        let jwks: JwkSet = http
+            .get(&metadata.jwks_uri)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .context("fetching upstream jwks")?
+            .json()
+            .await
+            .context("parsing upstream jwks")?;
+        let jwk = match (&header.kid, jwks.keys.as_slice()) {
+            (Some(kid), _) => jwks.find(kid),
+            (None, [only]) => Some(only),
+            (None, _) => None,
+        }
+        .ok_or_else(invalid)?;
+        let key = DecodingKey::from_jwk(jwk).map_err(|_| invalid())?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.client_id]);
+        // exactly as the provider spells it, e.g. with a trailing slash
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        decode::<UpstreamClaims>(id_token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|_| invalid())
+    }
+}
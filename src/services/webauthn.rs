@@ -0,0 +1,307 @@
+use std::env;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use p256::ecdsa::signature::Verifier;
+use rsa::{BigUint, Pkcs1v15Sign, RsaPublicKey};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::utils::cbor::{self, Value};
+
+/// COSE 算法（RFC 9053）
+pub const ALG_ES256: i64 = -7;
+pub const ALG_EDDSA: i64 = -8;
+pub const ALG_RS256: i64 = -257;
+
+/// 签名计数没有递增（可能是被克隆的认证器），调用方据此记录安全事件
+pub const COUNTER_REGRESSION: &str = "signature counter did not increase";
+
+/// 接受的最短 RSA 模数
+const MIN_RSA_BITS: usize = 2048;
+
+/// authenticator data flags（WebAuthn §6.1）
+const FLAG_UP: u8 = 0x01;
+const FLAG_UV: u8 = 0x04;
+const FLAG_AT: u8 = 0x40;
+
+/// Relying Party：rp id 是浏览器中的域名（不含端口），origins 是允许发起 ceremony 的页面来源
+#[derive(Debug, Clone)]
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+    pub origins: Vec<String>,
+}
+
+impl Default for RelyingParty {
+    fn default() -> Self {
+        Self {
+            id: "localhost".to_string(),
+            name: "rust_web_backend".to_string(),
+            origins: vec!["http://localhost:3000".to_string()],
+        }
+    }
+}
+
+impl RelyingParty {
+    /// 读取环境变量：
+    // WEBAUTHN_RP_ID=example.com
+    // WEBAUTHN_RP_NAME=Example
+    // WEBAUTHN_ORIGINS=https://example.com,https://app.example.com
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            id: env::var("WEBAUTHN_RP_ID").unwrap_or(default.id),
+            name: env::var("WEBAUTHN_RP_NAME").unwrap_or(default.name),
+            origins: env::var("WEBAUTHN_ORIGINS")
+                .map(|v| {
+                    v.split(',')
+                        .map(|o| o.trim().trim_end_matches('/').to_string())
+                        .filter(|o| !o.is_empty())
+                        .collect()
+                })
+                .unwrap_or(default.origins),
+        }
+    }
+
+    fn id_hash(&self) -> [u8; 32] {
+        Sha256::digest(self.id.as_bytes()).into()
+    }
+}
+
+/// 凭证公钥（COSE_Key，RFC 9052 §7）
+pub enum CoseKey {
+    Es256(p256::ecdsa::VerifyingKey),
+    EdDsa(ed25519_dalek::VerifyingKey),
+    Rs256(RsaPublicKey),
+}
+
+impl CoseKey {
+    /// 只接受注册时声明支持的算法（pubKeyCredParams）
+    pub fn from_cbor(bytes: &[u8]) -> Result<Self, &'static str> {
+        let key = cbor::decode(bytes).map_err(|_| "malformed credential public key")?;
+        let int = |label| key.get_int(label).and_then(Value::as_integer);
+        let bytes = |label| key.get_int(label).and_then(Value::as_bytes);
+        match (int(1), int(3)) {
+            // kty EC2, crv P-256
+            (Some(2), Some(ALG_ES256)) if int(-1) == Some(1) => {
+                let (Some(x), Some(y)) = (bytes(-2), bytes(-3)) else {
+                    return Err("malformed EC2 key");
+                };
+                if x.len() != 32 || y.len() != 32 {
+                    return Err("malformed EC2 key");
+                }
+                let point = p256::EncodedPoint::from_affine_coordinates(x.into(), y.into(), false);
+                p256::ecdsa::VerifyingKey::from_encoded_point(&point)
+                    .map(Self::Es256)
+                    .map_err(|_| "invalid P-256 public key")
+            }
+            // kty OKP, crv Ed25519
+            (Some(1), Some(ALG_EDDSA)) if int(-1) == Some(6) => {
+                let x: [u8; 32] = bytes(-2)
+                    .and_then(|x| x.try_into().ok())
+                    .ok_or("malformed OKP key")?;
+                ed25519_dalek::VerifyingKey::from_bytes(&x)
+                    .map(Self::EdDsa)
+                    .map_err(|_| "invalid Ed25519 public key")
+            }
+            // kty RSA
+            (Some(3), Some(ALG_RS256)) => {
+                let (Some(n), Some(e)) = (bytes(-1), bytes(-2)) else {
+                    return Err("malformed RSA key");
+                };
+                let n = BigUint::from_bytes_be(n);
+                if n.bits() < MIN_RSA_BITS {
+                    return Err("RSA key is too short");
+                }
+                RsaPublicKey::new(n, BigUint::from_bytes_be(e))
+                    .map(Self::Rs256)
+                    .map_err(|_| "invalid RSA public key")
+            }
+            _ => Err("unsupported credential algorithm"),
+        }
+    }
+
+    /// ES256 签名是 DER 编码（WebAuthn §6.5.5）
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        match self {
+            Self::Es256(key) => p256::ecdsa::Signature::from_der(signature)
+                .is_ok_and(|sig| key.verify(message, &sig).is_ok()),
+            Self::EdDsa(key) => ed25519_dalek::Signature::from_slice(signature)
+                .is_ok_and(|sig| key.verify_strict(message, &sig).is_ok()),
+            Self::Rs256(key) => key
+                .verify(
+                    Pkcs1v15Sign::new::<Sha256>(),
+                    &Sha256::digest(message),
+                    signature,
+                )
+                .is_ok(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CollectedClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+    #[serde(rename = "crossOrigin", default)]
+    cross_origin: bool,
+}
+
+/// 浏览器生成的 clientDataJSON（WebAuthn §5.8.1），签名覆盖的是原始字节的 sha256
+pub struct ClientData {
+    pub challenge: String,
+    kind: String,
+    origin: String,
+    cross_origin: bool,
+    hash: [u8; 32],
+}
+
+impl ClientData {
+    pub fn parse(raw: &[u8]) -> Result<Self, &'static str> {
+        let data: CollectedClientData =
+            serde_json::from_slice(raw).map_err(|_| "malformed clientDataJSON")?;
+        Ok(Self {
+            challenge: data.challenge,
+            kind: data.kind,
+            origin: data.origin,
+            cross_origin: data.cross_origin,
+            hash: Sha256::digest(raw).into(),
+        })
+    }
+
+    /// ceremony 类型与来源；challenge 由调用方在 redis 中核对
+    fn check(&self, kind: &str, rp: &RelyingParty) -> Result<(), &'static str> {
+        if self.kind != kind {
+            return Err("unexpected ceremony type");
+        }
+        if !rp.origins.contains(&self.origin) || self.cross_origin {
+            return Err("origin is not allowed");
+        }
+        Ok(())
+    }
+}
+
+/// authenticator data（WebAuthn §6.1）
+struct AuthenticatorData {
+    rp_id_hash: [u8; 32],
+    flags: u8,
+    sign_count: u32,
+    /// 注册时的 attested credential data：(credential id, COSE key)
+    attested: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl AuthenticatorData {
+    fn parse(data: &[u8]) -> Result<Self, &'static str> {
+        const MALFORMED: &str = "malformed authenticator data";
+        if data.len() < 37 {
+            return Err(MALFORMED);
+        }
+        let flags = data[32];
+        let mut parsed = Self {
+            rp_id_hash: data[..32].try_into().map_err(|_| MALFORMED)?,
+            flags,
+            sign_count: u32::from_be_bytes(data[33..37].try_into().map_err(|_| MALFORMED)?),
+            attested: None,
+        };
+        if flags & FLAG_AT != 0 {
+            // aaguid (16) | credential id length (2) | credential id | COSE key | extensions
+            let rest = data.get(37 + 16..).ok_or(MALFORMED)?;
+            let id_len = u16::from_be_bytes(rest.get(..2).ok_or(MALFORMED)?.try_into().unwrap());
+            let id = rest.get(2..2 + id_len as usize).ok_or(MALFORMED)?;
+            let key_bytes = &rest[2 + id_len as usize..];
+            let (_, key_len) = cbor::decode_prefix(key_bytes).map_err(|_| MALFORMED)?;
+            parsed.attested = Some((id.to_vec(), key_bytes[..key_len].to_vec()));
+        }
+        Ok(parsed)
+    }
+
+    /// rp id 与 flags：必须有用户在场（UP），需要时还要有用户验证（UV）
+    fn check(&self, rp: &RelyingParty, require_uv: bool) -> Result<(), &'static str> {
+        if self.rp_id_hash != rp.id_hash() {
+            return Err("credential belongs to another relying party");
+        }
+        if self.flags & FLAG_UP == 0 {
+            return Err("user presence is required");
+        }
+        if require_uv && self.flags & FLAG_UV == 0 {
+            return Err("user verification is required");
+        }
+        Ok(())
+    }
+}
+
+/// 注册成功后要保存的凭证
+pub struct NewCredential {
+    /// base64url
+    pub credential_id: String,
+    /// COSE key 原始字节
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+}
+
+/// 注册响应（WebAuthn §7.1）：
+// 1) clientDataJSON：type=webauthn.create，origin 允许
+// 2) attestationObject：authData 的 rp id hash、UP（/ UV）、attested credential data
+// 3) 公钥必须是支持的算法；attestation 语句不验证（注册时请求的是 attestation=none）
+pub fn verify_registration(
+    rp: &RelyingParty,
+    client_data: &ClientData,
+    attestation_object: &[u8],
+    require_uv: bool,
+) -> Result<NewCredential, &'static str> {
+    client_data.check("webauthn.create", rp)?;
+    let attestation = cbor::decode(attestation_object).map_err(|_| "malformed attestation")?;
+    let auth_data = attestation
+        .get_text("authData")
+        .and_then(Value::as_bytes)
+        .ok_or("malformed attestation")?;
+    let auth_data = AuthenticatorData::parse(auth_data)?;
+    auth_data.check(rp, require_uv)?;
+    let (credential_id, public_key) = auth_data
+        .attested
+        .ok_or("attested credential data is missing")?;
+    if credential_id.is_empty() || credential_id.len() > 1023 {
+        return Err("invalid credential id");
+    }
+    CoseKey::from_cbor(&public_key)?;
+    Ok(NewCredential {
+        credential_id: URL_SAFE_NO_PAD.encode(credential_id),
+        public_key,
+        sign_count: auth_data.sign_count,
+    })
+}
+
+/// 认证响应（WebAuthn §7.2）：
+// 1) clientDataJSON：type=webauthn.get，origin 允许
+// 2) authenticatorData：rp id hash、UP（/ UV）
+// 3) 签名覆盖 authenticatorData || sha256(clientDataJSON)
+// 4) 签名计数：任一方不为 0 时必须递增，否则可能是被克隆的认证器
+// 返回新的签名计数
+pub fn verify_assertion(
+    rp: &RelyingParty,
+    client_data: &ClientData,
+    authenticator_data: &[u8],
+    signature: &[u8],
+    public_key: &[u8],
+    stored_sign_count: u32,
+    require_uv: bool,
+) -> Result<u32, &'static str> {
+    client_data.check("webauthn.get", rp)?;
+    let parsed = AuthenticatorData::parse(authenticator_data)?;
+    parsed.check(rp, require_uv)?;
+
+    let key = CoseKey::from_cbor(public_key)?;
+    let mut message = authenticator_data.to_vec();
+    message.extend_from_slice(&client_data.hash);
+    if !key.verify(&message, signature) {
+        return Err("invalid signature");
+    }
+
+    if (parsed.sign_count != 0 || stored_sign_count != 0) && parsed.sign_count <= stored_sign_count
+    {
+        return Err(COUNTER_REGRESSION);
+    }
+    Ok(parsed.sign_count)
+}
@@ -0,0 +1,415 @@
+use crate::{
+    auth::extractor::{AuthUser, ClientInfo},
+    error::{AppError, AppResult},
+    models::{
+        user::User,
+        webauthn::{
+            AssertionCredential, RegistrationCredential, WebauthnChallenge, WebauthnCredential,
+        },
+    },
+    repositories::{
+        identity_repo::list_identities,
+        user_repo::get_user_by_id,
+        webauthn_repo::{
+            count_credentials, create_credential, delete_credential, find_credential,
+            list_credentials, rename_credential, update_sign_count,
+        },
+    },
+    services::{
+        auth_service::{LoginResult, check_current_password, start_session},
+        mfa_service::{
+            check_second_factor, claim_attempt, fail_challenge, finish_challenge, load_challenge,
+        },
+        security_event::{SecurityEvent, emit},
+        session_service::load_session,
+        webauthn::{
+            ALG_EDDSA, ALG_ES256, ALG_RS256, COUNTER_REGRESSION, ClientData, verify_assertion,
+            verify_registration,
+        },
+    },
+    state::AppState,
+    utils::{
+        redis_keys::webauthn_challenge_key,
+        token::{hash_token, random_token},
+    },
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::Utc;
+use deadpool_redis::redis::{AsyncCommands, cmd};
+use serde_json::{Value, json};
+
+/// 一次 ceremony 的有效期（也作为浏览器端的 timeout）
+const CHALLENGE_TTL_SECS: usize = 60 * 5;
+/// 每个用户最多注册的凭证数
+const MAX_CREDENTIALS_PER_USER: usize = 10;
+/// 没有密码也没有 TOTP 的账号：会话在这段时间内登录的才能注册 passkey
+const RECENT_LOGIN_SECS: i64 = 60 * 10;
+
+/// 注册时的 user.id：用户 id 的 8 字节大端序；discoverable 凭证登录时原样返回（userHandle）
+fn user_handle(user_id: i64) -> String {
+    URL_SAFE_NO_PAD.encode(user_id.to_be_bytes())
+}
+
+/// 生成 challenge 并保存 ceremony（只存 challenge 的 sha256）
+async fn start_ceremony(ceremony: &WebauthnChallenge, state: &AppState) -> AppResult<String> {
+    let challenge = random_token(32);
+    let mut conn = state.redis.get().await?;
+    let _: () = conn
+        .set_ex(
+            webauthn_challenge_key(&hash_token(&challenge)),
+            serde_json::to_string(ceremony)?,
+            CHALLENGE_TTL_SECS,
+        )
+        .await?;
+    Ok(challenge)
+}
+
+/// 取出 ceremony（GETDEL，只能用一次）
+async fn take_ceremony(challenge: &str, state: &AppState) -> AppResult<Option<WebauthnChallenge>> {
+    let mut conn = state.redis.get().await?;
+    let raw: Option<String> = cmd("GETDEL")
+        .arg(webauthn_challenge_key(&hash_token(challenge)))
+        .query_async(&mut conn)
+        .await?;
+    Ok(raw.and_then(|raw| serde_json::from_str(&raw).ok()))
+}
+
+fn descriptors(credentials: &[WebauthnCredential]) -> Vec<Value> {
+    credentials
+        .iter()
+        .map(|c| json!({"type": "public-key", "id": c.credential_id, "transports": c.transports}))
+        .collect()
+}
+
+fn base64url(value: &str) -> Option<Vec<u8>> {
+    URL_SAFE_NO_PAD.decode(value.trim_end_matches('=')).ok()
+}
+
+/// 注册前重新验证身份：passkey 登录不再要求 TOTP，被盗的会话不能借此给账号加上攻击者的 passkey
+// 1) 有密码：必须输入正确的密码
+// 2) 开启了 TOTP：还要输入当前验证码（或恢复码）
+// 3) 两者都没有（上游身份 / 只有 passkey）：当前会话必须是最近登录的
+// 密码和验证码错误都计入账号的失败次数，被盗的会话不能借此无限次猜测
+async fn reauthenticate(
+    user: &User,
+    session_jti: &str,
+    password: Option<&str>,
+    code: Option<&str>,
+    state: &AppState,
+) -> AppResult<()> {
+    if user.has_password() {
+        let password = password.ok_or_else(|| {
+            AppError::invalid_field("password", "enter your password to register a passkey")
+        })?;
+        if !check_current_password(user, password, state).await? {
+            return Err(AppError::forbidden("password is incorrect"));
+        }
+    }
+    if user.totp_enabled {
+        let code = code.ok_or_else(|| {
+            AppError::invalid_field("code", "enter your two-factor code to register a passkey")
+        })?;
+        if !check_second_factor(user, code, state).await? {
+            return Err(AppError::forbidden("invalid two-factor code"));
+        }
+    }
+    if !user.has_password() && !user.totp_enabled {
+        let mut conn = state.redis.get().await?;
+        let recent = load_session(&mut conn, session_jti)
+            .await?
+            .is_some_and(|s| Utc::now().timestamp() - s.created_at <= RECENT_LOGIN_SECS);
+        if !recent {
+            return Err(AppError::forbidden("sign in again to register a passkey"));
+        }
+    }
+    Ok(())
+}
+
+/// 注册选项（PublicKeyCredentialCreationOptions JSON）：
+// 1) 服务账号不能注册；其他用户先重新验证身份
+// 2) 已注册的凭证放进 excludeCredentials，同一个认证器不会注册两次
+// 3) residentKey=preferred：支持的认证器创建 passkey，可用于只用 passkey 登录，所以必须验证用户（UV）
+pub async fn registration_options(
+    session: &AuthUser,
+    password: Option<&str>,
+    code: Option<&str>,
+    state: &AppState,
+) -> AppResult<Value> {
+    let user_id = session.id;
+    let user = get_user_by_id(&state.db, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("user not found".into()))?;
+    if user.service_account {
+        return Err(AppError::forbidden(
+            "service accounts cannot register passkeys",
+        ));
+    }
+    reauthenticate(&user, &session.jti, password, code, state).await?;
+    let existing = list_credentials(&state.db, user_id).await?;
+    if existing.len() >= MAX_CREDENTIALS_PER_USER {
+        return Err(AppError::Conflict("too many passkeys registered".into()));
+    }
+    let challenge = start_ceremony(&WebauthnChallenge::Registration { user_id }, state).await?;
+    let rp = &state.webauthn;
+    let params: Vec<Value> = [ALG_ES256, ALG_EDDSA, ALG_RS256]
+        .iter()
+        .map(|alg| json!({"type": "public-key", "alg": alg}))
+        .collect();
+    Ok(json!({
+        "challenge": challenge,
+        "rp": {"id": rp.id, "name": rp.name},
+        "user": {
+            "id": user_handle(user_id),
+            "name": user.username,
+            "displayName": user.username,
+        },
+        "pubKeyCredParams": params,
+        "timeout": CHALLENGE_TTL_SECS * 1000,
+        "attestation": "none",
+        "excludeCredentials": descriptors(&existing),
+        "authenticatorSelection": {
+            "residentKey": "preferred",
+            "userVerification": "required",
+        },
+    }))
+}
+
+/// 完成注册：challenge 必须是这个用户发起的注册，然后按 WebAuthn §7.1 验证（要求 UV）
+pub async fn register_passkey(
+    user_id: i64,
+    name: &str,
+    credential: &RegistrationCredential,
+    state: &AppState,
+) -> AppResult<WebauthnCredential> {
+    let invalid = |message: &str| AppError::invalid_field("credential", message);
+    let client_data_json = base64url(&credential.response.client_data_json)
+        .ok_or_else(|| invalid("clientDataJSON is not base64url"))?;
+    let attestation_object = base64url(&credential.response.attestation_object)
+        .ok_or_else(|| invalid("attestationObject is not base64url"))?;
+    let client_data = ClientData::parse(&client_data_json).map_err(invalid)?;
+    match take_ceremony(&client_data.challenge, state).await? {
+        Some(WebauthnChallenge::Registration { user_id: owner }) if owner == user_id => {}
+        _ => return Err(invalid("invalid or expired challenge")),
+    }
+
+    let new = verify_registration(&state.webauthn, &client_data, &attestation_object, true)
+        .map_err(invalid)?;
+    if new.credential_id != credential.id.trim_end_matches('=') {
+        return Err(invalid("credential id does not match the attestation"));
+    }
+    if count_credentials(&state.db, user_id).await? >= MAX_CREDENTIALS_PER_USER as i64 {
+        return Err(AppError::Conflict("too many passkeys registered".into()));
+    }
+    create_credential(
+        &state.db,
+        user_id,
+        &new.credential_id,
+        &new.public_key,
+        new.sign_count as i64,
+        name,
+        &credential.response.transports,
+    )
+    .await
+}
+
+/// 验证认证响应：
+// 1) 取出 challenge（只能用一次），凭证必须存在
+// 2) expected 检查 ceremony 与凭证是否匹配（登录方式 / 用户）；userHandle 必须是凭证的用户
+// 3) 签名与计数；计数回退时记录安全事件
+async fn verify_credential_assertion(
+    credential: &AssertionCredential,
+    require_uv: bool,
+    expected: impl FnOnce(&WebauthnChallenge, &WebauthnCredential) -> bool,
+    state: &AppState,
+) -> AppResult<WebauthnCredential> {
+    let invalid = || AppError::unauthorized("invalid passkey assertion");
+    let response = &credential.response;
+    let (Some(client_data_json), Some(authenticator_data), Some(signature)) = (
+        base64url(&response.client_data_json),
+        base64url(&response.authenticator_data),
+        base64url(&response.signature),
+    ) else {
+        return Err(invalid());
+    };
+    let client_data = ClientData::parse(&client_data_json).map_err(|_| invalid())?;
+    let ceremony = take_ceremony(&client_data.challenge, state)
+        .await?
+        .ok_or_else(|| AppError::unauthorized("invalid or expired challenge"))?;
+    let stored = find_credential(&state.db, credential.id.trim_end_matches('='))
+        .await?
+        .ok_or_else(invalid)?;
+    if !expected(&ceremony, &stored) {
+        return Err(invalid());
+    }
+    if let Some(handle) = &response.user_handle
+        && handle.trim_end_matches('=') != user_handle(stored.user_id)
+    {
+        return Err(invalid());
+    }
+
+    let regression = || {
+        emit(&SecurityEvent::PasskeyCounterRegression {
+            user_id: stored.user_id,
+            credential_id: &stored.credential_id,
+            stored: stored.sign_count,
+        });
+        invalid()
+    };
+    let sign_count = match verify_assertion(
+        &state.webauthn,
+        &client_data,
+        &authenticator_data,
+        &signature,
+        &stored.public_key,
+        stored.sign_count as u32,
+        require_uv,
+    ) {
+        Ok(sign_count) => sign_count,
+        Err(COUNTER_REGRESSION) => return Err(regression()),
+        Err(_) => return Err(invalid()),
+    };
+    if !update_sign_count(&state.db, stored.id, sign_count as i64).await? {
+        return Err(regression());
+    }
+    Ok(stored)
+}
+
+/// 只用 passkey 登录的选项：不指定凭证，由认证器列出 discoverable 凭证；必须验证用户（UV）
+pub async fn login_options(state: &AppState) -> AppResult<Value> {
+    let challenge = start_ceremony(&WebauthnChallenge::Login, state).await?;
+    Ok(json!({
+        "challenge": challenge,
+        "rpId": state.webauthn.id,
+        "timeout": CHALLENGE_TTL_SECS * 1000,
+        "userVerification": "required",
+        "allowCredentials": [],
+    }))
+}
+
+/// 只用 passkey 登录：
+// passkey（持有 + 用户验证）本身就是多因素，不再要求 TOTP；禁用的用户 403
+pub async fn login_with_passkey(
+    credential: &AssertionCredential,
+    client: &ClientInfo,
+    state: &AppState,
+) -> AppResult<LoginResult> {
+    let stored = verify_credential_assertion(
+        credential,
+        true,
+        |ceremony, _| matches!(ceremony, WebauthnChallenge::Login),
+        state,
+    )
+    .await?;
+    let user = get_user_by_id(&state.db, stored.user_id)
+        .await?
+        .filter(|u| !u.service_account)
+        .ok_or_else(|| AppError::unauthorized("invalid passkey assertion"))?;
+    if user.disabled {
+        return Err(AppError::UserDisabled);
+    }
+    start_session(&user, client, state).await
+}
+
+/// 第二因素的选项：只允许这个用户的凭证，challenge 绑定到 mfa pending token
+pub async fn second_factor_options(mfa_token: &str, state: &AppState) -> AppResult<Value> {
+    let challenge = load_challenge(mfa_token, state).await?;
+    let credentials = list_credentials(&state.db, challenge.user_id).await?;
+    if credentials.is_empty() {
+        return Err(AppError::Validation("no passkeys registered".into()));
+    }
+    let ceremony = WebauthnChallenge::SecondFactor {
+        user_id: challenge.user_id,
+        mfa_token_hash: hash_token(mfa_token),
+    };
+    let challenge = start_ceremony(&ceremony, state).await?;
+    Ok(json!({
+        "challenge": challenge,
+        "rpId": state.webauthn.id,
+        "timeout": CHALLENGE_TTL_SECS * 1000,
+        "userVerification": "discouraged",
+        "allowCredentials": descriptors(&credentials),
+    }))
+}
+
+/// 登录第二步用 passkey 代替验证码
+pub async fn login_mfa_passkey(
+    mfa_token: &str,
+    credential: &AssertionCredential,
+    state: &AppState,
+) -> AppResult<LoginResult> {
+    let (user, client) = verify_mfa_passkey(mfa_token, credential, state).await?;
+    start_session(&user, &client, state).await
+}
+
+/// 用 passkey 校验第二因素，返回用户与发起登录时的客户端信息；失败计入 mfa pending token 与账号的失败次数
+pub async fn verify_mfa_passkey(
+    mfa_token: &str,
+    credential: &AssertionCredential,
+    state: &AppState,
+) -> AppResult<(User, ClientInfo)> {
+    let challenge = claim_attempt(mfa_token, state).await?;
+    let user = get_user_by_id(&state.db, challenge.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("user not found".into()))?;
+    if user.disabled {
+        return Err(AppError::UserDisabled);
+    }
+
+    let token_hash = hash_token(mfa_token);
+    let verified = verify_credential_assertion(
+        credential,
+        false,
+        |ceremony, stored| {
+            matches!(ceremony, WebauthnChallenge::SecondFactor { user_id, mfa_token_hash }
+                if *user_id == user.id && stored.user_id == user.id && *mfa_token_hash == token_hash)
+        },
+        state,
+    )
+    .await;
+    match verified {
+        Ok(_) => {}
+        Err(AppError::Unauthorized(message)) => {
+            fail_challenge(mfa_token, &challenge, state).await?;
+            return Err(AppError::Unauthorized(message));
+        }
+        Err(e) => return Err(e),
+    }
+    finish_challenge(mfa_token, &challenge, state).await?;
+    Ok((user, challenge.client()))
+}
+
+pub async fn list_passkeys(user_id: i64, state: &AppState) -> AppResult<Vec<WebauthnCredential>> {
+    list_credentials(&state.db, user_id).await
+}
+
+pub async fn rename_passkey(
+    user_id: i64,
+    id: i64,
+    name: &str,
+    state: &AppState,
+) -> AppResult<WebauthnCredential> {
+    rename_credential(&state.db, user_id, id, name)
+        .await?
+        .ok_or_else(|| AppError::NotFound("passkey not found".into()))
+}
+
+/// 删除凭证；没有密码也没有关联身份的用户不能删除最后一个（否则无法再登录）
+pub async fn delete_passkey(user_id: i64, id: i64, state: &AppState) -> AppResult<()> {
+    let user = get_user_by_id(&state.db, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("user not found".into()))?;
+    let credentials = list_credentials(&state.db, user_id).await?;
+    if !credentials.iter().any(|c| c.id == id) {
+        return Err(AppError::NotFound("passkey not found".into()));
+    }
+    if credentials.len() == 1
+        && !user.has_password()
+        && list_identities(&state.db, user_id).await?.is_empty()
+    {
+        return Err(AppError::Conflict(
+            "cannot delete the only sign-in method".into(),
+        ));
+    }
+    delete_credential(&state.db, user_id, id).await?;
+    Ok(())
+}
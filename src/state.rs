@@ -1,27 +1,75 @@
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc, time::Duration};
 
 use deadpool_redis::Pool as RedisPool;
 use sqlx::PgPool;
 
+use crate::auth::extractor::TrustedProxies;
+use crate::auth::keys::{JwtKey, KeyRing};
+use crate::services::mailer::{LogMailer, Mailer};
+use crate::services::password_policy::PasswordPolicy;
+use crate::services::upstream_oidc::UpstreamProvider;
+use crate::services::webauthn::RelyingParty;
+use crate::utils::hash::PasswordHasher;
+
 #[derive(Clone)]
 pub struct AppState {
     pub db: PgPool,
     pub redis: RedisPool,
-    pub jwt_secret: Arc<Vec<u8>>, // keep as bytes
+    pub jwt_keys: Arc<KeyRing>, // current signing key + verify-only keys by kid
+    pub jwt_key_dir: PathBuf,   // PEM files of keys rotated in through the admin api
+    pub key_sync_secs: u64,     // how often every instance reloads the key ring from the db
     pub session_ttl_secs: i64,
     pub refresh_ttl_secs: i64,        // refresh token ttl
     pub max_sessions_per_user: usize, // 多端控制
+    pub login_max_failures: u64,      // per account, then locked
+    pub login_ip_max_failures: u64,   // per source ip
+    pub login_lockout_secs: u64,
+    pub mailer: Arc<dyn Mailer>,
+    pub password_hasher: PasswordHasher, // preferred scheme for new hashes
+    pub password_policy: PasswordPolicy,
+    pub password_reset_url: String, // link sent in reset mails, token is appended
+    pub email_verify_url: String,   // link sent in verification mails
+    pub magic_link_url: String,     // link sent in sign-in mails
+    pub oidc_issuer: String,        // iss of id tokens, base url in the discovery document
+    pub upstream_providers: Arc<Vec<UpstreamProvider>>, // federated login via external OIDC providers
+    pub http: reqwest::Client,                          // outgoing requests to upstream providers
+    pub webauthn: RelyingParty,                         // passkeys: rp id, name and allowed origins
+    pub trusted_proxies: Arc<TrustedProxies>, // whose X-Forwarded-For is believed; none by default
 }
 
 impl AppState {
-    pub fn new(db: PgPool, redis: RedisPool, secret: Vec<u8>) -> Self {
+    pub fn new(db: PgPool, redis: RedisPool, jwt_key: impl Into<JwtKey>) -> Self {
         Self {
             db,
             redis,
-            jwt_secret: Arc::new(secret),
+            jwt_keys: Arc::new(KeyRing::new(jwt_key.into())),
+            jwt_key_dir: PathBuf::from("keys"),
+            key_sync_secs: 10,
             session_ttl_secs: 60 * 15,
             refresh_ttl_secs: 60 * 60 * 24 * 7, // 7 days
             max_sessions_per_user: 5,
+            login_max_failures: 5,
+            login_ip_max_failures: 20,
+            login_lockout_secs: 60 * 15,
+            mailer: Arc::new(LogMailer),
+            password_hasher: PasswordHasher::default(),
+            password_policy: PasswordPolicy::default(),
+            password_reset_url: "http://localhost:3000/reset-password".to_string(),
+            email_verify_url: "http://localhost:3000/verify-email".to_string(),
+            magic_link_url: "http://localhost:3000/login/magic".to_string(),
+            oidc_issuer: "http://localhost:3000".to_string(),
+            upstream_providers: Arc::new(Vec::new()),
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("http client"),
+            webauthn: RelyingParty::default(),
+            trusted_proxies: Arc::new(TrustedProxies::default()),
         }
     }
+
+    pub fn with_mailer(mut self, mailer: Arc<dyn Mailer>) -> Self {
+        self.mailer = mailer;
+        self
+    }
 }
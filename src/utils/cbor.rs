@@ -0,0 +1,215 @@
+use anyhow::{Result, bail};
+
+/// WebAuthn 用到的 CBOR 子集（RFC 8949）：整数、字节串、文本、数组、map、true / false / null。
+/// 认证器输出的是确定长度的 CTAP2 canonical CBOR，不支持不定长、tag 和浮点数
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Integer(i64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+    Bool(bool),
+    Null,
+}
+
+/// 嵌套深度上限，防止恶意输入耗尽栈
+const MAX_DEPTH: usize = 16;
+/// 数组 / map 按声明的长度最多预分配这么多项，更长的边解析边增长（长度来自不可信的输入）
+const MAX_PREALLOCATED: usize = 64;
+
+impl Value {
+    /// map 中整数 key 对应的值（COSE key 的 label）
+    pub fn get_int(&self, key: i64) -> Option<&Value> {
+        self.get(&Value::Integer(key))
+    }
+
+    /// map 中文本 key 对应的值
+    pub fn get_text(&self, key: &str) -> Option<&Value> {
+        self.get(&Value::Text(key.to_string()))
+    }
+
+    fn get(&self, key: &Value) -> Option<&Value> {
+        match self {
+            Value::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Value::Text(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// 解码完整的输入，不能有多余字节
+pub fn decode(input: &[u8]) -> Result<Value> {
+    let (value, used) = decode_prefix(input)?;
+    if used != input.len() {
+        bail!("trailing bytes after cbor value");
+    }
+    Ok(value)
+}
+
+/// 解码开头的一个值，返回值和用掉的字节数（authenticator data 中 COSE key 后面还可能有扩展）
+pub fn decode_prefix(input: &[u8]) -> Result<(Value, usize)> {
+    let mut decoder = Decoder { input, pos: 0 };
+    let value = decoder.value(0)?;
+    Ok((value, decoder.pos))
+}
+
+struct Decoder<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Decoder<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8]> {
+        if self.input.len() - self.pos < n {
+            bail!("unexpected end of cbor input");
+        }
+        let bytes = &self.input[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    /// 初始字节的 additional info 后面的参数
+    fn argument(&mut self, info: u8) -> Result<u64> {
+        Ok(match info {
+            0..=23 => info as u64,
+            24 => self.take(1)?[0] as u64,
+            25 => u16::from_be_bytes(self.take(2)?.try_into()?) as u64,
+            26 => u32::from_be_bytes(self.take(4)?.try_into()?) as u64,
+            27 => u64::from_be_bytes(self.take(8)?.try_into()?),
+            _ => bail!("unsupported cbor length encoding"),
+        })
+    }
+
+    /// 长度不能超过剩余输入（每个元素至少一个字节）
+    fn length(&mut self, info: u8) -> Result<usize> {
+        let len = self.argument(info)?;
+        if len > (self.input.len() - self.pos) as u64 {
+            bail!("cbor length exceeds input");
+        }
+        Ok(len as usize)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            bail!("cbor nesting too deep");
+        }
+        let initial = self.take(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        Ok(match major {
+            0 => Value::Integer(i64::try_from(self.argument(info)?)?),
+            1 => Value::Integer(-1 - i64::try_from(self.argument(info)?)?),
+            2 => {
+                let len = self.length(info)?;
+                Value::Bytes(self.take(len)?.to_vec())
+            }
+            3 => {
+                let len = self.length(info)?;
+                Value::Text(String::from_utf8(self.take(len)?.to_vec())?)
+            }
+            4 => {
+                let len = self.length(info)?;
+                let mut items = Vec::with_capacity(len.min(MAX_PREALLOCATED));
+                for _ in 0..len {
+                    items.push(self.value(depth + 1)?);
+                }
+                Value::Array(items)
+            }
+            5 => {
+                let len = self.length(info)?;
+                let mut entries = Vec::with_capacity(len.min(MAX_PREALLOCATED));
+                for _ in 0..len {
+                    let key = self.value(depth + 1)?;
+                    let value = self.value(depth + 1)?;
+                    entries.push((key, value));
+                }
+                Value::Map(entries)
+            }
+            7 => match info {
+                20 => Value::Bool(false),
+                21 => Value::Bool(true),
+                22 => Value::Null,
+                _ => bail!("unsupported cbor simple value"),
+            },
+            _ => bail!("unsupported cbor major type {}", major),
+        })
+    }
+}
+
+/// 编码（map 按给定顺序输出）；用于生成 COSE key 和测试中的软件认证器
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_value(&mut out, value);
+    out
+}
+
+fn write_head(out: &mut Vec<u8>, major: u8, argument: u64) {
+    let major = major << 5;
+    match argument {
+        0..=23 => out.push(major | argument as u8),
+        24..=0xff => out.extend([major | 24, argument as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend((argument as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend((argument as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend(argument.to_be_bytes());
+        }
+    }
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Integer(n) if *n >= 0 => write_head(out, 0, *n as u64),
+        Value::Integer(n) => write_head(out, 1, (-1 - *n) as u64),
+        Value::Bytes(b) => {
+            write_head(out, 2, b.len() as u64);
+            out.extend(b);
+        }
+        Value::Text(s) => {
+            write_head(out, 3, s.len() as u64);
+            out.extend(s.as_bytes());
+        }
+        Value::Array(items) => {
+            write_head(out, 4, items.len() as u64);
+            for item in items {
+                write_value(out, item);
+            }
+        }
+        Value::Map(entries) => {
+            write_head(out, 5, entries.len() as u64);
+            for (key, value) in entries {
+                write_value(out, key);
+                write_value(out, value);
+            }
+        }
+        Value::Bool(false) => out.push(0xf4),
+        Value::Bool(true) => out.push(0xf5),
+        Value::Null => out.push(0xf6),
+    }
+}
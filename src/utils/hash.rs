@@ -1,9 +1,143 @@
-use bcrypt::{DEFAULT_COST, hash, verify};
+use std::env;
 
-pub fn hash_password(password: &str) -> String {
-    hash(password, DEFAULT_COST).unwrap()
+use anyhow::{Context, anyhow};
+use argon2::{
+    Algorithm, Argon2, Params, PasswordHash, PasswordHasher as _, PasswordVerifier, Version,
+    password_hash::{SaltString, rand_core::OsRng},
+};
+
+use crate::error::AppResult;
+
+/// 新密码使用的算法与参数
+#[derive(Debug, Clone)]
+pub enum HashScheme {
+    /// memory 单位 KiB
+    Argon2id {
+        params: Params,
+    },
+    Bcrypt {
+        cost: u32,
+    },
+}
+
+/// 密码哈希：按 `HashScheme` 生成新哈希；校验时根据存储的格式识别算法
+// argon2id: PHC 字符串 `$argon2id$v=19$m=..,t=..,p=..$salt$hash`
+// bcrypt:   `$2a$` / `$2b$` / `$2y$`（只截取前 72 字节，仅用于旧数据）
+#[derive(Debug, Clone)]
+pub struct PasswordHasher {
+    scheme: HashScheme,
+}
+
+impl Default for PasswordHasher {
+    /// Argon2id，OWASP 推荐的 m=19456 KiB, t=2, p=1
+    fn default() -> Self {
+        Self {
+            scheme: HashScheme::Argon2id {
+                params: Params::default(),
+            },
+        }
+    }
+}
+
+impl PasswordHasher {
+    pub fn new(scheme: HashScheme) -> Self {
+        Self { scheme }
+    }
+
+    pub fn argon2id(memory_kib: u32, iterations: u32, parallelism: u32) -> anyhow::Result<Self> {
+        let params = Params::new(memory_kib, iterations, parallelism, None)
+            .map_err(|e| anyhow!("invalid argon2 params: {}", e))?;
+        Ok(Self::new(HashScheme::Argon2id { params }))
+    }
+
+    /// 读取环境变量：
+    // PASSWORD_HASH_SCHEME=argon2id（默认）| bcrypt
+    // PASSWORD_HASH_MEMORY_KIB / PASSWORD_HASH_ITERATIONS / PASSWORD_HASH_PARALLELISM: argon2id 参数
+    // PASSWORD_HASH_BCRYPT_COST: bcrypt cost
+    pub fn from_env() -> anyhow::Result<Self> {
+        let var = |name: &str| env::var(name).ok();
+        let number = |name: &str, default: u32| -> anyhow::Result<u32> {
+            var(name).map_or(Ok(default), |v| {
+                v.parse().with_context(|| format!("invalid {}", name))
+            })
+        };
+        match var("PASSWORD_HASH_SCHEME").as_deref() {
+            None | Some("argon2id") => Self::argon2id(
+                number("PASSWORD_HASH_MEMORY_KIB", Params::DEFAULT_M_COST)?,
+                number("PASSWORD_HASH_ITERATIONS", Params::DEFAULT_T_COST)?,
+                number("PASSWORD_HASH_PARALLELISM", Params::DEFAULT_P_COST)?,
+            ),
+            Some("bcrypt") => Ok(Self::new(HashScheme::Bcrypt {
+                cost: number("PASSWORD_HASH_BCRYPT_COST", bcrypt::DEFAULT_COST)?,
+            })),
+            Some(other) => Err(anyhow!("unknown PASSWORD_HASH_SCHEME {}", other)),
+        }
+    }
+
+    pub fn hash(&self, password: &str) -> AppResult<String> {
+        match &self.scheme {
+            HashScheme::Argon2id { params } => {
+                let salt = SaltString::generate(&mut OsRng);
+                let hash = argon2(params.clone())
+                    .hash_password(password.as_bytes(), &salt)
+                    .map_err(|e| anyhow!("argon2 hash failed: {}", e))?;
+                Ok(hash.to_string())
+            }
+            HashScheme::Bcrypt { cost } => Ok(bcrypt::hash(password, *cost)?),
+        }
+    }
+
+    /// 校验密码；无法识别的哈希视为不匹配
+    pub fn verify(&self, password: &str, stored: &str) -> AppResult<bool> {
+        if is_bcrypt(stored) {
+            return Ok(bcrypt::verify(password, stored)?);
+        }
+        let Ok(parsed) = PasswordHash::new(stored) else {
+            return Ok(false);
+        };
+        // algorithm and params come from the PHC string itself
+        Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok())
+    }
+
+    /// 存储的哈希不是当前的算法 / 参数时，登录成功后应重新哈希
+    pub fn needs_rehash(&self, stored: &str) -> bool {
+        match &self.scheme {
+            HashScheme::Bcrypt { cost } => !is_bcrypt(stored) || bcrypt_cost(stored) != Some(*cost),
+            HashScheme::Argon2id { params } => {
+                let Ok(parsed) = PasswordHash::new(stored) else {
+                    return true;
+                };
+                if parsed.algorithm != argon2::ARGON2ID_IDENT
+                    || parsed.version != Some(Version::V0x13.into())
+                {
+                    return true;
+                }
+                match Params::try_from(&parsed) {
+                    Ok(stored_params) => {
+                        stored_params.m_cost() != params.m_cost()
+                            || stored_params.t_cost() != params.t_cost()
+                            || stored_params.p_cost() != params.p_cost()
+                    }
+                    Err(_) => true,
+                }
+            }
+        }
+    }
+}
+
+fn argon2(params: Params) -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+}
+
+fn is_bcrypt(stored: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"]
+        .iter()
+        .any(|prefix| stored.starts_with(prefix))
 }
 
-pub fn verify_password(password: &str, hash: &str) -> bool {
-    verify(password, hash).unwrap()
+/// `$2b$12$...` -> 12
+fn bcrypt_cost(stored: &str) -> Option<u32> {
+    stored.get(4..6)?.parse().ok()
 }
@@ -1,3 +1,6 @@
+pub mod cbor;
 pub mod hash;
 pub mod jwt;
 pub mod redis_keys;
+pub mod token;
+pub mod validation;
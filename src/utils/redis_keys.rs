@@ -13,3 +13,48 @@ pub fn blacklist_key(jti: &str) -> String {
 pub fn user_permissions_key(user_id: i64) -> String {
     format!("user:{}:perms", user_id)
 }
+pub fn family_key(family: &str) -> String {
+    format!("family:{}", family)
+} // current access jti of a session family
+pub fn mfa_pending_key(token_hash: &str) -> String {
+    format!("mfa:{}", token_hash)
+} // login waiting for the second factor
+pub fn mfa_attempts_key(token_hash: &str) -> String {
+    format!("mfa:{}:attempts", token_hash)
+} // second-factor attempts of a pending login (INCR)
+pub fn totp_used_key(user_id: i64, step: u64) -> String {
+    format!("user:{}:totp:{}", user_id, step)
+} // prevents replaying a code within its window
+pub fn password_reset_key(token_hash: &str) -> String {
+    format!("password_reset:{}", token_hash)
+} // single-use reset token -> user id
+pub fn email_verify_key(token_hash: &str) -> String {
+    format!("email_verify:{}", token_hash)
+} // pending verification -> {user_id, email}
+pub fn login_failures_key(subject: &str) -> String {
+    format!("login_failures:{}", subject)
+} // failed logins in the current window (subject = user:{id} | login:{name} | ip:{ip})
+pub fn login_delay_key(subject: &str) -> String {
+    format!("login_delay:{}", subject)
+} // progressive backoff, TTL = delay
+pub fn login_lock_key(subject: &str) -> String {
+    format!("login_lock:{}", subject)
+} // temporary lockout, TTL = lockout
+pub fn rate_limit_key(policy: &str, subject: &str, window: u64) -> String {
+    format!("ratelimit:{}:{}:{}", policy, subject, window)
+} // requests in one fixed window of a policy
+pub fn oauth_code_key(code_hash: &str) -> String {
+    format!("oauth_code:{}", code_hash)
+} // single-use authorization code -> AuthorizationCode
+pub fn authorize_form_key(token_hash: &str) -> String {
+    format!("authorize_form:{}", token_hash)
+} // single-use CSRF token of a rendered /oauth/authorize page -> client_id
+pub fn federated_login_key(state_hash: &str) -> String {
+    format!("federated_login:{}", state_hash)
+} // pending upstream OIDC login -> FederatedLogin
+pub fn magic_link_key(token_hash: &str) -> String {
+    format!("magic_link:{}", token_hash)
+} // single-use sign-in link token -> user id
+pub fn webauthn_challenge_key(challenge_hash: &str) -> String {
+    format!("webauthn_challenge:{}", challenge_hash)
+} // pending WebAuthn ceremony -> WebauthnChallenge
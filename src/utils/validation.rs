@@ -0,0 +1,131 @@
+use axum::{
+    Json, async_trait,
+    extract::{FromRequest, Request, rejection::JsonRejection},
+};
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::error::AppError;
+
+/// 单个字段的错误，出现在 422 响应的 `errors` 数组中
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// 收集一个请求体中所有字段的错误
+#[derive(Debug, Default)]
+pub struct FieldErrors(Vec<FieldError>);
+
+impl FieldErrors {
+    pub fn add(&mut self, field: &str, message: impl Into<String>) {
+        self.0.push(FieldError {
+            field: field.to_string(),
+            message: message.into(),
+        });
+    }
+
+    /// 规则返回 Err 时记录到该字段
+    pub fn check<T>(&mut self, field: &str, result: Result<T, String>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(message) => {
+                self.add(field, message);
+                None
+            }
+        }
+    }
+
+    pub fn into_result(self) -> Result<(), AppError> {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        Err(AppError::InvalidFields(self.0))
+    }
+}
+
+/// 请求 DTO 的校验；可以顺便规范化字段（如用户名 trim + 小写）
+pub trait Validate {
+    fn validate(&mut self, errors: &mut FieldErrors);
+}
+
+/// 与 `Json<T>` 相同，但反序列化后执行 `Validate`，
+/// 无法解析的请求体同样返回 problem+json
+pub struct ValidJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ValidJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(mut value) = Json::<T>::from_request(req, state)
+            .await
+            .map_err(|e: JsonRejection| AppError::Validation(e.body_text()))?;
+        let mut errors = FieldErrors::default();
+        value.validate(&mut errors);
+        errors.into_result()?;
+        Ok(Self(value))
+    }
+}
+
+const USERNAME_MIN: usize = 3;
+const USERNAME_MAX: usize = 64;
+
+/// 用户名规范化：去掉首尾空白、转小写；只允许 a-z 0-9 _ . -，以字母或数字开头
+pub fn normalize_username(username: &str) -> Result<String, String> {
+    let username = username.trim().to_lowercase();
+    let length = username.chars().count();
+    if !(USERNAME_MIN..=USERNAME_MAX).contains(&length) {
+        return Err(format!(
+            "must be {} to {} characters",
+            USERNAME_MIN, USERNAME_MAX
+        ));
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '.' | '-'))
+    {
+        return Err("may only contain letters, digits, '_', '.' and '-'".into());
+    }
+    if !username.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        return Err("must start with a letter or digit".into());
+    }
+    Ok(username)
+}
+
+/// 邮箱基本格式：local@domain.tld，不做 RFC 5322 完整解析
+pub fn normalize_email(email: &str) -> Result<String, String> {
+    let email = email.trim();
+    let valid = email.len() <= 254
+        && !email.chars().any(|c| c.is_whitespace() || c.is_control())
+        && matches!(
+            email.split_once('@'),
+            Some((local, domain)) if !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+        );
+    if !valid {
+        return Err("invalid email address".into());
+    }
+    Ok(email.to_string())
+}
+
+/// 非空、不超过 max 字节、不含控制字符
+pub fn bounded_text(value: &str, max: usize) -> Result<(), String> {
+    if value.is_empty() {
+        return Err("must not be empty".into());
+    }
+    if value.len() > max {
+        return Err(format!("must be at most {} bytes", max));
+    }
+    if value.chars().any(char::is_control) {
+        return Err("must not contain control characters".into());
+    }
+    Ok(())
+}
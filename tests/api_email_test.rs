@@ -0,0 +1,220 @@
+use std::{env, net::SocketAddr, sync::Arc};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    extract::ConnectInfo,
+    http::{Request, StatusCode},
+};
+use serde_json::{Value, json};
+use tower::ServiceExt; // for `oneshot`
+use web_backend::db::{init_db_pool, init_redis_pool};
+use web_backend::routes::create_router;
+use web_backend::services::mailer::FileMailer;
+use web_backend::state::AppState;
+
+/// a client address nobody else uses, so tests stay clear of each other's per-ip limits
+fn peer() -> ConnectInfo<SocketAddr> {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    ConnectInfo(SocketAddr::from((
+        [10, bytes[0], bytes[1], bytes[2]],
+        40000,
+    )))
+}
+
+async fn send(app: &Router, mut request: Request<Body>) -> (StatusCode, Value) {
+    request.extensions_mut().get_or_insert_with(peer);
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&body_bytes).unwrap_or(Value::Null),
+    )
+}
+
+fn post_json(uri: &str, token: Option<&str>, payload: &Value) -> Request<Body> {
+    let mut builder = Request::post(uri).header("content-type", "application/json");
+    if let Some(token) = token {
+        builder = builder.header("Authorization", format!("Bearer {}", token));
+    }
+    builder.body(Body::from(payload.to_string())).unwrap()
+}
+
+/// (recipient, token) of the last mail in the outbox file
+fn last_mail(outbox: &str) -> (String, String) {
+    let content = std::fs::read_to_string(outbox).unwrap();
+    let mail: Value = serde_json::from_str(content.lines().last().unwrap()).unwrap();
+    let token = mail["body"]
+        .as_str()
+        .unwrap()
+        .split("token=")
+        .nth(1)
+        .unwrap();
+    (
+        mail["to"].as_str().unwrap().to_string(),
+        token.trim().to_string(),
+    )
+}
+
+async fn test_app() -> (Router, String) {
+    let outbox = env::temp_dir()
+        .join(format!("mail_{}.jsonl", uuid::Uuid::new_v4().simple()))
+        .to_string_lossy()
+        .to_string();
+    let state = AppState::new(
+        init_db_pool().await,
+        init_redis_pool(),
+        env::var("JWT_SECRET").unwrap().into_bytes(),
+    )
+    .with_mailer(Arc::new(FileMailer::new(&outbox)));
+    (create_router(state), outbox)
+}
+
+#[tokio::test]
+async fn test_verify_email_and_login_by_email() {
+    let (app, outbox) = test_app().await;
+    let username = format!("email_test_{}", uuid::Uuid::new_v4().simple());
+    let email = format!("{}@Example.com", username);
+
+    let (status, _) = send(
+        &app,
+        post_json(
+            "/api/register",
+            None,
+            &json!({"username": username, "password": "123456", "email": "not-an-email"}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, _) = send(
+        &app,
+        post_json(
+            "/api/register",
+            None,
+            &json!({"username": username, "password": "123456", "email": email}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (to, token) = last_mail(&outbox);
+    assert_eq!(to, email);
+
+    // an unverified address does not keep anyone else from it
+    let register_rival = |suffix: &str| {
+        post_json(
+            "/api/register",
+            None,
+            &json!({
+                "username": format!("{}_{}", username, suffix),
+                "password": "123456",
+                "email": email.to_uppercase()
+            }),
+        )
+    };
+    let (status, _) = send(&app, register_rival("2")).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, rival_token) = last_mail(&outbox);
+
+    // unverified addresses cannot be used to log in
+    let by_email = json!({"username": email.to_lowercase(), "password": "123456"});
+    let (status, _) = send(&app, post_json("/api/login", None, &by_email)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, body) = send(
+        &app,
+        post_json(
+            "/api/login",
+            None,
+            &json!({"username": username, "password": "123456"}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["user"].get("email").is_none());
+
+    let (status, _) = send(
+        &app,
+        post_json("/api/email/verify", None, &json!({"token": token})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(
+        &app,
+        post_json("/api/email/verify", None, &json!({"token": token})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // once verified the address is taken, whatever the case: the other verification fails
+    let (status, body) = send(
+        &app,
+        post_json("/api/email/verify", None, &json!({"token": rival_token})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["detail"], "email already in use");
+    let (status, _) = send(&app, register_rival("3")).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, body) = send(&app, post_json("/api/login", None, &by_email)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["email"], email);
+
+    let _ = std::fs::remove_file(&outbox);
+}
+
+#[tokio::test]
+async fn test_change_email_invalidates_old_verification() {
+    let (app, outbox) = test_app().await;
+    let username = format!("email_test_{}", uuid::Uuid::new_v4().simple());
+    let credentials = json!({"username": username, "password": "123456"});
+    send(&app, post_json("/api/register", None, &credentials)).await;
+    let (_, body) = send(&app, post_json("/api/login", None, &credentials)).await;
+    let access = body["access_token"].as_str().unwrap().to_string();
+
+    let first = format!("{}@example.com", username);
+    let (status, _) = send(
+        &app,
+        post_json("/api/me/email", Some(&access), &json!({"email": first})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, stale_token) = last_mail(&outbox);
+
+    let second = format!("{}@example.org", username);
+    send(
+        &app,
+        post_json("/api/me/email", Some(&access), &json!({"email": second})),
+    )
+    .await;
+    let (to, token) = last_mail(&outbox);
+    assert_eq!(to, second);
+
+    let (status, _) = send(
+        &app,
+        post_json("/api/email/verify", None, &json!({"token": stale_token})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send(
+        &app,
+        post_json("/api/email/verify", None, &json!({"token": token})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = send(
+        &app,
+        post_json(
+            "/api/login",
+            None,
+            &json!({"username": second, "password": "123456"}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let _ = std::fs::remove_file(&outbox);
+}
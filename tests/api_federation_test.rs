@@ -0,0 +1,611 @@
+use std::{
+    collections::HashMap,
+    fs,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    Form, Json, Router,
+    body::{Body, to_bytes},
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, Request, StatusCode},
+    routing::{get, post},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use jsonwebtoken::{Algorithm, Header, encode};
+use serde_json::{Value, json};
+use tokio::net::TcpListener;
+use tower::ServiceExt; // for `oneshot`
+use url::Url;
+use web_backend::auth::keys::JwtKey;
+use web_backend::db::{init_db_pool, init_redis_pool};
+use web_backend::error::AppError;
+use web_backend::repositories::user_repo::create_federated_user;
+use web_backend::routes::create_router;
+use web_backend::services::oauth_service::pkce_challenge;
+use web_backend::services::upstream_oidc::UpstreamProvider;
+use web_backend::state::AppState;
+
+const PROVIDER: &str = "mock";
+const CLIENT_ID: &str = "backend";
+const CLIENT_SECRET: &str = "mock-secret";
+
+fn load_key(alg: Algorithm, name: &str) -> JwtKey {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/keys");
+    let private_pem = fs::read(format!("{}/{}_private.pem", dir, name)).unwrap();
+    let public_pem = fs::read(format!("{}/{}_public.pem", dir, name)).unwrap();
+    JwtKey::from_pem(alg, &private_pem, &public_pem, None).unwrap()
+}
+
+/// 用户在上游完成登录后，上游为这个授权码记住的信息
+struct Grant {
+    claims: Value,
+    code_challenge: String,
+    redirect_uri: String,
+}
+
+/// 本地的模拟 issuer：discovery、JWKS 和 token 端点，ID token 用 RSA fixture 密钥签名
+struct MockIssuer {
+    issuer: String,
+    /// iss in discovery and ID tokens; some providers (Auth0) spell it with a trailing slash
+    iss: String,
+    key: JwtKey,
+    /// 签名 ID token 的密钥（默认是 key；换成不在 JWKS 中的密钥模拟伪造的 token）
+    signing_key: Mutex<Option<JwtKey>>,
+    grants: Mutex<HashMap<String, Grant>>,
+}
+
+impl MockIssuer {
+    async fn start() -> Arc<Self> {
+        Self::start_with_trailing_slash(false).await
+    }
+
+    async fn start_with_trailing_slash(trailing_slash: bool) -> Arc<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let mock = Arc::new(Self {
+            iss: if trailing_slash {
+                format!("{}/", issuer)
+            } else {
+                issuer.clone()
+            },
+            issuer,
+            key: load_key(Algorithm::RS256, "rsa"),
+            signing_key: Mutex::new(None),
+            grants: Mutex::new(HashMap::new()),
+        });
+        let app = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/jwks", get(jwks))
+            .route("/token", post(token))
+            .with_state(mock.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        mock
+    }
+
+    fn provider(&self) -> UpstreamProvider {
+        UpstreamProvider::new(PROVIDER, &self.issuer, CLIENT_ID, CLIENT_SECRET)
+    }
+
+    /// 模拟用户在上游登录并同意：检查授权请求，返回回调地址
+    fn authorize(&self, authorization_url: &Url, claims: Value) -> String {
+        let params: HashMap<_, _> = authorization_url.query_pairs().into_owned().collect();
+        assert!(authorization_url.as_str().starts_with(&self.issuer));
+        assert_eq!(params["client_id"], CLIENT_ID);
+        assert_eq!(params["response_type"], "code");
+        assert_eq!(params["code_challenge_method"], "S256");
+        let mut claims = claims;
+        claims["nonce"] = json!(params["nonce"]);
+        let code = uuid::Uuid::new_v4().simple().to_string();
+        self.grants.lock().unwrap().insert(
+            code.clone(),
+            Grant {
+                claims,
+                code_challenge: params["code_challenge"].clone(),
+                redirect_uri: params["redirect_uri"].clone(),
+            },
+        );
+        let callback = Url::parse(&params["redirect_uri"]).unwrap();
+        format!(
+            "{}?code={}&state={}",
+            callback.path(),
+            code,
+            params["state"]
+        )
+    }
+}
+
+async fn discovery(State(mock): State<Arc<MockIssuer>>) -> Json<Value> {
+    Json(json!({
+        "issuer": mock.iss,
+        "authorization_endpoint": format!("{}/authorize", mock.issuer),
+        "token_endpoint": format!("{}/token", mock.issuer),
+        "jwks_uri": format!("{}/jwks", mock.issuer),
+    }))
+}
+
+async fn jwks(State(mock): State<Arc<MockIssuer>>) -> Json<Value> {
+    Json(json!({ "keys": [mock.key.jwk().unwrap()] }))
+}
+
+async fn token(
+    State(mock): State<Arc<MockIssuer>>,
+    headers: HeaderMap,
+    Form(form): Form<HashMap<String, String>>,
+) -> (StatusCode, Json<Value>) {
+    let basic = format!(
+        "Basic {}",
+        STANDARD.encode(format!("{}:{}", CLIENT_ID, CLIENT_SECRET))
+    );
+    if headers.get("authorization").and_then(|v| v.to_str().ok()) != Some(basic.as_str()) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({"error": "invalid_client"})),
+        );
+    }
+    let grant = mock.grants.lock().unwrap().remove(&form["code"]);
+    let Some(grant) = grant.filter(|g| {
+        g.redirect_uri == form["redirect_uri"]
+            && g.code_challenge == pkce_challenge(&form["code_verifier"])
+    }) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "invalid_grant"})),
+        );
+    };
+
+    let now = chrono::Utc::now().timestamp();
+    let mut claims = json!({
+        "iss": mock.iss,
+        "aud": CLIENT_ID,
+        "iat": now,
+        "exp": now + 300,
+    });
+    for (key, value) in grant.claims.as_object().unwrap() {
+        claims[key] = value.clone();
+    }
+    let signing_key = mock.signing_key.lock().unwrap();
+    let key = signing_key.as_ref().unwrap_or(&mock.key);
+    let mut header = Header::new(key.alg);
+    header.kid = Some(mock.key.kid.clone());
+    let id_token = encode(&header, &claims, key.encoding_key()).unwrap();
+    (
+        StatusCode::OK,
+        Json(json!({"access_token": "upstream", "token_type": "Bearer", "id_token": id_token})),
+    )
+}
+
+/// a client address nobody else uses, so tests stay clear of each other's per-ip limits
+fn peer() -> ConnectInfo<SocketAddr> {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    ConnectInfo(SocketAddr::from((
+        [10, bytes[0], bytes[1], bytes[2]],
+        40000,
+    )))
+}
+
+async fn send(app: &Router, mut request: Request<Body>) -> (StatusCode, Option<Url>, Value) {
+    request.extensions_mut().get_or_insert_with(peer);
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let location = response
+        .headers()
+        .get("location")
+        .map(|v| Url::parse(v.to_str().unwrap()).unwrap());
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        location,
+        serde_json::from_slice(&body_bytes).unwrap_or(Value::Null),
+    )
+}
+
+/// like `send`, for responses that start a login: also returns the state cookie they set
+async fn start(
+    app: &Router,
+    mut request: Request<Body>,
+) -> (StatusCode, Option<Url>, Value, String) {
+    request.extensions_mut().get_or_insert_with(peer);
+    let response = app.clone().oneshot(request).await.unwrap();
+    let cookie = response
+        .headers()
+        .get("set-cookie")
+        .map(|v| v.to_str().unwrap().split(';').next().unwrap().to_string())
+        .unwrap_or_default();
+    let status = response.status();
+    let location = response
+        .headers()
+        .get("location")
+        .map(|v| Url::parse(v.to_str().unwrap()).unwrap());
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body = serde_json::from_slice(&body_bytes).unwrap_or(Value::Null);
+    (status, location, body, cookie)
+}
+
+/// the browser following the upstream redirect back, with its cookie
+fn callback_request(uri: &str, cookie: &str) -> Request<Body> {
+    Request::get(uri)
+        .header("Cookie", cookie)
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn request(method: &str, uri: &str, token: Option<&str>, body: Option<Value>) -> Request<Body> {
+    let mut builder = Request::builder().method(method).uri(uri);
+    if let Some(token) = token {
+        builder = builder.header("Authorization", format!("Bearer {}", token));
+    }
+    match body {
+        Some(body) => builder
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap(),
+        None => builder.body(Body::empty()).unwrap(),
+    }
+}
+
+struct Fixture {
+    app: Router,
+    state: AppState,
+    mock: Arc<MockIssuer>,
+    default_role: String,
+}
+
+async fn setup() -> Fixture {
+    setup_with(MockIssuer::start().await).await
+}
+
+async fn setup_with(mock: Arc<MockIssuer>) -> Fixture {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let mut state = AppState::new(pg_pool, redis_pool, b"test-secret".to_vec());
+    state.oidc_issuer = "https://auth.test".to_string();
+
+    let default_role = format!("sso_{}", uuid::Uuid::new_v4().simple());
+    sqlx::query("INSERT INTO roles (name) VALUES ($1)")
+        .bind(&default_role)
+        .execute(&state.db)
+        .await
+        .unwrap();
+    state.upstream_providers = Arc::new(vec![
+        mock.provider()
+            .with_default_roles(vec![default_role.clone(), "no_such_role".to_string()]),
+    ]);
+    Fixture {
+        app: create_router(state.clone()),
+        state,
+        mock,
+        default_role,
+    }
+}
+
+fn upstream_user(name: &str) -> Value {
+    json!({
+        "sub": uuid::Uuid::new_v4().to_string(),
+        "preferred_username": name,
+        "email": format!("{}@corp.example.com", name),
+        "email_verified": true,
+    })
+}
+
+/// 浏览器打开 /auth/mock/login → 上游登录 → 回调
+async fn federated_login(fixture: &Fixture, claims: &Value) -> (StatusCode, Value) {
+    let (status, location, _, cookie) = start(
+        &fixture.app,
+        request("GET", &format!("/auth/{}/login", PROVIDER), None, None),
+    )
+    .await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    let callback = fixture.mock.authorize(&location.unwrap(), claims.clone());
+    let (status, _, body) = send(&fixture.app, callback_request(&callback, &cookie)).await;
+    (status, body)
+}
+
+#[tokio::test]
+async fn first_login_provisions_user_with_default_roles() {
+    let fixture = setup().await;
+    let name = format!("sso{}", &uuid::Uuid::new_v4().simple().to_string()[..12]);
+    let claims = upstream_user(&name);
+
+    let (status, body) = federated_login(&fixture, &claims).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let user_id = body["user"]["id"].as_i64().unwrap();
+    assert_eq!(body["user"]["username"], name.as_str());
+    // the upstream verified the email
+    assert_eq!(body["user"]["email"], claims["email"]);
+
+    let access = body["access_token"].as_str().unwrap();
+    let (_, _, me) = send(&fixture.app, request("GET", "/api/me", Some(access), None)).await;
+    assert_eq!(me["roles"], json!([fixture.default_role]));
+
+    let (_, _, body) = send(
+        &fixture.app,
+        request("GET", "/api/me/identities", Some(access), None),
+    )
+    .await;
+    assert_eq!(body["identities"][0]["provider"], PROVIDER);
+    assert_eq!(body["identities"][0]["subject"], claims["sub"]);
+
+    // the same upstream subject signs in to the same account
+    let (status, body) = federated_login(&fixture, &claims).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["id"], user_id);
+
+    // provisioned users have no password
+    let (status, _, _) = send(
+        &fixture.app,
+        request(
+            "POST",
+            "/api/login",
+            None,
+            Some(json!({"username": name, "password": "!"})),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn taken_username_gets_a_suffix() {
+    let fixture = setup().await;
+    let name = format!("sso{}", &uuid::Uuid::new_v4().simple().to_string()[..12]);
+    let (status, _) = federated_login(&fixture, &upstream_user(&name)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // another upstream user with the same preferred_username but no verified email
+    let mut claims = upstream_user(&name);
+    claims["email_verified"] = json!(false);
+    let (status, body) = federated_login(&fixture, &claims).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let username = body["user"]["username"].as_str().unwrap();
+    assert!(username.starts_with(&format!("{}_", name)), "{}", username);
+    assert!(body["user"].get("email").is_none());
+}
+
+#[tokio::test]
+async fn existing_email_is_not_merged_automatically() {
+    let fixture = setup().await;
+    let name = format!("local{}", &uuid::Uuid::new_v4().simple().to_string()[..12]);
+    let claims = upstream_user(&name);
+    let (_, _, body) = send(
+        &fixture.app,
+        request(
+            "POST",
+            "/api/register",
+            None,
+            Some(json!({"username": name, "password": "123456"})),
+        ),
+    )
+    .await;
+    sqlx::query("UPDATE users SET email = $2, email_verified = TRUE WHERE id = $1")
+        .bind(body["id"].as_i64().unwrap())
+        .bind(claims["email"].as_str().unwrap())
+        .execute(&fixture.state.db)
+        .await
+        .unwrap();
+
+    let (status, _) = federated_login(&fixture, &claims).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let linked: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM user_identities WHERE subject = $1")
+        .bind(claims["sub"].as_str().unwrap())
+        .fetch_one(&fixture.state.db)
+        .await
+        .unwrap();
+    assert_eq!(linked, 0);
+}
+
+#[tokio::test]
+async fn link_and_unlink_identity() {
+    let fixture = setup().await;
+    let name = format!("local{}", &uuid::Uuid::new_v4().simple().to_string()[..12]);
+    let credentials = json!({"username": name, "password": "123456"});
+    let (_, _, body) = send(
+        &fixture.app,
+        request("POST", "/api/register", None, Some(credentials.clone())),
+    )
+    .await;
+    let user_id = body["id"].as_i64().unwrap();
+    let (_, _, body) = send(
+        &fixture.app,
+        request("POST", "/api/login", None, Some(credentials)),
+    )
+    .await;
+    let access = body["access_token"].as_str().unwrap().to_string();
+
+    // start linking while signed in, finish in the same browser
+    let (status, _, body, cookie) = start(
+        &fixture.app,
+        request(
+            "POST",
+            &format!("/api/me/identities/{}", PROVIDER),
+            Some(&access),
+            None,
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let url = Url::parse(body["authorization_url"].as_str().unwrap()).unwrap();
+    let claims = upstream_user(&format!("corp{}", user_id));
+    // a victim's browser lured to the callback has no matching cookie
+    let callback = fixture.mock.authorize(&url, claims.clone());
+    let (status, _, body) = send(&fixture.app, request("GET", &callback, None, None)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["detail"], "login was not started in this browser");
+    let (status, _, body) = send(&fixture.app, callback_request(&callback, &cookie)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["linked"]["subject"], claims["sub"]);
+
+    // the upstream identity now signs in to the local account
+    let (status, body) = federated_login(&fixture, &claims).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["id"], user_id);
+
+    let (status, _, _) = send(
+        &fixture.app,
+        request(
+            "DELETE",
+            &format!("/api/me/identities/{}", PROVIDER),
+            Some(&access),
+            None,
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, _, body) = send(
+        &fixture.app,
+        request("GET", "/api/me/identities", Some(&access), None),
+    )
+    .await;
+    assert_eq!(body["identities"], json!([]));
+}
+
+#[tokio::test]
+async fn identity_linked_elsewhere_cannot_be_linked_again() {
+    let fixture = setup().await;
+    let claims = upstream_user(&format!(
+        "sso{}",
+        &uuid::Uuid::new_v4().simple().to_string()[..12]
+    ));
+    let (status, _) = federated_login(&fixture, &claims).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let name = format!("local{}", &uuid::Uuid::new_v4().simple().to_string()[..12]);
+    let credentials = json!({"username": name, "password": "123456"});
+    send(
+        &fixture.app,
+        request("POST", "/api/register", None, Some(credentials.clone())),
+    )
+    .await;
+    let (_, _, body) = send(
+        &fixture.app,
+        request("POST", "/api/login", None, Some(credentials)),
+    )
+    .await;
+    let access = body["access_token"].as_str().unwrap().to_string();
+    let (_, _, body, cookie) = start(
+        &fixture.app,
+        request(
+            "POST",
+            &format!("/api/me/identities/{}", PROVIDER),
+            Some(&access),
+            None,
+        ),
+    )
+    .await;
+    let url = Url::parse(body["authorization_url"].as_str().unwrap()).unwrap();
+    let callback = fixture.mock.authorize(&url, claims);
+    let (status, _, _) = send(&fixture.app, callback_request(&callback, &cookie)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn only_sign_in_method_cannot_be_unlinked() {
+    let fixture = setup().await;
+    let name = format!("sso{}", &uuid::Uuid::new_v4().simple().to_string()[..12]);
+    let (_, body) = federated_login(&fixture, &upstream_user(&name)).await;
+    let access = body["access_token"].as_str().unwrap();
+
+    let (status, _, _) = send(
+        &fixture.app,
+        request(
+            "DELETE",
+            &format!("/api/me/identities/{}", PROVIDER),
+            Some(access),
+            None,
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn state_is_single_use() {
+    let fixture = setup().await;
+    let (_, location, _, cookie) = start(
+        &fixture.app,
+        request("GET", &format!("/auth/{}/login", PROVIDER), None, None),
+    )
+    .await;
+    let location = location.unwrap();
+    let name = format!("sso{}", &uuid::Uuid::new_v4().simple().to_string()[..12]);
+    let callback = fixture.mock.authorize(&location, upstream_user(&name));
+    let (status, _, _) = send(&fixture.app, callback_request(&callback, &cookie)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // replaying the callback with a fresh code but the used state
+    let callback = fixture.mock.authorize(&location, upstream_user(&name));
+    let (status, _, body) = send(&fixture.app, callback_request(&callback, &cookie)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["detail"], "invalid or expired login state");
+}
+
+#[tokio::test]
+async fn forged_id_token_is_rejected() {
+    let fixture = setup().await;
+    // signed with a key that is not in the upstream JWKS
+    *fixture.mock.signing_key.lock().unwrap() = Some(load_key(Algorithm::RS256, "rsa_other"));
+    let name = format!("sso{}", &uuid::Uuid::new_v4().simple().to_string()[..12]);
+    let (status, body) = federated_login(&fixture, &upstream_user(&name)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["detail"], "invalid id token");
+}
+
+#[tokio::test]
+async fn upstream_errors_and_unknown_providers() {
+    let fixture = setup().await;
+    let (status, _, _) = send(&fixture.app, request("GET", "/auth/nope/login", None, None)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _, body) = send(
+        &fixture.app,
+        request(
+            "GET",
+            &format!("/auth/{}/callback?error=access_denied&state=x", PROVIDER),
+            None,
+            None,
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(body["detail"].as_str().unwrap().contains("access_denied"));
+}
+
+#[tokio::test]
+async fn provisioning_leaves_nothing_behind_on_failure() {
+    let fixture = setup().await;
+    let name = format!("sso{}", &uuid::Uuid::new_v4().simple().to_string()[..12]);
+    let claims = upstream_user(&name);
+    let (status, _) = federated_login(&fixture, &claims).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // a concurrent first login for the same subject loses on the identity insert
+    let username = format!("{}_race", name);
+    let err = create_federated_user(
+        &fixture.state.db,
+        &username,
+        None,
+        &[],
+        PROVIDER,
+        claims["sub"].as_str().unwrap(),
+        None,
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, AppError::Conflict(_)));
+    let orphans: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE username = $1")
+        .bind(&username)
+        .fetch_one(&fixture.state.db)
+        .await
+        .unwrap();
+    assert_eq!(orphans, 0);
+}
+
+#[tokio::test]
+async fn issuer_with_trailing_slash() {
+    let fixture = setup_with(MockIssuer::start_with_trailing_slash(true).await).await;
+    let name = format!("sso{}", &uuid::Uuid::new_v4().simple().to_string()[..12]);
+    let (status, body) = federated_login(&fixture, &upstream_user(&name)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["user"]["username"], name.as_str());
+}
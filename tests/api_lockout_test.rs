@@ -0,0 +1,196 @@
+use std::{env, net::SocketAddr, time::Duration};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    extract::ConnectInfo,
+    http::{Request, StatusCode},
+};
+use serde_json::{Value, json};
+use tower::ServiceExt; // for `oneshot`
+use web_backend::db::{init_db_pool, init_redis_pool};
+use web_backend::routes::create_router;
+use web_backend::state::AppState;
+
+/// a client address nobody else uses, so tests stay clear of each other's per-ip limits
+fn peer() -> ConnectInfo<SocketAddr> {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    ConnectInfo(SocketAddr::from((
+        [10, bytes[0], bytes[1], bytes[2]],
+        40000,
+    )))
+}
+
+async fn send(app: &Router, mut request: Request<Body>) -> (StatusCode, Option<String>, Value) {
+    request.extensions_mut().get_or_insert_with(peer);
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let retry_after = response
+        .headers()
+        .get("retry-after")
+        .map(|v| v.to_str().unwrap().to_string());
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        retry_after,
+        serde_json::from_slice(&body_bytes).unwrap_or(Value::Null),
+    )
+}
+
+fn login(username: &str, password: &str) -> Request<Body> {
+    Request::post("/api/login")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            json!({"username": username, "password": password}).to_string(),
+        ))
+        .unwrap()
+}
+
+async fn test_state() -> AppState {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+    let mut state = AppState::new(pg_pool, redis_pool, jwt_secret);
+    state.login_max_failures = 3;
+    state
+}
+
+/// the statuses seen by an attacker: 401, 401, 429 (backoff), 401 (locked), 429
+async fn fail_until_locked(app: &Router, username: &str) -> Vec<Value> {
+    let mut bodies = vec![];
+    for _ in 0..2 {
+        let (status, _, body) = send(app, login(username, "wrong-password")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        bodies.push(body);
+    }
+    let (status, retry_after, _) = send(app, login(username, "wrong-password")).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(retry_after.as_deref(), Some("1"));
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let (status, _, body) = send(app, login(username, "wrong-password")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    bodies.push(body);
+    bodies
+}
+
+#[tokio::test]
+async fn test_lockout_and_admin_unlock() {
+    let state = test_state().await;
+    let app = create_router(state.clone());
+
+    let username = format!("lockout_{}", uuid::Uuid::new_v4().simple());
+    let register = Request::post("/api/register")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            json!({"username": username, "password": "123456"}).to_string(),
+        ))
+        .unwrap();
+    let (status, _, body) = send(&app, register).await;
+    assert_eq!(status, StatusCode::OK);
+    let user_id = body["id"].as_i64().unwrap();
+
+    fail_until_locked(&app, &username).await;
+
+    // locked: even the right password is refused
+    let (status, retry_after, _) = send(&app, login(&username, "123456")).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(retry_after.unwrap().parse::<u64>().unwrap() > 60);
+
+    // an admin lifts the lock
+    let admin = format!("lockout_admin_{}", uuid::Uuid::new_v4().simple());
+    let register = Request::post("/api/register")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            json!({"username": admin, "password": "123456"}).to_string(),
+        ))
+        .unwrap();
+    let (_, _, body) = send(&app, register).await;
+    grant_permission(&state, body["id"].as_i64().unwrap(), "admin:users").await;
+    let (_, _, body) = send(&app, login(&admin, "123456")).await;
+    let admin_token = body["access_token"].as_str().unwrap().to_string();
+
+    let unlock = |token: &str| {
+        Request::post(format!("/api/admin/users/{}/unlock", user_id))
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap()
+    };
+    let (status, _, _) = send(&app, unlock(&admin_token)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _, _) = send(&app, login(&username, "123456")).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_unknown_user_is_indistinguishable() {
+    let state = test_state().await;
+    let app = create_router(state);
+
+    let username = format!("lockout_nobody_{}", uuid::Uuid::new_v4().simple());
+    let bodies = fail_until_locked(&app, &username).await;
+    for body in bodies {
+        assert_eq!(body["type"], "/problems/invalid-credentials");
+        assert_eq!(body["detail"], "invalid credentials");
+    }
+    let (status, _, _) = send(&app, login(&username, "anything")).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn test_ip_counter_ignores_spoofed_forwarded_for() {
+    let state = test_state().await;
+    let app = create_router(state);
+
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    let peer: SocketAddr = format!("10.{}.{}.{}:40000", bytes[0], bytes[1], bytes[2])
+        .parse()
+        .unwrap();
+    // a different account and X-Forwarded-For every time, from the same connection address
+    let attempt = |i: usize| {
+        let mut request = login(&format!("lockout_spray_{}_{}", i, peer.ip()), "wrong");
+        request.extensions_mut().insert(ConnectInfo(peer));
+        request.headers_mut().insert(
+            "x-forwarded-for",
+            format!("198.51.100.{}", i).parse().unwrap(),
+        );
+        request
+    };
+    for i in 0..2 {
+        let (status, _, _) = send(&app, attempt(i)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+    let (status, retry_after, _) = send(&app, attempt(2)).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(retry_after.as_deref(), Some("1"));
+}
+
+async fn grant_permission(state: &AppState, user_id: i64, permission: &str) {
+    let role = format!("role_{}", uuid::Uuid::new_v4().simple());
+    let role_id: i64 = sqlx::query_scalar("INSERT INTO roles (name) VALUES ($1) RETURNING id")
+        .bind(&role)
+        .fetch_one(&state.db)
+        .await
+        .unwrap();
+    let permission_id: i64 = sqlx::query_scalar(
+        "INSERT INTO permissions (code) VALUES ($1)
+         ON CONFLICT (code) DO UPDATE SET code = EXCLUDED.code RETURNING id",
+    )
+    .bind(permission)
+    .fetch_one(&state.db)
+    .await
+    .unwrap();
+    sqlx::query("INSERT INTO role_permissions (role_id, permission_id) VALUES ($1, $2)")
+        .bind(role_id)
+        .bind(permission_id)
+        .execute(&state.db)
+        .await
+        .unwrap();
+    sqlx::query("INSERT INTO user_roles (user_id, role_id) VALUES ($1, $2)")
+        .bind(user_id)
+        .bind(role_id)
+        .execute(&state.db)
+        .await
+        .unwrap();
+}
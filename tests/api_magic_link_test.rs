@@ -0,0 +1,237 @@
+use std::{env, net::SocketAddr, sync::Arc};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    extract::ConnectInfo,
+    http::{Request, StatusCode},
+};
+use serde_json::{Value, json};
+use tower::ServiceExt; // for `oneshot`
+use web_backend::db::{init_db_pool, init_redis_pool};
+use web_backend::routes::create_router;
+use web_backend::services::mailer::FileMailer;
+use web_backend::state::AppState;
+
+/// a client address nobody else uses, so tests stay clear of each other's per-ip limits
+fn peer() -> ConnectInfo<SocketAddr> {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    ConnectInfo(SocketAddr::from((
+        [10, bytes[0], bytes[1], bytes[2]],
+        40000,
+    )))
+}
+
+async fn send(app: &Router, mut request: Request<Body>) -> (StatusCode, Value) {
+    request.extensions_mut().get_or_insert_with(peer);
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&body_bytes).unwrap_or(Value::Null),
+    )
+}
+
+fn post_json(uri: &str, payload: &Value) -> Request<Body> {
+    Request::post(uri)
+        .header("content-type", "application/json")
+        .body(Body::from(payload.to_string()))
+        .unwrap()
+}
+
+fn get(uri: &str, token: &str) -> Request<Body> {
+    Request::get(uri)
+        .header("Authorization", format!("Bearer {}", token))
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// the token from the last mail in the outbox file
+fn last_mail_token(outbox: &str) -> Option<String> {
+    let content = std::fs::read_to_string(outbox).ok()?;
+    let mail: Value = serde_json::from_str(content.lines().last()?).unwrap();
+    let body = mail["body"].as_str().unwrap();
+    let token = body.split("token=").nth(1)?;
+    Some(token.split_whitespace().next()?.to_string())
+}
+
+struct Fixture {
+    app: Router,
+    state: AppState,
+    outbox: String,
+}
+
+async fn setup(max_sessions: usize) -> Fixture {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+    let outbox = env::temp_dir()
+        .join(format!("mail_{}.jsonl", uuid::Uuid::new_v4().simple()))
+        .to_string_lossy()
+        .to_string();
+    let mut state = AppState::new(pg_pool, redis_pool, jwt_secret)
+        .with_mailer(Arc::new(FileMailer::new(&outbox)));
+    state.max_sessions_per_user = max_sessions;
+    Fixture {
+        app: create_router(state.clone()),
+        state,
+        outbox,
+    }
+}
+
+/// registers a user with a verified email, returns (id, username, email)
+async fn create_user(fixture: &Fixture) -> (i64, String, String) {
+    let username = format!("magic_{}", uuid::Uuid::new_v4().simple());
+    let email = format!("{}@example.com", username);
+    let (status, body) = send(
+        &fixture.app,
+        post_json(
+            "/api/register",
+            &json!({"username": username, "password": "123456"}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let id = body["id"].as_i64().unwrap();
+    sqlx::query("UPDATE users SET email = $2, email_verified = TRUE WHERE id = $1")
+        .bind(id)
+        .bind(&email)
+        .execute(&fixture.state.db)
+        .await
+        .unwrap();
+    (id, username, email)
+}
+
+async fn request_link(fixture: &Fixture, login: &str) -> Option<String> {
+    let (status, body) = send(
+        &fixture.app,
+        post_json("/api/login/magic", &json!({"username": login})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({"ok": true}));
+    last_mail_token(&fixture.outbox)
+}
+
+#[tokio::test]
+async fn magic_link_signs_in_once() {
+    let fixture = setup(5).await;
+    let (id, _, email) = create_user(&fixture).await;
+
+    let token = request_link(&fixture, &email).await.unwrap();
+    let (status, body) = send(
+        &fixture.app,
+        post_json(
+            "/api/login/magic/verify",
+            &json!({"token": token, "device": "laptop"}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["id"], id);
+    let access = body["access_token"].as_str().unwrap().to_string();
+    assert!(body["refresh_token"].is_string());
+
+    let (status, me) = send(&fixture.app, get("/api/me", &access)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(me["id"], id);
+    let (_, sessions) = send(&fixture.app, get("/api/sessions", &access)).await;
+    assert_eq!(sessions["sessions"][0]["device"], "laptop");
+
+    // single use
+    let (status, body) = send(
+        &fixture.app,
+        post_json("/api/login/magic/verify", &json!({"token": token})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["detail"], "invalid or expired sign-in link");
+}
+
+#[tokio::test]
+async fn no_mail_without_verified_email() {
+    let fixture = setup(5).await;
+    let (id, username, _) = create_user(&fixture).await;
+
+    assert_eq!(request_link(&fixture, "nobody@example.com").await, None);
+
+    sqlx::query("UPDATE users SET email_verified = FALSE WHERE id = $1")
+        .bind(id)
+        .execute(&fixture.state.db)
+        .await
+        .unwrap();
+    assert_eq!(request_link(&fixture, &username).await, None);
+}
+
+#[tokio::test]
+async fn disabled_user_cannot_use_link() {
+    let fixture = setup(5).await;
+    let (id, username, _) = create_user(&fixture).await;
+    let token = request_link(&fixture, &username).await.unwrap();
+
+    sqlx::query("UPDATE users SET disabled = TRUE WHERE id = $1")
+        .bind(id)
+        .execute(&fixture.state.db)
+        .await
+        .unwrap();
+    let (status, _) = send(
+        &fixture.app,
+        post_json("/api/login/magic/verify", &json!({"token": token})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn two_factor_is_still_required() {
+    let fixture = setup(5).await;
+    let (id, username, _) = create_user(&fixture).await;
+    sqlx::query(
+        "UPDATE users SET totp_secret = 'JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP', totp_enabled = TRUE WHERE id = $1",
+    )
+    .bind(id)
+    .execute(&fixture.state.db)
+    .await
+    .unwrap();
+
+    let token = request_link(&fixture, &username).await.unwrap();
+    let (status, body) = send(
+        &fixture.app,
+        post_json("/api/login/magic/verify", &json!({"token": token})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["mfa_required"], true);
+    assert!(body.get("access_token").is_none());
+}
+
+#[tokio::test]
+async fn session_limit_applies() {
+    let fixture = setup(1).await;
+    let (_, username, _) = create_user(&fixture).await;
+    let (_, body) = send(
+        &fixture.app,
+        post_json(
+            "/api/login",
+            &json!({"username": username, "password": "123456"}),
+        ),
+    )
+    .await;
+    let password_access = body["access_token"].as_str().unwrap().to_string();
+
+    let token = request_link(&fixture, &username).await.unwrap();
+    let (status, body) = send(
+        &fixture.app,
+        post_json("/api/login/magic/verify", &json!({"token": token})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let magic_access = body["access_token"].as_str().unwrap().to_string();
+
+    // the oldest session was evicted
+    let (status, _) = send(&fixture.app, get("/api/me", &password_access)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send(&fixture.app, get("/api/me", &magic_access)).await;
+    assert_eq!(status, StatusCode::OK);
+}
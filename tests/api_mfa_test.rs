@@ -0,0 +1,301 @@
+use std::{env, net::SocketAddr};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    extract::ConnectInfo,
+    http::{Request, StatusCode},
+};
+use serde_json::{Value, json};
+use totp_rs::{Algorithm, Secret, TOTP};
+use tower::ServiceExt; // for `oneshot`
+use web_backend::db::{init_db_pool, init_redis_pool};
+use web_backend::routes::create_router;
+use web_backend::state::AppState;
+
+/// a client address nobody else uses, so tests stay clear of each other's per-ip limits
+fn peer() -> ConnectInfo<SocketAddr> {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    ConnectInfo(SocketAddr::from((
+        [10, bytes[0], bytes[1], bytes[2]],
+        40000,
+    )))
+}
+
+async fn send(app: &Router, mut request: Request<Body>) -> (StatusCode, Value) {
+    request.extensions_mut().get_or_insert_with(peer);
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&body_bytes).unwrap_or(Value::Null),
+    )
+}
+
+fn post_json(uri: &str, token: Option<&str>, payload: &Value) -> Request<Body> {
+    let mut builder = Request::post(uri).header("content-type", "application/json");
+    if let Some(token) = token {
+        builder = builder.header("Authorization", format!("Bearer {}", token));
+    }
+    builder.body(Body::from(payload.to_string())).unwrap()
+}
+
+fn totp_code(secret: &str, time: u64) -> String {
+    let totp = TOTP::new_unchecked(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        Secret::Encoded(secret.to_string()).to_bytes().unwrap(),
+        None,
+        "test".to_string(),
+    );
+    totp.generate(time)
+}
+
+fn now() -> u64 {
+    chrono::Utc::now().timestamp() as u64
+}
+
+#[tokio::test]
+async fn test_totp_enroll_and_login() {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+    let app = create_router(AppState::new(pg_pool, redis_pool, jwt_secret));
+
+    let username = format!("mfa_test_{}", uuid::Uuid::new_v4().simple());
+    let credentials = json!({"username": username, "password": "123456"});
+    let (status, _) = send(&app, post_json("/api/register", None, &credentials)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, body) = send(&app, post_json("/api/login", None, &credentials)).await;
+    let access = body["access_token"].as_str().unwrap().to_string();
+
+    // enroll + confirm
+    let (status, body) = send(
+        &app,
+        post_json("/api/me/2fa/totp/enroll", Some(&access), &json!({})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let secret = body["secret"].as_str().unwrap().to_string();
+    assert!(
+        body["otpauth_uri"]
+            .as_str()
+            .unwrap()
+            .starts_with("otpauth://totp/")
+    );
+
+    let (status, _) = send(
+        &app,
+        post_json(
+            "/api/me/2fa/totp/confirm",
+            Some(&access),
+            &json!({"code": "000000x"}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, body) = send(
+        &app,
+        post_json(
+            "/api/me/2fa/totp/confirm",
+            Some(&access),
+            &json!({"code": totp_code(&secret, now())}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let recovery_codes: Vec<String> =
+        serde_json::from_value(body["recovery_codes"].clone()).unwrap();
+    assert_eq!(recovery_codes.len(), 10);
+
+    // password alone is no longer enough
+    let (status, body) = send(&app, post_json("/api/login", None, &credentials)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["mfa_required"], true);
+    assert!(body.get("access_token").is_none());
+    let mfa_token = body["mfa_token"].as_str().unwrap().to_string();
+
+    let (status, _) = send(
+        &app,
+        post_json(
+            "/api/login/2fa",
+            None,
+            &json!({"mfa_token": mfa_token, "code": "not-a-code"}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    // the wrong confirm code and this one both count: wait out the backoff
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+    // next window's code is accepted (clock skew)
+    let (status, body) = send(
+        &app,
+        post_json(
+            "/api/login/2fa",
+            None,
+            &json!({"mfa_token": mfa_token, "code": totp_code(&secret, now() + 30)}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["access_token"].is_string());
+
+    // the pending token is single use
+    let (status, _) = send(
+        &app,
+        post_json(
+            "/api/login/2fa",
+            None,
+            &json!({"mfa_token": mfa_token, "code": totp_code(&secret, now())}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_recovery_code_is_single_use() {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+    let app = create_router(AppState::new(pg_pool, redis_pool, jwt_secret));
+
+    let username = format!("mfa_test_{}", uuid::Uuid::new_v4().simple());
+    let credentials = json!({"username": username, "password": "123456"});
+    send(&app, post_json("/api/register", None, &credentials)).await;
+    let (_, body) = send(&app, post_json("/api/login", None, &credentials)).await;
+    let access = body["access_token"].as_str().unwrap().to_string();
+    let (_, body) = send(
+        &app,
+        post_json("/api/me/2fa/totp/enroll", Some(&access), &json!({})),
+    )
+    .await;
+    let secret = body["secret"].as_str().unwrap().to_string();
+    let (_, body) = send(
+        &app,
+        post_json(
+            "/api/me/2fa/totp/confirm",
+            Some(&access),
+            &json!({"code": totp_code(&secret, now())}),
+        ),
+    )
+    .await;
+    let recovery_code = body["recovery_codes"][0].as_str().unwrap().to_uppercase();
+
+    for expected in [StatusCode::OK, StatusCode::UNAUTHORIZED] {
+        let (_, body) = send(&app, post_json("/api/login", None, &credentials)).await;
+        let mfa_token = body["mfa_token"].as_str().unwrap();
+        let (status, _) = send(
+            &app,
+            post_json(
+                "/api/login/2fa",
+                None,
+                &json!({"mfa_token": mfa_token, "code": recovery_code}),
+            ),
+        )
+        .await;
+        assert_eq!(status, expected);
+    }
+}
+
+#[tokio::test]
+async fn test_second_factor_failures_lock_the_account() {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+    let mut state = AppState::new(pg_pool, redis_pool, jwt_secret);
+    state.login_max_failures = 3;
+    let app = create_router(state);
+
+    let username = format!("mfa_test_{}", uuid::Uuid::new_v4().simple());
+    let credentials = json!({"username": username, "password": "123456"});
+    send(&app, post_json("/api/register", None, &credentials)).await;
+    let (_, body) = send(&app, post_json("/api/login", None, &credentials)).await;
+    let access = body["access_token"].as_str().unwrap().to_string();
+    let (_, body) = send(
+        &app,
+        post_json("/api/me/2fa/totp/enroll", Some(&access), &json!({})),
+    )
+    .await;
+    let secret = body["secret"].as_str().unwrap().to_string();
+    send(
+        &app,
+        post_json(
+            "/api/me/2fa/totp/confirm",
+            Some(&access),
+            &json!({"code": totp_code(&secret, now())}),
+        ),
+    )
+    .await;
+
+    // signing in with the password again does not reset the failed codes
+    for _ in 0..3 {
+        let (status, body) = send(&app, post_json("/api/login", None, &credentials)).await;
+        assert_eq!(status, StatusCode::OK);
+        let mfa_token = body["mfa_token"].as_str().unwrap();
+        let (status, _) = send(
+            &app,
+            post_json(
+                "/api/login/2fa",
+                None,
+                &json!({"mfa_token": mfa_token, "code": "000000"}),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        // wait out the backoff
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    }
+    let (status, _) = send(&app, post_json("/api/login", None, &credentials)).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn test_disable_totp_guesses_count_against_the_account() {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+    let app = create_router(AppState::new(pg_pool, redis_pool, jwt_secret));
+
+    let username = format!("mfa_test_{}", uuid::Uuid::new_v4().simple());
+    let credentials = json!({"username": username, "password": "123456"});
+    send(&app, post_json("/api/register", None, &credentials)).await;
+    let (_, body) = send(&app, post_json("/api/login", None, &credentials)).await;
+    let access = body["access_token"].as_str().unwrap().to_string();
+    let (_, body) = send(
+        &app,
+        post_json("/api/me/2fa/totp/enroll", Some(&access), &json!({})),
+    )
+    .await;
+    let secret = body["secret"].as_str().unwrap().to_string();
+    let (status, _) = send(
+        &app,
+        post_json(
+            "/api/me/2fa/totp/confirm",
+            Some(&access),
+            &json!({"code": totp_code(&secret, now())}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // a stolen session cannot guess its way to switching 2FA off
+    let disable = |code: String| {
+        post_json(
+            "/api/me/2fa/totp/disable",
+            Some(&access),
+            &json!({"code": code}),
+        )
+    };
+    for guess in ["000000", "111111"] {
+        let (status, _) = send(&app, disable(guess.to_string())).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    let (status, _) = send(&app, disable(totp_code(&secret, now() + 30))).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+}
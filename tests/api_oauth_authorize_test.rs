@@ -0,0 +1,434 @@
+use std::{collections::HashMap, env, net::SocketAddr};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    extract::ConnectInfo,
+    http::{Request, StatusCode},
+};
+use serde_json::{Value, json};
+use tower::ServiceExt; // for `oneshot`
+use url::{Url, form_urlencoded};
+use web_backend::db::{init_db_pool, init_redis_pool};
+use web_backend::routes::create_router;
+use web_backend::services::oauth_client_service::register_app_client;
+use web_backend::services::oauth_service::pkce_challenge;
+use web_backend::state::AppState;
+
+const REDIRECT_URI: &str = "http://localhost:5173/callback";
+const VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+
+struct Reply {
+    status: StatusCode,
+    location: Option<Url>,
+    text: String,
+}
+
+impl Reply {
+    fn json(&self) -> Value {
+        serde_json::from_str(&self.text).unwrap_or(Value::Null)
+    }
+
+    /// one-time token of the form on a rendered authorize page
+    fn form_token(&self) -> String {
+        let (_, rest) = self
+            .text
+            .split_once(r#"name="form_token" value=""#)
+            .expect("expected the authorize page");
+        rest.split('"').next().unwrap().to_string()
+    }
+
+    /// query parameters of the redirect back to the client
+    fn redirect_params(&self) -> HashMap<String, String> {
+        self.location
+            .as_ref()
+            .expect("expected a redirect")
+            .query_pairs()
+            .into_owned()
+            .collect()
+    }
+}
+
+/// a client address nobody else uses, so tests stay clear of each other's per-ip limits
+fn peer() -> ConnectInfo<SocketAddr> {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    ConnectInfo(SocketAddr::from((
+        [10, bytes[0], bytes[1], bytes[2]],
+        40000,
+    )))
+}
+
+async fn send(app: &Router, mut request: Request<Body>) -> Reply {
+    request.extensions_mut().get_or_insert_with(peer);
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let location = response
+        .headers()
+        .get("location")
+        .map(|v| Url::parse(v.to_str().unwrap()).unwrap());
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    Reply {
+        status,
+        location,
+        text: String::from_utf8_lossy(&body_bytes).into_owned(),
+    }
+}
+
+fn encode(pairs: &[(&str, &str)]) -> String {
+    form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(pairs)
+        .finish()
+}
+
+fn post_form(uri: &str, pairs: &[(&str, &str)]) -> Request<Body> {
+    Request::post(uri)
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(Body::from(encode(pairs)))
+        .unwrap()
+}
+
+fn post_json(uri: &str, token: Option<&str>, payload: &Value) -> Request<Body> {
+    let mut builder = Request::post(uri).header("content-type", "application/json");
+    if let Some(token) = token {
+        builder = builder.header("Authorization", format!("Bearer {}", token));
+    }
+    builder.body(Body::from(payload.to_string())).unwrap()
+}
+
+struct Fixture {
+    app: Router,
+    state: AppState,
+    client_id: String,
+    user_id: i64,
+    username: String,
+}
+
+async fn setup() -> Fixture {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+    let state = AppState::new(pg_pool, redis_pool, jwt_secret);
+    let app = create_router(state.clone());
+
+    let (secret, client) = register_app_client(
+        "Demo SPA",
+        &[REDIRECT_URI.to_string()],
+        &["tasks:read".to_string()],
+        false,
+        &state,
+    )
+    .await
+    .unwrap();
+    assert!(secret.is_none());
+
+    let username = format!("authorize_{}", uuid::Uuid::new_v4().simple());
+    let reply = send(
+        &app,
+        post_json(
+            "/api/register",
+            None,
+            &json!({"username": username, "password": "123456"}),
+        ),
+    )
+    .await;
+    assert_eq!(reply.status, StatusCode::OK);
+    Fixture {
+        app,
+        state,
+        client_id: client.client_id,
+        user_id: reply.json()["id"].as_i64().unwrap(),
+        username,
+    }
+}
+
+fn authorize_params<'a>(client_id: &'a str, challenge: &'a str) -> Vec<(&'a str, &'a str)> {
+    vec![
+        ("response_type", "code"),
+        ("client_id", client_id),
+        ("redirect_uri", REDIRECT_URI),
+        ("state", "xyz"),
+        ("code_challenge", challenge),
+        ("code_challenge_method", "S256"),
+    ]
+}
+
+/// opens the authorize page
+async fn open_page(app: &Router, pairs: &[(&str, &str)]) -> Reply {
+    send(
+        app,
+        Request::get(format!("/oauth/authorize?{}", encode(pairs)))
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await
+}
+
+/// signs in on the authorize page and returns the authorization code
+async fn authorize(fixture: &Fixture, challenge: &str) -> String {
+    let mut pairs = authorize_params(&fixture.client_id, challenge);
+    let token = open_page(&fixture.app, &pairs).await.form_token();
+    pairs.extend([
+        ("form_token", token.as_str()),
+        ("username", fixture.username.as_str()),
+        ("password", "123456"),
+        ("action", "allow"),
+    ]);
+    let reply = send(&fixture.app, post_form("/oauth/authorize", &pairs)).await;
+    assert_eq!(reply.status, StatusCode::SEE_OTHER);
+    let params = reply.redirect_params();
+    assert_eq!(params["state"], "xyz");
+    params["code"].clone()
+}
+
+fn exchange(client_id: &str, code: &str, verifier: &str) -> Request<Body> {
+    post_form(
+        "/oauth/token",
+        &[
+            ("grant_type", "authorization_code"),
+            ("client_id", client_id),
+            ("code", code),
+            ("redirect_uri", REDIRECT_URI),
+            ("code_verifier", verifier),
+        ],
+    )
+}
+
+#[tokio::test]
+async fn test_authorization_code_flow_with_pkce() {
+    let fixture = setup().await;
+    let app = &fixture.app;
+    let challenge = pkce_challenge(VERIFIER);
+
+    let reply = open_page(app, &authorize_params(&fixture.client_id, &challenge)).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert!(reply.text.contains("Sign in to Demo SPA"));
+    let token = reply.form_token();
+
+    // a form not rendered by us (or replayed) is not accepted
+    let mut pairs = authorize_params(&fixture.client_id, &challenge);
+    pairs.extend([
+        ("username", fixture.username.as_str()),
+        ("password", "123456"),
+        ("action", "allow"),
+    ]);
+    let reply = send(app, post_form("/oauth/authorize", &pairs)).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert!(reply.location.is_none());
+    assert!(reply.text.contains("this page has expired"));
+
+    // wrong password: stay on the page
+    let mut pairs = authorize_params(&fixture.client_id, &challenge);
+    pairs.extend([
+        ("form_token", token.as_str()),
+        ("username", fixture.username.as_str()),
+        ("password", "nope"),
+    ]);
+    let reply = send(app, post_form("/oauth/authorize", &pairs)).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert!(reply.location.is_none());
+    assert!(reply.text.contains("invalid credentials"));
+    let reply = send(app, post_form("/oauth/authorize", &pairs)).await;
+    assert!(reply.text.contains("this page has expired"));
+
+    // a wrong verifier burns the code
+    let code = authorize(&fixture, &challenge).await;
+    let wrong = "x".repeat(43);
+    let reply = send(app, exchange(&fixture.client_id, &code, &wrong)).await;
+    assert_eq!(reply.status, StatusCode::BAD_REQUEST);
+    assert_eq!(reply.json()["error"], "invalid_grant");
+    let reply = send(app, exchange(&fixture.client_id, &code, VERIFIER)).await;
+    assert_eq!(reply.json()["error"], "invalid_grant");
+
+    let code = authorize(&fixture, &challenge).await;
+    let reply = send(app, exchange(&fixture.client_id, &code, VERIFIER)).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let tokens = reply.json();
+    assert_eq!(tokens["token_type"], "Bearer");
+    let access = tokens["access_token"].as_str().unwrap();
+    let refresh = tokens["refresh_token"].as_str().unwrap();
+
+    let reply = send(
+        app,
+        Request::get("/api/me")
+            .header("Authorization", format!("Bearer {}", access))
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert_eq!(reply.json()["id"], fixture.user_id);
+
+    // codes are single use
+    let reply = send(app, exchange(&fixture.client_id, &code, VERIFIER)).await;
+    assert_eq!(reply.json()["error"], "invalid_grant");
+
+    let reply = send(
+        app,
+        post_form(
+            "/oauth/token",
+            &[
+                ("grant_type", "refresh_token"),
+                ("client_id", &fixture.client_id),
+                ("refresh_token", refresh),
+            ],
+        ),
+    )
+    .await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let tokens = reply.json();
+    assert_ne!(tokens["refresh_token"], refresh);
+    let access = tokens["access_token"].as_str().unwrap();
+    let refresh = tokens["refresh_token"].as_str().unwrap();
+
+    // the token carries the granted scope only, and cannot manage the account
+    let reply = send(
+        app,
+        Request::get("/api/me")
+            .header("Authorization", format!("Bearer {}", access))
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(reply.json()["permissions"], json!([]));
+    let reply = send(
+        app,
+        post_json("/api/me/tokens", Some(access), &json!({"name": "ci"})),
+    )
+    .await;
+    assert_eq!(reply.status, StatusCode::FORBIDDEN);
+
+    // refresh tokens only work for the client they were issued to
+    let (_, other) = register_app_client(
+        "Other SPA",
+        &[REDIRECT_URI.to_string()],
+        &[],
+        false,
+        &fixture.state,
+    )
+    .await
+    .unwrap();
+    let refresh_with = |client_id: &str| {
+        post_form(
+            "/oauth/token",
+            &[
+                ("grant_type", "refresh_token"),
+                ("client_id", client_id),
+                ("refresh_token", refresh),
+            ],
+        )
+    };
+    let reply = send(app, refresh_with(&other.client_id)).await;
+    assert_eq!(reply.status, StatusCode::BAD_REQUEST);
+    assert_eq!(reply.json()["error"], "invalid_grant");
+    let reply = send(
+        app,
+        post_json("/api/refresh", None, &json!({"refresh_token": refresh})),
+    )
+    .await;
+    assert_eq!(reply.status, StatusCode::UNAUTHORIZED);
+    let reply = send(app, refresh_with(&fixture.client_id)).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let access = reply.json()["access_token"].as_str().unwrap().to_string();
+
+    // and only that client can revoke them
+    let revoke_with = |client_id: &str| {
+        post_form(
+            "/oauth/revoke",
+            &[("client_id", client_id), ("token", access.as_str())],
+        )
+    };
+    let reply = send(app, revoke_with(&other.client_id)).await;
+    assert_eq!(reply.status, StatusCode::BAD_REQUEST);
+    assert_eq!(reply.json()["error"], "unauthorized_client");
+    let reply = send(app, revoke_with(&fixture.client_id)).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let reply = send(
+        app,
+        Request::get("/api/me")
+            .header("Authorization", format!("Bearer {}", access))
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(reply.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_authorize_request_errors() {
+    let fixture = setup().await;
+    let app = &fixture.app;
+    let challenge = pkce_challenge(VERIFIER);
+
+    // unregistered redirect uris are never redirected to
+    let mut pairs = authorize_params(&fixture.client_id, &challenge);
+    pairs[2] = ("redirect_uri", "https://evil.example/callback");
+    let reply = open_page(app, &pairs).await;
+    assert_eq!(reply.status, StatusCode::BAD_REQUEST);
+    assert!(reply.location.is_none());
+
+    // PKCE is mandatory
+    let reply = open_page(app, &authorize_params(&fixture.client_id, &challenge)[..4]).await;
+    assert_eq!(reply.status, StatusCode::SEE_OTHER);
+    let params = reply.redirect_params();
+    assert_eq!(params["error"], "invalid_request");
+    assert_eq!(params["state"], "xyz");
+
+    // only scopes the client was registered with, shown to the user before they allow them
+    let mut pairs = authorize_params(&fixture.client_id, &challenge);
+    pairs.push(("scope", "openid tasks:read"));
+    let reply = open_page(app, &pairs).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert!(reply.text.contains(r#"<li data-scope="openid">"#));
+    assert!(reply.text.contains(r#"<li data-scope="tasks:read">"#));
+    pairs.pop();
+    pairs.push(("scope", "tasks:read admin:keys"));
+    let reply = open_page(app, &pairs).await;
+    assert_eq!(reply.status, StatusCode::SEE_OTHER);
+    assert_eq!(reply.redirect_params()["error"], "invalid_scope");
+
+    let mut pairs = authorize_params(&fixture.client_id, &challenge);
+    let token = open_page(app, &pairs).await.form_token();
+    pairs.extend([("form_token", token.as_str()), ("action", "deny")]);
+    let reply = send(app, post_form("/oauth/authorize", &pairs)).await;
+    assert_eq!(reply.redirect_params()["error"], "access_denied");
+
+    // confidential clients must authenticate when exchanging codes
+    let (secret, client) = register_app_client(
+        "Backend app",
+        &[REDIRECT_URI.to_string()],
+        &[],
+        true,
+        &fixture.state,
+    )
+    .await
+    .unwrap();
+    assert!(secret.is_some());
+    let confidential = Fixture {
+        client_id: client.client_id,
+        app: fixture.app.clone(),
+        state: fixture.state.clone(),
+        user_id: fixture.user_id,
+        username: fixture.username.clone(),
+    };
+    // redirect_uri sent with the authorization request must be sent again
+    let code = authorize(&fixture, &challenge).await;
+    let reply = send(
+        app,
+        post_form(
+            "/oauth/token",
+            &[
+                ("grant_type", "authorization_code"),
+                ("client_id", &fixture.client_id),
+                ("code", &code),
+                ("code_verifier", VERIFIER),
+            ],
+        ),
+    )
+    .await;
+    assert_eq!(reply.json()["error"], "invalid_grant");
+
+    let code = authorize(&confidential, &challenge).await;
+    let reply = send(app, exchange(&confidential.client_id, &code, VERIFIER)).await;
+    assert_eq!(reply.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(reply.json()["error"], "invalid_client");
+}
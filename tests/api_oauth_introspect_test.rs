@@ -0,0 +1,245 @@
+use std::{env, net::SocketAddr};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    extract::ConnectInfo,
+    http::{Request, StatusCode},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde_json::{Value, json};
+use tower::ServiceExt; // for `oneshot`
+use web_backend::auth::extractor::AuthUser;
+use web_backend::db::{init_db_pool, init_redis_pool};
+use web_backend::routes::create_router;
+use web_backend::services::service_account_service::create_account;
+use web_backend::state::AppState;
+
+/// a client address nobody else uses, so tests stay clear of each other's per-ip limits
+fn peer() -> ConnectInfo<SocketAddr> {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    ConnectInfo(SocketAddr::from((
+        [10, bytes[0], bytes[1], bytes[2]],
+        40000,
+    )))
+}
+
+async fn send(app: &Router, mut request: Request<Body>) -> (StatusCode, Value) {
+    request.extensions_mut().get_or_insert_with(peer);
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&body_bytes).unwrap_or(Value::Null),
+    )
+}
+
+fn post_json(uri: &str, token: Option<&str>, payload: &Value) -> Request<Body> {
+    let mut builder = Request::post(uri).header("content-type", "application/json");
+    if let Some(token) = token {
+        builder = builder.header("Authorization", format!("Bearer {}", token));
+    }
+    builder.body(Body::from(payload.to_string())).unwrap()
+}
+
+fn get_with_token(uri: &str, token: &str) -> Request<Body> {
+    Request::get(uri)
+        .header("Authorization", format!("Bearer {}", token))
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn form(uri: &str, client: Option<&(String, String)>, body: &str) -> Request<Body> {
+    let mut builder =
+        Request::post(uri).header("content-type", "application/x-www-form-urlencoded");
+    if let Some((id, secret)) = client {
+        let encoded = STANDARD.encode(format!("{}:{}", id, secret));
+        builder = builder.header("Authorization", format!("Basic {}", encoded));
+    }
+    builder.body(Body::from(body.to_string())).unwrap()
+}
+
+struct Fixture {
+    app: Router,
+    client: (String, String),
+    user_id: i64,
+    access: String,
+    refresh: String,
+}
+
+/// a gateway client plus a logged in user
+async fn setup() -> Fixture {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+    let state = AppState::new(pg_pool, redis_pool, jwt_secret);
+    let app = create_router(state.clone());
+
+    let gateway = format!("gateway_{}", uuid::Uuid::new_v4().simple());
+    // a role-less account needs no permissions from whoever creates it
+    let admin = AuthUser {
+        id: 0,
+        jti: String::new(),
+        roles: vec![],
+        permissions: vec![],
+        pat_id: None,
+        scopes: None,
+    };
+    let account = create_account(&admin, &gateway, &[], &state).await.unwrap();
+    let client = (account.client.client_id, account.client_secret);
+
+    let username = format!("introspect_{}", uuid::Uuid::new_v4().simple());
+    let credentials = json!({"username": username, "password": "123456"});
+    let (_, body) = send(&app, post_json("/api/register", None, &credentials)).await;
+    let user_id = body["id"].as_i64().unwrap();
+    let (_, body) = send(&app, post_json("/api/login", None, &credentials)).await;
+    Fixture {
+        app,
+        client,
+        user_id,
+        access: body["access_token"].as_str().unwrap().to_string(),
+        refresh: body["refresh_token"].as_str().unwrap().to_string(),
+    }
+}
+
+#[tokio::test]
+async fn test_introspect_and_revoke_session_tokens() {
+    let Fixture {
+        app,
+        client,
+        user_id,
+        access,
+        refresh,
+    } = setup().await;
+    let introspect = |token: &str| {
+        form(
+            "/oauth/introspect",
+            Some(&client),
+            &format!("token={}", token),
+        )
+    };
+
+    // callers must authenticate as a client
+    let (status, body) = send(
+        &app,
+        form("/oauth/introspect", None, &format!("token={}", access)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"], "invalid_client");
+
+    let (status, body) = send(&app, introspect(&access)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["active"], true);
+    assert_eq!(body["sub"], user_id.to_string());
+    assert_eq!(body["token_type"], "Bearer");
+    assert!(body["exp"].is_i64());
+    assert!(body["jti"].is_string());
+    assert!(body["scope"].is_string());
+
+    let (_, body) = send(&app, introspect(&refresh)).await;
+    assert_eq!(body["active"], true);
+    assert!(body.get("token_type").is_none());
+
+    let (_, body) = send(&app, introspect("not-a-token")).await;
+    assert_eq!(body, json!({"active": false}));
+
+    // clients can only revoke tokens issued to them
+    let (status, body) = send(
+        &app,
+        form(
+            "/oauth/revoke",
+            Some(&client),
+            &format!("token={}&token_type_hint=refresh_token", refresh),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "unauthorized_client");
+    let (_, body) = send(&app, introspect(&access)).await;
+    assert_eq!(body["active"], true);
+
+    // logging out kills the whole session before exp
+    let (status, _) = send(&app, post_json("/api/logout", Some(&access), &json!({}))).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, body) = send(&app, introspect(&access)).await;
+    assert_eq!(body, json!({"active": false}));
+    let (_, body) = send(&app, introspect(&refresh)).await;
+    assert_eq!(body, json!({"active": false}));
+
+    // the client's own token is revocable
+    let (_, body) = send(
+        &app,
+        form(
+            "/oauth/token",
+            Some(&client),
+            "grant_type=client_credentials",
+        ),
+    )
+    .await;
+    let own = body["access_token"].as_str().unwrap().to_string();
+    let (status, _) = send(
+        &app,
+        form("/oauth/revoke", Some(&client), &format!("token={}", own)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, body) = send(&app, introspect(&own)).await;
+    assert_eq!(body, json!({"active": false}));
+    let (status, _) = send(&app, get_with_token("/api/me", &own)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // revoking again (or garbage) is not an error
+    let (status, _) = send(
+        &app,
+        form("/oauth/revoke", Some(&client), &format!("token={}", own)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, form("/oauth/revoke", Some(&client), "token=garbage")).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_introspect_and_revoke_personal_access_token() {
+    let Fixture {
+        app,
+        client,
+        user_id,
+        access,
+        ..
+    } = setup().await;
+
+    let (_, created) = send(
+        &app,
+        post_json("/api/me/tokens", Some(&access), &json!({"name": "cli"})),
+    )
+    .await;
+    let pat = created["token"].as_str().unwrap();
+
+    let (_, body) = send(
+        &app,
+        form(
+            "/oauth/introspect",
+            Some(&client),
+            &format!("token={}", pat),
+        ),
+    )
+    .await;
+    assert_eq!(body["active"], true);
+    assert_eq!(body["sub"], user_id.to_string());
+    assert_eq!(body["jti"], format!("pat:{}", created["id"]));
+    assert_eq!(body["scope"], "");
+
+    // personal access tokens belong to no client: only their owner deletes them
+    let (status, body) = send(
+        &app,
+        form("/oauth/revoke", Some(&client), &format!("token={}", pat)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "unauthorized_client");
+    let (status, _) = send(&app, get_with_token("/api/me", pat)).await;
+    assert_eq!(status, StatusCode::OK);
+}
@@ -0,0 +1,280 @@
+use std::{collections::HashMap, fs, net::SocketAddr};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    extract::ConnectInfo,
+    http::{Request, StatusCode},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, jwk::Jwk};
+use serde_json::{Value, json};
+use tower::ServiceExt; // for `oneshot`
+use url::{Url, form_urlencoded};
+use web_backend::auth::keys::JwtKey;
+use web_backend::db::{init_db_pool, init_redis_pool};
+use web_backend::routes::create_router;
+use web_backend::services::oauth_client_service::register_app_client;
+use web_backend::services::oauth_service::pkce_challenge;
+use web_backend::state::AppState;
+
+const ISSUER: &str = "https://auth.test";
+const REDIRECT_URI: &str = "https://grafana.test/login/generic_oauth";
+const VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+
+fn load_key(alg: Algorithm, name: &str) -> JwtKey {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/keys");
+    let private_pem = fs::read(format!("{}/{}_private.pem", dir, name)).unwrap();
+    let public_pem = fs::read(format!("{}/{}_public.pem", dir, name)).unwrap();
+    JwtKey::from_pem(alg, &private_pem, &public_pem, None).unwrap()
+}
+
+/// a client address nobody else uses, so tests stay clear of each other's per-ip limits
+fn peer() -> ConnectInfo<SocketAddr> {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    ConnectInfo(SocketAddr::from((
+        [10, bytes[0], bytes[1], bytes[2]],
+        40000,
+    )))
+}
+
+async fn send(app: &Router, mut request: Request<Body>) -> (StatusCode, Option<Url>, Value) {
+    request.extensions_mut().get_or_insert_with(peer);
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let location = response
+        .headers()
+        .get("location")
+        .map(|v| Url::parse(v.to_str().unwrap()).unwrap());
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        location,
+        serde_json::from_slice(&body_bytes).unwrap_or(Value::Null),
+    )
+}
+
+fn post_form(uri: &str, pairs: &[(&str, &str)]) -> Request<Body> {
+    let body = form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(pairs)
+        .finish();
+    Request::post(uri)
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+fn get(uri: &str, token: Option<&str>) -> Request<Body> {
+    let mut builder = Request::get(uri);
+    if let Some(token) = token {
+        builder = builder.header("Authorization", format!("Bearer {}", token));
+    }
+    builder.body(Body::empty()).unwrap()
+}
+
+struct Fixture {
+    app: Router,
+    client_id: String,
+    client_secret: String,
+    user_id: i64,
+    username: String,
+    email: String,
+}
+
+async fn setup() -> Fixture {
+    setup_with_key(load_key(Algorithm::ES256, "ec")).await
+}
+
+async fn setup_with_key(key: JwtKey) -> Fixture {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let mut state = AppState::new(pg_pool, redis_pool, key);
+    state.oidc_issuer = ISSUER.to_string();
+    let app = create_router(state.clone());
+
+    let (client_secret, client) =
+        register_app_client("Grafana", &[REDIRECT_URI.to_string()], &[], true, &state)
+            .await
+            .unwrap();
+    let username = format!("oidc_{}", uuid::Uuid::new_v4().simple());
+    let email = format!("{}@example.com", username);
+    let (_, _, body) = send(
+        &app,
+        Request::post("/api/register")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                json!({"username": username, "password": "123456"}).to_string(),
+            ))
+            .unwrap(),
+    )
+    .await;
+    let user_id = body["id"].as_i64().unwrap();
+    sqlx::query("UPDATE users SET email = $2, email_verified = TRUE WHERE id = $1")
+        .bind(user_id)
+        .bind(&email)
+        .execute(&state.db)
+        .await
+        .unwrap();
+    Fixture {
+        app,
+        client_id: client.client_id,
+        client_secret: client_secret.unwrap(),
+        user_id,
+        username,
+        email,
+    }
+}
+
+/// opens the authorize page and returns the one-time token of its form
+async fn form_token(app: &Router, pairs: &[(&str, &str)]) -> String {
+    let query = form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(pairs)
+        .finish();
+    let response = app
+        .clone()
+        .oneshot(get(&format!("/oauth/authorize?{}", query), None))
+        .await
+        .unwrap();
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let page = String::from_utf8_lossy(&body_bytes);
+    let (_, rest) = page.split_once(r#"name="form_token" value=""#).unwrap();
+    rest.split('"').next().unwrap().to_string()
+}
+
+/// runs the authorization code flow and returns the token response
+async fn sign_in(fixture: &Fixture, scope: &str) -> Value {
+    let challenge = pkce_challenge(VERIFIER);
+    let mut pairs = vec![
+        ("response_type", "code"),
+        ("client_id", fixture.client_id.as_str()),
+        ("redirect_uri", REDIRECT_URI),
+        ("scope", scope),
+        ("nonce", "n-0S6_WzA2Mj"),
+        ("code_challenge", challenge.as_str()),
+        ("code_challenge_method", "S256"),
+    ];
+    let token = form_token(&fixture.app, &pairs).await;
+    pairs.extend([
+        ("form_token", token.as_str()),
+        ("username", fixture.username.as_str()),
+        ("password", "123456"),
+        ("action", "allow"),
+    ]);
+    let (status, location, _) = send(&fixture.app, post_form("/oauth/authorize", &pairs)).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    let params: HashMap<String, String> = location.unwrap().query_pairs().into_owned().collect();
+
+    let pairs = [
+        ("grant_type", "authorization_code"),
+        ("client_id", fixture.client_id.as_str()),
+        ("client_secret", fixture.client_secret.as_str()),
+        ("code", params["code"].as_str()),
+        ("redirect_uri", REDIRECT_URI),
+        ("code_verifier", VERIFIER),
+    ];
+    let (status, _, body) = send(&fixture.app, post_form("/oauth/token", &pairs)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body
+}
+
+#[tokio::test]
+async fn test_discovery_and_id_token() {
+    let fixture = setup().await;
+    let app = &fixture.app;
+
+    let (status, _, discovery) = send(app, get("/.well-known/openid-configuration", None)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(discovery["issuer"], ISSUER);
+    assert_eq!(
+        discovery["authorization_endpoint"],
+        format!("{}/oauth/authorize", ISSUER)
+    );
+    assert_eq!(
+        discovery["id_token_signing_alg_values_supported"],
+        json!(["ES256"])
+    );
+    assert_eq!(
+        discovery["code_challenge_methods_supported"],
+        json!(["S256"])
+    );
+    assert_eq!(
+        discovery["userinfo_endpoint"],
+        format!("{}/userinfo", ISSUER)
+    );
+
+    let (_, _, jwks) = send(app, get("/.well-known/jwks.json", None)).await;
+    let jwk: Jwk = serde_json::from_value(jwks["keys"][0].clone()).unwrap();
+
+    let tokens = sign_in(&fixture, "openid profile email").await;
+    let id_token = tokens["id_token"].as_str().unwrap();
+
+    // verifiable with nothing but the published jwks
+    let mut validation = Validation::new(Algorithm::ES256);
+    validation.set_audience(&[&fixture.client_id]);
+    validation.set_issuer(&[ISSUER]);
+    let claims = decode::<Value>(id_token, &DecodingKey::from_jwk(&jwk).unwrap(), &validation)
+        .unwrap()
+        .claims;
+    assert_eq!(claims["sub"], fixture.user_id.to_string());
+    assert_eq!(claims["nonce"], "n-0S6_WzA2Mj");
+    assert_eq!(claims["preferred_username"], fixture.username.as_str());
+    assert_eq!(claims["email"], fixture.email.as_str());
+    assert_eq!(claims["email_verified"], true);
+    assert!(claims["auth_time"].as_i64().unwrap() <= claims["iat"].as_i64().unwrap());
+
+    // an id token is not an access token
+    let (status, _, _) = send(app, get("/api/me", Some(id_token))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let access = tokens["access_token"].as_str().unwrap();
+    let (status, _, userinfo) = send(app, get("/userinfo", Some(access))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(userinfo["sub"], claims["sub"]);
+    assert_eq!(userinfo["preferred_username"], fixture.username.as_str());
+    assert_eq!(userinfo["email"], fixture.email.as_str());
+
+    let (status, _, _) = send(app, get("/userinfo", None)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_no_id_token_without_openid_scope() {
+    let fixture = setup().await;
+    let tokens = sign_in(&fixture, "profile").await;
+    assert!(tokens["access_token"].is_string());
+    assert!(tokens.get("id_token").is_none());
+}
+
+#[tokio::test]
+async fn test_email_claims_need_the_email_scope() {
+    let fixture = setup().await;
+    let tokens = sign_in(&fixture, "openid profile").await;
+
+    let id_token = tokens["id_token"].as_str().unwrap();
+    let payload = id_token.split('.').nth(1).unwrap();
+    let claims: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap();
+    assert_eq!(claims["preferred_username"], fixture.username.as_str());
+    assert!(claims.get("email").is_none());
+    assert!(claims.get("email_verified").is_none());
+
+    let access = tokens["access_token"].as_str().unwrap();
+    let (status, _, userinfo) = send(&fixture.app, get("/userinfo", Some(access))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(userinfo["sub"], fixture.user_id.to_string());
+    assert!(userinfo.get("email").is_none());
+}
+
+#[tokio::test]
+async fn test_oidc_needs_an_asymmetric_key() {
+    let fixture = setup_with_key(JwtKey::hs256(b"a shared secret nobody else has")).await;
+    let (status, _, _) = send(&fixture.app, get("/.well-known/openid-configuration", None)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // the code flow still works, but without an id token
+    let tokens = sign_in(&fixture, "openid profile email").await;
+    assert!(tokens["access_token"].is_string());
+    assert!(tokens.get("id_token").is_none());
+    let access = tokens["access_token"].as_str().unwrap();
+    let (status, _, _) = send(&fixture.app, get("/userinfo", Some(access))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
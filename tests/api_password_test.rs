@@ -0,0 +1,294 @@
+use std::{env, net::SocketAddr, sync::Arc};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    extract::ConnectInfo,
+    http::{Request, StatusCode},
+};
+use serde_json::{Value, json};
+use tower::ServiceExt; // for `oneshot`
+use web_backend::db::{init_db_pool, init_redis_pool};
+use web_backend::routes::create_router;
+use web_backend::services::mailer::FileMailer;
+use web_backend::state::AppState;
+
+/// a client address nobody else uses, so tests stay clear of each other's per-ip limits
+fn peer() -> ConnectInfo<SocketAddr> {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    ConnectInfo(SocketAddr::from((
+        [10, bytes[0], bytes[1], bytes[2]],
+        40000,
+    )))
+}
+
+async fn send(app: &Router, mut request: Request<Body>) -> (StatusCode, Value) {
+    request.extensions_mut().get_or_insert_with(peer);
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&body_bytes).unwrap_or(Value::Null),
+    )
+}
+
+fn post_json(uri: &str, payload: &Value) -> Request<Body> {
+    Request::post(uri)
+        .header("content-type", "application/json")
+        .body(Body::from(payload.to_string()))
+        .unwrap()
+}
+
+/// the token from the last mail in the outbox file
+fn last_mail_token(outbox: &str) -> Option<String> {
+    let content = std::fs::read_to_string(outbox).ok()?;
+    let mail: Value = serde_json::from_str(content.lines().last()?).unwrap();
+    let body = mail["body"].as_str().unwrap();
+    let token = body.split("token=").nth(1)?;
+    Some(token.trim().to_string())
+}
+
+#[tokio::test]
+async fn test_password_reset_flow() {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+    let outbox = env::temp_dir()
+        .join(format!("mail_{}.jsonl", uuid::Uuid::new_v4().simple()))
+        .to_string_lossy()
+        .to_string();
+    let state = AppState::new(pg_pool, redis_pool, jwt_secret)
+        .with_mailer(Arc::new(FileMailer::new(&outbox)));
+    let app = create_router(state.clone());
+
+    let username = format!("reset_test_{}", uuid::Uuid::new_v4().simple());
+    let email = format!("{}@example.com", username);
+    let (status, _) = send(
+        &app,
+        post_json(
+            "/api/register",
+            &json!({"username": username, "password": "123456", "email": email}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // no mail until the address is verified
+    let verify_token = last_mail_token(&outbox).unwrap();
+    let (status, _) = send(
+        &app,
+        post_json("/api/password/forgot", &json!({"username": username})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(last_mail_token(&outbox).unwrap(), verify_token);
+    let (status, _) = send(
+        &app,
+        post_json("/api/email/verify", &json!({"token": verify_token})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, body) = send(
+        &app,
+        post_json(
+            "/api/login",
+            &json!({"username": username, "password": "123456"}),
+        ),
+    )
+    .await;
+    let refresh = body["refresh_token"].as_str().unwrap().to_string();
+
+    // unknown users get the same answer and no mail
+    let (status, _) = send(
+        &app,
+        post_json("/api/password/forgot", &json!({"username": "no_such_user"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(last_mail_token(&outbox).unwrap(), verify_token);
+
+    let (status, _) = send(
+        &app,
+        post_json("/api/password/forgot", &json!({"username": email})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let token = last_mail_token(&outbox).unwrap();
+    assert_ne!(token, verify_token);
+
+    // a rejected new password still uses up the token
+    let weak = json!({"token": token, "new_password": "123"});
+    let (status, body) = send(&app, post_json("/api/password/reset", &weak)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["errors"][0]["field"], "new_password");
+    let reset = json!({"token": token, "new_password": "new-secret"});
+    let (status, _) = send(&app, post_json("/api/password/reset", &reset)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let forgot = json!({"username": email});
+    send(&app, post_json("/api/password/forgot", &forgot)).await;
+    let token = last_mail_token(&outbox).unwrap();
+    let reset = json!({"token": token, "new_password": "new-secret"});
+    let (status, _) = send(&app, post_json("/api/password/reset", &reset)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // single use
+    let (status, _) = send(&app, post_json("/api/password/reset", &reset)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // existing sessions are gone
+    let (status, _) = send(
+        &app,
+        post_json("/api/refresh", &json!({"refresh_token": refresh})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = send(
+        &app,
+        post_json(
+            "/api/login",
+            &json!({"username": username, "password": "123456"}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send(
+        &app,
+        post_json(
+            "/api/login",
+            &json!({"username": username, "password": "new-secret"}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // a token sent before the account was disabled no longer works
+    send(&app, post_json("/api/password/forgot", &forgot)).await;
+    let token = last_mail_token(&outbox).unwrap();
+    sqlx::query("UPDATE users SET disabled = TRUE WHERE username = $1")
+        .bind(&username)
+        .execute(&state.db)
+        .await
+        .unwrap();
+    let reset = json!({"token": token, "new_password": "another-secret"});
+    let (status, _) = send(&app, post_json("/api/password/reset", &reset)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let _ = std::fs::remove_file(&outbox);
+}
+
+fn with_token(uri: &str, token: &str, payload: &Value) -> Request<Body> {
+    Request::post(uri)
+        .header("content-type", "application/json")
+        .header("Authorization", format!("Bearer {}", token))
+        .body(Body::from(payload.to_string()))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_change_password_revokes_other_sessions() {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+    let app = create_router(AppState::new(pg_pool, redis_pool, jwt_secret));
+
+    let username = format!("change_pw_{}", uuid::Uuid::new_v4().simple());
+    let credentials = json!({"username": username, "password": "123456"});
+    send(&app, post_json("/api/register", &credentials)).await;
+    let (_, laptop) = send(&app, post_json("/api/login", &credentials)).await;
+    let (_, phone) = send(&app, post_json("/api/login", &credentials)).await;
+    let laptop = laptop["access_token"].as_str().unwrap();
+    let phone = phone["access_token"].as_str().unwrap();
+
+    let (status, _) = send(
+        &app,
+        with_token(
+            "/api/me/password",
+            laptop,
+            &json!({"current_password": "wrong-one", "new_password": "changed-secret"}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = send(
+        &app,
+        with_token(
+            "/api/me/password",
+            laptop,
+            &json!({"current_password": "123456", "new_password": "123"}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, body) = send(
+        &app,
+        with_token(
+            "/api/me/password",
+            laptop,
+            &json!({
+                "current_password": "123456",
+                "new_password": "changed-secret",
+                "revoke_other_sessions": true
+            }),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["revoked_sessions"], 1);
+
+    let me = |token: &str| {
+        Request::get("/api/me")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap()
+    };
+    let (status, _) = send(&app, me(laptop)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, me(phone)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = send(
+        &app,
+        post_json(
+            "/api/login",
+            &json!({"username": username, "password": "changed-secret"}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_change_password_guesses_count_against_the_account() {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+    let app = create_router(AppState::new(pg_pool, redis_pool, jwt_secret));
+
+    let username = format!("change_pw_guess_{}", uuid::Uuid::new_v4().simple());
+    let credentials = json!({"username": username, "password": "123456"});
+    send(&app, post_json("/api/register", &credentials)).await;
+    let (_, body) = send(&app, post_json("/api/login", &credentials)).await;
+    let token = body["access_token"].as_str().unwrap();
+
+    let change = |current: &str| {
+        with_token(
+            "/api/me/password",
+            token,
+            &json!({"current_password": current, "new_password": "changed-secret"}),
+        )
+    };
+    for guess in ["guess-1", "guess-2"] {
+        let (status, _) = send(&app, change(guess)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+    // backing off like a failed login, even for the right password
+    let (status, _) = send(&app, change("123456")).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    let (status, _) = send(&app, post_json("/api/login", &credentials)).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+}
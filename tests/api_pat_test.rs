@@ -0,0 +1,274 @@
+use std::{env, net::SocketAddr};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    extract::ConnectInfo,
+    http::{Request, StatusCode},
+};
+use serde_json::{Value, json};
+use tower::ServiceExt; // for `oneshot`
+use web_backend::db::{init_db_pool, init_redis_pool};
+use web_backend::routes::create_router;
+use web_backend::state::AppState;
+
+/// a client address nobody else uses, so tests stay clear of each other's per-ip limits
+fn peer() -> ConnectInfo<SocketAddr> {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    ConnectInfo(SocketAddr::from((
+        [10, bytes[0], bytes[1], bytes[2]],
+        40000,
+    )))
+}
+
+async fn send(app: &Router, mut request: Request<Body>) -> (StatusCode, Value) {
+    request.extensions_mut().get_or_insert_with(peer);
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&body_bytes).unwrap_or(Value::Null),
+    )
+}
+
+fn request(method: &str, uri: &str, token: Option<&str>, payload: Option<Value>) -> Request<Body> {
+    let mut builder = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json");
+    if let Some(token) = token {
+        builder = builder.header("Authorization", format!("Bearer {}", token));
+    }
+    let body = payload.map_or_else(Body::empty, |p| Body::from(p.to_string()));
+    builder.body(body).unwrap()
+}
+
+async fn grant_permission(state: &AppState, user_id: i64, permission: &str) {
+    let role_id: i64 = sqlx::query_scalar("INSERT INTO roles (name) VALUES ($1) RETURNING id")
+        .bind(format!("role_{}", uuid::Uuid::new_v4().simple()))
+        .fetch_one(&state.db)
+        .await
+        .unwrap();
+    let permission_id: i64 = sqlx::query_scalar(
+        "INSERT INTO permissions (code) VALUES ($1)
+         ON CONFLICT (code) DO UPDATE SET code = EXCLUDED.code RETURNING id",
+    )
+    .bind(permission)
+    .fetch_one(&state.db)
+    .await
+    .unwrap();
+    sqlx::query("INSERT INTO role_permissions (role_id, permission_id) VALUES ($1, $2)")
+        .bind(role_id)
+        .bind(permission_id)
+        .execute(&state.db)
+        .await
+        .unwrap();
+    sqlx::query("INSERT INTO user_roles (user_id, role_id) VALUES ($1, $2)")
+        .bind(user_id)
+        .bind(role_id)
+        .execute(&state.db)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_personal_access_token_lifecycle() {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+    let state = AppState::new(pg_pool, redis_pool, jwt_secret);
+    let app = create_router(state.clone());
+
+    let username = format!("pat_test_{}", uuid::Uuid::new_v4().simple());
+    let credentials = json!({"username": username, "password": "123456"});
+    let (_, body) = send(
+        &app,
+        request("POST", "/api/register", None, Some(credentials.clone())),
+    )
+    .await;
+    grant_permission(&state, body["id"].as_i64().unwrap(), "report:read").await;
+    let (_, body) = send(&app, request("POST", "/api/login", None, Some(credentials))).await;
+    let access = body["access_token"].as_str().unwrap().to_string();
+
+    // scopes are limited to what the user has
+    let (status, _) = send(
+        &app,
+        request(
+            "POST",
+            "/api/me/tokens",
+            Some(&access),
+            Some(json!({"name": "ci", "scopes": ["admin:keys"]})),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // lifetimes over the cap are rejected, not shortened
+    let (status, body) = send(
+        &app,
+        request(
+            "POST",
+            "/api/me/tokens",
+            Some(&access),
+            Some(json!({"name": "ci", "expires_in_days": 366})),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["errors"][0]["field"], "expires_in_days");
+
+    let (status, created) = send(
+        &app,
+        request(
+            "POST",
+            "/api/me/tokens",
+            Some(&access),
+            Some(json!({"name": "ci", "scopes": ["report:read"], "expires_in_days": 30})),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let pat = created["token"].as_str().unwrap().to_string();
+    assert!(pat.starts_with("pat_"));
+    assert!(created["expires_at"].is_string());
+    assert!(created["last_used_at"].is_null());
+
+    let (status, me) = send(&app, request("GET", "/api/me", Some(&pat), None)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(me["permissions"], json!(["report:read"]));
+    assert_eq!(me["roles"], json!([]));
+
+    // tokens cannot manage tokens; the listing never contains the secret
+    let (status, _) = send(&app, request("GET", "/api/me/tokens", Some(&pat), None)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (_, listed) = send(&app, request("GET", "/api/me/tokens", Some(&access), None)).await;
+    let listed = &listed["tokens"][0];
+    assert_eq!(listed["name"], "ci");
+    assert!(listed.get("token").is_none());
+    assert!(listed["last_used_at"].is_string());
+
+    let uri = format!("/api/me/tokens/{}", created["id"]);
+    let (status, _) = send(&app, request("DELETE", &uri, Some(&access), None)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, request("GET", "/api/me", Some(&pat), None)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send(&app, request("DELETE", &uri, Some(&access), None)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_expired_and_unknown_tokens_are_rejected() {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+    let state = AppState::new(pg_pool, redis_pool, jwt_secret);
+    let app = create_router(state.clone());
+
+    let username = format!("pat_test_{}", uuid::Uuid::new_v4().simple());
+    let credentials = json!({"username": username, "password": "123456"});
+    send(
+        &app,
+        request("POST", "/api/register", None, Some(credentials.clone())),
+    )
+    .await;
+    let (_, body) = send(&app, request("POST", "/api/login", None, Some(credentials))).await;
+    let access = body["access_token"].as_str().unwrap().to_string();
+    let (_, created) = send(
+        &app,
+        request(
+            "POST",
+            "/api/me/tokens",
+            Some(&access),
+            Some(json!({"name": "old", "expires_in_days": 1})),
+        ),
+    )
+    .await;
+    let pat = created["token"].as_str().unwrap();
+
+    let (status, _) = send(&app, request("GET", "/api/me", Some(pat), None)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    sqlx::query(
+        "UPDATE personal_access_tokens SET expires_at = now() - interval '1 second' WHERE id = $1",
+    )
+    .bind(created["id"].as_i64().unwrap())
+    .execute(&state.db)
+    .await
+    .unwrap();
+    let (status, _) = send(&app, request("GET", "/api/me", Some(pat), None)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = send(
+        &app,
+        request("GET", "/api/me", Some("pat_not-a-real-token"), None),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_tokens_cannot_manage_the_account() {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+    let state = AppState::new(pg_pool, redis_pool, jwt_secret);
+    let app = create_router(state.clone());
+
+    let username = format!("pat_test_{}", uuid::Uuid::new_v4().simple());
+    let credentials = json!({"username": username, "password": "123456"});
+    let (_, body) = send(
+        &app,
+        request("POST", "/api/register", None, Some(credentials.clone())),
+    )
+    .await;
+    let user_id = body["id"].as_i64().unwrap();
+    grant_permission(&state, user_id, "admin:users").await;
+    let (_, body) = send(&app, request("POST", "/api/login", None, Some(credentials))).await;
+    let access = body["access_token"].as_str().unwrap().to_string();
+    let mut tokens = Vec::new();
+    for scopes in [json!(["admin:users"]), json!([])] {
+        let (_, created) = send(
+            &app,
+            request(
+                "POST",
+                "/api/me/tokens",
+                Some(&access),
+                Some(json!({"name": "ci", "scopes": scopes})),
+            ),
+        )
+        .await;
+        // without expires_in_days the token does not expire
+        assert!(created["expires_at"].is_null(), "{}", created);
+        tokens.push(created["token"].as_str().unwrap().to_string());
+    }
+    let (admin_pat, plain_pat) = (&tokens[0], &tokens[1]);
+
+    // account management needs a login session, whatever the token's scopes
+    for (method, uri, payload) in [
+        (
+            "POST",
+            "/api/me/email",
+            Some(json!({"email": "attacker@example.com"})),
+        ),
+        ("POST", "/api/me/2fa/totp/enroll", None),
+        ("GET", "/api/sessions", None),
+        ("DELETE", "/api/sessions/others", None),
+        ("POST", "/api/logout/all", None),
+        ("GET", "/api/me/tokens", None),
+    ] {
+        let (status, _) = send(&app, request(method, uri, Some(admin_pat), payload)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{} {}", method, uri);
+    }
+    let (status, _) = send(&app, request("GET", "/api/sessions", Some(&access), None)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // routes are reachable only when they require a permission within the scopes
+    let unlock = format!("/api/admin/users/{}/unlock", user_id);
+    let (status, _) = send(&app, request("POST", &unlock, Some(admin_pat), None)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, request("POST", &unlock, Some(plain_pat), None)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = send(&app, request("POST", "/api/logout", Some(admin_pat), None)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
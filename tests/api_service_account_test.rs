@@ -0,0 +1,336 @@
+use std::{env, net::SocketAddr};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    extract::ConnectInfo,
+    http::{HeaderMap, Request, StatusCode},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde_json::{Value, json};
+use tower::ServiceExt; // for `oneshot`
+use web_backend::db::{init_db_pool, init_redis_pool};
+use web_backend::routes::create_router;
+use web_backend::state::AppState;
+
+/// a client address nobody else uses, so tests stay clear of each other's per-ip limits
+fn peer() -> ConnectInfo<SocketAddr> {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    ConnectInfo(SocketAddr::from((
+        [10, bytes[0], bytes[1], bytes[2]],
+        40000,
+    )))
+}
+
+async fn send(app: &Router, mut request: Request<Body>) -> (StatusCode, HeaderMap, Value) {
+    request.extensions_mut().get_or_insert_with(peer);
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        headers,
+        serde_json::from_slice(&body_bytes).unwrap_or(Value::Null),
+    )
+}
+
+fn json_request(
+    method: &str,
+    uri: &str,
+    token: Option<&str>,
+    payload: Option<Value>,
+) -> Request<Body> {
+    let mut builder = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json");
+    if let Some(token) = token {
+        builder = builder.header("Authorization", format!("Bearer {}", token));
+    }
+    let body = payload.map_or_else(Body::empty, |p| Body::from(p.to_string()));
+    builder.body(body).unwrap()
+}
+
+fn token_request(basic: Option<(&str, &str)>, form: &str) -> Request<Body> {
+    let mut builder =
+        Request::post("/oauth/token").header("content-type", "application/x-www-form-urlencoded");
+    if let Some((id, secret)) = basic {
+        let encoded = STANDARD.encode(format!("{}:{}", id, secret));
+        builder = builder.header("Authorization", format!("Basic {}", encoded));
+    }
+    builder.body(Body::from(form.to_string())).unwrap()
+}
+
+/// creates a role holding the given permissions and returns its name
+async fn create_role(state: &AppState, permissions: &[&str]) -> String {
+    let name = format!("role_{}", uuid::Uuid::new_v4().simple());
+    let role_id: i64 = sqlx::query_scalar("INSERT INTO roles (name) VALUES ($1) RETURNING id")
+        .bind(&name)
+        .fetch_one(&state.db)
+        .await
+        .unwrap();
+    for permission in permissions {
+        let permission_id: i64 = sqlx::query_scalar(
+            "INSERT INTO permissions (code) VALUES ($1)
+             ON CONFLICT (code) DO UPDATE SET code = EXCLUDED.code RETURNING id",
+        )
+        .bind(permission)
+        .fetch_one(&state.db)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO role_permissions (role_id, permission_id) VALUES ($1, $2)")
+            .bind(role_id)
+            .bind(permission_id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+    }
+    name
+}
+
+/// registers a user holding `admin:users` and the `report:*` permissions it hands out,
+/// returns its access token
+async fn admin_token(app: &Router, state: &AppState) -> String {
+    let username = format!("sa_admin_{}", uuid::Uuid::new_v4().simple());
+    let credentials = json!({"username": username, "password": "123456"});
+    let (_, _, body) = send(
+        app,
+        json_request("POST", "/api/register", None, Some(credentials.clone())),
+    )
+    .await;
+    let role = create_role(state, &["admin:users", "report:read", "report:write"]).await;
+    sqlx::query(
+        "INSERT INTO user_roles (user_id, role_id) SELECT $1, id FROM roles WHERE name = $2",
+    )
+    .bind(body["id"].as_i64().unwrap())
+    .bind(role)
+    .execute(&state.db)
+    .await
+    .unwrap();
+    let (_, _, body) = send(
+        app,
+        json_request("POST", "/api/login", None, Some(credentials)),
+    )
+    .await;
+    body["access_token"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn test_client_credentials_grant() {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+    let state = AppState::new(pg_pool, redis_pool, jwt_secret);
+    let app = create_router(state.clone());
+
+    let admin = admin_token(&app, &state).await;
+    let role = create_role(&state, &["report:read", "report:write"]).await;
+    let name = format!("billing_{}", uuid::Uuid::new_v4().simple());
+
+    // only admins manage service accounts, roles must exist
+    let (status, _, _) = send(
+        &app,
+        json_request(
+            "POST",
+            "/api/admin/service-accounts",
+            Some(&admin),
+            Some(json!({"name": name, "roles": ["no_such_role"]})),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    // nor may they hand out permissions they don't hold themselves
+    let privileged = create_role(&state, &["report:read", "admin:keys"]).await;
+    let (status, _, _) = send(
+        &app,
+        json_request(
+            "POST",
+            "/api/admin/service-accounts",
+            Some(&admin),
+            Some(json!({"name": name, "roles": [privileged]})),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _, account) = send(
+        &app,
+        json_request(
+            "POST",
+            "/api/admin/service-accounts",
+            Some(&admin),
+            Some(json!({"name": name, "roles": [role]})),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let client_id = account["client_id"].as_str().unwrap().to_string();
+    let client_secret = account["client_secret"].as_str().unwrap().to_string();
+
+    let (status, headers, body) = send(
+        &app,
+        token_request(
+            Some((&client_id, &client_secret)),
+            "grant_type=client_credentials",
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["cache-control"], "no-store");
+    assert_eq!(body["token_type"], "Bearer");
+    assert!(body.get("refresh_token").is_none());
+    let access = body["access_token"].as_str().unwrap().to_string();
+
+    let (status, _, me) = send(&app, json_request("GET", "/api/me", Some(&access), None)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(me["roles"], json!([role]));
+    let mut permissions: Vec<String> = serde_json::from_value(me["permissions"].clone()).unwrap();
+    permissions.sort();
+    assert_eq!(permissions, ["report:read", "report:write"]);
+
+    // client credentials don't mint personal access tokens
+    let (status, _, _) = send(
+        &app,
+        json_request(
+            "POST",
+            "/api/me/tokens",
+            Some(&access),
+            Some(json!({"name": "ci"})),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // a narrower scope in the request body restricts the token
+    let form = format!(
+        "grant_type=client_credentials&client_id={}&client_secret={}&scope=report:read",
+        client_id, client_secret
+    );
+    let (status, _, body) = send(&app, token_request(None, &form)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["scope"], "report:read");
+    let scoped = body["access_token"].as_str().unwrap();
+    let (_, _, me) = send(&app, json_request("GET", "/api/me", Some(scoped), None)).await;
+    assert_eq!(me["permissions"], json!(["report:read"]));
+
+    let (status, _, body) = send(
+        &app,
+        token_request(
+            Some((&client_id, &client_secret)),
+            "grant_type=client_credentials&scope=admin:keys",
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "invalid_scope");
+
+    // service accounts cannot log in with a password
+    let (status, _, _) = send(
+        &app,
+        json_request(
+            "POST",
+            "/api/login",
+            None,
+            Some(json!({"username": name, "password": "!"})),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // deleting the client revokes its credentials
+    let uri = format!(
+        "/api/admin/service-accounts/{}/clients/{}",
+        account["id"], client_id
+    );
+    let (status, _, _) = send(&app, json_request("DELETE", &uri, Some(&admin), None)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _, body) = send(
+        &app,
+        token_request(
+            Some((&client_id, &client_secret)),
+            "grant_type=client_credentials",
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"], "invalid_client");
+}
+
+#[tokio::test]
+async fn test_token_endpoint_errors_and_client_rotation() {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+    let state = AppState::new(pg_pool, redis_pool, jwt_secret);
+    let app = create_router(state.clone());
+
+    let admin = admin_token(&app, &state).await;
+    let name = format!("worker_{}", uuid::Uuid::new_v4().simple());
+    let (_, _, account) = send(
+        &app,
+        json_request(
+            "POST",
+            "/api/admin/service-accounts",
+            Some(&admin),
+            Some(json!({"name": name})),
+        ),
+    )
+    .await;
+    let client_id = account["client_id"].as_str().unwrap();
+
+    let (status, headers, body) = send(
+        &app,
+        token_request(Some((client_id, "wrong")), "grant_type=client_credentials"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"], "invalid_client");
+    assert!(headers.contains_key("www-authenticate"));
+
+    let (status, _, body) = send(&app, token_request(None, "grant_type=password")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "unsupported_grant_type");
+
+    let (status, _, body) = send(&app, token_request(None, "scope=x")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "invalid_request");
+
+    // a second client for the same account, listed without secrets
+    let uri = format!("/api/admin/service-accounts/{}/clients", account["id"]);
+    let (status, _, created) = send(
+        &app,
+        json_request("POST", &uri, Some(&admin), Some(json!({"name": "rotated"}))),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _, _) = send(
+        &app,
+        token_request(
+            Some((
+                created["client_id"].as_str().unwrap(),
+                created["client_secret"].as_str().unwrap(),
+            )),
+            "grant_type=client_credentials",
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, _, listed) = send(&app, json_request("GET", &uri, Some(&admin), None)).await;
+    let clients = listed["clients"].as_array().unwrap();
+    assert_eq!(clients.len(), 2);
+    assert!(clients.iter().all(|c| c.get("client_secret").is_none()));
+    assert!(clients.iter().any(|c| c["last_used_at"].is_string()));
+
+    // regular users are not service accounts
+    let (status, _, _) = send(
+        &app,
+        json_request(
+            "GET",
+            "/api/admin/service-accounts/0/clients",
+            Some(&admin),
+            None,
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,270 @@
+use std::{env, net::SocketAddr};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    extract::ConnectInfo,
+    http::{Request, StatusCode},
+};
+use serde_json::{Value, json};
+use tower::ServiceExt; // for `oneshot`
+use web_backend::auth::extractor::ClientInfo;
+use web_backend::db::{init_db_pool, init_redis_pool};
+use web_backend::routes::create_router;
+use web_backend::services::auth_service::refresh_tokens;
+use web_backend::state::AppState;
+
+/// a client address nobody else uses, so tests stay clear of each other's per-ip limits
+fn peer() -> ConnectInfo<SocketAddr> {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    ConnectInfo(SocketAddr::from((
+        [10, bytes[0], bytes[1], bytes[2]],
+        40000,
+    )))
+}
+
+async fn send(app: &Router, mut request: Request<Body>) -> (StatusCode, Value) {
+    request.extensions_mut().get_or_insert_with(peer);
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&body_bytes).unwrap_or(Value::Null),
+    )
+}
+
+fn post_json(uri: &str, payload: &Value) -> Request<Body> {
+    Request::post(uri)
+        .header("content-type", "application/json")
+        .body(Body::from(payload.to_string()))
+        .unwrap()
+}
+
+fn with_token(method: &str, uri: &str, token: &str) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Authorization", format!("Bearer {}", token))
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// registers a fresh user and returns its username
+async fn register_user(app: &Router, prefix: &str) -> String {
+    let username = format!("{}_{}", prefix, uuid::Uuid::new_v4().simple());
+    let (status, _) = send(
+        app,
+        post_json(
+            "/api/register",
+            &json!({"username": username, "password": "123456"}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    username
+}
+
+async fn login_user(app: &Router, username: &str) -> Value {
+    let (status, body) = send(
+        app,
+        post_json(
+            "/api/login",
+            &json!({"username": username, "password": "123456"}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    body
+}
+
+#[tokio::test]
+async fn test_list_and_revoke_sessions() {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+
+    let state = AppState::new(pg_pool, redis_pool, jwt_secret);
+    let app = create_router(state.clone());
+
+    let username = register_user(&app, "session_test").await;
+
+    let mut tokens = vec![];
+    for device in ["laptop", "phone"] {
+        let (status, body) = send(
+            &app,
+            post_json(
+                "/api/login",
+                &json!({"username": username, "password": "123456", "device": device}),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        tokens.push(body["access_token"].as_str().unwrap().to_string());
+    }
+
+    let (status, body) = send(&app, with_token("GET", "/api/sessions", &tokens[0])).await;
+    assert_eq!(status, StatusCode::OK);
+    let sessions = body["sessions"].as_array().unwrap();
+    assert_eq!(sessions.len(), 2);
+    let current: Vec<&Value> = sessions.iter().filter(|s| s["current"] == true).collect();
+    assert_eq!(current.len(), 1);
+    assert_eq!(current[0]["device"], "laptop");
+
+    let (status, body) = send(
+        &app,
+        with_token("DELETE", "/api/sessions/others", &tokens[0]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["revoked"], 1);
+
+    // the phone session is gone
+    let (status, _) = send(&app, with_token("GET", "/api/me", &tokens[1])).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (_, body) = send(&app, with_token("GET", "/api/sessions", &tokens[0])).await;
+    assert_eq!(body["sessions"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_logout_current_and_all() {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+
+    let state = AppState::new(pg_pool, redis_pool, jwt_secret);
+    let app = create_router(state.clone());
+
+    let username = register_user(&app, "logout_test").await;
+    let first = login_user(&app, &username).await;
+    let second = login_user(&app, &username).await;
+    let third = login_user(&app, &username).await;
+    let token = |v: &Value| v["access_token"].as_str().unwrap().to_string();
+
+    // logout requires a token
+    let (status, _) = send(&app, post_json("/api/logout", &json!({}))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = send(&app, with_token("POST", "/api/logout", &token(&first))).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, with_token("GET", "/api/me", &token(&first))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send(&app, with_token("GET", "/api/me", &token(&second))).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = send(&app, with_token("POST", "/api/logout/all", &token(&second))).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, with_token("GET", "/api/me", &token(&third))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_refresh_token_reuse_revokes_family() {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+
+    let state = AppState::new(pg_pool, redis_pool, jwt_secret);
+    let app = create_router(state.clone());
+
+    let username = register_user(&app, "reuse_test").await;
+    let login = login_user(&app, &username).await;
+    let stolen = login["refresh_token"].as_str().unwrap();
+    let client = ClientInfo::default();
+
+    let rotated = refresh_tokens(stolen, &client, None, &state).await.unwrap();
+    let (status, _) = send(&app, with_token("GET", "/api/me", &rotated.access_token)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // replaying the already rotated refresh token kills the whole family
+    assert!(refresh_tokens(stolen, &client, None, &state).await.is_err());
+    let (status, _) = send(&app, with_token("GET", "/api/me", &rotated.access_token)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(
+        refresh_tokens(&rotated.refresh_token, &client, None, &state)
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_refresh_without_access_token_and_token_types() {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+
+    let state = AppState::new(pg_pool, redis_pool, jwt_secret);
+    let app = create_router(state.clone());
+
+    let username = register_user(&app, "refresh_test").await;
+    let login = login_user(&app, &username).await;
+    let access = login["access_token"].as_str().unwrap();
+    let refresh = login["refresh_token"].as_str().unwrap();
+
+    // a refresh token is not an access token
+    let (status, _) = send(&app, with_token("GET", "/api/me", refresh)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // and an access token is not a refresh token
+    let (status, _) = send(
+        &app,
+        post_json("/api/refresh", &json!({"refresh_token": access})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // no Authorization header needed to refresh
+    let (status, body) = send(
+        &app,
+        post_json("/api/refresh", &json!({"refresh_token": refresh})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let new_access = body["access_token"].as_str().unwrap();
+    let (status, _) = send(&app, with_token("GET", "/api/me", new_access)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_refresh_is_single_use_and_checks_the_account() {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+
+    let state = AppState::new(pg_pool, redis_pool, jwt_secret);
+    let app = create_router(state.clone());
+
+    let username = register_user(&app, "refresh_once").await;
+    let login = login_user(&app, &username).await;
+    let refresh = login["refresh_token"].as_str().unwrap();
+    let client = ClientInfo::default();
+
+    // another client cannot use it, and does not use it up either
+    assert!(
+        refresh_tokens(refresh, &client, Some("someone-else"), &state)
+            .await
+            .is_err()
+    );
+
+    // concurrent refreshes with the same token: only one wins
+    let refresh_once = || refresh_tokens(refresh, &client, None, &state);
+    let results = tokio::join!(refresh_once(), refresh_once(), refresh_once());
+    let results = [results.0.is_ok(), results.1.is_ok(), results.2.is_ok()];
+    assert_eq!(results.iter().filter(|ok| **ok).count(), 1);
+
+    // a disabled user cannot refresh
+    let login = login_user(&app, &username).await;
+    sqlx::query("UPDATE users SET disabled = TRUE WHERE username = $1")
+        .bind(&username)
+        .execute(&state.db)
+        .await
+        .unwrap();
+    let refresh = login["refresh_token"].as_str().unwrap();
+    let (status, _) = send(
+        &app,
+        post_json("/api/refresh", &json!({"refresh_token": refresh})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
@@ -1,7 +1,8 @@
-use std::env;
+use std::{env, net::SocketAddr};
 
 use axum::{
     body::{Body, to_bytes},
+    extract::ConnectInfo,
     http::{Request, StatusCode},
 };
 use serde_json::json;
@@ -10,6 +11,15 @@ use web_backend::db::{init_db_pool, init_redis_pool};
 use web_backend::routes::create_router;
 use web_backend::state::AppState;
 
+/// a client address nobody else uses, so tests stay clear of each other's per-ip limits
+fn peer() -> ConnectInfo<SocketAddr> {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    ConnectInfo(SocketAddr::from((
+        [10, bytes[0], bytes[1], bytes[2]],
+        40000,
+    )))
+}
+
 #[tokio::test]
 async fn test_login_http() {
     let pg_pool = init_db_pool().await;
@@ -25,6 +35,14 @@ async fn test_login_http() {
         "password": "123456"
     });
 
+    // make sure the user exists; 409 if already registered
+    let request = Request::post("/api/register")
+        .header("content-type", "application/json")
+        .extension(peer())
+        .body(Body::from(payload.to_string()))
+        .unwrap();
+    app.clone().oneshot(request).await.unwrap();
+
     let request = Request::post("/api/login")
         .header("content-type", "application/json")
         .body(Body::from(payload.to_string()))
@@ -52,12 +70,13 @@ async fn test_register() {
     let app = create_router(state.clone());
 
     let payload = json!({
-        "username": "http_test1",
+        "username": format!("http_test_{}", uuid::Uuid::new_v4().simple()),
         "password": "123456"
     });
 
     let request = Request::post("/api/register") // 注意路径！
         .header("content-type", "application/json")
+        .extension(peer())
         .body(Body::from(payload.to_string()))
         .unwrap();
 
@@ -71,3 +90,67 @@ async fn test_register() {
 
     println!("Response body: {}", body_text);
 }
+
+#[tokio::test]
+async fn test_me_requires_auth() {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+
+    let state = AppState::new(pg_pool, redis_pool, jwt_secret);
+    let app = create_router(state.clone());
+
+    let request = Request::get("/api/me").body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let request = Request::get("/api/me")
+        .header("Authorization", "Bearer not-a-jwt")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_login_wrong_password_is_problem_json() {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+
+    let state = AppState::new(pg_pool, redis_pool, jwt_secret);
+    let app = create_router(state.clone());
+
+    // a fresh user, so failed-login counters don't leak into other tests
+    let username = format!("wrong_pw_{}", uuid::Uuid::new_v4().simple());
+    let request = Request::post("/api/register")
+        .header("content-type", "application/json")
+        .extension(peer())
+        .body(Body::from(
+            json!({"username": username, "password": "123456"}).to_string(),
+        ))
+        .unwrap();
+    app.clone().oneshot(request).await.unwrap();
+
+    let payload = json!({
+        "username": username,
+        "password": "wrong-password"
+    });
+
+    let request = Request::post("/api/login")
+        .header("content-type", "application/json")
+        .body(Body::from(payload.to_string()))
+        .unwrap();
+
+    let response = app.clone().oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        response.headers()["content-type"],
+        "application/problem+json"
+    );
+
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(body["status"], 401);
+}
@@ -0,0 +1,846 @@
+use std::{env, net::SocketAddr};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    extract::ConnectInfo,
+    http::{Request, StatusCode},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use ed25519_dalek::pkcs8::DecodePrivateKey as _;
+use p256::ecdsa::signature::Signer;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use tower::ServiceExt; // for `oneshot`
+use web_backend::db::{init_db_pool, init_redis_pool};
+use web_backend::routes::create_router;
+use web_backend::services::oauth_client_service::register_app_client;
+use web_backend::state::AppState;
+use web_backend::utils::cbor::{self, Value as Cbor};
+
+const ORIGIN: &str = "http://localhost:3000";
+const FLAG_UP: u8 = 0x01;
+const FLAG_UV: u8 = 0x04;
+const FLAG_AT: u8 = 0x40;
+
+/// a client address nobody else uses, so tests stay clear of each other's per-ip limits
+fn peer() -> ConnectInfo<SocketAddr> {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    ConnectInfo(SocketAddr::from((
+        [10, bytes[0], bytes[1], bytes[2]],
+        40000,
+    )))
+}
+
+async fn send(app: &Router, mut request: Request<Body>) -> (StatusCode, Value) {
+    request.extensions_mut().get_or_insert_with(peer);
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&body_bytes).unwrap_or(Value::Null),
+    )
+}
+
+fn request(method: &str, uri: &str, token: Option<&str>, payload: Option<&Value>) -> Request<Body> {
+    let mut builder = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json");
+    if let Some(token) = token {
+        builder = builder.header("Authorization", format!("Bearer {}", token));
+    }
+    let body = payload.map_or(Body::empty(), |p| Body::from(p.to_string()));
+    builder.body(body).unwrap()
+}
+
+fn post_json(uri: &str, token: Option<&str>, payload: &Value) -> Request<Body> {
+    request("POST", uri, token, Some(payload))
+}
+
+fn b64(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+enum Key {
+    Es256(p256::ecdsa::SigningKey),
+    EdDsa(ed25519_dalek::SigningKey),
+}
+
+/// software authenticator built from the fixture keys
+struct Authenticator {
+    key: Key,
+    credential_id: Vec<u8>,
+    sign_count: u32,
+}
+
+impl Authenticator {
+    fn es256() -> Self {
+        let pem = std::fs::read_to_string("tests/fixtures/keys/ec_private.pem").unwrap();
+        Self::new(Key::Es256(
+            p256::ecdsa::SigningKey::from_pkcs8_pem(&pem).unwrap(),
+        ))
+    }
+
+    fn ed25519() -> Self {
+        let pem = std::fs::read_to_string("tests/fixtures/keys/ed25519_private.pem").unwrap();
+        Self::new(Key::EdDsa(
+            ed25519_dalek::SigningKey::from_pkcs8_pem(&pem).unwrap(),
+        ))
+    }
+
+    fn new(key: Key) -> Self {
+        Self {
+            key,
+            credential_id: uuid::Uuid::new_v4().as_bytes().to_vec(),
+            sign_count: 0,
+        }
+    }
+
+    fn id(&self) -> String {
+        b64(&self.credential_id)
+    }
+
+    fn cose_key(&self) -> Vec<u8> {
+        let int = Cbor::Integer;
+        let map = match &self.key {
+            Key::Es256(key) => {
+                let point = key.verifying_key().to_encoded_point(false);
+                vec![
+                    (int(1), int(2)),
+                    (int(3), int(-7)),
+                    (int(-1), int(1)),
+                    (int(-2), Cbor::Bytes(point.x().unwrap().to_vec())),
+                    (int(-3), Cbor::Bytes(point.y().unwrap().to_vec())),
+                ]
+            }
+            Key::EdDsa(key) => vec![
+                (int(1), int(1)),
+                (int(3), int(-8)),
+                (int(-1), int(6)),
+                (
+                    int(-2),
+                    Cbor::Bytes(key.verifying_key().to_bytes().to_vec()),
+                ),
+            ],
+        };
+        cbor::encode(&Cbor::Map(map))
+    }
+
+    fn auth_data(&self, rp_id: &str, flags: u8) -> Vec<u8> {
+        let mut data = Sha256::digest(rp_id.as_bytes()).to_vec();
+        data.push(flags);
+        data.extend(self.sign_count.to_be_bytes());
+        if flags & FLAG_AT != 0 {
+            data.extend([0u8; 16]);
+            data.extend((self.credential_id.len() as u16).to_be_bytes());
+            data.extend(&self.credential_id);
+            data.extend(self.cose_key());
+        }
+        data
+    }
+
+    fn sign(&self, message: &[u8]) -> Vec<u8> {
+        match &self.key {
+            Key::Es256(key) => {
+                let signature: p256::ecdsa::Signature = key.sign(message);
+                signature.to_der().as_bytes().to_vec()
+            }
+            Key::EdDsa(key) => key.sign(message).to_bytes().to_vec(),
+        }
+    }
+
+    /// navigator.credentials.create()
+    fn create(&self, challenge: &str, origin: &str, flags: u8) -> Value {
+        let client_data = json!({
+            "type": "webauthn.create",
+            "challenge": challenge,
+            "origin": origin,
+        })
+        .to_string();
+        let attestation = Cbor::Map(vec![
+            (Cbor::Text("fmt".into()), Cbor::Text("none".into())),
+            (Cbor::Text("attStmt".into()), Cbor::Map(vec![])),
+            (
+                Cbor::Text("authData".into()),
+                Cbor::Bytes(self.auth_data("localhost", flags | FLAG_AT)),
+            ),
+        ]);
+        json!({
+            "id": self.id(),
+            "type": "public-key",
+            "response": {
+                "clientDataJSON": b64(client_data.as_bytes()),
+                "attestationObject": b64(&cbor::encode(&attestation)),
+                "transports": ["internal"],
+            },
+        })
+    }
+
+    /// navigator.credentials.get(); the counter goes up on every use
+    fn get(&mut self, challenge: &str, flags: u8, user_handle: Option<i64>) -> Value {
+        self.sign_count += 1;
+        let client_data = json!({
+            "type": "webauthn.get",
+            "challenge": challenge,
+            "origin": ORIGIN,
+        })
+        .to_string();
+        let auth_data = self.auth_data("localhost", flags);
+        let mut message = auth_data.clone();
+        message.extend(Sha256::digest(client_data.as_bytes()));
+        json!({
+            "id": self.id(),
+            "type": "public-key",
+            "response": {
+                "clientDataJSON": b64(client_data.as_bytes()),
+                "authenticatorData": b64(&auth_data),
+                "signature": b64(&self.sign(&message)),
+                "userHandle": user_handle.map(|id| b64(&id.to_be_bytes())),
+            },
+        })
+    }
+}
+
+struct Fixture {
+    app: Router,
+    state: AppState,
+}
+
+async fn setup() -> Fixture {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+    let state = AppState::new(pg_pool, redis_pool, jwt_secret);
+    Fixture {
+        app: create_router(state.clone()),
+        state,
+    }
+}
+
+/// registers and logs in a user, returns (id, username, access token)
+async fn create_user(fixture: &Fixture) -> (i64, String, String) {
+    let username = format!("passkey_{}", uuid::Uuid::new_v4().simple());
+    let credentials = json!({"username": username, "password": "123456"});
+    let (status, body) = send(&fixture.app, post_json("/api/register", None, &credentials)).await;
+    assert_eq!(status, StatusCode::OK);
+    let id = body["id"].as_i64().unwrap();
+    let (_, body) = send(&fixture.app, post_json("/api/login", None, &credentials)).await;
+    (
+        id,
+        username,
+        body["access_token"].as_str().unwrap().to_string(),
+    )
+}
+
+async fn registration_challenge(fixture: &Fixture, access: &str) -> String {
+    let (status, options) = send(
+        &fixture.app,
+        post_json(
+            "/api/me/passkeys/options",
+            Some(access),
+            &json!({"password": "123456"}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    options["challenge"].as_str().unwrap().to_string()
+}
+
+/// full registration ceremony, returns the stored passkey
+async fn register(fixture: &Fixture, access: &str, authenticator: &Authenticator) -> Value {
+    let challenge = registration_challenge(fixture, access).await;
+    let (status, body) = send(
+        &fixture.app,
+        post_json(
+            "/api/me/passkeys",
+            Some(access),
+            &json!({
+                "name": "laptop",
+                "credential": authenticator.create(&challenge, ORIGIN, FLAG_UP | FLAG_UV),
+            }),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body
+}
+
+async fn passkey_login(
+    fixture: &Fixture,
+    authenticator: &mut Authenticator,
+    flags: u8,
+    user_id: i64,
+) -> (StatusCode, Value) {
+    let (status, options) = send(
+        &fixture.app,
+        post_json("/api/login/passkey/options", None, &json!({})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(options["userVerification"], "required");
+    let credential =
+        authenticator.get(options["challenge"].as_str().unwrap(), flags, Some(user_id));
+    send(
+        &fixture.app,
+        post_json(
+            "/api/login/passkey",
+            None,
+            &json!({"credential": credential, "device": "phone"}),
+        ),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn register_list_rename_delete() {
+    let fixture = setup().await;
+    let (_, username, access) = create_user(&fixture).await;
+    let authenticator = Authenticator::es256();
+
+    let challenge = registration_challenge(&fixture, &access).await;
+    let (_, options) = send(
+        &fixture.app,
+        post_json(
+            "/api/me/passkeys/options",
+            Some(&access),
+            &json!({"password": "123456"}),
+        ),
+    )
+    .await;
+    assert_eq!(options["rp"]["id"], "localhost");
+    assert_eq!(options["user"]["name"], username.as_str());
+    assert_eq!(options["excludeCredentials"], json!([]));
+
+    let (status, passkey) = send(
+        &fixture.app,
+        post_json(
+            "/api/me/passkeys",
+            Some(&access),
+            &json!({
+                "name": "  laptop  ",
+                "credential": authenticator.create(&challenge, ORIGIN, FLAG_UP | FLAG_UV),
+            }),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", passkey);
+    assert_eq!(passkey["name"], "laptop");
+    assert_eq!(passkey["credential_id"], authenticator.id());
+    assert_eq!(passkey["transports"], json!(["internal"]));
+    assert!(passkey.get("public_key").is_none());
+    let id = passkey["id"].as_i64().unwrap();
+
+    // already registered credentials are excluded
+    let (_, options) = send(
+        &fixture.app,
+        post_json(
+            "/api/me/passkeys/options",
+            Some(&access),
+            &json!({"password": "123456"}),
+        ),
+    )
+    .await;
+    assert_eq!(options["excludeCredentials"][0]["id"], authenticator.id());
+
+    let (status, body) = send(
+        &fixture.app,
+        request(
+            "PATCH",
+            &format!("/api/me/passkeys/{}", id),
+            Some(&access),
+            Some(&json!({"name": "yubikey"})),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["name"], "yubikey");
+
+    let (status, body) = send(
+        &fixture.app,
+        request("GET", "/api/me/passkeys", Some(&access), None),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["passkeys"].as_array().unwrap().len(), 1);
+    assert_eq!(body["passkeys"][0]["name"], "yubikey");
+
+    let (status, _) = send(
+        &fixture.app,
+        request(
+            "DELETE",
+            &format!("/api/me/passkeys/{}", id),
+            Some(&access),
+            None,
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, body) = send(
+        &fixture.app,
+        request("GET", "/api/me/passkeys", Some(&access), None),
+    )
+    .await;
+    assert_eq!(body["passkeys"], json!([]));
+    let (status, _) = send(
+        &fixture.app,
+        request(
+            "DELETE",
+            &format!("/api/me/passkeys/{}", id),
+            Some(&access),
+            None,
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn registration_rejects_bad_ceremonies() {
+    let fixture = setup().await;
+    let (_, _, access) = create_user(&fixture).await;
+    let (_, _, other_access) = create_user(&fixture).await;
+    let authenticator = Authenticator::es256();
+    let register_with = |credential: Value, token: String| {
+        let app = fixture.app.clone();
+        async move {
+            send(
+                &app,
+                post_json(
+                    "/api/me/passkeys",
+                    Some(&token),
+                    &json!({"name": "key", "credential": credential}),
+                ),
+            )
+            .await
+        }
+    };
+
+    // wrong origin
+    let challenge = registration_challenge(&fixture, &access).await;
+    let (status, body) = register_with(
+        authenticator.create(&challenge, "https://evil.example", FLAG_UP | FLAG_UV),
+        access.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["errors"][0]["message"], "origin is not allowed");
+
+    // the challenge was used up by the failed attempt
+    let (status, body) = register_with(
+        authenticator.create(&challenge, ORIGIN, FLAG_UP | FLAG_UV),
+        access.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["errors"][0]["message"], "invalid or expired challenge");
+
+    // another user's challenge
+    let challenge = registration_challenge(&fixture, &access).await;
+    let (status, _) = register_with(
+        authenticator.create(&challenge, ORIGIN, FLAG_UP | FLAG_UV),
+        other_access.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    // no user presence
+    let challenge = registration_challenge(&fixture, &access).await;
+    let (status, body) =
+        register_with(authenticator.create(&challenge, ORIGIN, 0), access.clone()).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["errors"][0]["message"], "user presence is required");
+
+    // passkeys sign in without a password, so the user must be verified
+    let challenge = registration_challenge(&fixture, &access).await;
+    let (status, body) = register_with(
+        authenticator.create(&challenge, ORIGIN, FLAG_UP),
+        access.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        body["errors"][0]["message"],
+        "user verification is required"
+    );
+
+    // same credential cannot be registered twice
+    register(&fixture, &access, &authenticator).await;
+    let challenge = registration_challenge(&fixture, &other_access).await;
+    let (status, _) = register_with(
+        authenticator.create(&challenge, ORIGIN, FLAG_UP | FLAG_UV),
+        other_access.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn registration_requires_reauthentication() {
+    let fixture = setup().await;
+    let (_, _, access) = create_user(&fixture).await;
+    let options = |payload: Value| {
+        send(
+            &fixture.app,
+            post_json("/api/me/passkeys/options", Some(&access), &payload),
+        )
+    };
+
+    // a stolen session alone cannot add a passkey
+    let (status, body) = options(json!({})).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["errors"][0]["field"], "password");
+    let (status, _) = options(json!({"password": "nope"})).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, body) = options(json!({"password": "123456"})).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body["authenticatorSelection"]["userVerification"],
+        "required"
+    );
+
+    // guesses count against the account like failed logins
+    let (_, _, access) = create_user(&fixture).await;
+    let options = |password: &str| {
+        send(
+            &fixture.app,
+            post_json(
+                "/api/me/passkeys/options",
+                Some(&access),
+                &json!({"password": password}),
+            ),
+        )
+    };
+    for guess in ["guess-1", "guess-2"] {
+        let (status, _) = options(guess).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+    let (status, _) = options("123456").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[test]
+fn short_rsa_keys_are_rejected() {
+    use web_backend::services::webauthn::CoseKey;
+    let rsa_key = |bytes: usize| {
+        let int = Cbor::Integer;
+        cbor::encode(&Cbor::Map(vec![
+            (int(1), int(3)),
+            (int(3), int(-257)),
+            (int(-1), Cbor::Bytes(vec![0xc5; bytes])),
+            (int(-2), Cbor::Bytes(vec![0x01, 0x00, 0x01])),
+        ]))
+    };
+    assert_eq!(
+        CoseKey::from_cbor(&rsa_key(128)).err(),
+        Some("RSA key is too short")
+    );
+    assert!(CoseKey::from_cbor(&rsa_key(256)).is_ok());
+}
+
+#[tokio::test]
+async fn passkey_only_login() {
+    let fixture = setup().await;
+    let (id, _, access) = create_user(&fixture).await;
+    let mut authenticator = Authenticator::ed25519();
+    register(&fixture, &access, &authenticator).await;
+
+    let (status, body) = passkey_login(&fixture, &mut authenticator, FLAG_UP | FLAG_UV, id).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["user"]["id"], id);
+    let access = body["access_token"].as_str().unwrap().to_string();
+    let (status, me) = send(&fixture.app, request("GET", "/api/me", Some(&access), None)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(me["id"], id);
+    let (_, body) = send(
+        &fixture.app,
+        request("GET", "/api/me/passkeys", Some(&access), None),
+    )
+    .await;
+    assert!(body["passkeys"][0]["last_used_at"].is_string());
+
+    // user verification is required without a password
+    let (status, _) = passkey_login(&fixture, &mut authenticator, FLAG_UP, id).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // the user handle must belong to the credential
+    let (status, _) = passkey_login(&fixture, &mut authenticator, FLAG_UP | FLAG_UV, id + 1).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // a challenge is single use
+    let (_, options) = send(
+        &fixture.app,
+        post_json("/api/login/passkey/options", None, &json!({})),
+    )
+    .await;
+    let challenge = options["challenge"].as_str().unwrap();
+    let credential = authenticator.get(challenge, FLAG_UP | FLAG_UV, Some(id));
+    let (status, _) = send(
+        &fixture.app,
+        post_json(
+            "/api/login/passkey",
+            None,
+            &json!({"credential": credential}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let credential = authenticator.get(challenge, FLAG_UP | FLAG_UV, Some(id));
+    let (status, body) = send(
+        &fixture.app,
+        post_json(
+            "/api/login/passkey",
+            None,
+            &json!({"credential": credential}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["detail"], "invalid or expired challenge");
+}
+
+#[tokio::test]
+async fn counter_regression_is_rejected() {
+    let fixture = setup().await;
+    let (id, _, access) = create_user(&fixture).await;
+    let mut authenticator = Authenticator::es256();
+    register(&fixture, &access, &authenticator).await;
+
+    authenticator.sign_count = 9;
+    let (status, _) = passkey_login(&fixture, &mut authenticator, FLAG_UP | FLAG_UV, id).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // a cloned authenticator replays an older counter
+    authenticator.sign_count = 4;
+    let (status, _) = passkey_login(&fixture, &mut authenticator, FLAG_UP | FLAG_UV, id).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let stored: i64 =
+        sqlx::query_scalar("SELECT sign_count FROM webauthn_credentials WHERE user_id = $1")
+            .bind(id)
+            .fetch_one(&fixture.state.db)
+            .await
+            .unwrap();
+    assert_eq!(stored, 10);
+
+    // the genuine authenticator keeps working
+    authenticator.sign_count = 10;
+    let (status, _) = passkey_login(&fixture, &mut authenticator, FLAG_UP | FLAG_UV, id).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn passkey_as_second_factor() {
+    let fixture = setup().await;
+    let (id, username, access) = create_user(&fixture).await;
+    let mut authenticator = Authenticator::es256();
+    let mut other = Authenticator::ed25519();
+    register(&fixture, &access, &authenticator).await;
+    let (_, _, other_access) = create_user(&fixture).await;
+    register(&fixture, &other_access, &other).await;
+
+    // a registered passkey turns on the second step after the password
+    let credentials = json!({"username": username, "password": "123456"});
+    let (status, body) = send(&fixture.app, post_json("/api/login", None, &credentials)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["mfa_required"], true);
+    assert_eq!(body["methods"], json!(["webauthn"]));
+    assert!(body.get("access_token").is_none());
+    let mfa_token = body["mfa_token"].as_str().unwrap().to_string();
+
+    let options = |mfa_token: String| {
+        let app = fixture.app.clone();
+        async move {
+            let (status, options) = send(
+                &app,
+                post_json(
+                    "/api/login/2fa/passkey/options",
+                    None,
+                    &json!({"mfa_token": mfa_token}),
+                ),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            options
+        }
+    };
+    let body = options(mfa_token.clone()).await;
+    assert_eq!(body["allowCredentials"][0]["id"], authenticator.id());
+
+    // another user's passkey does not count
+    let credential = other.get(body["challenge"].as_str().unwrap(), FLAG_UP, None);
+    let (status, _) = send(
+        &fixture.app,
+        post_json(
+            "/api/login/2fa/passkey",
+            None,
+            &json!({"mfa_token": mfa_token, "credential": credential}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // user presence is enough here, the password was the first factor
+    let body = options(mfa_token.clone()).await;
+    let credential = authenticator.get(body["challenge"].as_str().unwrap(), FLAG_UP, None);
+    let (status, body) = send(
+        &fixture.app,
+        post_json(
+            "/api/login/2fa/passkey",
+            None,
+            &json!({"mfa_token": mfa_token, "credential": credential}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["user"]["id"], id);
+    assert!(body["access_token"].is_string());
+
+    // the mfa token is used up
+    let (status, _) = send(
+        &fixture.app,
+        post_json(
+            "/api/login/2fa/passkey/options",
+            None,
+            &json!({"mfa_token": mfa_token}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+/// value of a hidden field on the authorize page
+fn hidden_field(page: &str, name: &str) -> String {
+    let (_, rest) = page
+        .split_once(&format!(r#"name="{}" value=""#, name))
+        .unwrap_or_else(|| panic!("no {} on the page", name));
+    rest.split('"').next().unwrap().to_string()
+}
+
+/// posts the authorize form, returns (status, page, redirect location)
+async fn submit_authorize(app: &Router, pairs: &[(&str, &str)]) -> (StatusCode, String, String) {
+    let body = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(pairs)
+        .finish();
+    let request = Request::post("/oauth/authorize")
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(Body::from(body))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let location = response
+        .headers()
+        .get("location")
+        .map(|v| v.to_str().unwrap().to_string())
+        .unwrap_or_default();
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        String::from_utf8_lossy(&body_bytes).into_owned(),
+        location,
+    )
+}
+
+#[tokio::test]
+async fn passkey_on_authorize_page() {
+    let fixture = setup().await;
+    let (_, username, access) = create_user(&fixture).await;
+    let mut authenticator = Authenticator::es256();
+    register(&fixture, &access, &authenticator).await;
+    let (_, client) = register_app_client(
+        "Passkey SPA",
+        &["http://localhost:5173/callback".to_string()],
+        &[],
+        false,
+        &fixture.state,
+    )
+    .await
+    .unwrap();
+    let params = [
+        ("response_type", "code"),
+        ("client_id", client.client_id.as_str()),
+        ("redirect_uri", "http://localhost:5173/callback"),
+        (
+            "code_challenge",
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM",
+        ),
+        ("code_challenge_method", "S256"),
+    ];
+    let query = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params)
+        .finish();
+    let response = fixture
+        .app
+        .clone()
+        .oneshot(
+            Request::get(format!("/oauth/authorize?{}", query))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let page = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let form_token = hidden_field(&String::from_utf8_lossy(&page), "form_token");
+
+    // the password step leads to the passkey step
+    let mut pairs = params.to_vec();
+    pairs.extend([
+        ("form_token", form_token.as_str()),
+        ("username", username.as_str()),
+        ("password", "123456"),
+        ("action", "allow"),
+    ]);
+    let (status, page, _) = submit_authorize(&fixture.app, &pairs).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(page.contains("Allow with passkey"), "{}", page);
+    let form_token = hidden_field(&page, "form_token");
+    let mfa_token = hidden_field(&page, "mfa_token");
+
+    let (status, options) = send(
+        &fixture.app,
+        post_json(
+            "/api/login/2fa/passkey/options",
+            None,
+            &json!({"mfa_token": mfa_token}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let credential = authenticator.get(options["challenge"].as_str().unwrap(), FLAG_UP, None);
+    let credential = credential.to_string();
+    let mut pairs = params.to_vec();
+    pairs.extend([
+        ("form_token", form_token.as_str()),
+        ("mfa_token", mfa_token.as_str()),
+        ("passkey", credential.as_str()),
+    ]);
+    let (status, page, location) = submit_authorize(&fixture.app, &pairs).await;
+    assert_eq!(status, StatusCode::SEE_OTHER, "{}", page);
+    assert!(location.starts_with("http://localhost:5173/callback?code="));
+}
+
+#[tokio::test]
+async fn last_passkey_of_passwordless_user_is_kept() {
+    let fixture = setup().await;
+    let (id, _, access) = create_user(&fixture).await;
+    let passkey = register(&fixture, &access, &Authenticator::es256()).await;
+    let second = register(&fixture, &access, &Authenticator::ed25519()).await;
+    sqlx::query("UPDATE users SET password_hash = '!' WHERE id = $1")
+        .bind(id)
+        .execute(&fixture.state.db)
+        .await
+        .unwrap();
+
+    let delete = |passkey: &Value| {
+        request(
+            "DELETE",
+            &format!("/api/me/passkeys/{}", passkey["id"]),
+            Some(&access),
+            None,
+        )
+    };
+    let (status, _) = send(&fixture.app, delete(&second)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&fixture.app, delete(&passkey)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+}
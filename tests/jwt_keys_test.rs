@@ -0,0 +1,252 @@
+use std::{env, fs, net::SocketAddr};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    extract::ConnectInfo,
+    http::{Request, StatusCode},
+};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header, jwk::Jwk};
+use serde_json::{Value, json};
+use tower::ServiceExt; // for `oneshot`
+use web_backend::auth::jwt::{Claims, TokenType, decode_claims, encode_claims, make_claims};
+use web_backend::auth::keys::{JwtKey, KeyRing};
+use web_backend::db::{init_db_pool, init_redis_pool};
+use web_backend::routes::create_router;
+use web_backend::services::key_service;
+use web_backend::state::AppState;
+
+fn load_key(alg: Algorithm, name: &str) -> JwtKey {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/keys");
+    let private_pem = fs::read(format!("{}/{}_private.pem", dir, name)).unwrap();
+    let public_pem = fs::read(format!("{}/{}_public.pem", dir, name)).unwrap();
+    JwtKey::from_pem(alg, &private_pem, &public_pem, None).unwrap()
+}
+
+#[test]
+fn test_asymmetric_keys_verify_from_jwk() {
+    for (alg, name) in [
+        (Algorithm::RS256, "rsa"),
+        (Algorithm::ES256, "ec"),
+        (Algorithm::EdDSA, "ed25519"),
+    ] {
+        let ring = KeyRing::new(load_key(alg, name));
+        let key = ring.current();
+        let claims = make_claims(TokenType::Access, 1, 60, "family");
+        let token = encode_claims(&ring, &claims).unwrap();
+
+        let header = decode_header(&token).unwrap();
+        assert_eq!(header.alg, alg);
+        assert_eq!(header.kid.as_deref(), Some(key.kid.as_str()));
+
+        let decoded = decode_claims(&ring, &token, TokenType::Access).unwrap();
+        assert_eq!(decoded.jti, claims.jti);
+
+        // a downstream service only has the published JWK
+        let jwk: Jwk = serde_json::from_value(key.jwk().unwrap().clone()).unwrap();
+        let decoding = DecodingKey::from_jwk(&jwk).unwrap();
+        let data = decode::<Claims>(&token, &decoding, &Validation::new(alg)).unwrap();
+        assert_eq!(data.claims.sub, 1);
+    }
+}
+
+#[tokio::test]
+async fn test_key_rotation_is_shared_between_instances() {
+    // two instances of the same deployment: same configured key, same db
+    let replica = || async {
+        let mut state = AppState::new(
+            init_db_pool().await,
+            init_redis_pool(),
+            env::var("JWT_SECRET").unwrap().into_bytes(),
+        );
+        state.jwt_key_dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/keys").into();
+        state.key_sync_secs = 0;
+        state
+    };
+    let a = replica().await;
+    let b = replica().await;
+    sqlx::query("DELETE FROM jwt_signing_keys")
+        .execute(&a.db)
+        .await
+        .unwrap();
+    let app = create_router(a.clone());
+    let admin_token = admin_token(&app, &a).await;
+    let old_token =
+        encode_claims(&a.jwt_keys, &make_claims(TokenType::Access, 1, 60, "f")).unwrap();
+
+    // key material only comes from the key directory
+    let (status, body) = send(&app, rotate(&admin_token, "../keys/ec", None)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["errors"][0]["field"], "name");
+
+    let kid = format!("ec-{}", uuid::Uuid::new_v4().simple());
+    let (status, body) = send(&app, rotate(&admin_token, "ec", Some(&kid))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["kid"], kid.as_str());
+    assert_eq!(a.jwt_keys.current().kid, kid);
+
+    let (status, _) = send(&app, rotate(&admin_token, "ec", Some(&kid))).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    // the other instance picks the new key up on its next sync
+    let new_token =
+        encode_claims(&a.jwt_keys, &make_claims(TokenType::Access, 1, 60, "f")).unwrap();
+    assert_eq!(decode_header(&new_token).unwrap().kid, Some(kid.clone()));
+    key_service::sync(&b).await.unwrap();
+    assert_eq!(b.jwt_keys.current().kid, kid);
+    assert!(decode_claims(&b.jwt_keys, &new_token, TokenType::Access).is_ok());
+    assert!(decode_claims(&b.jwt_keys, &old_token, TokenType::Access).is_ok());
+    assert_eq!(b.jwt_keys.jwks().len(), 1);
+
+    // retiring the old key reaches every instance; the signing key cannot be retired
+    let (status, _) = send(&app, retire(&admin_token, &kid)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, _) = send(&app, retire(&admin_token, "no-such-key")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(&app, retire(&admin_token, "default")).await;
+    assert_eq!(status, StatusCode::OK);
+    key_service::sync(&b).await.unwrap();
+    assert!(decode_claims(&b.jwt_keys, &old_token, TokenType::Access).is_err());
+    assert!(decode_claims(&b.jwt_keys, &new_token, TokenType::Access).is_ok());
+
+    // a new key is verifiable everywhere before anyone signs with it
+    let mut c = replica().await;
+    c.key_sync_secs = 60;
+    let next = format!("ed-{}", uuid::Uuid::new_v4().simple());
+    key_service::rotate(Algorithm::EdDSA, "ed25519", Some(next.clone()), &c)
+        .await
+        .unwrap();
+    assert_eq!(c.jwt_keys.current().kid, kid);
+    assert!(c.jwt_keys.find(Some(&next)).is_some());
+
+    // neither the key still signing (now with a retire_at) nor the pending one can be retired;
+    // the first admin token was signed with the retired key
+    let admin_token = self::admin_token(&app, &a).await;
+    for pinned in [&kid, &next] {
+        let (status, body) = send(&app, retire(&admin_token, pinned)).await;
+        assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+    }
+    assert_eq!(c.jwt_keys.current().kid, kid);
+    sqlx::query("DELETE FROM jwt_signing_keys")
+        .execute(&c.db)
+        .await
+        .unwrap();
+}
+
+/// a client address nobody else uses, so tests stay clear of each other's per-ip limits
+fn peer() -> ConnectInfo<SocketAddr> {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    ConnectInfo(SocketAddr::from((
+        [10, bytes[0], bytes[1], bytes[2]],
+        40000,
+    )))
+}
+
+async fn send(app: &Router, mut request: Request<Body>) -> (StatusCode, Value) {
+    request.extensions_mut().get_or_insert_with(peer);
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&body_bytes).unwrap_or(Value::Null),
+    )
+}
+
+fn rotate(token: &str, name: &str, kid: Option<&str>) -> Request<Body> {
+    Request::post("/api/admin/keys/rotate")
+        .header("Authorization", format!("Bearer {}", token))
+        .header("content-type", "application/json")
+        .body(Body::from(
+            json!({"alg": "ES256", "name": name, "kid": kid}).to_string(),
+        ))
+        .unwrap()
+}
+
+fn retire(token: &str, kid: &str) -> Request<Body> {
+    Request::delete(format!("/api/admin/keys/{}", kid))
+        .header("Authorization", format!("Bearer {}", token))
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn admin_token(app: &Router, state: &AppState) -> String {
+    let username = format!("keys_admin_{}", uuid::Uuid::new_v4().simple());
+    let credentials = json!({"username": username, "password": "123456"}).to_string();
+    let post = |uri: &str| {
+        Request::post(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(credentials.clone()))
+            .unwrap()
+    };
+    let (_, body) = send(app, post("/api/register")).await;
+    let user_id = body["id"].as_i64().unwrap();
+
+    let role = format!("role_{}", uuid::Uuid::new_v4().simple());
+    let role_id: i64 = sqlx::query_scalar("INSERT INTO roles (name) VALUES ($1) RETURNING id")
+        .bind(&role)
+        .fetch_one(&state.db)
+        .await
+        .unwrap();
+    let permission_id: i64 = sqlx::query_scalar(
+        "INSERT INTO permissions (code) VALUES ('admin:keys')
+         ON CONFLICT (code) DO UPDATE SET code = EXCLUDED.code RETURNING id",
+    )
+    .fetch_one(&state.db)
+    .await
+    .unwrap();
+    sqlx::query("INSERT INTO role_permissions (role_id, permission_id) VALUES ($1, $2)")
+        .bind(role_id)
+        .bind(permission_id)
+        .execute(&state.db)
+        .await
+        .unwrap();
+    sqlx::query("INSERT INTO user_roles (user_id, role_id) VALUES ($1, $2)")
+        .bind(user_id)
+        .bind(role_id)
+        .execute(&state.db)
+        .await
+        .unwrap();
+
+    let (_, body) = send(app, post("/api/login")).await;
+    body["access_token"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn test_jwks_endpoint() {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+
+    let key = load_key(Algorithm::ES256, "ec");
+    let kid = key.kid.clone();
+    let state = AppState::new(pg_pool, redis_pool, key);
+    let app = create_router(state.clone());
+
+    let request = Request::get("/.well-known/jwks.json")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body_bytes).unwrap();
+    let keys = body["keys"].as_array().unwrap();
+    assert_eq!(keys.len(), 1);
+    assert_eq!(keys[0]["kid"], kid.as_str());
+    assert_eq!(keys[0]["kty"], "EC");
+    assert!(keys[0].get("d").is_none());
+
+    // the shared HS256 secret is never published
+    let state = AppState::new(
+        init_db_pool().await,
+        init_redis_pool(),
+        env::var("JWT_SECRET").unwrap().into_bytes(),
+    );
+    let request = Request::get("/.well-known/jwks.json")
+        .body(Body::empty())
+        .unwrap();
+    let response = create_router(state).oneshot(request).await.unwrap();
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert!(body["keys"].as_array().unwrap().is_empty());
+}
@@ -0,0 +1,101 @@
+use std::env;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use serde_json::json;
+use tower::ServiceExt; // for `oneshot`
+use web_backend::db::{init_db_pool, init_redis_pool};
+use web_backend::routes::create_router;
+use web_backend::state::AppState;
+use web_backend::utils::hash::{HashScheme, PasswordHasher};
+
+#[test]
+fn test_argon2id_hash_and_verify() {
+    let hasher = PasswordHasher::argon2id(1024, 1, 1).unwrap();
+    let hash = hasher.hash("correct horse").unwrap();
+    assert!(hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+    assert!(hasher.verify("correct horse", &hash).unwrap());
+    assert!(!hasher.verify("wrong horse", &hash).unwrap());
+    assert!(!hasher.needs_rehash(&hash));
+
+    // long passwords are not truncated
+    let long = "x".repeat(100);
+    let hash = hasher.hash(&long).unwrap();
+    assert!(!hasher.verify(&long[..72], &hash).unwrap());
+
+    assert!(!hasher.verify("anything", "not a hash").unwrap());
+}
+
+#[test]
+fn test_needs_rehash_detects_scheme_and_params() {
+    let bcrypt_hash = bcrypt::hash("secret", 4).unwrap();
+    let weak_argon = PasswordHasher::argon2id(1024, 1, 1)
+        .unwrap()
+        .hash("secret")
+        .unwrap();
+
+    let argon = PasswordHasher::argon2id(2048, 2, 1).unwrap();
+    // stored bcrypt hashes still verify
+    assert!(argon.verify("secret", &bcrypt_hash).unwrap());
+    assert!(!argon.verify("other", &bcrypt_hash).unwrap());
+    assert!(argon.needs_rehash(&bcrypt_hash));
+    assert!(argon.verify("secret", &weak_argon).unwrap());
+    assert!(argon.needs_rehash(&weak_argon));
+
+    let bcrypt = PasswordHasher::new(HashScheme::Bcrypt { cost: 4 });
+    assert!(!bcrypt.needs_rehash(&bcrypt_hash));
+    assert!(bcrypt.needs_rehash(&weak_argon));
+    assert!(PasswordHasher::new(HashScheme::Bcrypt { cost: 5 }).needs_rehash(&bcrypt_hash));
+}
+
+#[tokio::test]
+async fn test_login_migrates_bcrypt_hash() {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+    let state = AppState::new(pg_pool, redis_pool, jwt_secret);
+    let app = create_router(state.clone());
+
+    // a user created before the switch to argon2id
+    let username = format!("legacy_{}", uuid::Uuid::new_v4().simple());
+    let legacy_hash = bcrypt::hash("123456", 4).unwrap();
+    let user_id: i64 = sqlx::query_scalar(
+        "INSERT INTO users (username, password_hash) VALUES ($1, $2) RETURNING id",
+    )
+    .bind(&username)
+    .bind(&legacy_hash)
+    .fetch_one(&state.db)
+    .await
+    .unwrap();
+    let stored = || async {
+        sqlx::query_scalar::<_, String>("SELECT password_hash FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(&state.db)
+            .await
+            .unwrap()
+    };
+    let login = |password: &str| {
+        Request::post("/api/login")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                json!({"username": username, "password": password}).to_string(),
+            ))
+            .unwrap()
+    };
+
+    // a failed login leaves the hash alone
+    let response = app.clone().oneshot(login("wrong")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(stored().await, legacy_hash);
+
+    let response = app.clone().oneshot(login("123456")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let migrated = stored().await;
+    assert!(migrated.starts_with("$argon2id$"));
+
+    let response = app.clone().oneshot(login("123456")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(stored().await, migrated);
+}
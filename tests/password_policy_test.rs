@@ -0,0 +1,175 @@
+use std::{env, net::SocketAddr, sync::Arc};
+
+use axum::{
+    body::{Body, to_bytes},
+    extract::ConnectInfo,
+    http::{Request, StatusCode},
+};
+use serde_json::{Value, json};
+use tower::ServiceExt; // for `oneshot`
+use web_backend::db::{init_db_pool, init_redis_pool};
+use web_backend::error::AppError;
+use web_backend::routes::create_router;
+use web_backend::services::password_policy::{BreachedPasswords, PasswordPolicy};
+use web_backend::state::AppState;
+
+fn strict_policy() -> PasswordPolicy {
+    PasswordPolicy {
+        min_length: 10,
+        max_length: 64,
+        min_char_classes: 3,
+        disallow_username: true,
+        min_strength: 3,
+        breached: None,
+    }
+}
+
+/// messages reported under the `password` field
+async fn rejection(policy: &PasswordPolicy, password: &str, user_inputs: &[&str]) -> String {
+    match policy.check("password", password, user_inputs).await {
+        Err(AppError::InvalidFields(errors)) => {
+            assert!(errors.iter().all(|e| e.field == "password"));
+            errors
+                .iter()
+                .map(|e| e.message.as_str())
+                .collect::<Vec<_>>()
+                .join("; ")
+        }
+        other => panic!("expected a validation error, got {:?}", other),
+    }
+}
+
+/// a client address nobody else uses, so tests stay clear of each other's per-ip limits
+fn peer() -> ConnectInfo<SocketAddr> {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    ConnectInfo(SocketAddr::from((
+        [10, bytes[0], bytes[1], bytes[2]],
+        40000,
+    )))
+}
+
+#[tokio::test]
+async fn test_policy_rules() {
+    let policy = strict_policy();
+    assert!(
+        policy
+            .check("password", "Vivid-Otter-Lantern-42", &["colin"])
+            .await
+            .is_ok()
+    );
+
+    assert!(
+        rejection(&policy, "Ab1!", &[])
+            .await
+            .contains("at least 10 characters")
+    );
+    assert!(
+        rejection(&policy, &"Ab1!".repeat(20), &[])
+            .await
+            .contains("at most 64 bytes")
+    );
+    assert!(
+        rejection(&policy, "lowercaseonlywords", &[])
+            .await
+            .contains("mix at least 3")
+    );
+    assert!(
+        rejection(&policy, "Colin-Vivid-Otter-42", &["colin"])
+            .await
+            .contains("must not contain your username")
+    );
+    assert!(
+        rejection(&policy, "Password123", &[])
+            .await
+            .contains("too easy to guess")
+    );
+
+    // the default policy only asks for 6 characters
+    let lenient = PasswordPolicy::default();
+    assert!(
+        lenient
+            .check("password", "123456", &["colin"])
+            .await
+            .is_ok()
+    );
+    assert!(lenient.check("password", "", &[]).await.is_err());
+}
+
+#[tokio::test]
+async fn test_breached_password_lists() {
+    for path in [
+        "tests/fixtures/breached/range",
+        "tests/fixtures/breached/hashes.txt",
+    ] {
+        let breached = BreachedPasswords::open(path).unwrap();
+        assert!(
+            breached.contains("P@ssw0rd-2024").await.unwrap(),
+            "{}",
+            path
+        );
+        assert!(
+            !breached.contains("Vivid-Otter-Lantern-42").await.unwrap(),
+            "{}",
+            path
+        );
+    }
+
+    let policy = PasswordPolicy {
+        breached: Some(Arc::new(
+            BreachedPasswords::open("tests/fixtures/breached/range").unwrap(),
+        )),
+        ..PasswordPolicy::default()
+    };
+    assert!(
+        rejection(&policy, "P@ssw0rd-2024", &[])
+            .await
+            .contains("breached")
+    );
+    assert!(
+        policy
+            .check("password", "Vivid-Otter-Lantern-42", &[])
+            .await
+            .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn test_register_enforces_policy() {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+    let mut state = AppState::new(pg_pool, redis_pool, jwt_secret);
+    state.password_policy = PasswordPolicy {
+        breached: Some(Arc::new(
+            BreachedPasswords::open("tests/fixtures/breached/range").unwrap(),
+        )),
+        ..strict_policy()
+    };
+    let app = create_router(state);
+
+    let username = format!("policy_{}", uuid::Uuid::new_v4().simple());
+    let register = |password: &str| {
+        Request::post("/api/register")
+            .header("content-type", "application/json")
+            .extension(peer())
+            .body(Body::from(
+                json!({"username": username, "password": password}).to_string(),
+            ))
+            .unwrap()
+    };
+
+    for weak in ["", "123456", "P@ssw0rd-2024"] {
+        let response = app.clone().oneshot(register(weak)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["errors"][0]["field"], "password", "{}", body);
+    }
+
+    let response = app
+        .clone()
+        .oneshot(register("Vivid-Otter-Lantern-42"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
@@ -0,0 +1,280 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    Router,
+    body::Body,
+    extract::ConnectInfo,
+    http::{HeaderMap, Request, StatusCode},
+    response::Response,
+    routing::get,
+};
+use deadpool_redis::{Config, Runtime};
+use tower::ServiceExt; // for `oneshot`
+use web_backend::auth::extractor::{AuthUser, TrustedProxies, client_ip};
+use web_backend::auth::rate_limit::{RateLimitKey, RateLimitLayer, RateLimitPolicy};
+use web_backend::db::init_redis_pool;
+use web_backend::routes::{REGISTER_LIMIT, create_router};
+use web_backend::state::AppState;
+
+fn header<'a>(response: &'a Response, name: &str) -> Option<&'a str> {
+    response.headers().get(name).map(|v| v.to_str().unwrap())
+}
+
+/// a client address nobody else uses
+fn fresh_ip() -> String {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    format!("10.{}.{}.{}", bytes[0], bytes[1], bytes[2])
+}
+
+fn peer(ip: &str) -> ConnectInfo<SocketAddr> {
+    ConnectInfo(SocketAddr::new(ip.parse().unwrap(), 40000))
+}
+
+/// a POST /api/register with an invalid body: rejected by the handler but still counted
+fn register(peer_ip: &str, forwarded_for: &str) -> Request<Body> {
+    Request::post("/api/register")
+        .header("content-type", "application/json")
+        .header("x-forwarded-for", forwarded_for)
+        .extension(peer(peer_ip))
+        .body(Body::from("{}"))
+        .unwrap()
+}
+
+async fn test_state() -> AppState {
+    AppState::new(
+        web_backend::db::init_db_pool().await,
+        init_redis_pool(),
+        std::env::var("JWT_SECRET").unwrap().into_bytes(),
+    )
+}
+
+#[tokio::test]
+async fn test_register_is_rate_limited_per_ip() {
+    let mut state = test_state().await;
+    state.trusted_proxies = Arc::new(TrustedProxies::parse("127.0.0.1").unwrap());
+    let app = create_router(state);
+    let ip = fresh_ip();
+    // clients behind the trusted proxy are told apart by X-Forwarded-For
+    let register = |ip: &str| register("127.0.0.1", ip);
+
+    for i in 0..REGISTER_LIMIT.limit {
+        let response = app.clone().oneshot(register(&ip)).await.unwrap();
+        assert_ne!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(header(&response, "ratelimit-limit"), Some("10"));
+        let remaining = (REGISTER_LIMIT.limit - i - 1).to_string();
+        assert_eq!(header(&response, "ratelimit-remaining"), Some(&*remaining));
+    }
+
+    let response = app.clone().oneshot(register(&ip)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(header(&response, "ratelimit-remaining"), Some("0"));
+    assert_eq!(
+        header(&response, "content-type"),
+        Some("application/problem+json")
+    );
+    let retry_after: u64 = header(&response, "retry-after").unwrap().parse().unwrap();
+    assert!(retry_after > 0 && retry_after <= 3600);
+
+    // other clients are unaffected
+    let response = app.clone().oneshot(register(&fresh_ip())).await.unwrap();
+    assert_ne!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn test_forwarded_for_is_ignored_without_trusted_proxies() {
+    let app = create_router(test_state().await);
+    let ip = fresh_ip();
+    // a new X-Forwarded-For on every request does not reset the count
+    for _ in 0..REGISTER_LIMIT.limit {
+        let response = app
+            .clone()
+            .oneshot(register(&ip, &fresh_ip()))
+            .await
+            .unwrap();
+        assert_ne!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+    let response = app
+        .clone()
+        .oneshot(register(&ip, &fresh_ip()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[test]
+fn test_client_ip_takes_the_right_most_untrusted_hop() {
+    let trusted = TrustedProxies::parse("10.0.0.0/8, ::1").unwrap();
+    let resolve = |peer_ip: &str, forwarded: Option<&str>| {
+        let mut headers = HeaderMap::new();
+        if let Some(forwarded) = forwarded {
+            headers.insert("x-forwarded-for", forwarded.parse().unwrap());
+        }
+        let mut extensions = axum::http::Extensions::new();
+        extensions.insert(peer(peer_ip));
+        client_ip(&headers, &extensions, &trusted)
+    };
+    let resolve = |peer_ip, forwarded| resolve(peer_ip, forwarded).unwrap();
+
+    // untrusted peers cannot choose their address
+    assert_eq!(resolve("203.0.113.7", Some("198.51.100.1")), "203.0.113.7");
+    // a spoofed left-most entry is skipped
+    assert_eq!(
+        resolve("10.0.0.2", Some("198.51.100.1, 203.0.113.9, 10.0.0.1")),
+        "203.0.113.9"
+    );
+    assert_eq!(resolve("::1", Some("203.0.113.9")), "203.0.113.9");
+    assert_eq!(resolve("10.0.0.2", Some("10.0.0.3, 10.0.0.1")), "10.0.0.3");
+    assert_eq!(resolve("10.0.0.2", Some("junk, 10.0.0.1")), "10.0.0.1");
+    assert_eq!(resolve("10.0.0.2", None), "10.0.0.2");
+    // no connection address (in-process calls): nothing to go on
+    assert_eq!(
+        client_ip(
+            &HeaderMap::new(),
+            &axum::http::Extensions::new(),
+            &TrustedProxies::default()
+        ),
+        None
+    );
+    assert!(TrustedProxies::parse("10.0.0.0/33").is_err());
+    assert!(TrustedProxies::parse("proxy.local").is_err());
+}
+
+/// an authenticated request, as `AuthLayer` would leave it
+fn authenticated(user_id: i64, pat_id: Option<i64>) -> Request<Body> {
+    Request::get("/ping")
+        .extension(AuthUser {
+            id: user_id,
+            jti: String::new(),
+            roles: vec![],
+            permissions: vec![],
+            pat_id,
+            scopes: None,
+        })
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// ids nobody else counts against
+fn fresh_id() -> i64 {
+    (uuid::Uuid::new_v4().as_u128() >> 68) as i64
+}
+
+fn ping_app(redis: deadpool_redis::Pool, key: RateLimitKey) -> Router {
+    let policy = RateLimitPolicy {
+        name: "test_ping",
+        limit: 2,
+        window_secs: 60,
+        key,
+    };
+    Router::new().route(
+        "/ping",
+        get(|| async { "pong" }).layer(RateLimitLayer::new(redis, policy)),
+    )
+}
+
+async fn statuses(app: &Router, request: impl Fn() -> Request<Body>) -> Vec<StatusCode> {
+    let mut statuses = vec![];
+    for _ in 0..3 {
+        statuses.push(app.clone().oneshot(request()).await.unwrap().status());
+    }
+    statuses
+}
+
+const LIMITED: [StatusCode; 3] = [
+    StatusCode::OK,
+    StatusCode::OK,
+    StatusCode::TOO_MANY_REQUESTS,
+];
+
+#[tokio::test]
+async fn test_counts_per_user_and_per_token() {
+    let user = ping_app(init_redis_pool(), RateLimitKey::User);
+    let user_id = fresh_id();
+    assert_eq!(
+        statuses(&user, || authenticated(user_id, None)).await,
+        LIMITED
+    );
+    // every token of the user counts against the user
+    assert_eq!(
+        user.clone()
+            .oneshot(authenticated(user_id, Some(fresh_id())))
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(
+        statuses(&user, || authenticated(fresh_id(), None)).await[0],
+        StatusCode::OK
+    );
+
+    let api_key = ping_app(init_redis_pool(), RateLimitKey::ApiKey);
+    let user_id = fresh_id();
+    let pat_id = fresh_id();
+    assert_eq!(
+        statuses(&api_key, || authenticated(user_id, Some(pat_id))).await,
+        LIMITED
+    );
+    // the user's other tokens and sessions have their own counters
+    let other_pat_id = fresh_id();
+    assert_eq!(
+        statuses(&api_key, || authenticated(user_id, Some(other_pat_id))).await,
+        LIMITED
+    );
+    assert_eq!(
+        statuses(&api_key, || authenticated(user_id, None)).await,
+        LIMITED
+    );
+
+    // without authentication a bearer value of its own does not buy a new counter
+    let ip = fresh_ip();
+    let unauthenticated = || {
+        Request::get("/ping")
+            .header("authorization", format!("Bearer {}", uuid::Uuid::new_v4()))
+            .extension(peer(&ip))
+            .body(Body::empty())
+            .unwrap()
+    };
+    assert_eq!(statuses(&api_key, unauthenticated).await, LIMITED);
+}
+
+#[tokio::test]
+async fn test_falls_back_to_in_process_counters() {
+    // nothing listens on port 1
+    let redis = Config::from_url("redis://127.0.0.1:1/")
+        .create_pool(Some(Runtime::Tokio1))
+        .unwrap();
+    let app = ping_app(redis, RateLimitKey::ApiKey);
+
+    let response = app
+        .clone()
+        .oneshot(authenticated(1, Some(1)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .clone()
+        .oneshot(authenticated(1, Some(1)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .clone()
+        .oneshot(authenticated(1, Some(1)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(header(&response, "retry-after").is_some());
+
+    // counted per token
+    let response = app
+        .clone()
+        .oneshot(authenticated(1, Some(2)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // without a token or address the requests share one counter
+    let anonymous = || Request::get("/ping").body(Body::empty()).unwrap();
+    assert_eq!(statuses(&app, anonymous).await, LIMITED);
+}
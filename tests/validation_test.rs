@@ -0,0 +1,197 @@
+use std::{env, net::SocketAddr};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    extract::ConnectInfo,
+    http::{Request, StatusCode},
+};
+use serde_json::{Value, json};
+use tower::ServiceExt; // for `oneshot`
+use web_backend::db::{init_db_pool, init_redis_pool};
+use web_backend::error::AppError;
+use web_backend::repositories::user_repo::register_by_username_password_hash;
+use web_backend::routes::create_router;
+use web_backend::state::AppState;
+
+/// a client address nobody else uses, so tests stay clear of each other's per-ip limits
+fn peer() -> ConnectInfo<SocketAddr> {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    ConnectInfo(SocketAddr::from((
+        [10, bytes[0], bytes[1], bytes[2]],
+        40000,
+    )))
+}
+
+async fn send(app: &Router, mut request: Request<Body>) -> (StatusCode, Value) {
+    request.extensions_mut().get_or_insert_with(peer);
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body_bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&body_bytes).unwrap_or(Value::Null),
+    )
+}
+
+fn post_body(uri: &str, body: String) -> Request<Body> {
+    Request::post(uri)
+        .header("content-type", "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+async fn test_state() -> AppState {
+    let pg_pool = init_db_pool().await;
+    let redis_pool = init_redis_pool();
+    let jwt_secret = env::var("JWT_SECRET").unwrap().into_bytes();
+    AppState::new(pg_pool, redis_pool, jwt_secret)
+}
+
+#[tokio::test]
+async fn test_field_errors() {
+    let app = create_router(test_state().await);
+
+    let (status, body) = send(
+        &app,
+        post_body(
+            "/api/register",
+            json!({"username": " a\u{7}", "password": "123456", "email": "nope"}).to_string(),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["type"], "/problems/validation");
+    let fields: Vec<&str> = body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, ["username", "email"]);
+
+    let (status, body) = send(
+        &app,
+        post_body(
+            "/api/login",
+            json!({"username": "", "password": ""}).to_string(),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["errors"].as_array().unwrap().len(), 2);
+
+    // unparsable bodies are problem+json too
+    let (status, body) = send(&app, post_body("/api/login", "{not json".into())).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["status"], 422);
+}
+
+#[tokio::test]
+async fn test_username_is_normalized() {
+    let app = create_router(test_state().await);
+    let suffix = uuid::Uuid::new_v4().simple().to_string();
+
+    let (status, _) = send(
+        &app,
+        post_body(
+            "/api/register",
+            json!({"username": format!("  Mixed.Case_{}  ", suffix), "password": "123456"})
+                .to_string(),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = send(
+        &app,
+        post_body(
+            "/api/login",
+            json!({"username": format!("MIXED.CASE_{}", suffix), "password": "123456"}).to_string(),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["username"], format!("mixed.case_{}", suffix));
+
+    let (status, body) = send(
+        &app,
+        post_body(
+            "/api/register",
+            json!({"username": format!("mixed.CASE_{}", suffix), "password": "123456"}).to_string(),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["detail"], "username already exists");
+}
+
+#[tokio::test]
+async fn test_unique_violation_is_conflict() {
+    let state = test_state().await;
+    let username = format!("race_{}", uuid::Uuid::new_v4().simple());
+    register_by_username_password_hash(&state.db, &username, "x", None)
+        .await
+        .unwrap();
+
+    // what the loser of a concurrent registration sees
+    let err = register_by_username_password_hash(&state.db, &username.to_uppercase(), "x", None)
+        .await
+        .unwrap_err();
+    assert!(matches!(&err, AppError::Conflict(detail) if detail == "username already exists"));
+
+    // and over HTTP, racing two registrations never yields a 500
+    let app = create_router(state);
+    let username = format!("race_{}", uuid::Uuid::new_v4().simple());
+    let register = || {
+        post_body(
+            "/api/register",
+            json!({"username": username, "password": "123456"}).to_string(),
+        )
+    };
+    let (a, b) = tokio::join!(send(&app, register()), send(&app, register()));
+    let mut statuses = [a.0, b.0];
+    statuses.sort();
+    assert_eq!(statuses, [StatusCode::OK, StatusCode::CONFLICT]);
+}
+
+#[tokio::test]
+async fn test_token_bodies_are_validated() {
+    let app = create_router(test_state().await);
+
+    let (status, body) = send(
+        &app,
+        post_body(
+            "/api/login/2fa",
+            json!({"mfa_token": "", "code": "1".repeat(64)}).to_string(),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let fields: Vec<&str> = body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, ["mfa_token", "code"]);
+
+    let (status, body) = send(
+        &app,
+        post_body("/api/refresh", json!({"refresh_token": ""}).to_string()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["errors"][0]["field"], "refresh_token");
+
+    let (status, body) = send(
+        &app,
+        post_body(
+            "/api/email/verify",
+            json!({"token": "x".repeat(200)}).to_string(),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["errors"][0]["field"], "token");
+}